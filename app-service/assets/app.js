@@ -6,11 +6,24 @@ logoutLink.addEventListener("click", (e) => {
     e.preventDefault();
 
     let url = logoutLink.href;
+    let csrfTokenUrl = new URL('/csrf-token', url);
 
-    fetch(url, {
+    // The auth service requires a CSRF token with cookie authenticated requests.
+    // Its cookie belongs to another origin, so fetch the token first.
+    fetch(csrfTokenUrl, {
+        credentials: 'include',
+    }).then(response => {
+        if (!response.ok) {
+            throw new Error("Failed to get CSRF token");
+        }
+        return response.json();
+    }).then(data => fetch(url, {
         method: 'POST',
         credentials: 'include', // This will include cookies in the request
-    }).then(response => {
+        headers: {
+            'X-CSRF-Token': data.csrfToken,
+        },
+    })).then(response => {
         if (response.ok) {
             loginLink.style.display = "block";
             logoutLink.style.display = "none";
@@ -18,7 +31,7 @@ logoutLink.addEventListener("click", (e) => {
         } else {
             alert("Failed to logout");
         }
-    });
+    }).catch(() => alert("Failed to logout"));
 });
 
 (() => {
@@ -1,22 +1,36 @@
-use std::env;
+use std::{env, sync::Arc};
 
 use askama::Template;
 use axum::{
     http::StatusCode,
+    middleware,
     response::{Html, IntoResponse},
     routing::get,
-    Json, Router,
+    Extension, Json, Router,
 };
 use axum_extra::extract::CookieJar;
 use serde::Serialize;
 use tower_http::services::ServeDir;
 
+use security_headers::{
+    security_headers, CspNonce, SecurityHeaders, SecurityHeadersPolicy, CSP_NONCE_PLACEHOLDER,
+};
+
 #[tokio::main]
 async fn main() {
+    let headers = SecurityHeaders::from_env(default_security_headers());
+    // The protected route answers based on the session cookie, so it must not be cached
+    let security_headers_policy =
+        SecurityHeadersPolicy::new(headers.clone()).with_route("/protected", headers.no_store());
+
     let app = Router::new()
         .nest_service("/assets", ServeDir::new("assets"))
         .route("/", get(root))
-        .route("/protected", get(protected));
+        .route("/protected", get(protected))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(security_headers_policy),
+            security_headers,
+        ));
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
 
@@ -29,25 +43,55 @@ async fn main() {
 struct IndexTemplate {
     login_link: String,
     logout_link: String,
+    csp_nonce: String,
 }
 
-async fn root() -> impl IntoResponse {
+// The auth service as seen from the browser
+fn auth_service_origin() -> String {
     let mut address = env::var("AUTH_SERVICE_IP").unwrap_or("localhost".to_owned());
     if address.is_empty() {
         address = "localhost".to_owned();
     }
-    let login_link = format!("http://{}:3000", address);
-    let logout_link = format!("http://{}:3000/logout", address);
+    format!("http://{}:3000", address)
+}
+
+// The page loads the protected image from another host and calls the auth service
+fn default_security_headers() -> SecurityHeaders {
+    let content_security_policy = format!(
+        "default-src 'self'; script-src 'nonce-{}' 'strict-dynamic'; \
+         style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+         img-src 'self' data: https://i.ibb.co; connect-src 'self' {}; \
+         object-src 'none'; base-uri 'none'; form-action 'self'; frame-ancestors 'none'",
+        CSP_NONCE_PLACEHOLDER,
+        auth_service_origin()
+    );
+    SecurityHeaders {
+        content_security_policy: Some(content_security_policy),
+        strict_transport_security: Some("max-age=63072000; includeSubDomains".to_owned()),
+        frame_options: Some("DENY".to_owned()),
+        referrer_policy: Some("no-referrer".to_owned()),
+        permissions_policy: Some(
+            "camera=(), microphone=(), geolocation=(), payment=(), usb=()".to_owned(),
+        ),
+        cache_control: None,
+    }
+}
+
+async fn root(Extension(nonce): Extension<CspNonce>) -> impl IntoResponse {
+    let login_link = auth_service_origin();
+    let logout_link = format!("{}/logout", login_link);
 
     let template = IndexTemplate {
         login_link,
         logout_link,
+        csp_nonce: nonce.as_ref().to_owned(),
     };
     Html(template.render().unwrap())
 }
 
 async fn protected(jar: CookieJar) -> impl IntoResponse {
-    let jwt_cookie = match jar.get("jwt") {
+    // The auth service may be configured to use the `__Host-` cookie prefix
+    let jwt_cookie = match jar.get("__Host-jwt").or_else(|| jar.get("jwt")) {
         Some(cookie) => cookie,
         None => {
             return StatusCode::UNAUTHORIZED.into_response();
@@ -56,8 +100,10 @@ async fn protected(jar: CookieJar) -> impl IntoResponse {
 
     let api_client = reqwest::Client::builder().build().unwrap();
 
+    // The auth service rejects tokens that were not issued for this service
     let verify_token_body = serde_json::json!({
         "token": &jwt_cookie.value(),
+        "audience": "app-service",
     });
 
     let auth_hostname = env::var("AUTH_SERVICE_HOST_NAME").unwrap_or("0.0.0.0".to_owned());
@@ -161,4 +161,52 @@ TwoFAButton.addEventListener("click", (e) => {
             });
         }
     });
-});
\ No newline at end of file
+});
+
+// The "this wasn't me" link of a login alert opens this page with `reportToken` set
+const reportSection = document.getElementById("report-section");
+const reportForm = document.getElementById("report-form");
+const reportButton = document.getElementById("report-form-submit");
+const reportErrAlter = document.getElementById("report-err-alert");
+
+const reportToken = new URLSearchParams(window.location.search).get("reportToken");
+if (reportToken) {
+    reportForm.token.value = reportToken;
+    loginSection.style.display = "none";
+    reportSection.style.display = "block";
+}
+
+reportButton.addEventListener("click", (e) => {
+    e.preventDefault();
+
+    const token = reportForm.token.value;
+    const newPassword = reportForm.new_password.value;
+
+    fetch('/login/report', {
+        method: 'POST',
+        headers: {
+            'Content-Type': 'application/json',
+        },
+        body: JSON.stringify({ token, newPassword }),
+    }).then(response => {
+        if (response.ok) {
+            reportForm.token.value = "";
+            reportForm.new_password.value = "";
+            reportErrAlter.style.display = "none";
+            window.history.replaceState(null, "", window.location.pathname);
+            alert("The device was signed out and your password was changed.");
+            loginSection.style.display = "block";
+            reportSection.style.display = "none";
+        } else {
+            response.json().then(data => {
+                let error_msg = data.error;
+                if (error_msg !== undefined && error_msg !== null && error_msg !== "") {
+                    reportErrAlter.innerHTML = `<span><strong>Error: </strong>${error_msg}</span>`;
+                    reportErrAlter.style.display = "block";
+                } else {
+                    reportErrAlter.style.display = "none";
+                }
+            });
+        }
+    });
+});
@@ -0,0 +1,2 @@
+-- Version of the server-side pepper the password hash was computed with, NULL if unpeppered
+ALTER TABLE users ADD COLUMN IF NOT EXISTS password_pepper_version INTEGER;
@@ -0,0 +1,12 @@
+-- WebAuthn credentials, with the P-256 public key as a SEC1 encoded point
+CREATE TABLE IF NOT EXISTS passkey_credentials (
+    credential_id BYTEA NOT NULL PRIMARY KEY,
+    email TEXT NOT NULL REFERENCES users (email) ON DELETE CASCADE,
+    user_handle BYTEA NOT NULL,
+    public_key BYTEA NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS passkey_credentials_email_idx ON passkey_credentials (email);
@@ -0,0 +1,12 @@
+-- Accounts at upstream OpenID Connect providers, linked to users by their verified email on the
+-- first login. Later logins are matched by subject, which stays the same when the email changes.
+CREATE TABLE IF NOT EXISTS oidc_identities (
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT NOT NULL REFERENCES users (email) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ,
+    PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS oidc_identities_email_idx ON oidc_identities (email);
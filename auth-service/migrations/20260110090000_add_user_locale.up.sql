@@ -0,0 +1,2 @@
+-- Language of the emails sent to the user, e.g. 'de', NULL for the configured default
+ALTER TABLE users ADD COLUMN IF NOT EXISTS locale TEXT;
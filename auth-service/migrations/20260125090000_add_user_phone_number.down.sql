@@ -0,0 +1,4 @@
+ALTER TABLE users
+    DROP CONSTRAINT IF EXISTS users_phone_channel_needs_number,
+    DROP COLUMN IF EXISTS two_fa_channel,
+    DROP COLUMN IF EXISTS phone_number;
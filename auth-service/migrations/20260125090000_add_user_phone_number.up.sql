@@ -0,0 +1,7 @@
+-- A verified phone number in E.164 form, and whether 2FA codes go there rather than by email
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS phone_number TEXT,
+    ADD COLUMN IF NOT EXISTS two_fa_channel TEXT NOT NULL DEFAULT 'email'
+        CHECK (two_fa_channel IN ('email', 'sms', 'voice')),
+    ADD CONSTRAINT users_phone_channel_needs_number
+        CHECK (two_fa_channel = 'email' OR phone_number IS NOT NULL);
@@ -0,0 +1,13 @@
+-- The devices users logged in from, so logins from anywhere else can be reported to them. Devices
+-- are identified by the hash of their device cookie, and directory users have no row in `users`.
+CREATE TABLE IF NOT EXISTS known_devices (
+    email TEXT NOT NULL,
+    device_id TEXT NOT NULL,
+    user_agent TEXT,
+    ip_address TEXT,
+    -- ISO 3166-1 alpha-2 code of the country of the last login, if known
+    country TEXT,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (email, device_id)
+);
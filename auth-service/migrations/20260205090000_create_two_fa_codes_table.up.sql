@@ -0,0 +1,8 @@
+-- The 2FA code of each user's pending login. It lives next to the email outbox, so a code is
+-- only stored together with the email that delivers it.
+CREATE TABLE IF NOT EXISTS two_fa_codes (
+    email TEXT PRIMARY KEY,
+    login_attempt_id TEXT NOT NULL,
+    code TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
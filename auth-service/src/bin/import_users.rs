@@ -0,0 +1,48 @@
+// Imports users migrated from a legacy system into the users table:
+//
+//     import_users <export.jsonl>
+//
+// See `utils::user_import` for the export format.
+use std::{fs::File, io::BufReader, process::ExitCode};
+
+use auth_service::{
+    get_postgres_pool,
+    services::data_stores::postgres_user_store::PostgresUserStore,
+    utils::{constants::DATABASE_URL, tracing::init_tracing, user_import::import_users},
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    color_eyre::install().expect("Failed to install color_eyre!");
+    init_tracing().expect("Failed to initialise tracking");
+
+    let path = std::env::args()
+        .nth(1)
+        .ok_or_else(|| eyre!("Usage: import_users <export.jsonl>"))?;
+    let export = File::open(&path).wrap_err_with(|| format!("Failed to open {}", path))?;
+
+    let pg_pool = get_postgres_pool(&DATABASE_URL)
+        .await
+        .wrap_err("Failed to create Postgres connection pool")?;
+    sqlx::migrate!()
+        .run(&pg_pool)
+        .await
+        .wrap_err("Failed to run migrations")?;
+
+    let summary = import_users(&PostgresUserStore::new(pg_pool), BufReader::new(export)).await?;
+    info!(
+        imported = summary.imported,
+        already_existing = summary.already_existing,
+        rejected = summary.rejected,
+        "Imported users"
+    );
+
+    // Lets scripts notice that some users were left behind
+    Ok(if summary.rejected == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
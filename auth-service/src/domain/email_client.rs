@@ -1,7 +1,18 @@
 use color_eyre::eyre::Result;
 use std::future::Future;
 
-use super::models::Email;
+use super::{
+    mock_email_client::MockEmailClient, models::Email, resend_email_client::ResendEmailClient,
+    smtp_email_client::SmtpEmailClient,
+};
+
+// An email with both an HTML and a plain-text body, for mail clients that do not show HTML
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailMessage {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
 
 // This trait represents the interface all concrete email clients should implement
 pub trait EmailClient {
@@ -11,4 +22,37 @@ pub trait EmailClient {
         subject: &str,
         content: &str,
     ) -> impl Future<Output = Result<()>> + Send;
+
+    // Sends `message` as a multipart/alternative email
+    fn send_multipart_email(
+        &self,
+        recipient: &Email,
+        message: &EmailMessage,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+// Selects the client implementation at startup, so `main.rs` can pick one from configuration
+#[derive(Clone)]
+pub enum EmailClientBackend {
+    Resend(ResendEmailClient),
+    Smtp(SmtpEmailClient),
+    Mock(MockEmailClient),
+}
+
+impl EmailClient for EmailClientBackend {
+    async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()> {
+        match self {
+            Self::Resend(client) => client.send_email(recipient, subject, content).await,
+            Self::Smtp(client) => client.send_email(recipient, subject, content).await,
+            Self::Mock(client) => client.send_email(recipient, subject, content).await,
+        }
+    }
+
+    async fn send_multipart_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        match self {
+            Self::Resend(client) => client.send_multipart_email(recipient, message).await,
+            Self::Smtp(client) => client.send_multipart_email(recipient, message).await,
+            Self::Mock(client) => client.send_multipart_email(recipient, message).await,
+        }
+    }
 }
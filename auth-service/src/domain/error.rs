@@ -1,18 +1,46 @@
 use color_eyre::eyre::Report;
 use thiserror::Error;
 
+use super::PasswordPolicyViolation;
+
 #[derive(Debug, Error)]
 pub enum AuthAPIError {
     #[error("User already exists")]
     UserAlreadyExists,
     #[error("Invalid credentials")]
     InvalidCredentials,
+    #[error("Password does not meet requirements")]
+    WeakPassword(Vec<PasswordPolicyViolation>),
     #[error("Incorrect credentials")]
     IncorrectCredentials,
     #[error("Missing token")]
     MissingToken,
     #[error("Invalid token")]
     InvalidToken,
+    #[error("Missing or invalid CSRF token")]
+    InvalidCsrfToken,
+    #[error("Invalid DPoP proof")]
+    InvalidDpopProof,
+    #[error("Invalid passkey")]
+    InvalidPasskey,
+    #[error("Invalid or expired link")]
+    InvalidMagicLink,
+    #[error("Unknown identity provider")]
+    UnknownOidcProvider,
+    #[error("External login failed")]
+    InvalidOidcLogin,
+    #[error("SAML is not configured")]
+    SamlNotConfigured,
+    #[error("External login failed")]
+    InvalidSamlLogin,
+    #[error("Invalid phone number")]
+    InvalidPhoneNumber,
+    #[error("SMS is not configured")]
+    SmsNotConfigured,
+    #[error("Invalid or expired link")]
+    InvalidLoginReport,
+    #[error("Password is managed by the directory")]
+    ReadOnlyPassword,
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
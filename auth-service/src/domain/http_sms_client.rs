@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{Context, Result};
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Serialize;
+
+use crate::domain::{PhoneNumber, SmsClient, TwoFAChannel};
+
+// Where and how to reach the SMS gateway
+#[derive(Clone)]
+pub struct SmsGatewayConfig {
+    pub url: String,
+    // Sent as a bearer token, if the gateway wants one
+    pub token: Option<SecretString>,
+    // Sender ID or number the gateway should send from, its default if `None`
+    pub sender: Option<String>,
+    pub timeout: Duration,
+}
+
+#[derive(Serialize)]
+struct GatewayRequest<'a> {
+    channel: &'static str,
+    to: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<&'a str>,
+    message: &'a str,
+}
+
+// Delivers messages through an HTTP gateway, which most SMS providers offer or can be fronted
+// with. Every message is a JSON POST of `channel` (`sms` or `voice`), `to`, `from` and
+// `message`, and any 2xx response counts as accepted.
+#[derive(Clone)]
+pub struct HttpSmsClient {
+    http_client: Client,
+    url: String,
+    token: Option<SecretString>,
+    sender: Option<String>,
+}
+
+impl HttpSmsClient {
+    pub fn new(config: &SmsGatewayConfig) -> Result<Self> {
+        let http_client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .wrap_err("Failed to build SMS gateway client")?;
+        Ok(Self {
+            http_client,
+            url: config.url.clone(),
+            token: config.token.clone(),
+            sender: config.sender.clone(),
+        })
+    }
+
+    async fn send(
+        &self,
+        channel: TwoFAChannel,
+        recipient: &PhoneNumber,
+        message: &str,
+    ) -> Result<()> {
+        let request = self.http_client.post(&self.url).json(&GatewayRequest {
+            channel: channel.as_str(),
+            to: recipient.as_str(),
+            from: self.sender.as_deref(),
+            message,
+        });
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token.expose_secret()),
+            None => request,
+        };
+
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .wrap_err("SMS gateway did not accept the message")?;
+        Ok(())
+    }
+}
+
+impl SmsClient for HttpSmsClient {
+    #[tracing::instrument(name = "Sending SMS", skip_all)]
+    async fn send_sms(&self, recipient: &PhoneNumber, message: &str) -> Result<()> {
+        self.send(TwoFAChannel::Sms, recipient, message).await
+    }
+
+    #[tracing::instrument(name = "Sending voice message", skip_all)]
+    async fn send_voice_message(&self, recipient: &PhoneNumber, message: &str) -> Result<()> {
+        self.send(TwoFAChannel::Voice, recipient, message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{body_json, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn client(server: &MockServer, token: Option<&str>, sender: Option<&str>) -> HttpSmsClient {
+        HttpSmsClient::new(&SmsGatewayConfig {
+            url: format!("{}/messages", server.uri()),
+            token: token.map(|token| token.to_owned().into()),
+            sender: sender.map(str::to_owned),
+            timeout: Duration::from_secs(5),
+        })
+        .unwrap()
+    }
+
+    fn recipient() -> PhoneNumber {
+        PhoneNumber::parse("+4930123456").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_posts_sms_to_gateway() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/messages"))
+            .and(header("Authorization", "Bearer secret"))
+            .and(body_json(serde_json::json!({
+                "channel": "sms",
+                "to": "+4930123456",
+                "from": "AuthService",
+                "message": "123456 is your code",
+            })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client(&server, Some("secret"), Some("AuthService"))
+            .send_sms(&recipient(), "123456 is your code")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_posts_voice_message_without_optional_fields() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_json(serde_json::json!({
+                "channel": "voice",
+                "to": "+4930123456",
+                "message": "Your code is 1 2 3",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client(&server, None, None)
+            .send_voice_message(&recipient(), "Your code is 1 2 3")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_returns_error_when_gateway_refuses() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+
+        assert!(client(&server, None, None)
+            .send_sms(&recipient(), "123456")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+// Languages emails are available in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Fr,
+}
+
+impl Locale {
+    // Picks the supported language of a BCP 47 tag such as `de-AT`, ignoring its region
+    pub fn negotiate(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next()?;
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Some(Self::En),
+            "de" => Some(Self::De),
+            "fr" => Some(Self::Fr),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::De => "de",
+            Self::Fr => "fr",
+        }
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiates_language_of_tag() {
+        assert_eq!(Locale::negotiate("de"), Some(Locale::De));
+        assert_eq!(Locale::negotiate("de-AT"), Some(Locale::De));
+        assert_eq!(Locale::negotiate("FR_ca"), Some(Locale::Fr));
+        assert_eq!(Locale::negotiate("en-GB"), Some(Locale::En));
+        assert_eq!(Locale::negotiate("ja"), None);
+        assert_eq!(Locale::negotiate(""), None);
+    }
+
+    #[test]
+    fn test_round_trips_through_string() {
+        for locale in [Locale::En, Locale::De, Locale::Fr] {
+            assert_eq!(Locale::negotiate(locale.as_str()), Some(locale));
+        }
+    }
+}
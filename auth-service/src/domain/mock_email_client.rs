@@ -1,10 +1,73 @@
-use crate::domain::{models::Email, EmailClient};
-use color_eyre::eyre::Result;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::domain::{models::Email, EmailClient, EmailMessage};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
 use secrecy::ExposeSecret;
 use tracing::debug;
 
-#[derive(Clone)]
-pub struct MockEmailClient;
+// The inbox only keeps the latest messages, so a long running dev server does not grow forever
+const MOCK_INBOX_CAPACITY: usize = 100;
+
+// A message the mock client was asked to send
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockEmail {
+    pub recipient: String,
+    pub subject: String,
+    // Plain-text emails have no HTML body
+    pub html: Option<String>,
+    pub text: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+// Where the mock client keeps the messages it was asked to send. Clones share the messages, so
+// tests and the dev mailbox can read what the app sent.
+#[derive(Clone, Default)]
+pub struct MockInbox {
+    messages: Arc<Mutex<VecDeque<MockEmail>>>,
+}
+
+impl MockInbox {
+    fn record(&self, message: MockEmail) -> Result<()> {
+        let mut messages = self.messages.lock().map_err(|e| eyre!("{}", e))?;
+        if messages.len() == MOCK_INBOX_CAPACITY {
+            messages.pop_back();
+        }
+        messages.push_front(message);
+        Ok(())
+    }
+
+    // All kept messages, newest first
+    pub fn messages(&self) -> Vec<MockEmail> {
+        self.messages
+            .lock()
+            .map(|messages| messages.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // The kept messages sent to `recipient`, newest first
+    pub fn messages_to(&self, recipient: &str) -> Vec<MockEmail> {
+        self.messages()
+            .into_iter()
+            .filter(|message| message.recipient == recipient)
+            .collect()
+    }
+}
+
+// Logs messages instead of sending them and keeps them in an inbox
+#[derive(Clone, Default)]
+pub struct MockEmailClient {
+    inbox: MockInbox,
+}
+
+impl MockEmailClient {
+    pub fn inbox(&self) -> MockInbox {
+        self.inbox.clone()
+    }
+}
 
 impl EmailClient for MockEmailClient {
     async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()> {
@@ -16,6 +79,85 @@ impl EmailClient for MockEmailClient {
             content
         );
 
-        Ok(())
+        self.inbox.record(MockEmail {
+            recipient: recipient.as_ref().expose_secret().to_owned(),
+            subject: subject.to_owned(),
+            html: None,
+            text: content.to_owned(),
+            sent_at: Utc::now(),
+        })
+    }
+
+    async fn send_multipart_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        debug!(
+            "Sending email to {} with subject: {} and content: {}",
+            recipient.as_ref().expose_secret(),
+            message.subject,
+            message.text
+        );
+
+        self.inbox.record(MockEmail {
+            recipient: recipient.as_ref().expose_secret().to_owned(),
+            subject: message.subject.clone(),
+            html: Some(message.html.clone()),
+            text: message.text.clone(),
+            sent_at: Utc::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(address: &str) -> Email {
+        Email::new(address.to_owned().into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_records_messages_newest_first() {
+        let client = MockEmailClient::default();
+        let inbox = client.inbox();
+
+        client
+            .send_email(&email("first@example.com"), "Hello", "Plain")
+            .await
+            .unwrap();
+        let message = EmailMessage {
+            subject: "Code".to_owned(),
+            html: "<p>123456</p>".to_owned(),
+            text: "123456".to_owned(),
+        };
+        client
+            .send_multipart_email(&email("second@example.com"), &message)
+            .await
+            .unwrap();
+
+        let messages = inbox.messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].recipient, "second@example.com");
+        assert_eq!(messages[0].html.as_deref(), Some("<p>123456</p>"));
+        assert_eq!(messages[1].subject, "Hello");
+        assert_eq!(messages[1].html, None);
+
+        let messages = inbox.messages_to("first@example.com");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].text, "Plain");
+    }
+
+    #[tokio::test]
+    async fn test_keeps_latest_messages_only() {
+        let client = MockEmailClient::default();
+        for i in 0..MOCK_INBOX_CAPACITY + 5 {
+            client
+                .send_email(&email("user@example.com"), &i.to_string(), "")
+                .await
+                .unwrap();
+        }
+
+        let messages = client.inbox().messages();
+        assert_eq!(messages.len(), MOCK_INBOX_CAPACITY);
+        assert_eq!(messages[0].subject, (MOCK_INBOX_CAPACITY + 4).to_string());
+        assert_eq!(messages[MOCK_INBOX_CAPACITY - 1].subject, "5");
     }
 }
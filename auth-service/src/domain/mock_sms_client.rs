@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::{eyre, Result};
+use tracing::debug;
+
+use crate::domain::{PhoneNumber, SmsClient, TwoFAChannel};
+
+// A message the mock client was asked to deliver
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockSms {
+    pub recipient: PhoneNumber,
+    pub channel: TwoFAChannel,
+    pub message: String,
+}
+
+// Logs messages instead of delivering them and keeps them, newest last. Clones share the messages.
+#[derive(Clone, Default)]
+pub struct MockSmsClient {
+    messages: Arc<Mutex<Vec<MockSms>>>,
+}
+
+impl MockSmsClient {
+    pub fn messages(&self) -> Vec<MockSms> {
+        self.messages
+            .lock()
+            .map(|messages| messages.clone())
+            .unwrap_or_default()
+    }
+
+    fn record(&self, recipient: &PhoneNumber, channel: TwoFAChannel, message: &str) -> Result<()> {
+        debug!(
+            "Sending {} to {} with content: {}",
+            channel.as_str(),
+            recipient,
+            message
+        );
+        self.messages
+            .lock()
+            .map_err(|e| eyre!("{}", e))?
+            .push(MockSms {
+                recipient: recipient.clone(),
+                channel,
+                message: message.to_owned(),
+            });
+        Ok(())
+    }
+}
+
+impl SmsClient for MockSmsClient {
+    async fn send_sms(&self, recipient: &PhoneNumber, message: &str) -> Result<()> {
+        self.record(recipient, TwoFAChannel::Sms, message)
+    }
+
+    async fn send_voice_message(&self, recipient: &PhoneNumber, message: &str) -> Result<()> {
+        self.record(recipient, TwoFAChannel::Voice, message)
+    }
+}
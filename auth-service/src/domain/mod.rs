@@ -1,11 +1,22 @@
 pub mod email_client;
 pub mod error;
+pub mod http_sms_client;
+pub mod locale;
 pub mod mock_email_client;
+pub mod mock_sms_client;
+pub mod password_policy;
+pub mod phone_number;
 pub mod resend_email_client;
+pub mod sms_client;
+pub mod smtp_email_client;
 pub mod user;
 
 pub use email_client::*;
 pub use error::*;
+pub use locale::*;
+pub use password_policy::*;
+pub use phone_number::*;
+pub use sms_client::*;
 pub use user::*;
 
 pub mod models {
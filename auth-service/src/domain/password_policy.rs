@@ -0,0 +1,253 @@
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    domain::models::Email,
+    utils::constants::{PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH, PASSWORD_MIN_STRENGTH_SCORE},
+};
+
+// Email local parts shorter than this are too generic to be worth rejecting (e.g. "a@example.com")
+const MIN_EMAIL_LOCAL_PART_LENGTH: usize = 3;
+
+lazy_static! {
+    static ref COMMON_PASSWORDS: HashSet<&'static str> =
+        include_str!("../../resources/common_passwords.txt")
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    // zxcvbn score between 0 (too guessable) and 4 (very unguessable)
+    pub min_strength_score: u8,
+    pub reject_email_local_part: bool,
+    pub reject_common_passwords: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: *PASSWORD_MIN_LENGTH,
+            max_length: *PASSWORD_MAX_LENGTH,
+            min_strength_score: *PASSWORD_MIN_STRENGTH_SCORE,
+            reject_email_local_part: true,
+            reject_common_passwords: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // Returns every rule the password breaks, so the client can show all of them at once
+    pub fn check(
+        &self,
+        password: &SecretString,
+        email: &Email,
+    ) -> Result<(), Vec<PasswordPolicyViolation>> {
+        let password = password.expose_secret();
+        let length = password.chars().count();
+        let mut violations = Vec::new();
+
+        if length < self.min_length {
+            violations.push(PasswordPolicyViolation::TooShort {
+                min_length: self.min_length,
+            });
+        }
+
+        if length > self.max_length {
+            violations.push(PasswordPolicyViolation::TooLong {
+                max_length: self.max_length,
+            });
+        }
+
+        let lowercase_password = password.to_lowercase();
+        let local_part = email_local_part(email);
+
+        if self.reject_email_local_part
+            && local_part.chars().count() >= MIN_EMAIL_LOCAL_PART_LENGTH
+            && lowercase_password.contains(&local_part)
+        {
+            violations.push(PasswordPolicyViolation::ContainsEmail);
+        }
+
+        if self.reject_common_passwords && COMMON_PASSWORDS.contains(lowercase_password.as_str()) {
+            violations.push(PasswordPolicyViolation::CommonPassword);
+        }
+
+        // Strength estimation is expensive on long inputs, and pointless once the length check failed
+        if length <= self.max_length {
+            let entropy = zxcvbn::zxcvbn(password, &[local_part.as_str()]);
+            let score = u8::from(entropy.score());
+            if score < self.min_strength_score {
+                let suggestions = entropy
+                    .feedback()
+                    .map(|feedback| {
+                        feedback
+                            .suggestions()
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                violations.push(PasswordPolicyViolation::TooWeak {
+                    score,
+                    min_score: self.min_strength_score,
+                    suggestions,
+                });
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+fn email_local_part(email: &Email) -> String {
+    let email = email.as_ref().expose_secret();
+    email.split('@').next().unwrap_or_default().to_lowercase()
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum PasswordPolicyViolation {
+    #[error("Password must be at least {min_length} characters long")]
+    TooShort { min_length: usize },
+    #[error("Password must be at most {max_length} characters long")]
+    TooLong { max_length: usize },
+    #[error(
+        "Password is too easy to guess (strength {score} of 4, at least {min_score} required)"
+    )]
+    TooWeak {
+        score: u8,
+        min_score: u8,
+        suggestions: Vec<String>,
+    },
+    #[error("Password must not contain your email address")]
+    ContainsEmail,
+    #[error("Password is too common")]
+    CommonPassword,
+    #[error("Password has appeared in {count} known data breaches")]
+    Breached { count: u64 },
+}
+
+impl PasswordPolicyViolation {
+    pub fn rule(&self) -> &'static str {
+        match self {
+            Self::TooShort { .. } => "min_length",
+            Self::TooLong { .. } => "max_length",
+            Self::TooWeak { .. } => "min_strength",
+            Self::ContainsEmail => "email_local_part",
+            Self::CommonPassword => "common_password",
+            Self::Breached { .. } => "breached_password",
+        }
+    }
+}
+
+// Per-rule feedback returned to the client when a password is rejected
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordRuleFeedback {
+    pub rule: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<String>,
+}
+
+impl From<&PasswordPolicyViolation> for PasswordRuleFeedback {
+    fn from(violation: &PasswordPolicyViolation) -> Self {
+        let suggestions = match violation {
+            PasswordPolicyViolation::TooWeak { suggestions, .. } => suggestions.clone(),
+            _ => Vec::new(),
+        };
+
+        Self {
+            rule: violation.rule().to_owned(),
+            message: violation.to_string(),
+            suggestions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 64,
+            min_strength_score: 3,
+            reject_email_local_part: true,
+            reject_common_passwords: true,
+        }
+    }
+
+    fn email() -> Email {
+        Email::new("jane.doe@example.com".into()).unwrap()
+    }
+
+    fn violations(password: &str) -> Vec<PasswordPolicyViolation> {
+        policy()
+            .check(&password.into(), &email())
+            .err()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_accepts_strong_password() {
+        assert!(policy()
+            .check(&"correct-Horse-battery-st4ple".into(), &email())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_rejects_short_password() {
+        assert!(violations("x7#Qp").contains(&PasswordPolicyViolation::TooShort { min_length: 8 }));
+    }
+
+    #[test]
+    fn test_rejects_long_password() {
+        let password = "a1B!".repeat(20);
+        assert_eq!(
+            violations(&password),
+            vec![PasswordPolicyViolation::TooLong { max_length: 64 }]
+        );
+    }
+
+    #[test]
+    fn test_rejects_password_containing_email_local_part() {
+        assert!(violations("Xq9!jane.doe#2024Zz").contains(&PasswordPolicyViolation::ContainsEmail));
+    }
+
+    #[test]
+    fn test_rejects_common_password() {
+        assert!(violations("Password123").contains(&PasswordPolicyViolation::CommonPassword));
+    }
+
+    #[test]
+    fn test_rejects_weak_password() {
+        assert!(violations("aaaaaaaaaa")
+            .iter()
+            .any(|v| matches!(v, PasswordPolicyViolation::TooWeak { .. })));
+    }
+
+    #[test]
+    fn test_feedback_has_rule_and_message() {
+        let feedback =
+            PasswordRuleFeedback::from(&PasswordPolicyViolation::TooShort { min_length: 8 });
+        assert_eq!(feedback.rule, "min_length");
+        assert_eq!(
+            feedback.message,
+            "Password must be at least 8 characters long"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+
+use color_eyre::eyre::{eyre, Result};
+
+// E.164 allows at most 15 digits, and the shortest numbers in use have 7
+const MIN_DIGITS: usize = 7;
+const MAX_DIGITS: usize = 15;
+
+// A phone number in E.164 form, e.g. `+4930123456`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PhoneNumber(String);
+
+impl PhoneNumber {
+    // Normalizes an international number as people write it, e.g. `+44 (0)20 7946-0958` or
+    // `0049 30 123456`. Without a country code there is no telling where a number is, so national
+    // numbers are refused.
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim().replace("(0)", "");
+        let digits = if let Some(rest) = input.strip_prefix('+') {
+            rest
+        } else if let Some(rest) = input.strip_prefix("00") {
+            rest
+        } else {
+            return Err(eyre!("Phone number has no country code"));
+        };
+
+        let digits: String = digits
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+            .collect();
+        if !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(eyre!("Phone number contains invalid characters"));
+        }
+        if !(MIN_DIGITS..=MAX_DIGITS).contains(&digits.len()) {
+            return Err(eyre!("Phone number has the wrong length"));
+        }
+        // Country codes never start with 0
+        if digits.starts_with('0') {
+            return Err(eyre!("Invalid country code"));
+        }
+
+        Ok(Self(format!("+{}", digits)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalizes_to_e164() {
+        for (input, expected) in [
+            ("+4930123456", "+4930123456"),
+            ("+49 30 123456", "+4930123456"),
+            ("0049 30 123456", "+4930123456"),
+            ("+44 (0)20 7946-0958", "+442079460958"),
+            ("+1 (415) 555.2671", "+14155552671"),
+            (" +33 1 23 45 67 89 ", "+33123456789"),
+        ] {
+            assert_eq!(PhoneNumber::parse(input).unwrap().as_str(), expected);
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_numbers() {
+        for input in [
+            "",
+            "030 123456",
+            "+",
+            "+49 30 12x456",
+            "+123456",
+            "+1234567890123456",
+            "+049301234567",
+            "+49+30123456",
+        ] {
+            assert!(PhoneNumber::parse(input).is_err(), "{} was accepted", input);
+        }
+    }
+}
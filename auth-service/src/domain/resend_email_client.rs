@@ -2,7 +2,7 @@ use color_eyre::eyre::Result;
 use resend_rs::{types::CreateEmailBaseOptions, Resend};
 use secrecy::{ExposeSecret, SecretString};
 
-use crate::domain::{models::Email, EmailClient};
+use crate::domain::{models::Email, EmailClient, EmailMessage};
 
 #[derive(Clone)]
 pub struct ResendEmailClient {
@@ -35,4 +35,19 @@ impl EmailClient for ResendEmailClient {
 
         Ok(())
     }
+
+    #[tracing::instrument(name = "Sending multipart email", skip_all)]
+    async fn send_multipart_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        let email = CreateEmailBaseOptions::new(
+            self.sender.as_ref().expose_secret(),
+            vec![recipient.as_ref().expose_secret()],
+            &message.subject,
+        )
+        .with_html(&message.html)
+        .with_text(&message.text);
+
+        self.resend.emails.send(email).await?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,53 @@
+use color_eyre::eyre::{eyre, Result};
+use std::future::Future;
+
+use super::{http_sms_client::HttpSmsClient, mock_sms_client::MockSmsClient, PhoneNumber};
+
+// This trait represents the interface all concrete SMS clients should implement. Gateways deliver
+// both text messages and calls that read a message out.
+pub trait SmsClient {
+    fn send_sms(
+        &self,
+        recipient: &PhoneNumber,
+        message: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn send_voice_message(
+        &self,
+        recipient: &PhoneNumber,
+        message: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+// Selects the client implementation at startup. Without a gateway, users cannot add phone numbers.
+#[derive(Clone, Default)]
+pub enum SmsClientBackend {
+    Http(HttpSmsClient),
+    Mock(MockSmsClient),
+    #[default]
+    Disabled,
+}
+
+impl SmsClientBackend {
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Self::Disabled)
+    }
+}
+
+impl SmsClient for SmsClientBackend {
+    async fn send_sms(&self, recipient: &PhoneNumber, message: &str) -> Result<()> {
+        match self {
+            Self::Http(client) => client.send_sms(recipient, message).await,
+            Self::Mock(client) => client.send_sms(recipient, message).await,
+            Self::Disabled => Err(eyre!("No SMS gateway is configured")),
+        }
+    }
+
+    async fn send_voice_message(&self, recipient: &PhoneNumber, message: &str) -> Result<()> {
+        match self {
+            Self::Http(client) => client.send_voice_message(recipient, message).await,
+            Self::Mock(client) => client.send_voice_message(recipient, message).await,
+            Self::Disabled => Err(eyre!("No SMS gateway is configured")),
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use std::{str::FromStr, time::Duration};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use lettre::{
+    message::{Mailbox, MessageBuilder, MultiPart, SinglePart},
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+        PoolConfig,
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::domain::{models::Email, EmailClient, EmailMessage};
+
+// How the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    // Plaintext, upgraded with STARTTLS before anything is sent; the connection fails without it
+    StartTls,
+    // TLS from the start, as on the submissions port 465
+    Implicit,
+    // Plaintext throughout, only for relays on a trusted network
+    None,
+}
+
+impl SmtpTls {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Self::StartTls => 587,
+            Self::Implicit => 465,
+            Self::None => 25,
+        }
+    }
+}
+
+impl FromStr for SmtpTls {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "starttls" => Ok(Self::StartTls),
+            "implicit" | "tls" => Ok(Self::Implicit),
+            "none" => Ok(Self::None),
+            _ => Err(eyre!("Unknown SMTP TLS mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    // Connections kept open to the server, reused across emails
+    pub pool_size: u32,
+    // Limit for connecting, and for handing over each email
+    pub timeout: Duration,
+}
+
+#[derive(Clone)]
+pub struct SmtpEmailClient {
+    sender: Email, // Sender's email address
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    timeout: Duration,
+}
+
+impl SmtpEmailClient {
+    pub fn new(sender: Email, config: &SmtpConfig) -> Result<Self> {
+        let tls = match config.tls {
+            SmtpTls::None => Tls::None,
+            tls => {
+                let parameters = TlsParameters::new(config.host.clone())
+                    .wrap_err("Invalid SMTP TLS parameters")?;
+                match tls {
+                    SmtpTls::Implicit => Tls::Wrapper(parameters),
+                    _ => Tls::Required(parameters),
+                }
+            }
+        };
+
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            .port(config.port)
+            .tls(tls)
+            .timeout(Some(config.timeout))
+            .pool_config(PoolConfig::new().max_size(config.pool_size));
+        match (&config.username, &config.password) {
+            (Some(username), Some(password)) => {
+                transport = transport.credentials(Credentials::new(
+                    username.to_owned(),
+                    password.expose_secret().to_owned(),
+                ));
+            }
+            (None, None) => {}
+            _ => return Err(eyre!("SMTP username and password must be set together")),
+        }
+
+        Ok(Self {
+            sender,
+            transport: transport.build(),
+            timeout: config.timeout,
+        })
+    }
+
+    fn builder(&self, recipient: &Email, subject: &str) -> Result<MessageBuilder> {
+        let from: Mailbox = self
+            .sender
+            .as_ref()
+            .expose_secret()
+            .parse()
+            .wrap_err("Invalid sender address")?;
+        let to: Mailbox = recipient
+            .as_ref()
+            .expose_secret()
+            .parse()
+            .wrap_err("Invalid recipient address")?;
+
+        Ok(Message::builder().from(from).to(to).subject(subject))
+    }
+
+    // The transport only limits how long connecting takes, so a server that stops answering
+    // midway is cut off here
+    async fn deliver(&self, message: Message) -> Result<()> {
+        tokio::time::timeout(self.timeout, self.transport.send(message))
+            .await
+            .map_err(|_| eyre!("Timed out sending email over SMTP"))?
+            .wrap_err("Failed to send email over SMTP")?;
+
+        Ok(())
+    }
+}
+
+impl EmailClient for SmtpEmailClient {
+    #[tracing::instrument(name = "Sending email over SMTP", skip_all)]
+    async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()> {
+        let message = self
+            .builder(recipient, subject)?
+            .singlepart(SinglePart::plain(content.to_owned()))
+            .wrap_err("Failed to build email")?;
+        self.deliver(message).await
+    }
+
+    #[tracing::instrument(name = "Sending multipart email over SMTP", skip_all)]
+    async fn send_multipart_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        let message = self
+            .builder(recipient, &message.subject)?
+            .multipart(MultiPart::alternative_plain_html(
+                message.text.clone(),
+                message.html.clone(),
+            ))
+            .wrap_err("Failed to build email")?;
+        self.deliver(message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    // What a client handed to the SMTP sink
+    #[derive(Default)]
+    struct Received {
+        auth: Vec<String>,
+        recipients: Vec<String>,
+        messages: Vec<String>,
+        connections: usize,
+    }
+
+    // A local SMTP server that accepts everything and keeps it, over plaintext only
+    struct SmtpSink {
+        port: u16,
+        received: Arc<Mutex<Received>>,
+    }
+
+    impl SmtpSink {
+        async fn start(responsive: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let received = Arc::new(Mutex::new(Received::default()));
+
+            let state = received.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    state.lock().unwrap().connections += 1;
+                    if !responsive {
+                        // Keeps the connection open without ever greeting the client
+                        tokio::spawn(async move {
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                            drop(stream);
+                        });
+                        continue;
+                    }
+                    tokio::spawn(Self::serve(stream, state.clone()));
+                }
+            });
+
+            Self { port, received }
+        }
+
+        async fn serve(stream: tokio::net::TcpStream, received: Arc<Mutex<Received>>) {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = line.to_ascii_uppercase();
+                let reply: &[u8] = if command.starts_with("EHLO") {
+                    b"250-sink\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n"
+                } else if let Some(auth) = line.strip_prefix("AUTH PLAIN ") {
+                    received.lock().unwrap().auth.push(auth.to_owned());
+                    b"235 2.7.0 Authentication successful\r\n"
+                } else if command.starts_with("RCPT TO:") {
+                    received
+                        .lock()
+                        .unwrap()
+                        .recipients
+                        .push(line[8..].to_owned());
+                    b"250 2.1.5 Ok\r\n"
+                } else if command == "DATA" {
+                    writer
+                        .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                        .await
+                        .unwrap();
+                    let mut message = String::new();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if line == "." {
+                            break;
+                        }
+                        message.push_str(&line);
+                        message.push('\n');
+                    }
+                    received.lock().unwrap().messages.push(message);
+                    b"250 2.0.0 Ok: queued\r\n"
+                } else if command == "QUIT" {
+                    writer.write_all(b"221 2.0.0 Bye\r\n").await.unwrap();
+                    return;
+                } else {
+                    b"250 Ok\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+        }
+    }
+
+    fn config(port: u16) -> SmtpConfig {
+        SmtpConfig {
+            host: "127.0.0.1".to_owned(),
+            port,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            pool_size: 2,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn email(address: &str) -> Email {
+        Email::new(address.to_owned().into()).unwrap()
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage {
+            subject: "Your code".to_owned(),
+            html: "<p>Your code is <strong>123456</strong></p>".to_owned(),
+            text: "Your code is 123456".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sends_multipart_email() {
+        let sink = SmtpSink::start(true).await;
+        let client = SmtpEmailClient::new(email("sender@example.com"), &config(sink.port)).unwrap();
+
+        client
+            .send_multipart_email(&email("user@example.com"), &message())
+            .await
+            .unwrap();
+
+        let received = sink.received.lock().unwrap();
+        assert_eq!(received.recipients, vec!["<user@example.com>"]);
+        let sent = &received.messages[0];
+        assert!(sent.contains("Subject: Your code"));
+        assert!(sent.contains("From: sender@example.com"));
+        assert!(sent.contains("multipart/alternative"));
+        assert!(sent.contains("text/plain"));
+        assert!(sent.contains("text/html"));
+        assert!(sent.contains("Your code is 123456"));
+        assert!(received.auth.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_authenticates_with_credentials() {
+        let sink = SmtpSink::start(true).await;
+        let config = SmtpConfig {
+            username: Some("mailer".to_owned()),
+            password: Some("hunter2".to_owned().into()),
+            ..config(sink.port)
+        };
+        let client = SmtpEmailClient::new(email("sender@example.com"), &config).unwrap();
+
+        client
+            .send_email(&email("user@example.com"), "Hello", "Hello there")
+            .await
+            .unwrap();
+
+        let received = sink.received.lock().unwrap();
+        assert_eq!(
+            STANDARD.decode(&received.auth[0]).unwrap(),
+            b"\0mailer\0hunter2"
+        );
+        assert!(received.messages[0].contains("Hello there"));
+    }
+
+    #[tokio::test]
+    async fn test_reuses_pooled_connection() {
+        let sink = SmtpSink::start(true).await;
+        let client = SmtpEmailClient::new(email("sender@example.com"), &config(sink.port)).unwrap();
+
+        for _ in 0..3 {
+            client
+                .send_multipart_email(&email("user@example.com"), &message())
+                .await
+                .unwrap();
+            // Connections go back to the pool in the background
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let received = sink.received.lock().unwrap();
+        assert_eq!(received.messages.len(), 3);
+        assert_eq!(received.connections, 1);
+    }
+
+    #[tokio::test]
+    async fn test_requires_starttls_when_configured() {
+        let sink = SmtpSink::start(true).await;
+        let config = SmtpConfig {
+            tls: SmtpTls::StartTls,
+            ..config(sink.port)
+        };
+        let client = SmtpEmailClient::new(email("sender@example.com"), &config).unwrap();
+
+        // The sink does not offer STARTTLS, so nothing may be sent in plaintext
+        assert!(client
+            .send_multipart_email(&email("user@example.com"), &message())
+            .await
+            .is_err());
+        assert!(sink.received.lock().unwrap().messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_times_out_on_unresponsive_server() {
+        let sink = SmtpSink::start(false).await;
+        let config = SmtpConfig {
+            timeout: Duration::from_millis(200),
+            ..config(sink.port)
+        };
+        let client = SmtpEmailClient::new(email("sender@example.com"), &config).unwrap();
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.send_multipart_email(&email("user@example.com"), &message()),
+        )
+        .await;
+        assert!(matches!(result, Ok(Err(_))));
+    }
+
+    #[test]
+    fn test_rejects_username_without_password() {
+        let config = SmtpConfig {
+            username: Some("mailer".to_owned()),
+            ..config(25)
+        };
+        assert!(SmtpEmailClient::new(email("sender@example.com"), &config).is_err());
+    }
+
+    #[test]
+    fn test_parses_tls_mode() {
+        assert_eq!("STARTTLS".parse::<SmtpTls>().unwrap(), SmtpTls::StartTls);
+        assert_eq!("implicit".parse::<SmtpTls>().unwrap(), SmtpTls::Implicit);
+        assert_eq!("none".parse::<SmtpTls>().unwrap(), SmtpTls::None);
+        assert!("ssl3".parse::<SmtpTls>().is_err());
+        assert_eq!(SmtpTls::Implicit.default_port(), 465);
+    }
+}
@@ -1,10 +1,55 @@
-use crate::domain::models::{Email, Password};
+use std::str::FromStr;
+
+use color_eyre::eyre::{eyre, Report};
+
+use crate::domain::{
+    models::{Email, Password},
+    Locale, PhoneNumber,
+};
+
+// Where 2FA codes are sent. Phone channels need a verified phone number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TwoFAChannel {
+    #[default]
+    Email,
+    Sms,
+    // A call that reads the code out
+    Voice,
+}
+
+impl TwoFAChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Sms => "sms",
+            Self::Voice => "voice",
+        }
+    }
+}
+
+impl FromStr for TwoFAChannel {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "email" => Ok(Self::Email),
+            "sms" => Ok(Self::Sms),
+            "voice" => Ok(Self::Voice),
+            other => Err(eyre!("Unknown 2FA channel: {}", other)),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct User {
     pub email: Email,
     pub password: Password,
     pub requires_2fa: bool,
+    // Language of the emails sent to the user, the configured default if `None`
+    pub locale: Option<Locale>,
+    // Only set once the user proved they receive messages at the number
+    pub phone_number: Option<PhoneNumber>,
+    pub two_fa_channel: TwoFAChannel,
 }
 
 impl User {
@@ -13,6 +58,20 @@ impl User {
             email,
             password,
             requires_2fa,
+            locale: None,
+            phone_number: None,
+            two_fa_channel: TwoFAChannel::Email,
         }
     }
+
+    pub fn with_locale(mut self, locale: Option<Locale>) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    pub fn with_phone_number(mut self, phone_number: PhoneNumber, channel: TwoFAChannel) -> Self {
+        self.phone_number = Some(phone_number);
+        self.two_fa_channel = channel;
+        self
+    }
 }
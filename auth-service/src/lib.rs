@@ -3,11 +3,13 @@ pub mod routes;
 pub mod services;
 pub mod utils;
 
-use std::error::Error;
+use std::{error::Error, future::Future, net::SocketAddr, pin::Pin};
 
 use axum::{
-    http::{self, Method},
-    response::{Html, IntoResponse, Response},
+    extract::connect_info::IntoMakeServiceWithConnectInfo,
+    http::{self, header::CONTENT_TYPE, HeaderName, Method},
+    middleware,
+    response::{IntoResponse, Response},
     routing::{get, post},
     serve::Serve,
     Json, Router,
@@ -15,22 +17,42 @@ use axum::{
 use redis::{Client, RedisResult};
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
-use sqlx::{database, postgres::PgPoolOptions};
+use sqlx::postgres::PgPoolOptions;
 use tower_http::{cors::CorsLayer, services::ServeDir, trace::TraceLayer};
 use tracing::info;
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, EmailClient},
+    domain::{AuthAPIError, EmailClient, PasswordRuleFeedback},
     routes::{
-        login_handler, logout_handler, signup_handler, verify_2fa_handler, verify_token_handler,
+        csrf_token_handler, dev_mailbox_handler, login_handler, login_page_handler,
+        login_report_handler, logout_handler, magic_link_consume_handler, magic_link_handler,
+        oidc_callback_handler, oidc_login_handler, passkey_login_finish_handler,
+        passkey_login_start_handler, passkey_registration_finish_handler,
+        passkey_registration_start_handler, phone_number_handler, phone_number_verify_handler,
+        saml_acs_handler, saml_login_handler, saml_metadata_handler, signup_handler,
+        verify_2fa_handler, verify_token_handler,
     },
     services::{BannedTokenStore, TwoFACodeStore, UserStore},
-    utils::tracing::{make_span_with_request_id, on_request, on_response},
+    utils::{
+        constants::CSRF_HEADER_NAME,
+        csrf::csrf_protect,
+        dpop::DPOP_HEADER_NAME,
+        rate_limit::rate_limit,
+        security_headers::security_headers,
+        tracing::{make_span_with_request_id, on_request, on_response},
+    },
 };
 
+type Server = Serve<
+    tokio::net::TcpListener,
+    IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    axum::middleware::AddExtension<Router, axum::extract::ConnectInfo<SocketAddr>>,
+>;
+
 pub struct Application {
-    server: Serve<tokio::net::TcpListener, Router, Router>,
+    server: Server,
+    email_outbox_worker: Pin<Box<dyn Future<Output = ()> + Send>>,
     pub address: String,
 }
 
@@ -56,15 +78,90 @@ impl Application {
             .allow_methods([Method::GET, Method::POST])
             // Allow cookies to be included in requests
             .allow_credentials(true)
+            // Allow JSON bodies, the CSRF token header and DPoP proofs on cross-origin requests
+            .allow_headers([
+                CONTENT_TYPE,
+                HeaderName::from_static(CSRF_HEADER_NAME),
+                HeaderName::from_static(DPOP_HEADER_NAME),
+            ])
             .allow_origin(allowed_origins);
 
+        let email_outbox_worker = Box::pin(
+            (*app_state.email_outbox)
+                .clone()
+                .run(app_state.email_client.clone()),
+        );
+
         let router = Router::new()
             .fallback_service(ServeDir::new("assets"))
+            .route("/", get(login_page_handler))
+            .route("/index.html", get(login_page_handler))
             .route("/signup", post(signup_handler))
             .route("/login", post(login_handler))
-            .route("/logout", post(logout_handler))
+            .route("/login/report", post(login_report_handler))
+            .route("/login/magic-link", post(magic_link_handler))
+            .route("/login/magic-link/consume", get(magic_link_consume_handler))
+            .route("/oidc/{provider}/login", get(oidc_login_handler))
+            .route("/oidc/{provider}/callback", get(oidc_callback_handler))
+            .route("/saml/metadata", get(saml_metadata_handler))
+            .route("/saml/login", get(saml_login_handler))
+            .route("/saml/acs", post(saml_acs_handler))
+            .route(
+                "/logout",
+                post(logout_handler).route_layer(middleware::from_fn_with_state(
+                    app_state.cookie_policy.clone(),
+                    csrf_protect,
+                )),
+            )
+            .route("/csrf-token", get(csrf_token_handler))
             .route("/verify-2fa", post(verify_2fa_handler))
             .route("/verify-token", post(verify_token_handler))
+            .route(
+                "/passkeys/register/start",
+                post(passkey_registration_start_handler).route_layer(
+                    middleware::from_fn_with_state(app_state.cookie_policy.clone(), csrf_protect),
+                ),
+            )
+            .route(
+                "/passkeys/register/finish",
+                post(passkey_registration_finish_handler).route_layer(
+                    middleware::from_fn_with_state(app_state.cookie_policy.clone(), csrf_protect),
+                ),
+            )
+            .route(
+                "/phone-number",
+                post(phone_number_handler).route_layer(middleware::from_fn_with_state(
+                    app_state.cookie_policy.clone(),
+                    csrf_protect,
+                )),
+            )
+            .route(
+                "/phone-number/verify",
+                post(phone_number_verify_handler).route_layer(middleware::from_fn_with_state(
+                    app_state.cookie_policy.clone(),
+                    csrf_protect,
+                )),
+            )
+            .route("/passkeys/login/start", post(passkey_login_start_handler))
+            .route("/passkeys/login/finish", post(passkey_login_finish_handler));
+
+        // Only development setups with the mock email client can read what was sent
+        let router = if app_state.dev_mailbox.is_some() {
+            router.route("/_dev/mailbox", get(dev_mailbox_handler))
+        } else {
+            router
+        };
+
+        let router = router
+            .route_layer(middleware::from_fn_with_state(
+                app_state.rate_limiter.clone(),
+                rate_limit,
+            ))
+            // Added with `layer` rather than `route_layer` so static assets get the headers too
+            .layer(middleware::from_fn_with_state(
+                app_state.security_headers.clone(),
+                security_headers,
+            ))
             .with_state(app_state)
             .layer(cors)
             .layer(
@@ -76,13 +173,23 @@ impl Application {
 
         let listener = tokio::net::TcpListener::bind(address).await?;
         let address = listener.local_addr()?.to_string();
-        let server: Serve<tokio::net::TcpListener, Router, Router> = axum::serve(listener, router);
+        // The peer address is needed to rate limit by client IP
+        let server: Server = axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        );
 
-        Ok(Self { server, address })
+        Ok(Self {
+            server,
+            email_outbox_worker,
+            address,
+        })
     }
 
     pub async fn run(self) -> Result<(), std::io::Error> {
         info!("listening on {}", &self.address);
+        // Emails are only sent while the server runs
+        tokio::spawn(self.email_outbox_worker);
         self.server.await
     }
 }
@@ -92,14 +199,32 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct PasswordPolicyErrorResponse {
+    pub error: String,
+    pub violations: Vec<PasswordRuleFeedback>,
+}
+
 impl IntoResponse for AuthAPIError {
     fn into_response(self) -> Response {
         log_error_chain(&self);
+        if let AuthAPIError::WeakPassword(violations) = &self {
+            let body = Json(PasswordPolicyErrorResponse {
+                error: self.to_string(),
+                violations: violations.iter().map(PasswordRuleFeedback::from).collect(),
+            });
+            return (http::StatusCode::BAD_REQUEST, body).into_response();
+        }
+
         let (status, error_message) = match self {
             AuthAPIError::UserAlreadyExists => (http::StatusCode::CONFLICT, "User already exists"),
             AuthAPIError::InvalidCredentials => {
                 (http::StatusCode::BAD_REQUEST, "Invalid credentials")
             }
+            AuthAPIError::WeakPassword(_) => (
+                http::StatusCode::BAD_REQUEST,
+                "Password does not meet requirements",
+            ),
             AuthAPIError::UnexpectedError(_) => {
                 (http::StatusCode::INTERNAL_SERVER_ERROR, "Unexpected error")
             }
@@ -108,6 +233,39 @@ impl IntoResponse for AuthAPIError {
             }
             AuthAPIError::MissingToken => (http::StatusCode::BAD_REQUEST, "Missing token"),
             AuthAPIError::InvalidToken => (http::StatusCode::UNAUTHORIZED, "Invalid token"),
+            AuthAPIError::InvalidCsrfToken => {
+                (http::StatusCode::FORBIDDEN, "Missing or invalid CSRF token")
+            }
+            AuthAPIError::InvalidDpopProof => (http::StatusCode::BAD_REQUEST, "Invalid DPoP proof"),
+            AuthAPIError::InvalidPasskey => (http::StatusCode::UNAUTHORIZED, "Invalid passkey"),
+            AuthAPIError::InvalidMagicLink => {
+                (http::StatusCode::UNAUTHORIZED, "Invalid or expired link")
+            }
+            AuthAPIError::UnknownOidcProvider => {
+                (http::StatusCode::NOT_FOUND, "Unknown identity provider")
+            }
+            AuthAPIError::InvalidOidcLogin => {
+                (http::StatusCode::UNAUTHORIZED, "External login failed")
+            }
+            AuthAPIError::SamlNotConfigured => {
+                (http::StatusCode::NOT_FOUND, "SAML is not configured")
+            }
+            AuthAPIError::InvalidSamlLogin => {
+                (http::StatusCode::UNAUTHORIZED, "External login failed")
+            }
+            AuthAPIError::InvalidPhoneNumber => {
+                (http::StatusCode::BAD_REQUEST, "Invalid phone number")
+            }
+            AuthAPIError::SmsNotConfigured => {
+                (http::StatusCode::NOT_FOUND, "SMS is not configured")
+            }
+            AuthAPIError::InvalidLoginReport => {
+                (http::StatusCode::UNAUTHORIZED, "Invalid or expired link")
+            }
+            AuthAPIError::ReadOnlyPassword => (
+                http::StatusCode::FORBIDDEN,
+                "Password is managed by the directory",
+            ),
         };
         let body = Json(ErrorResponse {
             error: error_message.to_string(),
@@ -147,12 +305,32 @@ pub fn get_redis_client(redis_hostname: String) -> RedisResult<Client> {
 
 pub mod app_state {
     use std::sync::Arc;
+
+    use axum::extract::FromRef;
     use tokio::sync::RwLock;
 
+    use crate::domain::mock_email_client::MockInbox;
     use crate::domain::EmailClient;
+    use crate::domain::PasswordPolicy;
+    use crate::domain::SmsClientBackend;
+    use crate::services::breached_passwords::BreachedPasswordCheckerBackend;
     use crate::services::BannedTokenStore;
     use crate::services::TwoFACodeStore;
     use crate::services::UserStore;
+    use crate::utils::cookies::CookiePolicy;
+    use crate::utils::dpop::DpopVerifier;
+    use crate::utils::email_outbox::EmailOutbox;
+    use crate::utils::email_templates::EmailTemplates;
+    use crate::utils::login_alerts::LoginAlerts;
+    use crate::utils::magic_link::MagicLinks;
+    use crate::utils::oidc::OidcRelyingParty;
+    use crate::utils::phone_verification::PhoneVerification;
+    use crate::utils::rate_limit::RateLimiter;
+    use crate::utils::saml::SamlServiceProvider;
+    use crate::utils::security_headers::{
+        auth_routes_policy, headers_from_config, SecurityHeadersPolicy,
+    };
+    use crate::utils::webauthn::WebauthnRelyingParty;
 
     // Using a type alias to improve readability!
     pub type UserStoreType<T> = Arc<RwLock<T>>;
@@ -172,6 +350,25 @@ pub mod app_state {
         pub banned_token_store: BannedTokenStoreType<U>,
         pub two_fa_code_store: TwoFACodeStoreType<V>,
         pub email_client: EmailClientType<W>,
+        pub breached_password_checker: Arc<BreachedPasswordCheckerBackend>,
+        pub email_templates: Arc<EmailTemplates>,
+        pub email_outbox: Arc<EmailOutbox>,
+        // Delivers 2FA codes to users who chose a phone channel
+        pub sms_client: Arc<SmsClientBackend>,
+        pub phone_verification: Arc<PhoneVerification>,
+        pub password_policy: Arc<PasswordPolicy>,
+        pub rate_limiter: Arc<RateLimiter>,
+        pub cookie_policy: Arc<CookiePolicy>,
+        pub security_headers: Arc<SecurityHeadersPolicy>,
+        pub dpop_verifier: Arc<DpopVerifier>,
+        pub webauthn: Arc<WebauthnRelyingParty>,
+        pub magic_links: Arc<MagicLinks>,
+        pub oidc: Arc<OidcRelyingParty>,
+        pub saml: Arc<SamlServiceProvider>,
+        // Tells users about logins from new devices or countries
+        pub login_alerts: Arc<LoginAlerts>,
+        // What the mock email client sent, served at `/_dev/mailbox` when set
+        pub dev_mailbox: Option<MockInbox>,
     }
 
     impl<T, U, V, W> AppState<T, U, V, W>
@@ -192,7 +389,144 @@ pub mod app_state {
                 banned_token_store,
                 two_fa_code_store,
                 email_client,
+                breached_password_checker: Arc::new(BreachedPasswordCheckerBackend::default()),
+                email_templates: Arc::new(EmailTemplates::default()),
+                email_outbox: Arc::new(EmailOutbox::default()),
+                sms_client: Arc::new(SmsClientBackend::default()),
+                phone_verification: Arc::new(PhoneVerification::default()),
+                password_policy: Arc::new(PasswordPolicy::default()),
+                rate_limiter: Arc::new(RateLimiter::default()),
+                cookie_policy: Arc::new(CookiePolicy::default()),
+                security_headers: Arc::new(auth_routes_policy(headers_from_config())),
+                dpop_verifier: Arc::new(DpopVerifier::default()),
+                webauthn: Arc::new(WebauthnRelyingParty::default()),
+                magic_links: Arc::new(MagicLinks::default()),
+                oidc: Arc::new(OidcRelyingParty::default()),
+                saml: Arc::new(SamlServiceProvider::default()),
+                login_alerts: Arc::new(LoginAlerts::default()),
+                dev_mailbox: None,
             }
         }
+
+        pub fn with_email_templates(mut self, email_templates: EmailTemplates) -> Self {
+            self.email_templates = Arc::new(email_templates);
+            self
+        }
+
+        pub fn with_email_outbox(mut self, email_outbox: EmailOutbox) -> Self {
+            self.email_outbox = Arc::new(email_outbox);
+            self
+        }
+
+        pub fn with_sms_client(mut self, sms_client: SmsClientBackend) -> Self {
+            self.sms_client = Arc::new(sms_client);
+            self
+        }
+
+        pub fn with_phone_verification(mut self, phone_verification: PhoneVerification) -> Self {
+            self.phone_verification = Arc::new(phone_verification);
+            self
+        }
+
+        pub fn with_password_policy(mut self, password_policy: PasswordPolicy) -> Self {
+            self.password_policy = Arc::new(password_policy);
+            self
+        }
+
+        pub fn with_breached_password_checker(
+            mut self,
+            breached_password_checker: BreachedPasswordCheckerBackend,
+        ) -> Self {
+            self.breached_password_checker = Arc::new(breached_password_checker);
+            self
+        }
+
+        pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+            self.rate_limiter = Arc::new(rate_limiter);
+            self
+        }
+
+        pub fn with_cookie_policy(mut self, cookie_policy: CookiePolicy) -> Self {
+            self.cookie_policy = Arc::new(cookie_policy);
+            self
+        }
+
+        pub fn with_security_headers(mut self, security_headers: SecurityHeadersPolicy) -> Self {
+            self.security_headers = Arc::new(security_headers);
+            self
+        }
+
+        pub fn with_dpop_verifier(mut self, dpop_verifier: DpopVerifier) -> Self {
+            self.dpop_verifier = Arc::new(dpop_verifier);
+            self
+        }
+
+        pub fn with_webauthn(mut self, webauthn: WebauthnRelyingParty) -> Self {
+            self.webauthn = Arc::new(webauthn);
+            self
+        }
+
+        pub fn with_magic_links(mut self, magic_links: MagicLinks) -> Self {
+            self.magic_links = Arc::new(magic_links);
+            self
+        }
+
+        pub fn with_oidc(mut self, oidc: OidcRelyingParty) -> Self {
+            self.oidc = Arc::new(oidc);
+            self
+        }
+
+        pub fn with_saml(mut self, saml: SamlServiceProvider) -> Self {
+            self.saml = Arc::new(saml);
+            self
+        }
+
+        pub fn with_login_alerts(mut self, login_alerts: LoginAlerts) -> Self {
+            self.login_alerts = Arc::new(login_alerts);
+            self
+        }
+
+        pub fn with_dev_mailbox(mut self, inbox: MockInbox) -> Self {
+            self.dev_mailbox = Some(inbox);
+            self
+        }
+    }
+
+    // Lets extractors such as `AuthToken` find the cookie names without knowing the stores
+    impl<T, U, V, W> FromRef<AppState<T, U, V, W>> for Arc<CookiePolicy>
+    where
+        T: UserStore,
+        U: BannedTokenStore,
+        V: TwoFACodeStore,
+        W: EmailClient,
+    {
+        fn from_ref(state: &AppState<T, U, V, W>) -> Self {
+            state.cookie_policy.clone()
+        }
+    }
+
+    // Lets `LoginDevice` find the client's address and country
+    impl<T, U, V, W> FromRef<AppState<T, U, V, W>> for Arc<RateLimiter>
+    where
+        T: UserStore,
+        U: BannedTokenStore,
+        V: TwoFACodeStore,
+        W: EmailClient,
+    {
+        fn from_ref(state: &AppState<T, U, V, W>) -> Self {
+            state.rate_limiter.clone()
+        }
+    }
+
+    impl<T, U, V, W> FromRef<AppState<T, U, V, W>> for Arc<LoginAlerts>
+    where
+        T: UserStore,
+        U: BannedTokenStore,
+        V: TwoFACodeStore,
+        W: EmailClient,
+    {
+        fn from_ref(state: &AppState<T, U, V, W>) -> Self {
+            state.login_alerts.clone()
+        }
     }
 }
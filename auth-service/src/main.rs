@@ -1,15 +1,59 @@
 use std::sync::Arc;
 
 use auth_service::{
-    domain::{models::Email, resend_email_client::ResendEmailClient},
+    domain::{
+        http_sms_client::HttpSmsClient,
+        mock_email_client::{MockEmailClient, MockInbox},
+        mock_sms_client::MockSmsClient,
+        models::Email,
+        resend_email_client::ResendEmailClient,
+        smtp_email_client::SmtpEmailClient,
+        EmailClientBackend, SmsClientBackend,
+    },
     get_postgres_pool, get_redis_client,
-    services::data_stores::{
-        postgres_user_store::PostgresUserStore, redis_banned_token_store::RedisBannedTokenStore,
-        redis_two_fa_code_store::RedisTwoFACodeStore,
+    services::{
+        breached_passwords::{
+            BreachedPasswordCheckerBackend, HttpRangeBreachedPasswordChecker,
+            RangeFileBreachedPasswordChecker,
+        },
+        data_stores::{
+            postgres_two_fa_code_store::PostgresTwoFACodeStore,
+            postgres_user_store::PostgresUserStore,
+            redis_banned_token_store::RedisBannedTokenStore, ChainedUserStore, LdapUserStore,
+        },
+        dpop_replay::{DpopReplayStoreBackend, RedisDpopReplayStore},
+        email_outbox::{EmailOutboxStoreBackend, PostgresEmailOutboxStore},
+        known_devices::{KnownDeviceStoreBackend, PostgresKnownDeviceStore},
+        magic_links::{MagicLinkStoreBackend, RedisMagicLinkStore},
+        oidc::{
+            OidcIdentityStoreBackend, OidcStateStoreBackend, PostgresOidcIdentityStore,
+            RedisOidcStateStore,
+        },
+        passkeys::{
+            PasskeyChallengeStoreBackend, PasskeyStoreBackend, PostgresPasskeyStore,
+            RedisPasskeyChallengeStore,
+        },
+        password_hashing::PasswordPeppers,
+        phone_verification::{PhoneVerificationStoreBackend, RedisPhoneVerificationStore},
+        rate_limiting::{HashmapRateLimitStore, RateLimitStoreBackend, RedisRateLimitStore},
+        saml::{RedisSamlStore, SamlStoreBackend},
     },
     utils::{
-        constants::{prod, DATABASE_URL, REDIS_HOST_NAME, RESEND_SECRET, SENDER_EMAIL},
+        constants::{
+            prod, BREACHED_PASSWORDS_API_URL, BREACHED_PASSWORDS_FILE, DATABASE_URL, DEV_MAILBOX,
+            EMAIL_BACKEND, RATE_LIMIT_BACKEND, REDIS_HOST_NAME, RESEND_SECRET, SENDER_EMAIL,
+            SMS_BACKEND, SMS_GATEWAY_CONFIG, SMTP_CONFIG,
+        },
+        dpop::DpopVerifier,
+        email_outbox::EmailOutbox,
+        login_alerts::LoginAlerts,
+        magic_link::MagicLinks,
+        oidc::OidcRelyingParty,
+        phone_verification::PhoneVerification,
+        rate_limit::RateLimiter,
+        saml::SamlServiceProvider,
         tracing::init_tracing,
+        webauthn::WebauthnRelyingParty,
     },
     Application,
 };
@@ -23,21 +67,78 @@ async fn main() {
 
     let pg_pool = configure_postgresql().await;
     let redis_connection = configure_redis().await;
-    let resend_client = configure_resend_client();
+    let email_client = configure_email_client();
+    let sms_client = configure_sms_client();
+    let breached_password_checker = configure_breached_password_checker();
+    let rate_limiter = configure_rate_limiter(redis_connection.clone());
+    let dpop_verifier = DpopVerifier::new(DpopReplayStoreBackend::Redis(
+        RedisDpopReplayStore::new(redis_connection.clone()),
+    ));
+
+    let magic_links = MagicLinks::new(MagicLinkStoreBackend::Redis(RedisMagicLinkStore::new(
+        redis_connection.clone(),
+    )));
+
+    let email_outbox = EmailOutbox::new(EmailOutboxStoreBackend::Postgres(
+        PostgresEmailOutboxStore::new(pg_pool.clone()),
+    ));
+
+    let phone_verification = PhoneVerification::new(PhoneVerificationStoreBackend::Redis(
+        RedisPhoneVerificationStore::new(redis_connection.clone()),
+    ));
+
+    let login_alerts = LoginAlerts::new(
+        KnownDeviceStoreBackend::Postgres(PostgresKnownDeviceStore::new(pg_pool.clone())),
+        MagicLinkStoreBackend::Redis(RedisMagicLinkStore::new(redis_connection.clone())),
+    );
+
+    let saml = SamlServiceProvider::new(SamlStoreBackend::Redis(RedisSamlStore::new(
+        redis_connection.clone(),
+    )));
 
     let banned_token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(
         redis_connection.clone(),
     )));
-    let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
-    let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(redis_connection)));
-    let email_client = Arc::new(RwLock::new(resend_client));
+    let password_peppers = PasswordPeppers::from_config().expect("Failed to load password peppers");
+    let webauthn = WebauthnRelyingParty::new(
+        PasskeyStoreBackend::Postgres(PostgresPasskeyStore::new(pg_pool.clone())),
+        PasskeyChallengeStoreBackend::Redis(RedisPasskeyChallengeStore::new(
+            redis_connection.clone(),
+        )),
+    );
+    let oidc = OidcRelyingParty::new(
+        OidcStateStoreBackend::Redis(RedisOidcStateStore::new(redis_connection.clone())),
+        OidcIdentityStoreBackend::Postgres(PostgresOidcIdentityStore::new(pg_pool.clone())),
+    );
+    // Directory users, if an LDAP server is configured, come before those who signed up
+    let user_store = Arc::new(RwLock::new(ChainedUserStore::new(
+        LdapUserStore::from_config(),
+        PostgresUserStore::new(pg_pool.clone()).with_peppers(password_peppers),
+    )));
+    let two_fa_code_store = Arc::new(RwLock::new(PostgresTwoFACodeStore::new(pg_pool)));
+    let dev_mailbox = configure_dev_mailbox(&email_client);
+    let email_client = Arc::new(RwLock::new(email_client));
 
-    let app_state = auth_service::app_state::AppState::new(
+    let mut app_state = auth_service::app_state::AppState::new(
         user_store,
         banned_token_store,
         two_fa_code_store,
         email_client,
-    );
+    )
+    .with_breached_password_checker(breached_password_checker)
+    .with_email_outbox(email_outbox)
+    .with_sms_client(sms_client)
+    .with_phone_verification(phone_verification)
+    .with_rate_limiter(rate_limiter)
+    .with_dpop_verifier(dpop_verifier)
+    .with_webauthn(webauthn)
+    .with_magic_links(magic_links)
+    .with_oidc(oidc)
+    .with_saml(saml)
+    .with_login_alerts(login_alerts);
+    if let Some(inbox) = dev_mailbox {
+        app_state = app_state.with_dev_mailbox(inbox);
+    }
 
     let app = Application::build(app_state, prod::APP_ADDRESS)
         .await
@@ -46,11 +147,75 @@ async fn main() {
     app.run().await.expect("Failed to run application");
 }
 
-fn configure_resend_client() -> ResendEmailClient {
-    ResendEmailClient::new(
-        Email::new(SENDER_EMAIL.clone()).expect("Cannot make email"),
-        &RESEND_SECRET,
-    )
+fn configure_email_client() -> EmailClientBackend {
+    match EMAIL_BACKEND.as_str() {
+        "resend" => EmailClientBackend::Resend(ResendEmailClient::new(
+            Email::new(SENDER_EMAIL.clone()).expect("Cannot make email"),
+            &RESEND_SECRET,
+        )),
+        "smtp" => {
+            let config = SMTP_CONFIG
+                .as_ref()
+                .expect("SMTP_HOST must be set for the smtp email backend.");
+            let client = SmtpEmailClient::new(
+                Email::new(SENDER_EMAIL.clone()).expect("Cannot make email"),
+                config,
+            )
+            .expect("Failed to build SMTP email client");
+            EmailClientBackend::Smtp(client)
+        }
+        "mock" => EmailClientBackend::Mock(MockEmailClient::default()),
+        other => panic!("Unknown email backend: {}", other),
+    }
+}
+
+fn configure_sms_client() -> SmsClientBackend {
+    match SMS_BACKEND.as_str() {
+        "http" => {
+            let config = SMS_GATEWAY_CONFIG
+                .as_ref()
+                .expect("SMS_GATEWAY_URL must be set for the http SMS backend.");
+            let client = HttpSmsClient::new(config).expect("Failed to build SMS gateway client");
+            SmsClientBackend::Http(client)
+        }
+        "mock" => SmsClientBackend::Mock(MockSmsClient::default()),
+        "disabled" => SmsClientBackend::Disabled,
+        other => panic!("Unknown SMS backend: {}", other),
+    }
+}
+
+// Only the mock client keeps what it sent, so the dev mailbox cannot be used with a real one
+fn configure_dev_mailbox(email_client: &EmailClientBackend) -> Option<MockInbox> {
+    match (email_client, *DEV_MAILBOX) {
+        (_, false) => None,
+        (EmailClientBackend::Mock(client), true) => Some(client.inbox()),
+        (_, true) => panic!("DEV_MAILBOX requires the mock email backend."),
+    }
+}
+
+// A local range file takes precedence over the range API, so production never needs network access
+fn configure_breached_password_checker() -> BreachedPasswordCheckerBackend {
+    if let Some(path) = BREACHED_PASSWORDS_FILE.as_ref() {
+        let checker = RangeFileBreachedPasswordChecker::open(path)
+            .expect("Failed to open breached passwords file");
+        BreachedPasswordCheckerBackend::RangeFile(checker)
+    } else if let Some(url) = BREACHED_PASSWORDS_API_URL.as_ref() {
+        let checker = HttpRangeBreachedPasswordChecker::new(url.to_owned())
+            .expect("Failed to build breached passwords client");
+        BreachedPasswordCheckerBackend::HttpRange(checker)
+    } else {
+        BreachedPasswordCheckerBackend::Disabled
+    }
+}
+
+// Redis shares the limits between all instances; the in-memory store only suits a single one
+fn configure_rate_limiter(redis_connection: redis::aio::MultiplexedConnection) -> RateLimiter {
+    let store = match RATE_LIMIT_BACKEND.as_str() {
+        "redis" => RateLimitStoreBackend::Redis(RedisRateLimitStore::new(redis_connection)),
+        "memory" => RateLimitStoreBackend::InMemory(HashmapRateLimitStore::default()),
+        other => panic!("Unknown rate limit backend: {}", other),
+    };
+    RateLimiter::new(store)
 }
 
 async fn configure_postgresql() -> PgPool {
@@ -0,0 +1,46 @@
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, EmailClient},
+    services::{BannedTokenStore, TwoFACodeStore, UserStore},
+    utils::{
+        auth::{validate_token, AuthToken},
+        csrf::generate_csrf_token,
+    },
+};
+
+// Hands the CSRF token to frontends on other origins, which cannot read the CSRF cookie.
+// CORS keeps the response away from origins that are not allowed.
+#[instrument(skip_all)]
+pub async fn csrf_token_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    AuthToken { token, dpop, .. }: AuthToken,
+) -> Result<Json<CsrfTokenResponse>, AuthAPIError>
+where
+    T: UserStore,
+    U: BannedTokenStore + Send + Sync,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    validate_token(
+        &token,
+        &*state.banned_token_store.read().await,
+        &state.dpop_verifier,
+        dpop.as_ref(),
+    )
+    .await
+    .map_err(|_| AuthAPIError::InvalidToken)?;
+
+    let csrf_token = generate_csrf_token(&token).map_err(AuthAPIError::UnexpectedError)?;
+
+    Ok(Json(CsrfTokenResponse { csrf_token }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CsrfTokenResponse {
+    #[serde(rename = "csrfToken")]
+    pub csrf_token: String,
+}
@@ -0,0 +1,72 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{mock_email_client::MockEmail, EmailClient},
+    services::{BannedTokenStore, TwoFACodeStore, UserStore},
+};
+
+// Lists what the mock email client sent, newest first, so codes and links can be read during
+// development. The route only exists when the dev mailbox is turned on.
+pub async fn dev_mailbox_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    Query(query): Query<DevMailboxQuery>,
+) -> Result<Json<DevMailboxResponse>, StatusCode>
+where
+    T: UserStore,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let inbox = state.dev_mailbox.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+
+    let messages = match query.recipient {
+        Some(recipient) => inbox.messages_to(&recipient),
+        None => inbox.messages(),
+    };
+    let messages = messages
+        .into_iter()
+        .take(query.limit.unwrap_or(usize::MAX))
+        .map(DevMailboxMessage::from)
+        .collect();
+
+    Ok(Json(DevMailboxResponse { messages }))
+}
+
+#[derive(Deserialize)]
+pub struct DevMailboxQuery {
+    pub recipient: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DevMailboxResponse {
+    pub messages: Vec<DevMailboxMessage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DevMailboxMessage {
+    pub recipient: String,
+    pub subject: String,
+    pub html: Option<String>,
+    pub text: String,
+    #[serde(rename = "sentAt")]
+    pub sent_at: String,
+}
+
+impl From<MockEmail> for DevMailboxMessage {
+    fn from(message: MockEmail) -> Self {
+        Self {
+            recipient: message.recipient,
+            subject: message.subject,
+            html: message.html,
+            text: message.text,
+            sent_at: message.sent_at.to_rfc3339(),
+        }
+    }
+}
@@ -1,30 +1,43 @@
 use axum::{extract::State, http, response::IntoResponse, Json};
 use axum_extra::extract::CookieJar;
-use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
 use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
-use tracing::instrument;
+use tracing::{instrument, warn};
 
 use crate::{
     app_state::AppState,
     domain::{
         models::{Email, Password},
-        AuthAPIError, EmailClient,
+        AuthAPIError, EmailClient, PhoneNumber, TwoFAChannel, User,
     },
     services::{BannedTokenStore, LoginAttemptId, TwoFACode, TwoFACodeStore, UserStore},
-    utils::auth::generate_auth_cookie,
+    utils::{
+        auth::{
+            generate_auth_cookie, generate_dpop_bound_token, issued_token_claims, TOKEN_TTL_SECONDS,
+        },
+        csrf::generate_csrf_cookie,
+        dpop::{DpopProof, DpopRequest, DpopVerifier},
+        login_alerts::LoginDevice,
+        phone_verification::send_code,
+    },
 };
 
 #[derive(serde::Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: SecretString,
+    // API clients that cannot keep cookies ask for the token in the response body
+    #[serde(default, rename = "includeToken")]
+    pub include_token: bool,
 }
 
 #[instrument(skip_all)]
 pub async fn login_handler<T, U, V, W>(
     State(state): State<AppState<T, U, V, W>>,
     jar: CookieJar,
+    dpop: Option<DpopRequest>,
+    device: LoginDevice,
     Json(request): Json<LoginRequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>)
 where
@@ -35,27 +48,46 @@ where
 {
     let email = request.email;
     let password = request.password;
+    let include_token = request.include_token;
 
     let (email, password) = match (Email::new(email.into()), Password::new(password)) {
         (Ok(email), Ok(password)) => (email, password),
         _ => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let user_store = &state.user_store.read().await;
-    if let Ok(_) = user_store.validate(&email, password.as_ref()).await {
-        let user = user_store.get(&email).await.unwrap();
-        match user.requires_2fa {
-            true => handle_2fa(&email, &state, jar).await,
-            false => handle_no_2fa(&user.email, jar).await,
+    let dpop = match verify_login_proof(&state.dpop_verifier, dpop).await {
+        Ok(dpop) => dpop,
+        Err(e) => return (jar, Err(e)),
+    };
+
+    // The store is released before signing in, which reads the user again for login alerts
+    let user = {
+        let user_store = state.user_store.read().await;
+        if user_store
+            .validate(&email, password.as_ref())
+            .await
+            .is_err()
+        {
+            return (jar, Err(AuthAPIError::IncorrectCredentials));
         }
-    } else {
-        (jar, Err(AuthAPIError::IncorrectCredentials))
+        // A directory store looks the user up again, which can fail even after validating
+        match user_store.get(&email).await {
+            Ok(user) => user,
+            Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+        }
+    };
+    match user.requires_2fa {
+        true => handle_2fa(&user, &state, jar).await,
+        false => handle_no_2fa(&user.email, include_token, dpop, &device, &state, jar).await,
     }
 }
 
+// Sends a 2FA code, in the user's language, and starts the login attempt it has to be verified
+// for. Users with a verified phone number get it over the channel they chose, everyone else, or
+// anyone whose text or call could not be placed, by email.
 #[instrument(skip_all)]
-async fn handle_2fa<T, U, V, W>(
-    email: &Email,
+pub(crate) async fn handle_2fa<T, U, V, W>(
+    user: &User,
     state: &AppState<T, U, V, W>,
     jar: CookieJar,
 ) -> (
@@ -72,60 +104,263 @@ where
     let login_attempt_id = LoginAttemptId::default();
     let code = TwoFACode::default();
 
-    let two_fa_store = &mut state.two_fa_code_store.write().await;
-    let add_result = two_fa_store
-        .add_code(email.clone(), login_attempt_id.clone(), code.clone())
-        .await;
+    let message = match state
+        .email_templates
+        .two_fa_code(user.locale, code.as_ref())
+    {
+        Ok(message) => message,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
 
-    if let Err(e) = add_result {
-        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    if let Some(phone_number) = phone_number_for_codes(user) {
+        // The code has to be stored before it can reach the user. The store is released before
+        // the gateway is called, so a slow gateway does not hold up everyone else's logins.
+        let add_result = state
+            .two_fa_code_store
+            .write()
+            .await
+            .add_code(user.email.clone(), login_attempt_id.clone(), code.clone())
+            .await;
+        if let Err(e) = add_result {
+            return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+        }
+        if send_code_by_phone(user, phone_number, state, &code).await {
+            return (jar, Ok(two_fa_required(&login_attempt_id)));
+        }
     }
 
-    let email_client = &state.email_client.read().await;
-
-    if let Err(e) = email_client
-        .send_email(
-            email,
-            "Your 2FA Code",
-            &format!("Your 2FA code is: {}", code.as_ref()),
-        )
+    // The code is only stored together with the email that delivers it
+    let add_result = state
+        .two_fa_code_store
+        .write()
         .await
-    {
-        return (jar, Err(AuthAPIError::UnexpectedError(eyre!(e))));
+        .add_code_and_enqueue(
+            user.email.clone(),
+            login_attempt_id.clone(),
+            code,
+            &state.email_outbox,
+            format!("two-fa-code:{}", login_attempt_id.as_ref()),
+            message,
+        )
+        .await;
+    if let Err(e) = add_result {
+        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
     }
 
+    (jar, Ok(two_fa_required(&login_attempt_id)))
+}
+
+fn two_fa_required(login_attempt_id: &LoginAttemptId) -> (http::StatusCode, Json<LoginResponse>) {
     let response = Json(LoginResponse::TwoFactorAuth(TwoFactorAuthResponse {
         message: "2FA required".to_owned(),
         login_attempt_id: login_attempt_id.as_ref().to_owned(),
     }));
-    return (jar, Ok((http::StatusCode::PARTIAL_CONTENT, response)));
+    (http::StatusCode::PARTIAL_CONTENT, response)
 }
 
+// The verified phone number of users who get their codes by text or call
+fn phone_number_for_codes(user: &User) -> Option<&PhoneNumber> {
+    match user.two_fa_channel {
+        TwoFAChannel::Email => None,
+        _ => user.phone_number.as_ref(),
+    }
+}
+
+// Returns whether the code was texted or read out to the user
+async fn send_code_by_phone<T, U, V, W>(
+    user: &User,
+    phone_number: &PhoneNumber,
+    state: &AppState<T, U, V, W>,
+    code: &TwoFACode,
+) -> bool
+where
+    T: UserStore,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    match send_code(
+        &*state.sms_client,
+        &state.email_templates,
+        user.locale,
+        phone_number,
+        user.two_fa_channel,
+        code,
+    )
+    .await
+    {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(error = ?e, "Failed to send 2FA code by phone, falling back to email");
+            false
+        }
+    }
+}
+
+// Checks the DPoP proof a login was sent with, if any, whose key the issued token is bound to
+pub(crate) async fn verify_login_proof(
+    dpop_verifier: &DpopVerifier,
+    dpop: Option<DpopRequest>,
+) -> Result<Option<DpopProof>, AuthAPIError> {
+    let Some(dpop) = dpop else {
+        return Ok(None);
+    };
+    match dpop_verifier.verify(&dpop, None).await {
+        Ok(proof) => Ok(Some(proof)),
+        Err(e) => {
+            warn!(error = ?e, "Rejected DPoP proof");
+            Err(AuthAPIError::InvalidDpopProof)
+        }
+    }
+}
+
+// Signs the user in once every factor they need was checked
 #[instrument(skip_all)]
-async fn handle_no_2fa(
+pub(crate) async fn handle_no_2fa<T, U, V, W>(
     email: &Email,
+    include_token: bool,
+    dpop: Option<DpopProof>,
+    device: &LoginDevice,
+    state: &AppState<T, U, V, W>,
     jar: CookieJar,
 ) -> (
     CookieJar,
     Result<(http::StatusCode, Json<LoginResponse>), AuthAPIError>,
-) {
-    let jar = match generate_auth_cookie(&email) {
-        Ok(cookie) => jar.add(cookie),
+)
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    // DPoP clients hold their token themselves, and a cookie would not be bound to their key
+    if let Some(proof) = dpop {
+        let token = match generate_dpop_bound_token(email, &proof.jkt) {
+            Ok(token) => token,
+            Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+        };
+        track_login(email, &token, device, state).await;
+        let jar = jar.add(device.cookie(&state.cookie_policy));
+        return (
+            jar,
+            Ok((
+                http::StatusCode::OK,
+                Json(LoginResponse::Token(TokenResponse::dpop(token))),
+            )),
+        );
+    }
+
+    let auth_cookie = match generate_auth_cookie(email, &state.cookie_policy) {
+        Ok(cookie) => cookie,
         Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
     };
-    (
-        jar,
-        Ok((http::StatusCode::OK, Json(LoginResponse::RegularAuth))),
-    )
+    track_login(email, auth_cookie.value(), device, state).await;
+    let response = match include_token {
+        true => LoginResponse::Token(TokenResponse::new(auth_cookie.value().to_owned())),
+        false => LoginResponse::RegularAuth,
+    };
+    let jar = match generate_csrf_cookie(auth_cookie.value(), &state.cookie_policy) {
+        Ok(csrf_cookie) => jar
+            .add(auth_cookie)
+            .add(csrf_cookie)
+            .add(device.cookie(&state.cookie_policy)),
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
+    (jar, Ok((http::StatusCode::OK, Json(response))))
+}
+
+// Emails the user about a login from a device or country their account was not used from before,
+// with a link to report it, and remembers where the login came from. `token` is the token the
+// login was issued. The user did authenticate, so failing to do this never fails the login.
+#[instrument(skip_all)]
+pub(crate) async fn track_login<T, U, V, W>(
+    email: &Email,
+    token: &str,
+    device: &LoginDevice,
+    state: &AppState<T, U, V, W>,
+) where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    if let Err(e) = alert_and_remember_login(email, token, device, state).await {
+        warn!(error = ?e, "Failed to track login");
+    }
+}
+
+async fn alert_and_remember_login<T, U, V, W>(
+    email: &Email,
+    token: &str,
+    device: &LoginDevice,
+    state: &AppState<T, U, V, W>,
+) -> Result<()>
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let login_alerts = &state.login_alerts;
+    let check = login_alerts.check(email, device).await?;
+
+    if let Some(reason) = check.alert {
+        let session = issued_token_claims(token)?;
+        let link = login_alerts.issue_report_link(email, &session, &check)?;
+        let locale = state.user_store.read().await.get(email).await?.locale;
+        let message = state.email_templates.login_alert(
+            locale,
+            reason,
+            &check.device,
+            &link,
+            login_alerts.report_ttl(),
+        )?;
+        state
+            .email_outbox
+            .enqueue(format!("login-alert:{}", session.jti), email, message)
+            .await?;
+    }
+
+    login_alerts.remember(email, &check).await
 }
 
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum LoginResponse {
     RegularAuth,
+    Token(TokenResponse),
     TwoFactorAuth(TwoFactorAuthResponse),
 }
 
+// Returned alongside the cookies when the client asks for the token, to be sent back as
+// `Authorization: Bearer <token>`. DPoP-bound tokens are sent back as `Authorization: DPoP
+// <token>` together with a proof.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub token: String,
+    #[serde(rename = "tokenType")]
+    pub token_type: String,
+    #[serde(rename = "expiresIn")]
+    pub expires_in: u64,
+}
+
+impl TokenResponse {
+    pub fn new(token: String) -> Self {
+        Self {
+            token,
+            token_type: "Bearer".to_owned(),
+            expires_in: TOKEN_TTL_SECONDS,
+        }
+    }
+
+    pub fn dpop(token: String) -> Self {
+        Self {
+            token_type: "DPoP".to_owned(),
+            ..Self::new(token)
+        }
+    }
+}
+
 // If a user requires 2FA, this JSON body should be returned!
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TwoFactorAuthResponse {
@@ -0,0 +1,12 @@
+use axum::{response::Html, Extension};
+
+use crate::utils::security_headers::CspNonce;
+
+// The script tags of the page carry this placeholder as their nonce
+const CSP_NONCE_PLACEHOLDER: &str = "{{csp_nonce}}";
+const LOGIN_PAGE: &str = include_str!("../../assets/index.html");
+
+// Serves the login page with the CSP nonce of the request, so only its own scripts may run
+pub async fn login_page_handler(Extension(nonce): Extension<CspNonce>) -> Html<String> {
+    Html(LOGIN_PAGE.replace(CSP_NONCE_PLACEHOLDER, nonce.as_ref()))
+}
@@ -0,0 +1,102 @@
+use axum::{extract::State, Json};
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use tracing::{instrument, warn};
+
+use crate::{
+    app_state::AppState,
+    domain::{models::Password, AuthAPIError, EmailClient},
+    services::{BannedTokenStore, TwoFACodeStore, UserStore},
+    utils::{login_alerts::LoginReportError, password::validate_new_password},
+};
+
+#[derive(Deserialize)]
+pub struct LoginReportRequest {
+    pub token: String,
+    #[serde(rename = "newPassword")]
+    pub new_password: SecretString,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginReportResponse {
+    pub message: String,
+}
+
+// Handles the "this wasn't me" link of a login alert: ends the session the login started, forgets
+// its device and replaces the password, which whoever signed in likely knows. The link is only
+// used up once all of that succeeded, so a failure leaves it for the user to retry. Directory
+// users have to change their password in the directory, so their reports are refused up front.
+#[instrument(skip_all)]
+pub async fn login_report_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    Json(request): Json<LoginReportRequest>,
+) -> Result<Json<LoginReportResponse>, AuthAPIError>
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore + Send + Sync,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let login_alerts = &state.login_alerts;
+    let report = login_alerts
+        .verify_report(&request.token)
+        .await
+        .map_err(login_report_error)?;
+    let can_set_password = state
+        .user_store
+        .read()
+        .await
+        .can_set_password(&report.email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    if !can_set_password {
+        return Err(AuthAPIError::ReadOnlyPassword);
+    }
+
+    validate_new_password(
+        &request.new_password,
+        &report.email,
+        &state.password_policy,
+        &*state.breached_password_checker,
+    )
+    .await?;
+    let password =
+        Password::new(request.new_password).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    state
+        .banned_token_store
+        .write()
+        .await
+        .ban_token(&report.session_id, report.session_ttl())
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    state
+        .user_store
+        .write()
+        .await
+        .set_password(&report.email, password)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    login_alerts
+        .forget_device(&report.email, &report.device_id)
+        .await
+        .map_err(AuthAPIError::UnexpectedError)?;
+    login_alerts
+        .consume_report(&report)
+        .await
+        .map_err(login_report_error)?;
+
+    Ok(Json(LoginReportResponse {
+        message: "The session was ended and your password was changed".to_owned(),
+    }))
+}
+
+fn login_report_error(e: LoginReportError) -> AuthAPIError {
+    match e {
+        LoginReportError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+        LoginReportError::InvalidReport(e) => {
+            warn!(error = ?e, "Rejected login report");
+            AuthAPIError::InvalidLoginReport
+        }
+    }
+}
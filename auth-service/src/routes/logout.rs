@@ -1,19 +1,20 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse};
-use axum_extra::extract::{cookie::Cookie, CookieJar};
+use axum_extra::extract::CookieJar;
 use color_eyre::eyre::Context;
 use tracing::instrument;
 
 use crate::{
     app_state::AppState,
-    domain::{models::Email, AuthAPIError, EmailClient},
+    domain::{AuthAPIError, EmailClient},
     services::{BannedTokenStore, TwoFACodeStore, UserStore},
-    utils::{auth::validate_token, constants::JWT_COOKIE_NAME},
+    utils::auth::{validate_token, AuthToken},
 };
 
 #[instrument(skip_all)]
 pub async fn logout_handler<T, U, V, W>(
     jar: CookieJar,
     state: State<AppState<T, U, V, W>>,
+    AuthToken { token, dpop, .. }: AuthToken,
 ) -> Result<(CookieJar, impl IntoResponse), AuthAPIError>
 where
     T: UserStore + Send + Sync,
@@ -21,17 +22,23 @@ where
     V: TwoFACodeStore,
     W: EmailClient,
 {
-    let cookie = jar.get(JWT_COOKIE_NAME).ok_or(AuthAPIError::MissingToken)?;
-    let token = cookie.value().to_owned();
+    let claims = validate_token(
+        &token,
+        &*state.banned_token_store.read().await,
+        &state.dpop_verifier,
+        dpop.as_ref(),
+    )
+    .await
+    .map_err(|_| AuthAPIError::InvalidToken)?;
 
-    validate_token(&token, &*state.banned_token_store.read().await)
-        .await
-        .map_err(|_| AuthAPIError::InvalidToken)?;
-
-    let jar = jar.remove(Cookie::from(JWT_COOKIE_NAME));
+    // Bearer clients have no cookies to clear, in which case this is a no-op
+    let cookie_policy = &state.cookie_policy;
+    let jar = jar
+        .remove(cookie_policy.removal_cookie(cookie_policy.auth_cookie_name()))
+        .remove(cookie_policy.removal_cookie(cookie_policy.csrf_cookie_name()));
     let mut banned_token_store = state.banned_token_store.write().await;
     banned_token_store
-        .ban_token(&token)
+        .ban_token(&claims.jti, claims.remaining_lifetime())
         .await
         .wrap_err("Failed to ban token")
         .map_err(AuthAPIError::UnexpectedError)?;
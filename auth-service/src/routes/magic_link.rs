@@ -0,0 +1,143 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use serde::{Deserialize, Serialize};
+use tracing::{instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{models::Email, AuthAPIError, EmailClient},
+    routes::login::{handle_2fa, handle_no_2fa},
+    services::{BannedTokenStore, TwoFACodeStore, UserStore, UserStoreError},
+    utils::{
+        login_alerts::LoginDevice,
+        magic_link::{MagicLinkError, MagicLinks},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MagicLinkResponse {
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkConsumeQuery {
+    pub token: String,
+}
+
+// Emails a sign-in link bound to this browser. The response is the same whether or not the
+// account exists, so it cannot be used to find out who has one.
+#[instrument(skip_all)]
+pub async fn magic_link_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    jar: CookieJar,
+    Json(request): Json<MagicLinkRequest>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>)
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let Ok(email) = Email::new(request.email.into()) else {
+        return (jar, Err(AuthAPIError::InvalidCredentials));
+    };
+
+    let nonce = MagicLinks::new_nonce();
+    let jar = jar.add(
+        state
+            .cookie_policy
+            .magic_link_nonce_cookie(nonce.clone(), state.magic_links.ttl().as_secs()),
+    );
+
+    let user = match state.user_store.read().await.get(&email).await {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => return (jar, Ok(Json(link_sent()))),
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+
+    let link = match state.magic_links.issue(&email, &nonce) {
+        Ok(link) => link,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+    };
+    let message =
+        match state
+            .email_templates
+            .magic_link(user.locale, &link, state.magic_links.ttl())
+        {
+            Ok(message) => message,
+            Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+        };
+    // Every request issues a new link, so each gets its own email
+    if let Err(e) = state
+        .email_outbox
+        .enqueue(format!("magic-link:{}", Uuid::new_v4()), &email, message)
+        .await
+    {
+        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    }
+
+    (jar, Ok(Json(link_sent())))
+}
+
+// Signs in with an emailed link, continuing with the 2FA code when the user requires it
+#[instrument(skip_all)]
+pub async fn magic_link_consume_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    jar: CookieJar,
+    device: LoginDevice,
+    Query(query): Query<MagicLinkConsumeQuery>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>)
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let nonce_cookie_name = state.cookie_policy.magic_link_nonce_cookie_name();
+    let nonce = jar
+        .get(&nonce_cookie_name)
+        .map(|cookie| cookie.value().to_owned());
+
+    let email = match state
+        .magic_links
+        .consume(&query.token, nonce.as_deref())
+        .await
+    {
+        Ok(email) => email,
+        Err(MagicLinkError::UnexpectedError(e)) => {
+            return (jar, Err(AuthAPIError::UnexpectedError(e)))
+        }
+        Err(e) => {
+            warn!(error = ?e, "Rejected magic link");
+            return (jar, Err(AuthAPIError::InvalidMagicLink));
+        }
+    };
+    // The nonce is spent together with the link
+    let jar = jar.remove(state.cookie_policy.removal_cookie(nonce_cookie_name));
+
+    let user = match state.user_store.read().await.get(&email).await {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => return (jar, Err(AuthAPIError::InvalidMagicLink)),
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+    match user.requires_2fa {
+        true => handle_2fa(&user, &state, jar).await,
+        false => handle_no_2fa(&user.email, false, None, &device, &state, jar).await,
+    }
+}
+
+fn link_sent() -> MagicLinkResponse {
+    MagicLinkResponse {
+        message: "If an account exists for this email, a sign-in link was sent".to_owned(),
+    }
+}
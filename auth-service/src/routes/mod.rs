@@ -1,12 +1,30 @@
+mod csrf_token;
+mod dev_mailbox;
 mod login;
+mod login_page;
+mod login_report;
 mod logout;
+mod magic_link;
+mod oidc;
+mod passkeys;
+mod phone_number;
+mod saml;
 mod signup;
 mod verify_2fa;
 mod verify_token;
 
 // re-export items from sub-modules
+pub use csrf_token::*;
+pub use dev_mailbox::*;
 pub use login::*;
+pub use login_page::*;
+pub use login_report::*;
 pub use logout::*;
+pub use magic_link::*;
+pub use oidc::*;
+pub use passkeys::*;
+pub use phone_number::*;
+pub use saml::*;
 pub use signup::*;
 pub use verify_2fa::*;
 pub use verify_token::*;
@@ -0,0 +1,137 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, EmailClient},
+    routes::login::{handle_2fa, handle_no_2fa},
+    services::{BannedTokenStore, TwoFACodeStore, UserStore, UserStoreError},
+    utils::{login_alerts::LoginDevice, oidc::OidcError},
+};
+
+// Providers redirect back with either a code or an error
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+}
+
+// Redirects the browser to log in at the provider
+#[instrument(skip_all)]
+pub async fn oidc_login_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    jar: CookieJar,
+    Path(provider): Path<String>,
+) -> Result<impl IntoResponse, AuthAPIError>
+where
+    T: UserStore,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let authorization = state
+        .oidc
+        .start_login(&provider)
+        .await
+        .map_err(oidc_error)?;
+    let jar = jar.add(
+        state
+            .cookie_policy
+            .oidc_state_cookie(authorization.state, state.oidc.state_ttl().as_secs()),
+    );
+    Ok((jar, Redirect::to(&authorization.url)))
+}
+
+// Signs in the user the provider vouched for. The provider's account is linked to the user with
+// the same email on the first login, if the provider verified that email. Users who require 2FA
+// continue with their 2FA code, as after a password login.
+#[instrument(skip_all)]
+pub async fn oidc_callback_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    jar: CookieJar,
+    device: LoginDevice,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcCallbackQuery>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>)
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    // The login has to come back to the browser it started in, so an attacker cannot sign a
+    // victim into the attacker's account with a callback URL of their own
+    let state_cookie_name = state.cookie_policy.oidc_state_cookie_name();
+    let bound_to_browser = jar
+        .get(&state_cookie_name)
+        .is_some_and(|cookie| cookie.value() == query.state);
+    let jar = jar.remove(state.cookie_policy.removal_cookie(state_cookie_name));
+    if !bound_to_browser {
+        warn!("OIDC callback without the state cookie of its login");
+        return (jar, Err(AuthAPIError::InvalidOidcLogin));
+    }
+    let code = match (query.code, query.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            warn!(error = ?error, "Provider did not authorize the login");
+            return (jar, Err(AuthAPIError::InvalidOidcLogin));
+        }
+    };
+
+    let identity = match state
+        .oidc
+        .finish_login(&provider, &query.state, &code)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(e) => return (jar, Err(oidc_error(e))),
+    };
+
+    let email = match state.oidc.linked_user(&identity).await {
+        Ok(Some(email)) => email,
+        Ok(None) => match identity.verified_email.clone() {
+            Some(email) => email,
+            None => {
+                warn!("Provider did not verify the email of an unlinked account");
+                return (jar, Err(AuthAPIError::InvalidOidcLogin));
+            }
+        },
+        Err(e) => return (jar, Err(oidc_error(e))),
+    };
+
+    let user = match state.user_store.read().await.get(&email).await {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => {
+            warn!("No user to link the provider's account to");
+            return (jar, Err(AuthAPIError::InvalidOidcLogin));
+        }
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+    if let Err(e) = state.oidc.link(&identity, &user.email).await {
+        return (jar, Err(oidc_error(e)));
+    }
+
+    match user.requires_2fa {
+        true => handle_2fa(&user, &state, jar).await,
+        false => handle_no_2fa(&user.email, false, None, &device, &state, jar).await,
+    }
+}
+
+fn oidc_error(e: OidcError) -> AuthAPIError {
+    match e {
+        OidcError::UnknownProvider => AuthAPIError::UnknownOidcProvider,
+        OidcError::ProviderError(e) | OidcError::UnexpectedError(e) => {
+            AuthAPIError::UnexpectedError(e)
+        }
+        e => {
+            warn!(error = ?e, "Rejected OIDC login");
+            AuthAPIError::InvalidOidcLogin
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::{
+    app_state::AppState,
+    domain::{models::Email, AuthAPIError, EmailClient},
+    routes::login::{handle_no_2fa, verify_login_proof},
+    services::{BannedTokenStore, LoginAttemptId, TwoFACodeStore, UserStore},
+    utils::{
+        auth::{validate_token, AuthToken},
+        dpop::DpopRequest,
+        login_alerts::LoginDevice,
+        webauthn::{
+            AuthenticationCredential, PasskeyAuthenticationOptions, PasskeyError,
+            PasskeyRegistrationOptions, RegistrationCredential,
+        },
+    },
+};
+
+#[derive(Deserialize)]
+pub struct PasskeyRegistrationRequest {
+    #[serde(rename = "challengeId")]
+    pub challenge_id: String,
+    pub credential: RegistrationCredential,
+}
+
+// Both are given to use a passkey in place of the emailed 2FA code of a login attempt, and
+// neither for a passwordless login
+#[derive(Deserialize)]
+pub struct PasskeyLoginStartRequest {
+    pub email: Option<String>,
+    #[serde(rename = "loginAttemptId")]
+    pub login_attempt_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLoginRequest {
+    #[serde(rename = "challengeId")]
+    pub challenge_id: String,
+    pub credential: AuthenticationCredential,
+    #[serde(default, rename = "includeToken")]
+    pub include_token: bool,
+}
+
+// Starts registering a passkey for the signed in user
+#[instrument(skip_all)]
+pub async fn passkey_registration_start_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    auth_token: AuthToken,
+) -> Result<Json<PasskeyRegistrationOptions>, AuthAPIError>
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore + Send + Sync,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let email = signed_in_user(&state, auth_token).await?;
+    let options = state
+        .webauthn
+        .start_registration(&email)
+        .await
+        .map_err(passkey_error)?;
+    Ok(Json(options))
+}
+
+#[instrument(skip_all)]
+pub async fn passkey_registration_finish_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    auth_token: AuthToken,
+    Json(request): Json<PasskeyRegistrationRequest>,
+) -> Result<impl IntoResponse, AuthAPIError>
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore + Send + Sync,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let email = signed_in_user(&state, auth_token).await?;
+    state
+        .webauthn
+        .finish_registration(&email, &request.challenge_id, &request.credential)
+        .await
+        .map_err(passkey_error)?;
+    Ok(StatusCode::CREATED)
+}
+
+#[instrument(skip_all)]
+pub async fn passkey_login_start_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    Json(request): Json<PasskeyLoginStartRequest>,
+) -> Result<Json<PasskeyAuthenticationOptions>, AuthAPIError>
+where
+    T: UserStore,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let options = match (request.email, request.login_attempt_id) {
+        (Some(email), Some(login_attempt_id)) => {
+            let (email, login_attempt_id) = match (
+                Email::new(email.into()),
+                LoginAttemptId::new(login_attempt_id),
+            ) {
+                (Ok(email), Ok(login_attempt_id)) => (email, login_attempt_id),
+                _ => return Err(AuthAPIError::InvalidCredentials),
+            };
+            // The user's passkeys are only listed to whoever got past the password
+            check_login_attempt(&state, &email, &login_attempt_id).await?;
+            state
+                .webauthn
+                .start_authentication(Some((&email, &login_attempt_id)))
+                .await
+        }
+        (None, None) => state.webauthn.start_authentication(None).await,
+        _ => return Err(AuthAPIError::InvalidCredentials),
+    };
+    Ok(Json(options.map_err(passkey_error)?))
+}
+
+// Signs the user in with a passkey, either on its own or as the second factor of a login attempt
+#[instrument(skip_all)]
+pub async fn passkey_login_finish_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    jar: CookieJar,
+    dpop: Option<DpopRequest>,
+    device: LoginDevice,
+    Json(request): Json<PasskeyLoginRequest>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>)
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let dpop = match verify_login_proof(&state.dpop_verifier, dpop).await {
+        Ok(dpop) => dpop,
+        Err(e) => return (jar, Err(e)),
+    };
+
+    let authentication = match state
+        .webauthn
+        .finish_authentication(&request.challenge_id, &request.credential)
+        .await
+    {
+        Ok(authentication) => authentication,
+        Err(e) => return (jar, Err(passkey_error(e))),
+    };
+    let email = authentication.email;
+
+    if let Some(login_attempt_id) = authentication.login_attempt_id {
+        if let Err(e) = check_login_attempt(&state, &email, &login_attempt_id).await {
+            return (jar, Err(e));
+        }
+        // The emailed code must not be usable once the login attempt is complete
+        if let Err(e) = state
+            .two_fa_code_store
+            .write()
+            .await
+            .remove_code(&email)
+            .await
+        {
+            return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+        }
+    }
+
+    handle_no_2fa(&email, request.include_token, dpop, &device, &state, jar).await
+}
+
+pub(crate) async fn signed_in_user<T, U, V, W>(
+    state: &AppState<T, U, V, W>,
+    AuthToken { token, dpop, .. }: AuthToken,
+) -> Result<Email, AuthAPIError>
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore + Send + Sync,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let claims = validate_token(
+        &token,
+        &*state.banned_token_store.read().await,
+        &state.dpop_verifier,
+        dpop.as_ref(),
+    )
+    .await
+    .map_err(|_| AuthAPIError::InvalidToken)?;
+    let email = Email::new(claims.sub.into()).map_err(|_| AuthAPIError::InvalidToken)?;
+    state
+        .user_store
+        .read()
+        .await
+        .get(&email)
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+    Ok(email)
+}
+
+async fn check_login_attempt<T, U, V, W>(
+    state: &AppState<T, U, V, W>,
+    email: &Email,
+    login_attempt_id: &LoginAttemptId,
+) -> Result<(), AuthAPIError>
+where
+    T: UserStore,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    match state.two_fa_code_store.read().await.get_code(email).await {
+        Ok((attempt, _)) if attempt == *login_attempt_id => Ok(()),
+        _ => Err(AuthAPIError::IncorrectCredentials),
+    }
+}
+
+fn passkey_error(e: PasskeyError) -> AuthAPIError {
+    match e {
+        PasskeyError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+        e => {
+            warn!(error = ?e, "Rejected passkey");
+            AuthAPIError::InvalidPasskey
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, EmailClient, PhoneNumber, TwoFAChannel},
+    routes::passkeys::signed_in_user,
+    services::{BannedTokenStore, TwoFACodeStore, UserStore},
+    utils::{
+        auth::AuthToken,
+        phone_verification::{send_code, PhoneVerificationError},
+    },
+};
+
+// The channels a phone number can be added for
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PhoneChannel {
+    #[default]
+    Sms,
+    Voice,
+}
+
+impl From<PhoneChannel> for TwoFAChannel {
+    fn from(channel: PhoneChannel) -> Self {
+        match channel {
+            PhoneChannel::Sms => Self::Sms,
+            PhoneChannel::Voice => Self::Voice,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PhoneNumberRequest {
+    #[serde(rename = "phoneNumber")]
+    pub phone_number: String,
+    #[serde(default)]
+    pub channel: PhoneChannel,
+}
+
+#[derive(Deserialize)]
+pub struct PhoneNumberVerifyRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PhoneNumberResponse {
+    // E.164 form of the number
+    #[serde(rename = "phoneNumber")]
+    pub phone_number: String,
+    pub channel: String,
+}
+
+// Sends a code to the number the signed in user wants their 2FA codes sent to, over the channel
+// they chose. The number is only saved once the code comes back.
+#[instrument(skip_all)]
+pub async fn phone_number_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    auth_token: AuthToken,
+    Json(request): Json<PhoneNumberRequest>,
+) -> Result<(StatusCode, Json<PhoneNumberResponse>), AuthAPIError>
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore + Send + Sync,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    if !state.sms_client.is_enabled() {
+        return Err(AuthAPIError::SmsNotConfigured);
+    }
+    let email = signed_in_user(&state, auth_token).await?;
+    let phone_number =
+        PhoneNumber::parse(&request.phone_number).map_err(|_| AuthAPIError::InvalidPhoneNumber)?;
+    let channel = request.channel.into();
+
+    let code = state
+        .phone_verification
+        .start(&email, &phone_number, channel)
+        .await
+        .map_err(phone_verification_error)?;
+    let locale = state
+        .user_store
+        .read()
+        .await
+        .get(&email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?
+        .locale;
+    send_code(
+        &*state.sms_client,
+        &state.email_templates,
+        locale,
+        &phone_number,
+        channel,
+        &code,
+    )
+    .await
+    .map_err(AuthAPIError::UnexpectedError)?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(PhoneNumberResponse {
+            phone_number: phone_number.to_string(),
+            channel: channel.as_str().to_owned(),
+        }),
+    ))
+}
+
+// Saves the number the code was sent to, and sends 2FA codes there from now on
+#[instrument(skip_all)]
+pub async fn phone_number_verify_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    auth_token: AuthToken,
+    Json(request): Json<PhoneNumberVerifyRequest>,
+) -> Result<Json<PhoneNumberResponse>, AuthAPIError>
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore + Send + Sync,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let email = signed_in_user(&state, auth_token).await?;
+    let (phone_number, channel) = state
+        .phone_verification
+        .finish(&email, &request.code)
+        .await
+        .map_err(phone_verification_error)?;
+
+    state
+        .user_store
+        .write()
+        .await
+        .set_phone_number(&email, phone_number.clone(), channel)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(Json(PhoneNumberResponse {
+        phone_number: phone_number.to_string(),
+        channel: channel.as_str().to_owned(),
+    }))
+}
+
+fn phone_verification_error(e: PhoneVerificationError) -> AuthAPIError {
+    match e {
+        PhoneVerificationError::InvalidCode => AuthAPIError::IncorrectCredentials,
+        PhoneVerificationError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+    }
+}
@@ -0,0 +1,118 @@
+use axum::{
+    extract::{Form, State},
+    http::header,
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, EmailClient},
+    routes::login::{handle_2fa, handle_no_2fa},
+    services::{BannedTokenStore, TwoFACodeStore, UserStore, UserStoreError},
+    utils::{login_alerts::LoginDevice, saml::SamlError},
+};
+
+// The identity provider posts its response with the HTTP-POST binding
+#[derive(Deserialize)]
+pub struct SamlAcsForm {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+}
+
+#[instrument(skip_all)]
+pub async fn saml_metadata_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+) -> Result<impl IntoResponse, AuthAPIError>
+where
+    T: UserStore,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let metadata = state.saml.metadata().map_err(saml_error)?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+        metadata,
+    ))
+}
+
+// Redirects the browser to log in at the identity provider, binding the request to the browser
+#[instrument(skip_all)]
+pub async fn saml_login_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, AuthAPIError>
+where
+    T: UserStore,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let login = state.saml.start_login().await.map_err(saml_error)?;
+    let jar = jar.add(
+        state
+            .cookie_policy
+            .saml_request_cookie(login.request_id, state.saml.request_ttl().as_secs()),
+    );
+    Ok((jar, Redirect::to(&login.url)))
+}
+
+// Signs in the user whose email is the NameID of the assertion. Users who require 2FA continue
+// with their 2FA code, as after a password login.
+#[instrument(skip_all)]
+pub async fn saml_acs_handler<T, U, V, W>(
+    State(state): State<AppState<T, U, V, W>>,
+    jar: CookieJar,
+    device: LoginDevice,
+    Form(form): Form<SamlAcsForm>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>)
+where
+    T: UserStore + Send + Sync,
+    U: BannedTokenStore,
+    V: TwoFACodeStore,
+    W: EmailClient,
+{
+    let request_cookie_name = state.cookie_policy.saml_request_cookie_name();
+    let request_id = jar
+        .get(&request_cookie_name)
+        .map(|cookie| cookie.value().to_owned());
+
+    let email = match state
+        .saml
+        .finish_login(&form.saml_response, request_id.as_deref())
+        .await
+    {
+        Ok(email) => email,
+        Err(e) => return (jar, Err(saml_error(e))),
+    };
+    // The request is spent together with its cookie
+    let jar = jar.remove(state.cookie_policy.removal_cookie(request_cookie_name));
+
+    let user = match state.user_store.read().await.get(&email).await {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => {
+            warn!("No user for the asserted NameID");
+            return (jar, Err(AuthAPIError::InvalidSamlLogin));
+        }
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+
+    match user.requires_2fa {
+        true => handle_2fa(&user, &state, jar).await,
+        false => handle_no_2fa(&user.email, false, None, &device, &state, jar).await,
+    }
+}
+
+fn saml_error(e: SamlError) -> AuthAPIError {
+    match e {
+        SamlError::NotConfigured => AuthAPIError::SamlNotConfigured,
+        SamlError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+        e => {
+            warn!(error = ?e, "Rejected SAML login");
+            AuthAPIError::InvalidSamlLogin
+        }
+    }
+}
@@ -11,9 +11,10 @@ use crate::{
     app_state::AppState,
     domain::{
         models::{Email, Password},
-        AuthAPIError, EmailClient, User,
+        AuthAPIError, EmailClient, Locale, User,
     },
     services::{BannedTokenStore, TwoFACodeStore, UserStore, UserStoreError},
+    utils::password::validate_new_password,
 };
 
 #[tracing::instrument(name = "Signup", skip_all)]
@@ -27,15 +28,21 @@ where
     V: TwoFACodeStore,
     W: EmailClient,
 {
-    let email = request.email;
-    let password = request.password;
+    let email = Email::new(request.email).map_err(|_| AuthAPIError::InvalidCredentials)?;
 
-    let (email, password) = match (Email::new(email), Password::new(password)) {
-        (Ok(email), Ok(password)) => (email, password),
-        _ => return Err(AuthAPIError::InvalidCredentials),
-    };
+    validate_new_password(
+        &request.password,
+        &email,
+        &app_state.password_policy,
+        &*app_state.breached_password_checker,
+    )
+    .await?;
 
-    let user = User::new(email, password, request.requires_2fa);
+    let password = Password::new(request.password).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    // Unsupported languages get the default one rather than failing the signup
+    let locale = request.locale.as_deref().and_then(Locale::negotiate);
+    let user = User::new(email, password, request.requires_2fa).with_locale(locale);
 
     let mut user_store = app_state.user_store.write().await;
 
@@ -67,4 +74,6 @@ pub struct SignupRequest {
     pub password: SecretString,
     #[serde(rename = "requires2FA")]
     pub requires_2fa: bool,
+    // BCP 47 language tag for the user's emails, e.g. `de-AT`
+    pub locale: Option<String>,
 }
@@ -1,21 +1,28 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use axum_extra::extract::CookieJar;
-use color_eyre::eyre::Context;
 use color_eyre::eyre::Result;
 use secrecy::SecretString;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
 use crate::{
     app_state::AppState,
     domain::{models::Email, AuthAPIError, EmailClient},
+    routes::{login::track_login, TokenResponse},
     services::{BannedTokenStore, LoginAttemptId, TwoFACode, TwoFACodeStore, UserStore},
-    utils::auth::generate_auth_cookie,
+    utils::{
+        auth::{generate_auth_cookie, generate_dpop_bound_token},
+        csrf::generate_csrf_cookie,
+        dpop::DpopRequest,
+        login_alerts::LoginDevice,
+    },
 };
 
 #[instrument(skip_all)]
 pub async fn verify_2fa_handler<T, U, V, W>(
     jar: CookieJar,
     State(state): State<AppState<T, U, V, W>>,
+    dpop: Option<DpopRequest>,
+    device: LoginDevice,
     Json(request): Json<Verify2FARequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>)
 where
@@ -42,14 +49,51 @@ where
                         return (jar, Err(AuthAPIError::IncorrectCredentials));
                     }
 
-                    match generate_auth_cookie(&email).map_err(AuthAPIError::UnexpectedError) {
+                    // DPoP clients get a token bound to their key instead of cookies
+                    if let Some(dpop) = dpop {
+                        let proof = match state.dpop_verifier.verify(&dpop, None).await {
+                            Ok(proof) => proof,
+                            Err(e) => {
+                                warn!(error = ?e, "Rejected DPoP proof");
+                                return (jar, Err(AuthAPIError::InvalidDpopProof));
+                            }
+                        };
+                        let token = match generate_dpop_bound_token(&email, &proof.jkt) {
+                            Ok(token) => token,
+                            Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+                        };
+                        track_login(&email, &token, &device, &state).await;
+                        let jar = jar.add(device.cookie(&state.cookie_policy));
+                        return match two_fa_code_store.remove_code(&email).await {
+                            Err(e) => (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+                            Ok(_) => (jar, Ok(Json(TokenResponse::dpop(token)).into_response())),
+                        };
+                    }
+
+                    let cookie_policy = &state.cookie_policy;
+                    let cookies =
+                        generate_auth_cookie(&email, cookie_policy).and_then(|auth_cookie| {
+                            let csrf_cookie =
+                                generate_csrf_cookie(auth_cookie.value(), cookie_policy)?;
+                            Ok((auth_cookie, csrf_cookie))
+                        });
+                    match cookies.map_err(AuthAPIError::UnexpectedError) {
                         Err(e) => return (jar, Err(e)),
-                        Ok(auth_cookie) => {
-                            let jar = jar.add(auth_cookie);
+                        Ok((auth_cookie, csrf_cookie)) => {
+                            track_login(&email, auth_cookie.value(), &device, &state).await;
+                            let response = match request.include_token {
+                                true => Json(TokenResponse::new(auth_cookie.value().to_owned()))
+                                    .into_response(),
+                                false => StatusCode::OK.into_response(),
+                            };
+                            let jar = jar
+                                .add(auth_cookie)
+                                .add(csrf_cookie)
+                                .add(device.cookie(cookie_policy));
 
                             return match two_fa_code_store.remove_code(&email).await {
                                 Err(e) => (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
-                                Ok(_) => (jar, Ok(StatusCode::OK.into_response())),
+                                Ok(_) => (jar, Ok(response)),
                             };
                         }
                     }
@@ -67,4 +111,6 @@ pub struct Verify2FARequest {
     pub login_attempt_id: String,
     #[serde(rename = "2FACode")]
     pub two_fa_code: String,
+    #[serde(default, rename = "includeToken")]
+    pub include_token: bool,
 }
@@ -1,6 +1,9 @@
-use std::ops::Deref;
-
-use axum::{extract::State, http, response::IntoResponse, Json};
+use axum::{
+    extract::{Query, State},
+    http,
+    response::IntoResponse,
+    Json,
+};
 use serde_json::json;
 use tracing::instrument;
 
@@ -8,18 +11,37 @@ use crate::{
     app_state::AppState,
     domain::EmailClient,
     services::{BannedTokenStore, TwoFACodeStore, UserStore},
-    utils::auth::validate_token,
+    utils::{
+        auth::{validate_token_for_audience, AuthToken},
+        constants::JWT_AUDIENCE,
+        dpop::DpopRequest,
+    },
 };
 
 #[derive(serde::Deserialize)]
 pub struct VerifyTokenRequest {
     pub token: String,
+    // The service the token is presented to. Tokens issued for other services are rejected.
+    pub audience: Option<String>,
+    // The DPoP proof the token was presented with, and the request it was presented in
+    pub dpop: Option<DpopRequest>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct VerifyTokenParams {
+    pub audience: Option<String>,
 }
 
+// Verifies the token in the JSON body, which is how other services check a user's token.
+// Without a body, the caller's own token from the Authorization header or cookie is verified.
+// The expected audience can be given in the body or as a query parameter, the body taking
+// precedence.
 #[instrument(skip_all)]
 pub async fn verify_token_handler<T, U, V, W>(
     State(app_state): State<AppState<T, U, V, W>>,
-    Json(payload): Json<VerifyTokenRequest>,
+    Query(params): Query<VerifyTokenParams>,
+    auth_token: Option<AuthToken>,
+    payload: Option<Json<VerifyTokenRequest>>,
 ) -> impl IntoResponse
 where
     T: UserStore,
@@ -27,7 +49,20 @@ where
     V: TwoFACodeStore,
     W: EmailClient,
 {
-    let token = payload.token;
+    let (token, audience, dpop) = match (payload, auth_token) {
+        (Some(Json(payload)), _) => (
+            payload.token,
+            payload.audience.or(params.audience),
+            payload.dpop,
+        ),
+        (None, Some(auth_token)) => (auth_token.token, params.audience, auth_token.dpop),
+        (None, None) => {
+            return (
+                http::StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Missing token"})),
+            )
+        }
+    };
     if token.trim().is_empty() {
         return (
             http::StatusCode::UNPROCESSABLE_ENTITY,
@@ -35,9 +70,19 @@ where
         );
     }
 
-    if validate_token(&token, &*app_state.banned_token_store.read().await)
-        .await
-        .is_ok()
+    let audience = match audience {
+        Some(audience) => vec![audience],
+        None => JWT_AUDIENCE.clone(),
+    };
+    if validate_token_for_audience(
+        &token,
+        &*app_state.banned_token_store.read().await,
+        &app_state.dpop_verifier,
+        dpop.as_ref(),
+        &audience,
+    )
+    .await
+    .is_ok()
     {
         (
             http::StatusCode::OK,
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use reqwest::Client;
+use secrecy::SecretString;
+use tracing::instrument;
+
+use super::{
+    match_range_line, password_sha1, BreachedPasswordChecker, BreachedPasswordCheckerError,
+    HASH_PREFIX_LENGTH,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Queries a Pwned Passwords compatible `/range/{prefix}` endpoint. Only the first five characters
+// of the password hash leave the process (k-anonymity), so the service never learns the password.
+#[derive(Clone)]
+pub struct HttpRangeBreachedPasswordChecker {
+    base_url: String,
+    http_client: Client,
+}
+
+impl HttpRangeBreachedPasswordChecker {
+    pub fn new(base_url: String) -> Result<Self> {
+        let http_client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            http_client,
+        })
+    }
+}
+
+impl BreachedPasswordChecker for HttpRangeBreachedPasswordChecker {
+    #[instrument(name = "Checking password against breach range API", skip_all)]
+    async fn breach_count(
+        &self,
+        password: &SecretString,
+    ) -> Result<u64, BreachedPasswordCheckerError> {
+        let hash = password_sha1(password);
+        let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+
+        let body = self
+            .http_client
+            .get(format!("{}/range/{}", self.base_url, prefix))
+            // Padding hides the real number of suffixes in the range from network observers
+            .header("Add-Padding", "true")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| BreachedPasswordCheckerError::UnexpectedError(eyre!(e)))?
+            .text()
+            .await
+            .map_err(|e| BreachedPasswordCheckerError::UnexpectedError(eyre!(e)))?;
+
+        Ok(body
+            .lines()
+            .find_map(|line| match_range_line(line, suffix))
+            .unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reports_breached_password() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/range/5BAA6"))
+            .and(header("Add-Padding", "true"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:10434004\r\n",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let checker = HttpRangeBreachedPasswordChecker::new(server.uri()).unwrap();
+        assert_eq!(
+            checker.breach_count(&"password".into()).await.unwrap(),
+            10434004
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ignores_padding_entries() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/range/5BAA6"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("1E4C9B93F3F0682250B6CF8331B7EE68FD8:0\r\n"),
+            )
+            .mount(&server)
+            .await;
+
+        let checker = HttpRangeBreachedPasswordChecker::new(server.uri()).unwrap();
+        assert_eq!(checker.breach_count(&"password".into()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_returns_error_on_server_failure() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let checker = HttpRangeBreachedPasswordChecker::new(server.uri()).unwrap();
+        assert!(checker.breach_count(&"password".into()).await.is_err());
+    }
+}
@@ -0,0 +1,104 @@
+pub mod http_range_checker;
+pub mod range_file_checker;
+
+use std::future::Future;
+
+use color_eyre::eyre::Report;
+use secrecy::{ExposeSecret, SecretString};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+pub use http_range_checker::HttpRangeBreachedPasswordChecker;
+pub use range_file_checker::RangeFileBreachedPasswordChecker;
+
+// Pwned Passwords splits SHA-1 hashes into a 5 character prefix and a 35 character suffix
+pub const HASH_PREFIX_LENGTH: usize = 5;
+
+// This trait represents the interface all breached password lookups should implement
+pub trait BreachedPasswordChecker {
+    // Returns how many times the password appears in the breach corpus, 0 if it was never seen
+    fn breach_count(
+        &self,
+        password: &SecretString,
+    ) -> impl Future<Output = Result<u64, BreachedPasswordCheckerError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum BreachedPasswordCheckerError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// Selects the checker implementation at startup, so `main.rs` can pick one from configuration
+#[derive(Clone, Default)]
+pub enum BreachedPasswordCheckerBackend {
+    RangeFile(RangeFileBreachedPasswordChecker),
+    HttpRange(HttpRangeBreachedPasswordChecker),
+    #[default]
+    Disabled,
+}
+
+impl BreachedPasswordChecker for BreachedPasswordCheckerBackend {
+    async fn breach_count(
+        &self,
+        password: &SecretString,
+    ) -> Result<u64, BreachedPasswordCheckerError> {
+        match self {
+            Self::RangeFile(checker) => checker.breach_count(password).await,
+            Self::HttpRange(checker) => checker.breach_count(password).await,
+            Self::Disabled => Ok(0),
+        }
+    }
+}
+
+// Uppercase hex SHA-1 of the password, as used by the Pwned Passwords range format
+pub fn password_sha1(password: &SecretString) -> String {
+    hex::encode_upper(Sha1::digest(password.expose_secret().as_bytes()))
+}
+
+// Parses one `SUFFIX:COUNT` line, returning the count if the suffix matches
+fn match_range_line(line: &str, suffix: &str) -> Option<u64> {
+    let (line_suffix, count) = line.trim().split_once(':')?;
+    if line_suffix.eq_ignore_ascii_case(suffix) {
+        count.trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_sha1() {
+        assert_eq!(
+            password_sha1(&"password".into()),
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8"
+        );
+    }
+
+    #[test]
+    fn test_match_range_line() {
+        assert_eq!(
+            match_range_line(
+                "1E4C9B93F3F0682250B6CF8331B7EE68FD8:42\r",
+                "1e4c9b93f3f0682250b6cf8331b7ee68fd8"
+            ),
+            Some(42)
+        );
+        assert_eq!(
+            match_range_line(
+                "1E4C9B93F3F0682250B6CF8331B7EE68FD9:42",
+                "1E4C9B93F3F0682250B6CF8331B7EE68FD8"
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disabled_backend_never_reports_breach() {
+        let checker = BreachedPasswordCheckerBackend::Disabled;
+        assert_eq!(checker.breach_count(&"password".into()).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    cmp::Ordering,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use memmap2::Mmap;
+use secrecy::SecretString;
+use tracing::instrument;
+
+use super::{
+    match_range_line, password_sha1, BreachedPasswordChecker, BreachedPasswordCheckerError,
+    HASH_PREFIX_LENGTH,
+};
+
+// Length of an uppercase hex SHA-1 hash
+const HASH_LENGTH: usize = 40;
+
+// Looks passwords up in a local copy of the Pwned Passwords corpus, without any network calls.
+//
+// Two layouts produced by the official downloader are supported:
+// - a single file of `HASH:COUNT` lines sorted by hash, which is memory-mapped and binary searched
+// - a directory of `<PREFIX>.txt` files holding the `SUFFIX:COUNT` lines of each range
+#[derive(Clone)]
+pub struct RangeFileBreachedPasswordChecker {
+    source: RangeSource,
+}
+
+#[derive(Clone)]
+enum RangeSource {
+    SortedFile(Arc<Mmap>),
+    Directory(PathBuf),
+}
+
+impl RangeFileBreachedPasswordChecker {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = if path.is_dir() {
+            RangeSource::Directory(path.to_path_buf())
+        } else {
+            let file = File::open(path)
+                .wrap_err_with(|| format!("Failed to open breached passwords file {:?}", path))?;
+            // SAFETY: the file is opened read-only and is expected to stay unchanged while the
+            // service runs; replacing it requires a restart.
+            let mmap = unsafe { Mmap::map(&file) }
+                .wrap_err_with(|| format!("Failed to map breached passwords file {:?}", path))?;
+            RangeSource::SortedFile(Arc::new(mmap))
+        };
+
+        Ok(Self { source })
+    }
+}
+
+impl BreachedPasswordChecker for RangeFileBreachedPasswordChecker {
+    #[instrument(name = "Checking password against breach file", skip_all)]
+    async fn breach_count(
+        &self,
+        password: &SecretString,
+    ) -> Result<u64, BreachedPasswordCheckerError> {
+        let hash = password_sha1(password);
+
+        match &self.source {
+            RangeSource::SortedFile(mmap) => Ok(search_sorted(mmap, hash.as_bytes()).unwrap_or(0)),
+            RangeSource::Directory(dir) => {
+                let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+                let path = dir.join(format!("{}.txt", prefix));
+                let contents = match tokio::fs::read_to_string(&path).await {
+                    Ok(contents) => contents,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+                    Err(e) => {
+                        return Err(BreachedPasswordCheckerError::UnexpectedError(
+                            eyre!(e).wrap_err(format!("Failed to read range file {:?}", path)),
+                        ))
+                    }
+                };
+
+                Ok(contents
+                    .lines()
+                    .find_map(|line| match_range_line(line, suffix))
+                    .unwrap_or(0))
+            }
+        }
+    }
+}
+
+// Binary search over the byte offsets of a file of sorted, newline separated `HASH:COUNT` lines
+fn search_sorted(data: &[u8], hash: &[u8]) -> Option<u64> {
+    let (mut low, mut high) = (0, data.len());
+
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let start = data[low..middle]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(low, |i| low + i + 1);
+        let end = data[start..high]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(high, |i| start + i);
+
+        let line = &data[start..end];
+        let line_hash = &line[..line.len().min(HASH_LENGTH)];
+
+        match line_hash.to_ascii_uppercase().as_slice().cmp(hash) {
+            Ordering::Equal => {
+                let count = std::str::from_utf8(&line[line_hash.len()..]).ok()?;
+                return count.trim().trim_start_matches(':').parse().ok();
+            }
+            Ordering::Less => low = end + 1,
+            Ordering::Greater => high = start,
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SORTED_FILE: &str = "\
+0000000A0E3B9F25FF41DE4B5AC238C2D545C7A8:15\r
+5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:10434004\r
+7C4A8D09CA3762AF61E59520943DC26494F8941B:37359195\r
+F7C3BC1D808E04732ADF679965CCC34CA7AE3441:2\r
+";
+
+    fn write_fixture(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", uuid::Uuid::new_v4(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_search_sorted() {
+        let data = SORTED_FILE.as_bytes();
+        for (hash, count) in [
+            ("0000000A0E3B9F25FF41DE4B5AC238C2D545C7A8", 15),
+            ("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8", 10434004),
+            ("7C4A8D09CA3762AF61E59520943DC26494F8941B", 37359195),
+            ("F7C3BC1D808E04732ADF679965CCC34CA7AE3441", 2),
+        ] {
+            assert_eq!(
+                search_sorted(data, hash.as_bytes()),
+                Some(count),
+                "{}",
+                hash
+            );
+        }
+        assert_eq!(
+            search_sorted(data, b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD9"),
+            None
+        );
+        assert_eq!(
+            search_sorted(b"", b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sorted_file_checker() {
+        let path = write_fixture("pwned.txt", SORTED_FILE);
+        let checker = RangeFileBreachedPasswordChecker::open(&path).unwrap();
+
+        assert_eq!(
+            checker.breach_count(&"password".into()).await.unwrap(),
+            10434004
+        );
+        assert_eq!(
+            checker
+                .breach_count(&"correct-Horse-battery-st4ple".into())
+                .await
+                .unwrap(),
+            0
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_directory_checker() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(
+            dir.join("5BAA6.txt"),
+            "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:10434004\r\n",
+        )
+        .unwrap();
+        let checker = RangeFileBreachedPasswordChecker::open(&dir).unwrap();
+
+        assert_eq!(
+            checker.breach_count(&"password".into()).await.unwrap(),
+            10434004
+        );
+        assert_eq!(checker.breach_count(&"123456".into()).await.unwrap(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_missing_file_fails() {
+        assert!(RangeFileBreachedPasswordChecker::open("/does/not/exist.txt").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use secrecy::SecretString;
+
+use crate::{
+    domain::{
+        models::{Email, Password},
+        PhoneNumber, TwoFAChannel, User,
+    },
+    services::{UserStore, UserStoreError},
+};
+
+// Looks users up in the primary store first, e.g. a corporate directory, and in the fallback
+// store after, e.g. the users who signed up. The primary store owns the emails it knows: their
+// passwords are never checked against the fallback store, and nobody can sign up with them.
+// Without a primary store this is just the fallback store.
+#[derive(Clone)]
+pub struct ChainedUserStore<P, F> {
+    primary: Option<P>,
+    fallback: F,
+}
+
+impl<P, F> ChainedUserStore<P, F> {
+    pub fn new(primary: Option<P>, fallback: F) -> Self {
+        Self { primary, fallback }
+    }
+}
+
+impl<P, F> UserStore for ChainedUserStore<P, F>
+where
+    P: UserStore + Send + Sync,
+    F: UserStore + Send + Sync,
+{
+    // New users always go to the fallback store
+    async fn insert(&mut self, value: User) -> Result<(), UserStoreError> {
+        if let Some(primary) = &self.primary {
+            match primary.get(&value.email).await {
+                Ok(_) => return Err(UserStoreError::UserAlreadyExists),
+                Err(UserStoreError::UserNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.fallback.insert(value).await
+    }
+
+    // The primary store failing fails the lookup, rather than risk answering for an account it owns
+    async fn get(&self, key: &Email) -> Result<User, UserStoreError> {
+        if let Some(primary) = &self.primary {
+            match primary.get(key).await {
+                Err(UserStoreError::UserNotFound) => {}
+                result => return result,
+            }
+        }
+        self.fallback.get(key).await
+    }
+
+    async fn validate(&self, key: &Email, value: &SecretString) -> Result<(), UserStoreError> {
+        if let Some(primary) = &self.primary {
+            match primary.validate(key, value).await {
+                Err(UserStoreError::UserNotFound) => {}
+                result => return result,
+            }
+        }
+        self.fallback.validate(key, value).await
+    }
+
+    async fn set_phone_number(
+        &mut self,
+        key: &Email,
+        phone_number: PhoneNumber,
+        channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError> {
+        if let Some(primary) = &mut self.primary {
+            match primary.get(key).await {
+                Ok(_) => return primary.set_phone_number(key, phone_number, channel).await,
+                Err(UserStoreError::UserNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.fallback
+            .set_phone_number(key, phone_number, channel)
+            .await
+    }
+
+    async fn set_password(
+        &mut self,
+        key: &Email,
+        password: Password,
+    ) -> Result<(), UserStoreError> {
+        if let Some(primary) = &mut self.primary {
+            match primary.get(key).await {
+                Ok(_) => return primary.set_password(key, password).await,
+                Err(UserStoreError::UserNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.fallback.set_password(key, password).await
+    }
+
+    async fn can_set_password(&self, key: &Email) -> Result<bool, UserStoreError> {
+        if let Some(primary) = &self.primary {
+            match primary.get(key).await {
+                Ok(_) => return primary.can_set_password(key).await,
+                Err(UserStoreError::UserNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.fallback.can_set_password(key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{domain::models::Password, services::data_stores::HashMapUserStore};
+
+    use super::*;
+
+    fn user(email: &str, password: &str, requires_2fa: bool) -> User {
+        User::new(
+            Email::new(email.to_owned().into()).unwrap(),
+            Password::new(password.to_owned().into()).unwrap(),
+            requires_2fa,
+        )
+    }
+
+    async fn store() -> ChainedUserStore<HashMapUserStore, HashMapUserStore> {
+        let mut directory = HashMapUserStore::default();
+        directory
+            .insert(user("staff@example.com", "directory-password", true))
+            .await
+            .unwrap();
+        let mut local = HashMapUserStore::default();
+        local
+            .insert(user("customer@example.com", "local-password", false))
+            .await
+            .unwrap();
+        ChainedUserStore::new(Some(directory), local)
+    }
+
+    #[tokio::test]
+    async fn test_finds_users_in_either_store() {
+        let store = store().await;
+
+        let staff = Email::new("staff@example.com".to_owned().into()).unwrap();
+        assert!(store.get(&staff).await.unwrap().requires_2fa);
+        assert!(store
+            .validate(&staff, &"directory-password".to_owned().into())
+            .await
+            .is_ok());
+
+        let customer = Email::new("customer@example.com".to_owned().into()).unwrap();
+        assert!(!store.get(&customer).await.unwrap().requires_2fa);
+        assert!(store
+            .validate(&customer, &"local-password".to_owned().into())
+            .await
+            .is_ok());
+
+        let stranger = Email::new("stranger@example.com".to_owned().into()).unwrap();
+        assert_eq!(
+            store.get(&stranger).await.err(),
+            Some(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_primary_store_owns_its_emails() {
+        let mut store = store().await;
+        let staff = user("staff@example.com", "local-password", false);
+
+        assert_eq!(
+            store.insert(staff.clone()).await,
+            Err(UserStoreError::UserAlreadyExists)
+        );
+        // Even if the fallback store had the email, only the primary password counts
+        store.fallback.insert(staff.clone()).await.unwrap();
+        assert_eq!(
+            store
+                .validate(&staff.email, &"local-password".to_owned().into())
+                .await,
+            Err(UserStoreError::InvalidCredentials)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inserts_into_fallback_store() {
+        let mut store = store().await;
+        let new_user = user("new@example.com", "new-password", false);
+
+        store.insert(new_user.clone()).await.unwrap();
+        assert!(store.fallback.get(&new_user.email).await.is_ok());
+        assert!(store
+            .primary
+            .as_ref()
+            .unwrap()
+            .get(&new_user.email)
+            .await
+            .is_err());
+    }
+}
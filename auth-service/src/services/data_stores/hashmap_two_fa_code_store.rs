@@ -1,11 +1,12 @@
-use std::{collections::HashMap, hash::Hash};
+use std::collections::HashMap;
 
 use crate::{
-    domain::models::Email,
+    domain::{models::Email, EmailMessage},
     services::{
         data_stores::{LoginAttemptId, TwoFACode},
         TwoFACodeStore, TwoFACodeStoreError,
     },
+    utils::email_outbox::EmailOutbox,
 };
 
 #[derive(Default, Clone)]
@@ -32,6 +33,22 @@ impl TwoFACodeStore for HashmapTwoFACodeStore {
         Ok(())
     }
 
+    async fn add_code_and_enqueue(
+        &mut self,
+        email: Email,
+        login_attempt_id: LoginAttemptId,
+        code: TwoFACode,
+        outbox: &EmailOutbox,
+        idempotency_key: String,
+        message: EmailMessage,
+    ) -> Result<(), TwoFACodeStoreError> {
+        self.add_code(email.clone(), login_attempt_id, code).await?;
+        outbox
+            .enqueue(idempotency_key, &email, message)
+            .await
+            .map_err(|e| TwoFACodeStoreError::UnexpectedError(e.into()))
+    }
+
     async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
         if self.codes.remove(email).is_some() {
             Ok(())
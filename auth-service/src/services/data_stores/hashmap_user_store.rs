@@ -3,7 +3,10 @@ use std::collections::HashMap;
 use secrecy::{ExposeSecret, SecretString};
 
 use crate::{
-    domain::{models::Email, User},
+    domain::{
+        models::{Email, Password},
+        PhoneNumber, TwoFAChannel, User,
+    },
     services::{UserStore, UserStoreError},
 };
 
@@ -41,6 +44,38 @@ impl UserStore for HashMapUserStore {
             Err(UserStoreError::UserNotFound)
         }
     }
+
+    async fn set_phone_number(
+        &mut self,
+        key: &Email,
+        phone_number: PhoneNumber,
+        channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError> {
+        let user = self
+            .users
+            .get_mut(key)
+            .ok_or(UserStoreError::UserNotFound)?;
+        user.phone_number = Some(phone_number);
+        user.two_fa_channel = channel;
+        Ok(())
+    }
+
+    async fn set_password(
+        &mut self,
+        key: &Email,
+        password: Password,
+    ) -> Result<(), UserStoreError> {
+        let user = self
+            .users
+            .get_mut(key)
+            .ok_or(UserStoreError::UserNotFound)?;
+        user.password = password;
+        Ok(())
+    }
+
+    async fn can_set_password(&self, _key: &Email) -> Result<bool, UserStoreError> {
+        Ok(true)
+    }
 }
 
 impl Default for HashMapUserStore {
@@ -59,8 +94,6 @@ impl HashMapUserStore {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::models::Password;
-
     use super::*;
 
     #[tokio::test]
@@ -113,4 +146,60 @@ mod tests {
             .await
             .is_err());
     }
+
+    #[tokio::test]
+    async fn test_set_phone_number() {
+        let mut store = HashMapUserStore::new();
+        let email = Email::new("test@example.com".into()).unwrap();
+        store
+            .insert(User::new(
+                email.clone(),
+                Password::new("password".into()).unwrap(),
+                true,
+            ))
+            .await
+            .unwrap();
+        let phone_number = PhoneNumber::parse("+4930123456").unwrap();
+
+        store
+            .set_phone_number(&email, phone_number.clone(), TwoFAChannel::Sms)
+            .await
+            .unwrap();
+
+        let user = store.get(&email).await.unwrap();
+        assert_eq!(user.phone_number, Some(phone_number.clone()));
+        assert_eq!(user.two_fa_channel, TwoFAChannel::Sms);
+        assert_eq!(
+            store
+                .set_phone_number(
+                    &Email::new("other@example.com".into()).unwrap(),
+                    phone_number,
+                    TwoFAChannel::Sms
+                )
+                .await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_password() {
+        let mut store = HashMapUserStore::new();
+        let email = Email::new("test@example.com".into()).unwrap();
+        store
+            .insert(User::new(
+                email.clone(),
+                Password::new("password".into()).unwrap(),
+                false,
+            ))
+            .await
+            .unwrap();
+
+        store
+            .set_password(&email, Password::new("new_password".into()).unwrap())
+            .await
+            .unwrap();
+
+        assert!(store.validate(&email, &"password".into()).await.is_err());
+        assert!(store.validate(&email, &"new_password".into()).await.is_ok());
+    }
 }
@@ -1,30 +1,47 @@
-use std::collections::HashSet;
-
-use crate::services::{
-    data_stores::{BannedTokenStore, BannedTokenStoreError},
-    UserStoreError,
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
 };
 
+use crate::services::data_stores::{BannedTokenStore, BannedTokenStoreError};
+
+// Keeps each banned `jti` until its ban expires. Expired entries are evicted whenever a token is
+// banned, so the store only ever holds tokens that are still within their lifetime.
 #[derive(Clone)]
 pub struct HashsetBannedTokenStore {
-    banned_tokens: HashSet<String>,
+    banned_tokens: HashMap<String, Instant>,
+}
+
+impl Default for HashsetBannedTokenStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HashsetBannedTokenStore {
     pub fn new() -> Self {
         Self {
-            banned_tokens: HashSet::new(),
+            banned_tokens: HashMap::new(),
         }
     }
+
+    fn evict_expired(&mut self, now: Instant) {
+        self.banned_tokens.retain(|_, expires_at| *expires_at > now);
+    }
 }
 impl BannedTokenStore for HashsetBannedTokenStore {
-    async fn ban_token(&mut self, token: &str) -> Result<(), BannedTokenStoreError> {
-        self.banned_tokens.insert(token.to_string());
+    async fn ban_token(&mut self, jti: &str, ttl: Duration) -> Result<(), BannedTokenStoreError> {
+        let now = Instant::now();
+        self.evict_expired(now);
+        self.banned_tokens.insert(jti.to_string(), now + ttl);
         Ok(())
     }
 
-    async fn is_token_banned(&self, token: &str) -> bool {
-        self.banned_tokens.contains(token)
+    async fn is_token_banned(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        Ok(self
+            .banned_tokens
+            .get(jti)
+            .is_some_and(|expires_at| *expires_at > Instant::now()))
     }
 }
 
@@ -34,19 +51,36 @@ mod tests {
 
     #[tokio::test]
     async fn test_ban_and_check_token() {
-        let mut store = HashsetBannedTokenStore {
-            banned_tokens: std::collections::HashSet::new(),
-        };
+        let mut store = HashsetBannedTokenStore::new();
 
-        let token = "sample_token";
+        let jti = "sample_jti";
 
         // Initially, the token should not be banned
-        assert_eq!(store.is_token_banned(token).await, false);
+        assert!(!store.is_token_banned(jti).await.unwrap());
 
         // Ban the token
-        store.ban_token(token).await.unwrap();
+        store.ban_token(jti, Duration::from_secs(60)).await.unwrap();
 
         // Now, the token should be banned
-        assert_eq!(store.is_token_banned(token).await, true);
+        assert!(store.is_token_banned(jti).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_bans_expire_and_are_evicted() {
+        let mut store = HashsetBannedTokenStore::new();
+
+        store
+            .ban_token("short_lived", Duration::from_millis(10))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!store.is_token_banned("short_lived").await.unwrap());
+
+        store
+            .ban_token("long_lived", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(!store.banned_tokens.contains_key("short_lived"));
+        assert!(store.is_token_banned("long_lived").await.unwrap());
     }
 }
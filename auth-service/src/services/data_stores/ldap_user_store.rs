@@ -0,0 +1,609 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Report};
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use secrecy::{ExposeSecret, SecretString};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        models::{Email, Password},
+        PhoneNumber, TwoFAChannel, User,
+    },
+    services::{UserStore, UserStoreError},
+    utils::constants::LDAP_CONFIG,
+};
+
+// LDAP result code of a bind with a wrong DN or password
+const INVALID_CREDENTIALS: u32 = 49;
+
+#[derive(Debug, Clone)]
+pub struct LdapConfig {
+    pub url: String,
+    pub base_dn: String,
+    // Service account users are looked up with; the lookup binds anonymously without one
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<SecretString>,
+    // `{email}` is replaced with the escaped email being looked up
+    pub user_filter: String,
+    pub email_attribute: String,
+    pub group_attribute: String,
+    // Only members of this group are users, if it is set
+    pub required_group: Option<String>,
+    // Members of any of these groups require 2FA
+    pub two_fa_groups: Vec<String>,
+    pub starttls: bool,
+    pub timeout: Duration,
+}
+
+struct DirectoryEntry {
+    dn: String,
+    email: String,
+    groups: Vec<String>,
+}
+
+// Users of a corporate directory, such as OpenLDAP or Active Directory. Passwords are checked by
+// binding as the user, so they never leave the directory, and the directory is never written to.
+#[derive(Debug, Clone)]
+pub struct LdapUserStore {
+    config: LdapConfig,
+}
+
+impl LdapUserStore {
+    pub fn new(config: LdapConfig) -> Self {
+        Self { config }
+    }
+
+    // The directory configured with `LDAP_URL`, if any
+    pub fn from_config() -> Option<Self> {
+        LDAP_CONFIG.clone().map(Self::new)
+    }
+
+    async fn connect(&self) -> Result<Ldap, UserStoreError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(self.config.timeout)
+            .set_starttls(self.config.starttls);
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+        ldap3::drive!(conn);
+        Ok(ldap)
+    }
+
+    // Binds as `dn`, returning whether the directory accepted the password
+    async fn bind(
+        &self,
+        ldap: &mut Ldap,
+        dn: &str,
+        password: &str,
+    ) -> Result<bool, UserStoreError> {
+        let result = ldap
+            .with_timeout(self.config.timeout)
+            .simple_bind(dn, password)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+        match result.rc {
+            0 => Ok(true),
+            INVALID_CREDENTIALS => Ok(false),
+            _ => Err(UserStoreError::UnexpectedError(eyre!(
+                "LDAP bind failed: {}",
+                result
+            ))),
+        }
+    }
+
+    // Looks the user up as the service account. Entries outside the required group are treated
+    // as unknown, so they fall through to other stores like any other stranger.
+    async fn find(&self, ldap: &mut Ldap, email: &Email) -> Result<DirectoryEntry, UserStoreError> {
+        if let (Some(dn), Some(password)) = (&self.config.bind_dn, &self.config.bind_password) {
+            if !self.bind(ldap, dn, password.expose_secret()).await? {
+                return Err(UserStoreError::UnexpectedError(eyre!(
+                    "LDAP service account was rejected"
+                )));
+            }
+        }
+
+        let filter = self
+            .config
+            .user_filter
+            .replace("{email}", &ldap_escape(email.as_ref().expose_secret()));
+        let attributes = [
+            self.config.email_attribute.as_str(),
+            self.config.group_attribute.as_str(),
+        ];
+        let (entries, _) = ldap
+            .with_timeout(self.config.timeout)
+            .search(&self.config.base_dn, Scope::Subtree, &filter, attributes)
+            .await
+            .and_then(|result| result.success())
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        let mut entries = entries.into_iter().map(SearchEntry::construct);
+        let entry = match (entries.next(), entries.next()) {
+            (Some(entry), None) => entry,
+            (None, _) => return Err(UserStoreError::UserNotFound),
+            (Some(_), Some(_)) => {
+                return Err(UserStoreError::UnexpectedError(eyre!(
+                    "More than one directory entry matches the email"
+                )))
+            }
+        };
+
+        let attribute = |name: &str| {
+            entry
+                .attrs
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, values)| values.clone())
+                .unwrap_or_default()
+        };
+        let entry = DirectoryEntry {
+            email: attribute(&self.config.email_attribute)
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    UserStoreError::UnexpectedError(eyre!("Directory entry has no email"))
+                })?,
+            groups: attribute(&self.config.group_attribute),
+            dn: entry.dn,
+        };
+        match &self.config.required_group {
+            Some(group) if !is_member(&entry.groups, group) => Err(UserStoreError::UserNotFound),
+            _ => Ok(entry),
+        }
+    }
+
+    fn to_user(&self, entry: DirectoryEntry) -> Result<User, UserStoreError> {
+        let requires_2fa = self
+            .config
+            .two_fa_groups
+            .iter()
+            .any(|group| is_member(&entry.groups, group));
+        let email = Email::new(entry.email.into()).map_err(UserStoreError::UnexpectedError)?;
+        // Directory passwords are never read, so the user holds an unguessable stand-in
+        let password = Password::new(Uuid::new_v4().to_string().into())
+            .map_err(UserStoreError::UnexpectedError)?;
+        Ok(User::new(email, password, requires_2fa))
+    }
+}
+
+// Group DNs compare case-insensitively, as directories do
+fn is_member(groups: &[String], group: &str) -> bool {
+    groups
+        .iter()
+        .any(|member_of| member_of.eq_ignore_ascii_case(group))
+}
+
+fn unbind(mut ldap: Ldap) {
+    tokio::spawn(async move {
+        if let Err(e) = ldap.unbind().await {
+            warn!(error = ?Report::from(e), "Failed to unbind from LDAP");
+        }
+    });
+}
+
+impl UserStore for LdapUserStore {
+    // Accounts are managed in the directory itself
+    #[tracing::instrument(name = "Adding user to LDAP", skip_all)]
+    async fn insert(&mut self, value: User) -> Result<(), UserStoreError> {
+        match self.get(&value.email).await {
+            Ok(_) => Err(UserStoreError::UserAlreadyExists),
+            Err(UserStoreError::UserNotFound) => Err(UserStoreError::UnexpectedError(eyre!(
+                "The LDAP directory is read-only"
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    #[tracing::instrument(name = "Retrieving user from LDAP", skip_all)]
+    async fn get(&self, key: &Email) -> Result<User, UserStoreError> {
+        let mut ldap = self.connect().await?;
+        let entry = self.find(&mut ldap, key).await;
+        unbind(ldap);
+        self.to_user(entry?)
+    }
+
+    #[tracing::instrument(name = "Validating user credentials in LDAP", skip_all)]
+    async fn validate(&self, key: &Email, value: &SecretString) -> Result<(), UserStoreError> {
+        let mut ldap = self.connect().await?;
+        let result = async {
+            let entry = self.find(&mut ldap, key).await?;
+            // An empty password would make the bind an unauthenticated one, which succeeds
+            if value.expose_secret().is_empty()
+                || !self
+                    .bind(&mut ldap, &entry.dn, value.expose_secret())
+                    .await?
+            {
+                return Err(UserStoreError::InvalidCredentials);
+            }
+            Ok(())
+        }
+        .await;
+        unbind(ldap);
+        result
+    }
+
+    // Directory users keep getting their codes by email
+    #[tracing::instrument(name = "Setting phone number in LDAP", skip_all)]
+    async fn set_phone_number(
+        &mut self,
+        _key: &Email,
+        _phone_number: PhoneNumber,
+        _channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError> {
+        Err(UserStoreError::UnexpectedError(eyre!(
+            "The LDAP directory is read-only"
+        )))
+    }
+
+    // Passwords are changed in the directory itself
+    #[tracing::instrument(name = "Setting password in LDAP", skip_all)]
+    async fn set_password(
+        &mut self,
+        _key: &Email,
+        _password: Password,
+    ) -> Result<(), UserStoreError> {
+        Err(UserStoreError::UnexpectedError(eyre!(
+            "The LDAP directory is read-only"
+        )))
+    }
+
+    async fn can_set_password(&self, _key: &Email) -> Result<bool, UserStoreError> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::utils::constants::{
+        DEFAULT_LDAP_EMAIL_ATTRIBUTE, DEFAULT_LDAP_GROUP_ATTRIBUTE, DEFAULT_LDAP_USER_FILTER,
+    };
+
+    use super::*;
+
+    const BASE_DN: &str = "dc=example,dc=com";
+    const SERVICE_DN: &str = "cn=auth-service,dc=example,dc=com";
+    const STAFF_GROUP: &str = "cn=staff,ou=groups,dc=example,dc=com";
+    const ADMINS_GROUP: &str = "cn=admins,ou=groups,dc=example,dc=com";
+
+    struct MockEntry {
+        dn: &'static str,
+        password: &'static str,
+        attributes: HashMap<&'static str, Vec<&'static str>>,
+    }
+
+    // A directory served over LDAP from memory. Only simple binds and searches with equality
+    // filters, ANDed together, are understood, and only bound clients may search.
+    fn directory() -> Vec<MockEntry> {
+        vec![
+            MockEntry {
+                dn: SERVICE_DN,
+                password: "service-password",
+                attributes: HashMap::from([("objectClass", vec!["applicationProcess"])]),
+            },
+            MockEntry {
+                dn: "uid=alice,ou=people,dc=example,dc=com",
+                password: "alice-password",
+                attributes: HashMap::from([
+                    ("objectClass", vec!["person"]),
+                    ("mail", vec!["alice@example.com"]),
+                    ("memberOf", vec![STAFF_GROUP, ADMINS_GROUP]),
+                ]),
+            },
+            MockEntry {
+                dn: "uid=bob,ou=people,dc=example,dc=com",
+                password: "bob-password",
+                attributes: HashMap::from([
+                    ("objectClass", vec!["person"]),
+                    ("mail", vec!["bob@example.com"]),
+                    ("memberOf", vec![STAFF_GROUP]),
+                ]),
+            },
+            MockEntry {
+                dn: "uid=carol,ou=contractors,dc=example,dc=com",
+                password: "carol-password",
+                attributes: HashMap::from([
+                    ("objectClass", vec!["person"]),
+                    ("mail", vec!["carol@example.com"]),
+                ]),
+            },
+        ]
+    }
+
+    async fn start_directory() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(socket));
+            }
+        });
+        format!("ldap://{}", address)
+    }
+
+    async fn serve(mut socket: TcpStream) {
+        let entries = directory();
+        let mut bound = false;
+        while let Some(message) = read_element(&mut socket).await {
+            let [(_, id), (op_tag, op), ..] = &elements(&message)[..] else {
+                return;
+            };
+            let id = id.iter().fold(0u32, |id, byte| id << 8 | *byte as u32);
+            let response = match op_tag {
+                // BindRequest: version, name, simple password
+                0x60 => {
+                    let fields = elements(op);
+                    let (dn, password) = (text(&fields[1].1), text(&fields[2].1));
+                    bound = if dn.is_empty() {
+                        password.is_empty()
+                    } else {
+                        entries
+                            .iter()
+                            .any(|entry| entry.dn == dn && entry.password == password)
+                    };
+                    vec![ldap_result(
+                        0x61,
+                        if bound { 0 } else { INVALID_CREDENTIALS as u8 },
+                    )]
+                }
+                // SearchRequest: base, scope, deref, size limit, time limit, types only, filter
+                0x63 if !bound => vec![ldap_result(0x65, 50)],
+                0x63 => {
+                    let fields = elements(op);
+                    let mut conditions = Vec::new();
+                    equality_conditions(fields[6].0, &fields[6].1, &mut conditions);
+                    let mut response: Vec<Vec<u8>> = entries
+                        .iter()
+                        .filter(|entry| {
+                            conditions.iter().all(|(name, value)| {
+                                entry.attributes.get(name.as_str()).is_some_and(|values| {
+                                    values.iter().any(|v| v.eq_ignore_ascii_case(value))
+                                })
+                            })
+                        })
+                        .map(search_result_entry)
+                        .collect();
+                    response.push(ldap_result(0x65, 0));
+                    response
+                }
+                // UnbindRequest
+                0x42 => return,
+                _ => vec![ldap_result(0x65, 53)],
+            };
+            for op in response {
+                let message = tlv(0x30, &[tlv(0x02, &[id as u8]), op].concat());
+                socket.write_all(&message).await.unwrap();
+            }
+        }
+    }
+
+    fn equality_conditions(tag: u8, content: &[u8], conditions: &mut Vec<(String, String)>) {
+        match tag {
+            // and
+            0xa0 => {
+                for (tag, content) in elements(content) {
+                    equality_conditions(tag, &content, conditions);
+                }
+            }
+            // equalityMatch
+            0xa3 => {
+                let fields = elements(content);
+                conditions.push((text(&fields[0].1), text(&fields[1].1)));
+            }
+            _ => panic!("Unsupported filter {:#x}", tag),
+        }
+    }
+
+    fn search_result_entry(entry: &MockEntry) -> Vec<u8> {
+        let attributes: Vec<u8> = entry
+            .attributes
+            .iter()
+            .map(|(name, values)| {
+                let values: Vec<u8> = values
+                    .iter()
+                    .flat_map(|v| tlv(0x04, v.as_bytes()))
+                    .collect();
+                tlv(
+                    0x30,
+                    &[tlv(0x04, name.as_bytes()), tlv(0x31, &values)].concat(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .concat();
+        tlv(
+            0x64,
+            &[tlv(0x04, entry.dn.as_bytes()), tlv(0x30, &attributes)].concat(),
+        )
+    }
+
+    fn ldap_result(tag: u8, code: u8) -> Vec<u8> {
+        tlv(
+            tag,
+            &[tlv(0x0a, &[code]), tlv(0x04, b""), tlv(0x04, b"")].concat(),
+        )
+    }
+
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag];
+        match content.len() {
+            length if length < 0x80 => encoded.push(length as u8),
+            length => {
+                let bytes = (length as u32).to_be_bytes();
+                let bytes: Vec<u8> = bytes.into_iter().skip_while(|byte| *byte == 0).collect();
+                encoded.push(0x80 | bytes.len() as u8);
+                encoded.extend(bytes);
+            }
+        }
+        encoded.extend_from_slice(content);
+        encoded
+    }
+
+    fn text(bytes: &[u8]) -> String {
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    // Splits the contents of a constructed element into its (tag, contents) elements
+    fn elements(mut bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut elements = Vec::new();
+        while !bytes.is_empty() {
+            let (header, length) = match bytes[1] {
+                length if length < 0x80 => (2, length as usize),
+                count => {
+                    let count = (count & 0x7f) as usize;
+                    let length = bytes[2..2 + count]
+                        .iter()
+                        .fold(0, |length, byte| length << 8 | *byte as usize);
+                    (2 + count, length)
+                }
+            };
+            elements.push((bytes[0], bytes[header..header + length].to_vec()));
+            bytes = &bytes[header + length..];
+        }
+        elements
+    }
+
+    // Reads the contents of the next LDAP message from the client
+    async fn read_element(socket: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut header = [0u8; 2];
+        socket.read_exact(&mut header).await.ok()?;
+        let length = match header[1] {
+            length if length < 0x80 => length as usize,
+            count => {
+                let mut bytes = vec![0u8; (count & 0x7f) as usize];
+                socket.read_exact(&mut bytes).await.ok()?;
+                bytes
+                    .iter()
+                    .fold(0, |length, byte| length << 8 | *byte as usize)
+            }
+        };
+        let mut content = vec![0u8; length];
+        socket.read_exact(&mut content).await.ok()?;
+        Some(content)
+    }
+
+    fn config(url: String) -> LdapConfig {
+        LdapConfig {
+            url,
+            base_dn: BASE_DN.to_owned(),
+            bind_dn: Some(SERVICE_DN.to_owned()),
+            bind_password: Some("service-password".to_owned().into()),
+            user_filter: DEFAULT_LDAP_USER_FILTER.to_owned(),
+            email_attribute: DEFAULT_LDAP_EMAIL_ATTRIBUTE.to_owned(),
+            group_attribute: DEFAULT_LDAP_GROUP_ATTRIBUTE.to_owned(),
+            required_group: None,
+            two_fa_groups: vec![ADMINS_GROUP.to_uppercase()],
+            starttls: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn email(email: &str) -> Email {
+        Email::new(email.to_owned().into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_maps_directory_entries_to_users() {
+        let store = LdapUserStore::new(config(start_directory().await));
+
+        let alice = store.get(&email("alice@example.com")).await.unwrap();
+        assert!(alice.email == email("alice@example.com"));
+        assert!(alice.requires_2fa);
+        let bob = store.get(&email("bob@example.com")).await.unwrap();
+        assert!(!bob.requires_2fa);
+
+        assert_eq!(
+            store.get(&email("dave@example.com")).await.err(),
+            Some(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validates_by_binding_as_user() {
+        let store = LdapUserStore::new(config(start_directory().await));
+        let alice = email("alice@example.com");
+
+        assert!(store
+            .validate(&alice, &"alice-password".to_owned().into())
+            .await
+            .is_ok());
+        for password in ["bob-password", ""] {
+            assert_eq!(
+                store.validate(&alice, &password.to_owned().into()).await,
+                Err(UserStoreError::InvalidCredentials)
+            );
+        }
+        assert_eq!(
+            store
+                .validate(
+                    &email("dave@example.com"),
+                    &"alice-password".to_owned().into()
+                )
+                .await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_required_group_limits_users() {
+        let mut config = config(start_directory().await);
+        config.required_group = Some(STAFF_GROUP.to_owned());
+        let store = LdapUserStore::new(config);
+
+        assert!(store.get(&email("bob@example.com")).await.is_ok());
+        assert_eq!(
+            store.get(&email("carol@example.com")).await.err(),
+            Some(UserStoreError::UserNotFound)
+        );
+        assert_eq!(
+            store
+                .validate(
+                    &email("carol@example.com"),
+                    &"carol-password".to_owned().into()
+                )
+                .await,
+            Err(UserStoreError::UserNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fails_when_service_account_is_rejected() {
+        let mut config = config(start_directory().await);
+        config.bind_password = Some("wrong-password".to_owned().into());
+        let store = LdapUserStore::new(config);
+
+        assert!(matches!(
+            store.get(&email("alice@example.com")).await,
+            Err(UserStoreError::UnexpectedError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_directory_is_read_only() {
+        let mut store = LdapUserStore::new(config(start_directory().await));
+        let user = |address: &str| {
+            User::new(
+                email(address),
+                Password::new("new-password".to_owned().into()).unwrap(),
+                false,
+            )
+        };
+
+        assert_eq!(
+            store.insert(user("alice@example.com")).await,
+            Err(UserStoreError::UserAlreadyExists)
+        );
+        assert!(matches!(
+            store.insert(user("dave@example.com")).await,
+            Err(UserStoreError::UnexpectedError(_))
+        ));
+    }
+}
@@ -1,22 +1,33 @@
+pub mod chained_user_store;
 pub mod hashmap_two_fa_code_store;
 pub mod hashmap_user_store;
 pub mod hashset_banned_store;
+pub mod ldap_user_store;
+pub mod postgres_two_fa_code_store;
 pub mod postgres_user_store;
 pub mod redis_banned_token_store;
 pub mod redis_two_fa_code_store;
+pub use chained_user_store::ChainedUserStore;
 use color_eyre::eyre::eyre;
 use color_eyre::eyre::Report;
 use color_eyre::eyre::Result;
 pub use hashmap_two_fa_code_store::HashmapTwoFACodeStore;
 pub use hashmap_user_store::HashMapUserStore;
+pub use ldap_user_store::{LdapConfig, LdapUserStore};
 use secrecy::SecretString;
 use thiserror::Error;
 
-use std::future::Future;
+use std::{future::Future, time::Duration};
 
-use rand::{Rng, RngCore};
+use rand::Rng;
 
-use crate::domain::{models::Email, User};
+use crate::{
+    domain::{
+        models::{Email, Password},
+        EmailMessage, PhoneNumber, TwoFAChannel, User,
+    },
+    utils::email_outbox::EmailOutbox,
+};
 
 // Email, crate::domain::User, crate::services::UserStoreError
 
@@ -28,6 +39,25 @@ pub trait UserStore {
         key: &Email,
         value: &SecretString,
     ) -> impl Future<Output = Result<(), UserStoreError>> + Send;
+    // Stores a phone number the user proved to receive messages at, and where codes should go
+    fn set_phone_number(
+        &mut self,
+        key: &Email,
+        phone_number: PhoneNumber,
+        channel: TwoFAChannel,
+    ) -> impl Future<Output = Result<(), UserStoreError>> + Send;
+    // Replaces the password of an existing user
+    fn set_password(
+        &mut self,
+        key: &Email,
+        password: Password,
+    ) -> impl Future<Output = Result<(), UserStoreError>> + Send;
+    // Whether `set_password` can replace the password of the user, which a read-only directory
+    // cannot
+    fn can_set_password(
+        &self,
+        key: &Email,
+    ) -> impl Future<Output = Result<bool, UserStoreError>> + Send;
 }
 
 #[derive(Debug, Error)]
@@ -60,12 +90,17 @@ pub enum BannedTokenStoreError {
     UnexpectedError(#[source] Report),
 }
 
+// Tokens are banned by their `jti` claim, for as long as they could still be accepted
 pub trait BannedTokenStore {
     fn ban_token(
         &mut self,
-        token: &str,
+        jti: &str,
+        ttl: Duration,
     ) -> impl Future<Output = Result<(), BannedTokenStoreError>> + Send;
-    fn is_token_banned(&self, token: &str) -> impl Future<Output = bool> + Send;
+    fn is_token_banned(
+        &self,
+        jti: &str,
+    ) -> impl Future<Output = Result<bool, BannedTokenStoreError>> + Send;
 }
 
 pub trait TwoFACodeStore {
@@ -75,6 +110,18 @@ pub trait TwoFACodeStore {
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
     ) -> impl Future<Output = Result<(), TwoFACodeStoreError>> + Send;
+    // Stores the code and enqueues the email that delivers it. Stores in the database of the
+    // outbox do both in one transaction. Others store the code first, so should enqueueing fail,
+    // the unsent code simply expires.
+    fn add_code_and_enqueue(
+        &mut self,
+        email: Email,
+        login_attempt_id: LoginAttemptId,
+        code: TwoFACode,
+        outbox: &EmailOutbox,
+        idempotency_key: String,
+        message: EmailMessage,
+    ) -> impl Future<Output = Result<(), TwoFACodeStoreError>> + Send;
     fn remove_code(
         &mut self,
         email: &Email,
@@ -108,7 +155,7 @@ pub struct LoginAttemptId(String);
 
 impl LoginAttemptId {
     pub fn new(id: String) -> Result<Self> {
-        if let Ok(_) = uuid::Uuid::parse_str(&id) {
+        if uuid::Uuid::parse_str(&id).is_ok() {
             Ok(LoginAttemptId(id))
         } else {
             Err(eyre!("Invalid UUID format"))
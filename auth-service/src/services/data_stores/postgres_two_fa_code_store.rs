@@ -0,0 +1,133 @@
+use color_eyre::eyre::Context;
+use secrecy::ExposeSecret;
+use sqlx::{PgExecutor, PgPool};
+use tracing::instrument;
+
+use crate::{
+    domain::{models::Email, EmailMessage},
+    services::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
+    utils::email_outbox::EmailOutbox,
+};
+
+// Codes expire after ten minutes, as they do in Redis
+const TEN_MINUTES_IN_SECONDS: f64 = 600.0;
+
+// Keeps login attempts in the database of the email outbox, so a code and the email that
+// delivers it are committed in one transaction
+#[derive(Clone)]
+pub struct PostgresTwoFACodeStore {
+    pool: PgPool,
+}
+
+impl PostgresTwoFACodeStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+// A new login replaces the pending one of the user
+async fn upsert<'e>(
+    executor: impl PgExecutor<'e>,
+    email: &Email,
+    login_attempt_id: &LoginAttemptId,
+    code: &TwoFACode,
+) -> Result<(), TwoFACodeStoreError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO two_fa_codes (email, login_attempt_id, code, expires_at)
+        VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))
+        ON CONFLICT (email) DO UPDATE
+        SET login_attempt_id = EXCLUDED.login_attempt_id,
+            code = EXCLUDED.code,
+            expires_at = EXCLUDED.expires_at
+        "#,
+        email.as_ref().expose_secret(),
+        login_attempt_id.as_ref(),
+        code.as_ref(),
+        TEN_MINUTES_IN_SECONDS
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| TwoFACodeStoreError::UnexpectedError(e.into()))?;
+    Ok(())
+}
+
+impl TwoFACodeStore for PostgresTwoFACodeStore {
+    #[instrument(name = "Adding 2FA code to PostgreSQL", skip_all)]
+    async fn add_code(
+        &mut self,
+        email: Email,
+        login_attempt_id: LoginAttemptId,
+        code: TwoFACode,
+    ) -> Result<(), TwoFACodeStoreError> {
+        upsert(&self.pool, &email, &login_attempt_id, &code).await
+    }
+
+    #[instrument(name = "Adding 2FA code and its email to PostgreSQL", skip_all)]
+    async fn add_code_and_enqueue(
+        &mut self,
+        email: Email,
+        login_attempt_id: LoginAttemptId,
+        code: TwoFACode,
+        outbox: &EmailOutbox,
+        idempotency_key: String,
+        message: EmailMessage,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| TwoFACodeStoreError::UnexpectedError(e.into()))?;
+        upsert(&mut *transaction, &email, &login_attempt_id, &code).await?;
+        outbox
+            .enqueue_in(&mut transaction, idempotency_key, &email, message)
+            .await
+            .map_err(|e| TwoFACodeStoreError::UnexpectedError(e.into()))?;
+        transaction
+            .commit()
+            .await
+            .map_err(|e| TwoFACodeStoreError::UnexpectedError(e.into()))?;
+
+        outbox.wake();
+        Ok(())
+    }
+
+    #[instrument(name = "Removing 2FA code from PostgreSQL", skip_all)]
+    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
+        sqlx::query!(
+            "DELETE FROM two_fa_codes WHERE email = $1",
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| TwoFACodeStoreError::UnexpectedError(e.into()))?;
+        Ok(())
+    }
+
+    #[instrument(name = "Retrieving 2FA code from PostgreSQL", skip_all)]
+    async fn get_code(
+        &self,
+        email: &Email,
+    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT login_attempt_id, code
+            FROM two_fa_codes
+            WHERE email = $1 AND expires_at > NOW()
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| TwoFACodeStoreError::UnexpectedError(e.into()))?
+        .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)?;
+
+        let login_attempt_id = LoginAttemptId::new(row.login_attempt_id)
+            .wrap_err("Invalid login attempt ID in PostgreSQL")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        let code = TwoFACode::new(row.code)
+            .wrap_err("Invalid 2FA code in PostgreSQL")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+        Ok((login_attempt_id, code))
+    }
+}
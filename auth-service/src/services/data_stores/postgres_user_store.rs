@@ -1,29 +1,110 @@
-use color_eyre::eyre::{eyre, Result};
-
-use argon2::{
-    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
-    PasswordVerifier, Version,
-};
+use color_eyre::eyre::Result;
 
 use secrecy::{ExposeSecret, SecretString};
 use sqlx::PgPool;
+use tracing::{info, warn, Instrument};
 
 use crate::{
     domain::{
         models::{Email, Password},
-        User,
+        Locale, PhoneNumber, TwoFAChannel, User,
+    },
+    services::{
+        password_hashing::{
+            compute_password_hash, verify_password_hash, PasswordHashAlgorithm, PasswordHashParams,
+            PasswordPeppers,
+        },
+        UserStore, UserStoreError,
     },
-    services::{UserStore, UserStoreError},
 };
 
 #[derive(Clone)]
 pub struct PostgresUserStore {
     pool: PgPool,
+    hash_params: PasswordHashParams,
+    peppers: PasswordPeppers,
 }
 
 impl PostgresUserStore {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            hash_params: PasswordHashParams::default(),
+            peppers: PasswordPeppers::default(),
+        }
+    }
+
+    pub fn with_hash_params(mut self, hash_params: PasswordHashParams) -> Self {
+        self.hash_params = hash_params;
+        self
+    }
+
+    pub fn with_peppers(mut self, peppers: PasswordPeppers) -> Self {
+        self.peppers = peppers;
+        self
+    }
+
+    // Version of the pepper the user's stored hash was computed with, `None` if it is unpeppered
+    #[tracing::instrument(name = "Retrieving password pepper version from PostgreSQL", skip_all)]
+    pub async fn password_pepper_version(
+        &self,
+        email: &Email,
+    ) -> Result<Option<i32>, UserStoreError> {
+        self.get_password_hash(email)
+            .await
+            .map(|(_, pepper_version)| pepper_version)
+    }
+
+    async fn get_password_hash(
+        &self,
+        email: &Email,
+    ) -> Result<(String, Option<i32>), UserStoreError> {
+        sqlx::query!(
+            r#"
+            SELECT password_hash, password_pepper_version
+            FROM users
+            WHERE email = $1
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+        .map(|record| (record.password_hash, record.password_pepper_version))
+        .ok_or(UserStoreError::UserNotFound)
+    }
+
+    // Imports a user migrated from a legacy system together with its existing password hash.
+    // bcrypt, PBKDF2 and scrypt hashes are accepted as is and upgraded to Argon2id on first login.
+    #[tracing::instrument(name = "Importing user into PostgreSQL", skip_all)]
+    pub async fn import_user(
+        &self,
+        email: &Email,
+        password_hash: &SecretString,
+        requires_2fa: bool,
+    ) -> Result<(), UserStoreError> {
+        PasswordHashAlgorithm::detect(password_hash.expose_secret())
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO users (email, password_hash, requires_2fa)
+            VALUES ($1, $2, $3)
+            "#,
+            email.as_ref().expose_secret(),
+            password_hash.expose_secret(),
+            requires_2fa
+        )
+        .execute(&self.pool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(db_err)) if db_err.code() == Some("23505".into()) => {
+                Err(UserStoreError::UserAlreadyExists)
+            }
+            Err(e) => Err(UserStoreError::UnexpectedError(e.into())),
+        }
     }
 }
 
@@ -38,18 +119,29 @@ impl UserStore for PostgresUserStore {
 
         let executor = &mut *connection;
 
-        let password_hash = compute_password_hash(value.password.as_ref().to_owned())
-            .await
-            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+        let password_hash = compute_password_hash(
+            value.password.as_ref().to_owned(),
+            self.hash_params,
+            self.peppers.current(),
+        )
+        .await
+        .map_err(UserStoreError::UnexpectedError)?;
 
         let result = sqlx::query!(
             r#"
-            INSERT INTO users (email, password_hash, requires_2fa)
-            VALUES ($1, $2, $3)
+            INSERT INTO users (
+                email, password_hash, password_pepper_version, requires_2fa, locale, phone_number,
+                two_fa_channel
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
             "#,
             value.email.as_ref().expose_secret(),
             password_hash,
-            value.requires_2fa
+            self.peppers.current_version(),
+            value.requires_2fa,
+            value.locale.map(|locale| locale.as_str()),
+            value.phone_number.as_ref().map(PhoneNumber::as_str),
+            value.two_fa_channel.as_str()
         )
         .execute(executor)
         .await;
@@ -75,7 +167,7 @@ impl UserStore for PostgresUserStore {
 
         sqlx::query!(
             r#"
-            SELECT email, password_hash, requires_2fa
+            SELECT email, password_hash, requires_2fa, locale, phone_number, two_fa_channel
             FROM users
             WHERE email = $1
             "#,
@@ -84,12 +176,23 @@ impl UserStore for PostgresUserStore {
         .fetch_one(executor)
         .await
         .map_err(|_| UserStoreError::UserNotFound)
-        .map(|record| {
-            User::new(
+        .and_then(|record| {
+            let user = User::new(
                 Email::new(record.email.into()).unwrap(),
                 Password::new(record.password_hash.into()).unwrap(),
                 record.requires_2fa,
             )
+            .with_locale(record.locale.as_deref().and_then(Locale::negotiate));
+            let Some(phone_number) = record.phone_number else {
+                return Ok(user);
+            };
+            let phone_number =
+                PhoneNumber::parse(&phone_number).map_err(UserStoreError::UnexpectedError)?;
+            let channel = record
+                .two_fa_channel
+                .parse()
+                .map_err(UserStoreError::UnexpectedError)?;
+            Ok(user.with_phone_number(phone_number, channel))
         })
     }
 
@@ -99,47 +202,141 @@ impl UserStore for PostgresUserStore {
         key: &crate::domain::models::Email,
         value: &SecretString,
     ) -> Result<(), super::UserStoreError> {
-        let user = self.get(key).await?;
+        let (stored_hash, pepper_version) = self.get_password_hash(key).await?;
+        let pepper = self
+            .peppers
+            .get(pepper_version)
+            .map_err(UserStoreError::UnexpectedError)?;
 
         verify_password_hash(
-            user.password.as_ref().expose_secret().to_string(),
+            stored_hash.clone(),
             value.expose_secret().to_string(),
+            pepper,
         )
         .await
-        .map_err(|_| UserStoreError::InvalidCredentials)
+        .map_err(|_| UserStoreError::InvalidCredentials)?;
+
+        // Hashes using a retired pepper are migrated to the current one the same way
+        let pepper_outdated = pepper_version != self.peppers.current_version();
+        match self.hash_params.needs_rehash(&stored_hash) {
+            Ok(needs_rehash) if needs_rehash || pepper_outdated => {
+                self.spawn_rehash(key, value, stored_hash)
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = ?e, "Failed to read stored password hash parameters"),
+        }
+
+        Ok(())
     }
-}
 
-#[tracing::instrument(name = "Verify password hash", skip_all)]
-async fn verify_password_hash(
-    expected_password_hash: String,
-    password_candidate: String,
-) -> Result<()> {
-    tokio::task::spawn_blocking(move || {
-        let expected_password_hash: PasswordHash<'_> = PasswordHash::new(&expected_password_hash)?;
+    #[tracing::instrument(name = "Setting phone number in PostgreSQL", skip_all)]
+    async fn set_phone_number(
+        &mut self,
+        key: &Email,
+        phone_number: PhoneNumber,
+        channel: TwoFAChannel,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users
+            SET phone_number = $2, two_fa_channel = $3
+            WHERE email = $1
+            "#,
+            key.as_ref().expose_secret(),
+            phone_number.as_str(),
+            channel.as_str()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
 
-        Argon2::default().verify_password(password_candidate.as_bytes(), &expected_password_hash)
-    })
-    .await??;
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
 
-    Ok(())
+    #[tracing::instrument(name = "Setting password in PostgreSQL", skip_all)]
+    async fn set_password(
+        &mut self,
+        key: &Email,
+        password: Password,
+    ) -> Result<(), UserStoreError> {
+        let password_hash = compute_password_hash(
+            password.as_ref().to_owned(),
+            self.hash_params,
+            self.peppers.current(),
+        )
+        .await
+        .map_err(UserStoreError::UnexpectedError)?;
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE users
+            SET password_hash = $2, password_pepper_version = $3
+            WHERE email = $1
+            "#,
+            key.as_ref().expose_secret(),
+            password_hash,
+            self.peppers.current_version()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn can_set_password(&self, _key: &Email) -> Result<bool, UserStoreError> {
+        Ok(true)
+    }
 }
 
-#[tracing::instrument(name = "Computing password hash", skip_all)]
-async fn compute_password_hash(password: SecretString) -> Result<String> {
-    let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
-    let hasher = Argon2::new(
-        Algorithm::Argon2id,
-        Version::V0x13,
-        Params::new(15000, 2, 1, None)?,
-    );
-    let password_hash: Result<String, argon2::password_hash::Error> =
-        tokio::task::spawn_blocking(move || {
-            Ok(hasher
-                .hash_password(password.expose_secret().as_bytes(), &salt)?
-                .to_string())
-        })
-        .await?;
+impl PostgresUserStore {
+    // Upgrades a stored hash to the configured parameters and pepper without delaying the login
+    // response.
+    // The update only applies if the hash has not changed in the meantime.
+    fn spawn_rehash(&self, email: &Email, password: &SecretString, old_hash: String) {
+        let pool = self.pool.clone();
+        let hash_params = self.hash_params;
+        let peppers = self.peppers.clone();
+        let email = email.as_ref().expose_secret().to_string();
+        let password = password.clone();
+
+        tokio::spawn(
+            async move {
+                let pepper = peppers.current();
+                let new_hash = match compute_password_hash(password, hash_params, pepper).await {
+                    Ok(new_hash) => new_hash,
+                    Err(e) => {
+                        warn!(error = ?e, "Failed to compute upgraded password hash");
+                        return;
+                    }
+                };
+
+                let result = sqlx::query!(
+                    r#"
+                    UPDATE users
+                    SET password_hash = $1, password_pepper_version = $2
+                    WHERE email = $3 AND password_hash = $4
+                    "#,
+                    new_hash,
+                    peppers.current_version(),
+                    email,
+                    old_hash
+                )
+                .execute(&pool)
+                .await;
 
-    Ok(password_hash?)
+                match result {
+                    Ok(_) => info!("Upgraded password hash"),
+                    Err(e) => warn!(error = ?e, "Failed to store upgraded password hash"),
+                }
+            }
+            .instrument(tracing::info_span!("Rehashing password")),
+        );
+    }
 }
@@ -1,11 +1,10 @@
+use std::time::Duration;
+
 use color_eyre::eyre::Context;
 use redis::AsyncCommands;
 use tracing::instrument;
 
-use crate::{
-    services::{data_stores::BannedTokenStoreError, BannedTokenStore},
-    utils::auth::TOKEN_TTL_SECONDS,
-};
+use crate::services::{data_stores::BannedTokenStoreError, BannedTokenStore};
 
 #[derive(Clone)]
 pub struct RedisBannedTokenStore {
@@ -20,12 +19,14 @@ impl RedisBannedTokenStore {
 
 impl BannedTokenStore for RedisBannedTokenStore {
     #[instrument(skip_all)]
-    async fn ban_token(&mut self, token: &str) -> Result<(), BannedTokenStoreError> {
-        let key = get_key(token);
+    async fn ban_token(&mut self, jti: &str, ttl: Duration) -> Result<(), BannedTokenStoreError> {
+        let key = get_key(jti);
+        // Redis rejects a TTL of zero, and a token that is about to expire still needs banning
+        let ttl_seconds = ttl.as_secs().max(1);
 
         let mut conn = self.connection_manager.clone();
         let _: () = conn
-            .set_ex(key, true, TOKEN_TTL_SECONDS)
+            .set_ex(key, true, ttl_seconds)
             .await
             .wrap_err("Failed to set banned token store in Redis")
             .map_err(BannedTokenStoreError::UnexpectedError)?;
@@ -33,15 +34,14 @@ impl BannedTokenStore for RedisBannedTokenStore {
     }
 
     #[instrument(skip_all)]
-    async fn is_token_banned(&self, token: &str) -> bool {
-        let key = get_key(token);
+    async fn is_token_banned(&self, jti: &str) -> Result<bool, BannedTokenStoreError> {
+        let key = get_key(jti);
 
         let mut conn = self.connection_manager.clone();
-        let result = conn.exists(key).await;
-        match result {
-            Ok(exists) => exists,
-            Err(_) => false,
-        }
+        conn.exists(key)
+            .await
+            .wrap_err("Failed to check banned token store in Redis")
+            .map_err(BannedTokenStoreError::UnexpectedError)
     }
 }
 
@@ -49,6 +49,6 @@ impl BannedTokenStore for RedisBannedTokenStore {
 const BANNED_TOKEN_KEY_PREFIX: &str = "banned_token:";
 
 #[instrument(skip_all)]
-fn get_key(token: &str) -> String {
-    format!("{}{}", BANNED_TOKEN_KEY_PREFIX, token)
+fn get_key(jti: &str) -> String {
+    format!("{}{}", BANNED_TOKEN_KEY_PREFIX, jti)
 }
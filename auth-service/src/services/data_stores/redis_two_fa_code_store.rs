@@ -1,12 +1,13 @@
 use color_eyre::eyre::Context;
-use redis::{aio::MultiplexedConnection, AsyncCommands, Commands, Connection};
+use redis::{aio::MultiplexedConnection, AsyncCommands};
 use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::{
-    domain::models::Email,
+    domain::{models::Email, EmailMessage},
     services::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
+    utils::email_outbox::EmailOutbox,
 };
 
 #[derive(Clone)]
@@ -46,6 +47,23 @@ impl TwoFACodeStore for RedisTwoFACodeStore {
         Ok(())
     }
 
+    #[instrument(skip_all)]
+    async fn add_code_and_enqueue(
+        &mut self,
+        email: Email,
+        login_attempt_id: LoginAttemptId,
+        code: TwoFACode,
+        outbox: &EmailOutbox,
+        idempotency_key: String,
+        message: EmailMessage,
+    ) -> Result<(), TwoFACodeStoreError> {
+        self.add_code(email.clone(), login_attempt_id, code).await?;
+        outbox
+            .enqueue(idempotency_key, &email, message)
+            .await
+            .map_err(|e| TwoFACodeStoreError::UnexpectedError(e.into()))
+    }
+
     #[instrument(skip_all)]
     async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
         let key = get_key(email);
@@ -89,9 +107,5 @@ const TWO_FA_CODE_PREFIX: &str = "two_fa_code:";
 
 #[instrument(skip_all)]
 fn get_key(email: &Email) -> String {
-    format!(
-        "{}{}",
-        TWO_FA_CODE_PREFIX,
-        email.as_ref().expose_secret()
-    )
+    format!("{}{}", TWO_FA_CODE_PREFIX, email.as_ref().expose_secret())
 }
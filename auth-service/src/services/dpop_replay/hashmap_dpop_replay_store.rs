@@ -0,0 +1,55 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{DpopReplayStore, DpopReplayStoreError};
+
+// Keeps used proofs in process memory, so proofs are not shared between instances. Meant for
+// single instance deployments and tests. Expired entries are evicted on every insert.
+#[derive(Clone, Default)]
+pub struct HashmapDpopReplayStore {
+    used: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl DpopReplayStore for HashmapDpopReplayStore {
+    async fn insert_if_absent(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<bool, DpopReplayStoreError> {
+        let now = Instant::now();
+        let mut used = self
+            .used
+            .lock()
+            .map_err(|e| DpopReplayStoreError::UnexpectedError(eyre!("{}", e)))?;
+
+        used.retain(|_, expires_at| *expires_at > now);
+        if used.contains_key(key) {
+            return Ok(false);
+        }
+        used.insert(key.to_owned(), now + ttl);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rejects_second_use_until_expired() {
+        let store = HashmapDpopReplayStore::default();
+        let ttl = Duration::from_millis(20);
+
+        assert!(store.insert_if_absent("proof", ttl).await.unwrap());
+        assert!(!store.insert_if_absent("proof", ttl).await.unwrap());
+        assert!(store.insert_if_absent("other", ttl).await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(store.insert_if_absent("proof", ttl).await.unwrap());
+    }
+}
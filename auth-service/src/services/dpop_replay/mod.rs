@@ -0,0 +1,47 @@
+pub mod hashmap_dpop_replay_store;
+pub mod redis_dpop_replay_store;
+
+use std::{future::Future, time::Duration};
+
+use color_eyre::eyre::Report;
+use thiserror::Error;
+
+pub use hashmap_dpop_replay_store::HashmapDpopReplayStore;
+pub use redis_dpop_replay_store::RedisDpopReplayStore;
+
+// Remembers the DPoP proofs that were already used, so a captured proof cannot be replayed.
+// Entries only need to outlive the window in which the proof's `iat` is accepted.
+pub trait DpopReplayStore {
+    // Records `key` for `ttl`, returning false if it was already recorded
+    fn insert_if_absent(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<bool, DpopReplayStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum DpopReplayStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// Redis shares the used proofs between all instances; the in-memory store only suits a single one
+#[derive(Clone)]
+pub enum DpopReplayStoreBackend {
+    InMemory(HashmapDpopReplayStore),
+    Redis(RedisDpopReplayStore),
+}
+
+impl DpopReplayStore for DpopReplayStoreBackend {
+    async fn insert_if_absent(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<bool, DpopReplayStoreError> {
+        match self {
+            Self::InMemory(store) => store.insert_if_absent(key, ttl).await,
+            Self::Redis(store) => store.insert_if_absent(key, ttl).await,
+        }
+    }
+}
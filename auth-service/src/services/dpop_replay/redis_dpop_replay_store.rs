@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use tracing::instrument;
+
+use super::{DpopReplayStore, DpopReplayStoreError};
+
+// We are using a key prefix to prevent collisions and organize data!
+const DPOP_PROOF_KEY_PREFIX: &str = "dpop_proof:";
+
+// Shares used proofs between all instances
+#[derive(Clone)]
+pub struct RedisDpopReplayStore {
+    connection_manager: MultiplexedConnection,
+}
+
+impl RedisDpopReplayStore {
+    pub fn new(connection_manager: MultiplexedConnection) -> Self {
+        Self { connection_manager }
+    }
+}
+
+impl DpopReplayStore for RedisDpopReplayStore {
+    #[instrument(skip_all)]
+    async fn insert_if_absent(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> Result<bool, DpopReplayStoreError> {
+        let key = format!("{}{}", DPOP_PROOF_KEY_PREFIX, key);
+        // `SET NX` is atomic, so two instances cannot both accept the same proof
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::PX(ttl.as_millis().max(1) as u64));
+
+        let mut conn = self.connection_manager.clone();
+        let inserted: Option<String> = conn
+            .set_options(key, true, options)
+            .await
+            .wrap_err("Failed to record DPoP proof in Redis")
+            .map_err(DpopReplayStoreError::UnexpectedError)?;
+        Ok(inserted.is_some())
+    }
+}
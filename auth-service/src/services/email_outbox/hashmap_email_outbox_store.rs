@@ -0,0 +1,207 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+use uuid::Uuid;
+
+use super::{EmailOutboxStore, EmailOutboxStoreError, OutboxEmail, OutboxEntry, OutboxStatus};
+
+struct Row {
+    id: Uuid,
+    email: OutboxEmail,
+    status: OutboxStatus,
+    attempts: u32,
+    next_attempt_at: Instant,
+    last_error: Option<String>,
+}
+
+// Keeps the outbox in process memory, in the order emails were enqueued. Meant for tests.
+#[derive(Clone, Default)]
+pub struct HashmapEmailOutboxStore {
+    rows: Arc<Mutex<Vec<Row>>>,
+}
+
+impl HashmapEmailOutboxStore {
+    fn rows(&self) -> Result<MutexGuard<'_, Vec<Row>>, EmailOutboxStoreError> {
+        self.rows
+            .lock()
+            .map_err(|e| EmailOutboxStoreError::UnexpectedError(eyre!("{}", e)))
+    }
+
+    // The error of the last failed attempt, for tests to check
+    pub fn last_error(&self, idempotency_key: &str) -> Option<String> {
+        let rows = self.rows().ok()?;
+        rows.iter()
+            .find(|row| row.email.idempotency_key == idempotency_key)
+            .and_then(|row| row.last_error.clone())
+    }
+}
+
+impl EmailOutboxStore for HashmapEmailOutboxStore {
+    async fn enqueue(&self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        let mut rows = self.rows()?;
+        if rows
+            .iter()
+            .any(|row| row.email.idempotency_key == email.idempotency_key)
+        {
+            return Ok(());
+        }
+        rows.push(Row {
+            id: Uuid::new_v4(),
+            email,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            next_attempt_at: Instant::now(),
+            last_error: None,
+        });
+        Ok(())
+    }
+
+    async fn claim_due(
+        &self,
+        limit: u32,
+        lease: Duration,
+    ) -> Result<Vec<OutboxEntry>, EmailOutboxStoreError> {
+        let now = Instant::now();
+        let mut rows = self.rows()?;
+        Ok(rows
+            .iter_mut()
+            .filter(|row| row.status == OutboxStatus::Pending && row.next_attempt_at <= now)
+            .take(limit as usize)
+            .map(|row| {
+                row.attempts += 1;
+                row.next_attempt_at = now + lease;
+                OutboxEntry {
+                    id: row.id,
+                    email: row.email.clone(),
+                    attempts: row.attempts,
+                }
+            })
+            .collect())
+    }
+
+    async fn mark_sent(&self, id: Uuid) -> Result<(), EmailOutboxStoreError> {
+        let mut rows = self.rows()?;
+        if let Some(row) = rows.iter_mut().find(|row| row.id == id) {
+            row.status = OutboxStatus::Sent;
+        }
+        Ok(())
+    }
+
+    async fn mark_failed(
+        &self,
+        id: Uuid,
+        error: &str,
+        retry_in: Option<Duration>,
+    ) -> Result<(), EmailOutboxStoreError> {
+        let mut rows = self.rows()?;
+        if let Some(row) = rows.iter_mut().find(|row| row.id == id) {
+            row.last_error = Some(error.to_owned());
+            match retry_in {
+                Some(retry_in) => row.next_attempt_at = Instant::now() + retry_in,
+                None => row.status = OutboxStatus::Dead,
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_status(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<OutboxStatus>, EmailOutboxStoreError> {
+        let rows = self.rows()?;
+        Ok(rows
+            .iter()
+            .find(|row| row.email.idempotency_key == idempotency_key)
+            .map(|row| row.status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{models::Email, EmailMessage};
+
+    use super::*;
+
+    fn email(idempotency_key: &str) -> OutboxEmail {
+        OutboxEmail {
+            idempotency_key: idempotency_key.to_owned(),
+            recipient: Email::new("test@example.com".to_owned().into()).unwrap(),
+            message: EmailMessage {
+                subject: "Subject".to_owned(),
+                html: "<p>Body</p>".to_owned(),
+                text: "Body".to_owned(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enqueues_each_key_once() {
+        let store = HashmapEmailOutboxStore::default();
+        store.enqueue(email("a")).await.unwrap();
+        store.enqueue(email("a")).await.unwrap();
+        store.enqueue(email("b")).await.unwrap();
+
+        let claimed = store.claim_due(10, Duration::from_secs(60)).await.unwrap();
+        let keys: Vec<_> = claimed
+            .iter()
+            .map(|entry| entry.email.idempotency_key.as_str())
+            .collect();
+        assert_eq!(keys, ["a", "b"]);
+        assert!(claimed.iter().all(|entry| entry.attempts == 1));
+    }
+
+    #[tokio::test]
+    async fn test_claimed_emails_are_leased() {
+        let store = HashmapEmailOutboxStore::default();
+        store.enqueue(email("a")).await.unwrap();
+
+        let claimed = store.claim_due(10, Duration::from_secs(60)).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert!(store
+            .claim_due(10, Duration::from_secs(60))
+            .await
+            .unwrap()
+            .is_empty());
+
+        // A failed attempt is retried once the backoff passed
+        store
+            .mark_failed(claimed[0].id, "unavailable", Some(Duration::ZERO))
+            .await
+            .unwrap();
+        let claimed = store.claim_due(10, Duration::from_secs(60)).await.unwrap();
+        assert_eq!(claimed[0].attempts, 2);
+        assert_eq!(store.last_error("a").as_deref(), Some("unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_sent_and_dead_emails_are_not_claimed() {
+        let store = HashmapEmailOutboxStore::default();
+        store.enqueue(email("a")).await.unwrap();
+        store.enqueue(email("b")).await.unwrap();
+        let claimed = store.claim_due(10, Duration::ZERO).await.unwrap();
+
+        store.mark_sent(claimed[0].id).await.unwrap();
+        store
+            .mark_failed(claimed[1].id, "rejected", None)
+            .await
+            .unwrap();
+
+        assert!(store
+            .claim_due(10, Duration::ZERO)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store.get_status("a").await.unwrap(),
+            Some(OutboxStatus::Sent)
+        );
+        assert_eq!(
+            store.get_status("b").await.unwrap(),
+            Some(OutboxStatus::Dead)
+        );
+        assert_eq!(store.get_status("c").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,155 @@
+pub mod hashmap_email_outbox_store;
+pub mod postgres_email_outbox_store;
+
+use std::{future::Future, time::Duration};
+
+use color_eyre::eyre::Report;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::domain::{models::Email, EmailMessage};
+
+pub use hashmap_email_outbox_store::HashmapEmailOutboxStore;
+pub use postgres_email_outbox_store::PostgresEmailOutboxStore;
+
+// An email to send. The idempotency key names what the email is about, e.g. the login attempt a
+// code is for, so enqueueing it again does not send it twice.
+#[derive(Clone)]
+pub struct OutboxEmail {
+    pub idempotency_key: String,
+    pub recipient: Email,
+    pub message: EmailMessage,
+}
+
+// An email claimed for delivery, with the number of attempts including the current one
+#[derive(Clone)]
+pub struct OutboxEntry {
+    pub id: Uuid,
+    pub email: OutboxEmail,
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxStatus {
+    Pending,
+    Sent,
+    // Every attempt failed, the email is kept for inspection but no longer retried
+    Dead,
+}
+
+impl OutboxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Sent => "sent",
+            Self::Dead => "dead",
+        }
+    }
+}
+
+impl std::str::FromStr for OutboxStatus {
+    type Err = EmailOutboxStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "sent" => Ok(Self::Sent),
+            "dead" => Ok(Self::Dead),
+            other => Err(EmailOutboxStoreError::UnexpectedError(Report::msg(
+                format!("Unknown outbox status: {}", other),
+            ))),
+        }
+    }
+}
+
+// Holds emails until a worker delivered them. Claiming an email counts as an attempt and hides
+// it from other workers for the lease, so a worker that dies midway only delays the email.
+pub trait EmailOutboxStore {
+    // Adds `email` unless one with the same idempotency key was enqueued before
+    fn enqueue(
+        &self,
+        email: OutboxEmail,
+    ) -> impl Future<Output = Result<(), EmailOutboxStoreError>> + Send;
+    // Claims up to `limit` pending emails whose next attempt is due
+    fn claim_due(
+        &self,
+        limit: u32,
+        lease: Duration,
+    ) -> impl Future<Output = Result<Vec<OutboxEntry>, EmailOutboxStoreError>> + Send;
+    fn mark_sent(&self, id: Uuid)
+        -> impl Future<Output = Result<(), EmailOutboxStoreError>> + Send;
+    // Records a failed attempt, to be retried after `retry_in`, or dead-lettered without it
+    fn mark_failed(
+        &self,
+        id: Uuid,
+        error: &str,
+        retry_in: Option<Duration>,
+    ) -> impl Future<Output = Result<(), EmailOutboxStoreError>> + Send;
+    fn get_status(
+        &self,
+        idempotency_key: &str,
+    ) -> impl Future<Output = Result<Option<OutboxStatus>, EmailOutboxStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum EmailOutboxStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// Postgres shares the outbox between all instances and lets handlers enqueue emails in their
+// transactions; the in-memory store loses emails on restart and only suits tests
+#[derive(Clone)]
+pub enum EmailOutboxStoreBackend {
+    InMemory(HashmapEmailOutboxStore),
+    Postgres(PostgresEmailOutboxStore),
+}
+
+impl EmailOutboxStore for EmailOutboxStoreBackend {
+    async fn enqueue(&self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        match self {
+            Self::InMemory(store) => store.enqueue(email).await,
+            Self::Postgres(store) => store.enqueue(email).await,
+        }
+    }
+
+    async fn claim_due(
+        &self,
+        limit: u32,
+        lease: Duration,
+    ) -> Result<Vec<OutboxEntry>, EmailOutboxStoreError> {
+        match self {
+            Self::InMemory(store) => store.claim_due(limit, lease).await,
+            Self::Postgres(store) => store.claim_due(limit, lease).await,
+        }
+    }
+
+    async fn mark_sent(&self, id: Uuid) -> Result<(), EmailOutboxStoreError> {
+        match self {
+            Self::InMemory(store) => store.mark_sent(id).await,
+            Self::Postgres(store) => store.mark_sent(id).await,
+        }
+    }
+
+    async fn mark_failed(
+        &self,
+        id: Uuid,
+        error: &str,
+        retry_in: Option<Duration>,
+    ) -> Result<(), EmailOutboxStoreError> {
+        match self {
+            Self::InMemory(store) => store.mark_failed(id, error, retry_in).await,
+            Self::Postgres(store) => store.mark_failed(id, error, retry_in).await,
+        }
+    }
+
+    async fn get_status(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<OutboxStatus>, EmailOutboxStoreError> {
+        match self {
+            Self::InMemory(store) => store.get_status(idempotency_key).await,
+            Self::Postgres(store) => store.get_status(idempotency_key).await,
+        }
+    }
+}
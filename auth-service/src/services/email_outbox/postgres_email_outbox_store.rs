@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use secrecy::ExposeSecret;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::{EmailOutboxStore, EmailOutboxStoreError, OutboxEmail, OutboxEntry, OutboxStatus};
+use crate::domain::{models::Email, EmailMessage};
+
+#[derive(Clone)]
+pub struct PostgresEmailOutboxStore {
+    pool: PgPool,
+}
+
+impl PostgresEmailOutboxStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    // Enqueues `email` in the caller's transaction, so it is only sent if the change it is about
+    // gets committed
+    #[tracing::instrument(name = "Enqueueing email in transaction", skip_all)]
+    pub async fn enqueue_in(
+        conn: &mut PgConnection,
+        email: &OutboxEmail,
+    ) -> Result<(), EmailOutboxStoreError> {
+        insert(conn, email).await
+    }
+}
+
+async fn insert<'e>(
+    executor: impl PgExecutor<'e>,
+    email: &OutboxEmail,
+) -> Result<(), EmailOutboxStoreError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (id, idempotency_key, recipient, subject, html_body, text_body)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (idempotency_key) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        email.idempotency_key,
+        email.recipient.as_ref().expose_secret(),
+        email.message.subject,
+        email.message.html,
+        email.message.text
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+    Ok(())
+}
+
+impl EmailOutboxStore for PostgresEmailOutboxStore {
+    #[tracing::instrument(name = "Enqueueing email in PostgreSQL", skip_all)]
+    async fn enqueue(&self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        insert(&self.pool, &email).await
+    }
+
+    #[tracing::instrument(name = "Claiming due emails from PostgreSQL", skip_all)]
+    async fn claim_due(
+        &self,
+        limit: u32,
+        lease: Duration,
+    ) -> Result<Vec<OutboxEntry>, EmailOutboxStoreError> {
+        // Rows another instance is claiming right now are skipped rather than waited for
+        let rows = sqlx::query!(
+            r#"
+            UPDATE email_outbox
+            SET attempts = attempts + 1, next_attempt_at = NOW() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id
+                FROM email_outbox
+                WHERE status = 'pending' AND next_attempt_at <= NOW()
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, idempotency_key, recipient, subject, html_body, text_body, attempts
+            "#,
+            i64::from(limit),
+            lease.as_secs_f64()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(OutboxEntry {
+                    id: row.id,
+                    email: OutboxEmail {
+                        idempotency_key: row.idempotency_key,
+                        recipient: Email::new(row.recipient.into())
+                            .map_err(EmailOutboxStoreError::UnexpectedError)?,
+                        message: EmailMessage {
+                            subject: row.subject,
+                            html: row.html_body,
+                            text: row.text_body,
+                        },
+                    },
+                    attempts: row.attempts.try_into().unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(name = "Marking email as sent in PostgreSQL", skip_all)]
+    async fn mark_sent(&self, id: Uuid) -> Result<(), EmailOutboxStoreError> {
+        // The bodies hold sign-in codes and links, which are of no use to anyone once sent
+        sqlx::query!(
+            r#"
+            UPDATE email_outbox
+            SET status = 'sent', sent_at = NOW(), html_body = '', text_body = '', last_error = NULL
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Recording failed email delivery in PostgreSQL", skip_all)]
+    async fn mark_failed(
+        &self,
+        id: Uuid,
+        error: &str,
+        retry_in: Option<Duration>,
+    ) -> Result<(), EmailOutboxStoreError> {
+        let status = match retry_in {
+            Some(_) => OutboxStatus::Pending,
+            None => OutboxStatus::Dead,
+        };
+        sqlx::query!(
+            r#"
+            UPDATE email_outbox
+            SET status = $2, last_error = $3, next_attempt_at = NOW() + make_interval(secs => $4)
+            WHERE id = $1
+            "#,
+            id,
+            status.as_str(),
+            error,
+            retry_in.unwrap_or_default().as_secs_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving email status from PostgreSQL", skip_all)]
+    async fn get_status(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<OutboxStatus>, EmailOutboxStoreError> {
+        let status = sqlx::query_scalar!(
+            r#"
+            SELECT status
+            FROM email_outbox
+            WHERE idempotency_key = $1
+            "#,
+            idempotency_key
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        status.map(|status| status.parse()).transpose()
+    }
+}
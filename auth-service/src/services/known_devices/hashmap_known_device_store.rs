@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{KnownDevice, KnownDeviceStore, KnownDeviceStoreError};
+use crate::domain::models::Email;
+
+// Keeps known devices in process memory. Meant for single instance deployments and tests.
+#[derive(Clone, Default)]
+pub struct HashmapKnownDeviceStore {
+    devices: Arc<Mutex<HashMap<Email, Vec<KnownDevice>>>>,
+}
+
+impl KnownDeviceStore for HashmapKnownDeviceStore {
+    async fn devices(&self, email: &Email) -> Result<Vec<KnownDevice>, KnownDeviceStoreError> {
+        let devices = self
+            .devices
+            .lock()
+            .map_err(|e| KnownDeviceStoreError::UnexpectedError(eyre!("{}", e)))?;
+        Ok(devices.get(email).cloned().unwrap_or_default())
+    }
+
+    async fn remember(
+        &self,
+        email: &Email,
+        device: &KnownDevice,
+        replaces: Option<&str>,
+    ) -> Result<(), KnownDeviceStoreError> {
+        let mut devices = self
+            .devices
+            .lock()
+            .map_err(|e| KnownDeviceStoreError::UnexpectedError(eyre!("{}", e)))?;
+        let devices = devices.entry(email.clone()).or_default();
+        let device_id = replaces.unwrap_or(&device.device_id);
+        match devices
+            .iter_mut()
+            .find(|known| known.device_id == device_id)
+        {
+            Some(known) => *known = device.clone(),
+            None => devices.push(device.clone()),
+        }
+        Ok(())
+    }
+
+    async fn forget(&self, email: &Email, device_id: &str) -> Result<(), KnownDeviceStoreError> {
+        let mut devices = self
+            .devices
+            .lock()
+            .map_err(|e| KnownDeviceStoreError::UnexpectedError(eyre!("{}", e)))?;
+        if let Some(devices) = devices.get_mut(email) {
+            devices.retain(|known| known.device_id != device_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(device_id: &str, country: &str) -> KnownDevice {
+        KnownDevice {
+            device_id: device_id.to_owned(),
+            user_agent: Some("Firefox".to_owned()),
+            ip_address: Some("192.0.2.1".to_owned()),
+            country: Some(country.to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remembers_updates_and_forgets_devices() {
+        let store = HashmapKnownDeviceStore::default();
+        let email = Email::new("user@example.com".to_owned().into()).unwrap();
+
+        store
+            .remember(&email, &device("a", "DE"), None)
+            .await
+            .unwrap();
+        store
+            .remember(&email, &device("a", "FR"), None)
+            .await
+            .unwrap();
+        store
+            .remember(&email, &device("b", "FR"), Some("a"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.devices(&email).await.unwrap(),
+            vec![device("b", "FR")]
+        );
+
+        store.forget(&email, "b").await.unwrap();
+        assert!(store.devices(&email).await.unwrap().is_empty());
+    }
+}
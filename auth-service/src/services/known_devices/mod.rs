@@ -0,0 +1,83 @@
+pub mod hashmap_known_device_store;
+pub mod postgres_known_device_store;
+
+use std::future::Future;
+
+use color_eyre::eyre::Report;
+use thiserror::Error;
+
+use crate::domain::models::Email;
+
+pub use hashmap_known_device_store::HashmapKnownDeviceStore;
+pub use postgres_known_device_store::PostgresKnownDeviceStore;
+
+// A device a user logged in from, as seen on its latest login
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownDevice {
+    // Hash of the device cookie
+    pub device_id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    // ISO 3166-1 alpha-2 code, if the country of the login was known
+    pub country: Option<String>,
+}
+
+pub trait KnownDeviceStore {
+    fn devices(
+        &self,
+        email: &Email,
+    ) -> impl Future<Output = Result<Vec<KnownDevice>, KnownDeviceStoreError>> + Send;
+    // Adds the device, or updates it if known. A device that was recognized without its cookie
+    // is stored under its new cookie, given as `replaces` the ID it had.
+    fn remember(
+        &self,
+        email: &Email,
+        device: &KnownDevice,
+        replaces: Option<&str>,
+    ) -> impl Future<Output = Result<(), KnownDeviceStoreError>> + Send;
+    fn forget(
+        &self,
+        email: &Email,
+        device_id: &str,
+    ) -> impl Future<Output = Result<(), KnownDeviceStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum KnownDeviceStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+#[derive(Clone)]
+pub enum KnownDeviceStoreBackend {
+    InMemory(HashmapKnownDeviceStore),
+    Postgres(PostgresKnownDeviceStore),
+}
+
+impl KnownDeviceStore for KnownDeviceStoreBackend {
+    async fn devices(&self, email: &Email) -> Result<Vec<KnownDevice>, KnownDeviceStoreError> {
+        match self {
+            Self::InMemory(store) => store.devices(email).await,
+            Self::Postgres(store) => store.devices(email).await,
+        }
+    }
+
+    async fn remember(
+        &self,
+        email: &Email,
+        device: &KnownDevice,
+        replaces: Option<&str>,
+    ) -> Result<(), KnownDeviceStoreError> {
+        match self {
+            Self::InMemory(store) => store.remember(email, device, replaces).await,
+            Self::Postgres(store) => store.remember(email, device, replaces).await,
+        }
+    }
+
+    async fn forget(&self, email: &Email, device_id: &str) -> Result<(), KnownDeviceStoreError> {
+        match self {
+            Self::InMemory(store) => store.forget(email, device_id).await,
+            Self::Postgres(store) => store.forget(email, device_id).await,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+use super::{KnownDevice, KnownDeviceStore, KnownDeviceStoreError};
+use crate::domain::models::Email;
+
+#[derive(Clone)]
+pub struct PostgresKnownDeviceStore {
+    pool: PgPool,
+}
+
+impl PostgresKnownDeviceStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl KnownDeviceStore for PostgresKnownDeviceStore {
+    #[tracing::instrument(name = "Retrieving known devices from PostgreSQL", skip_all)]
+    async fn devices(&self, email: &Email) -> Result<Vec<KnownDevice>, KnownDeviceStoreError> {
+        let records = sqlx::query!(
+            r#"
+            SELECT device_id, user_agent, ip_address, country
+            FROM known_devices
+            WHERE email = $1
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| KnownDeviceStoreError::UnexpectedError(e.into()))?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| KnownDevice {
+                device_id: record.device_id,
+                user_agent: record.user_agent,
+                ip_address: record.ip_address,
+                country: record.country,
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(name = "Remembering device in PostgreSQL", skip_all)]
+    async fn remember(
+        &self,
+        email: &Email,
+        device: &KnownDevice,
+        replaces: Option<&str>,
+    ) -> Result<(), KnownDeviceStoreError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO known_devices (email, device_id, user_agent, ip_address, country)
+            VALUES ($1, COALESCE($6, $2), $3, $4, $5)
+            ON CONFLICT (email, device_id) DO UPDATE
+            SET device_id = $2,
+                user_agent = EXCLUDED.user_agent,
+                ip_address = EXCLUDED.ip_address,
+                country = EXCLUDED.country,
+                last_seen_at = NOW()
+            "#,
+            email.as_ref().expose_secret(),
+            device.device_id,
+            device.user_agent,
+            device.ip_address,
+            device.country,
+            replaces
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| KnownDeviceStoreError::UnexpectedError(e.into()))?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Forgetting device in PostgreSQL", skip_all)]
+    async fn forget(&self, email: &Email, device_id: &str) -> Result<(), KnownDeviceStoreError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM known_devices
+            WHERE email = $1 AND device_id = $2
+            "#,
+            email.as_ref().expose_secret(),
+            device_id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| KnownDeviceStoreError::UnexpectedError(e.into()))?;
+        Ok(())
+    }
+}
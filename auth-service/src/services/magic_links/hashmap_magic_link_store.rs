@@ -0,0 +1,71 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{MagicLinkStore, MagicLinkStoreError};
+
+// Keeps used links in process memory. Meant for single instance deployments and tests. Expired
+// entries are evicted on every use.
+#[derive(Clone, Default)]
+pub struct HashmapMagicLinkStore {
+    used: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl MagicLinkStore for HashmapMagicLinkStore {
+    async fn mark_used(&self, jti: &str, ttl: Duration) -> Result<bool, MagicLinkStoreError> {
+        let now = Instant::now();
+        let mut used = self
+            .used
+            .lock()
+            .map_err(|e| MagicLinkStoreError::UnexpectedError(eyre!("{}", e)))?;
+
+        used.retain(|_, expires_at| *expires_at > now);
+        if used.contains_key(jti) {
+            return Ok(false);
+        }
+        used.insert(jti.to_owned(), now + ttl);
+        Ok(true)
+    }
+
+    async fn is_used(&self, jti: &str) -> Result<bool, MagicLinkStoreError> {
+        let used = self
+            .used
+            .lock()
+            .map_err(|e| MagicLinkStoreError::UnexpectedError(eyre!("{}", e)))?;
+        Ok(used
+            .get(jti)
+            .is_some_and(|expires_at| *expires_at > Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_link_is_only_used_once() {
+        let store = HashmapMagicLinkStore::default();
+        let ttl = Duration::from_secs(60);
+
+        assert!(store.mark_used("link", ttl).await.unwrap());
+        assert!(!store.mark_used("link", ttl).await.unwrap());
+        assert!(store.mark_used("other", ttl).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_is_used_does_not_use_link() {
+        let store = HashmapMagicLinkStore::default();
+
+        assert!(!store.is_used("link").await.unwrap());
+        assert!(!store.is_used("link").await.unwrap());
+        store
+            .mark_used("link", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(store.is_used("link").await.unwrap());
+    }
+}
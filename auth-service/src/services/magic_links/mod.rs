@@ -0,0 +1,52 @@
+pub mod hashmap_magic_link_store;
+pub mod redis_magic_link_store;
+
+use std::{future::Future, time::Duration};
+
+use color_eyre::eyre::Report;
+use thiserror::Error;
+
+pub use hashmap_magic_link_store::HashmapMagicLinkStore;
+pub use redis_magic_link_store::RedisMagicLinkStore;
+
+// Remembers the magic links that were already used, which makes each signed link single-use.
+// Entries only need to outlive the links themselves.
+pub trait MagicLinkStore {
+    // Records the link `jti` for `ttl`, returning false if it was already used
+    fn mark_used(
+        &self,
+        jti: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<bool, MagicLinkStoreError>> + Send;
+    // Whether the link `jti` was used, without recording it
+    fn is_used(&self, jti: &str) -> impl Future<Output = Result<bool, MagicLinkStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum MagicLinkStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// Redis shares the used links between all instances; the in-memory store only suits a single one
+#[derive(Clone)]
+pub enum MagicLinkStoreBackend {
+    InMemory(HashmapMagicLinkStore),
+    Redis(RedisMagicLinkStore),
+}
+
+impl MagicLinkStore for MagicLinkStoreBackend {
+    async fn mark_used(&self, jti: &str, ttl: Duration) -> Result<bool, MagicLinkStoreError> {
+        match self {
+            Self::InMemory(store) => store.mark_used(jti, ttl).await,
+            Self::Redis(store) => store.mark_used(jti, ttl).await,
+        }
+    }
+
+    async fn is_used(&self, jti: &str) -> Result<bool, MagicLinkStoreError> {
+        match self {
+            Self::InMemory(store) => store.is_used(jti).await,
+            Self::Redis(store) => store.is_used(jti).await,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use tracing::instrument;
+
+use super::{MagicLinkStore, MagicLinkStoreError};
+
+// We are using a key prefix to prevent collisions and organize data!
+const USED_MAGIC_LINK_KEY_PREFIX: &str = "used_magic_link:";
+
+// Shares used links between all instances
+#[derive(Clone)]
+pub struct RedisMagicLinkStore {
+    connection_manager: MultiplexedConnection,
+}
+
+impl RedisMagicLinkStore {
+    pub fn new(connection_manager: MultiplexedConnection) -> Self {
+        Self { connection_manager }
+    }
+}
+
+impl MagicLinkStore for RedisMagicLinkStore {
+    #[instrument(skip_all)]
+    async fn mark_used(&self, jti: &str, ttl: Duration) -> Result<bool, MagicLinkStoreError> {
+        let key = format!("{}{}", USED_MAGIC_LINK_KEY_PREFIX, jti);
+        // `SET NX` is atomic, so a link opened twice at once still only signs in once
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::PX(ttl.as_millis().max(1) as u64));
+
+        let mut conn = self.connection_manager.clone();
+        let inserted: Option<String> = conn
+            .set_options(key, true, options)
+            .await
+            .wrap_err("Failed to record magic link in Redis")
+            .map_err(MagicLinkStoreError::UnexpectedError)?;
+        Ok(inserted.is_some())
+    }
+
+    #[instrument(skip_all)]
+    async fn is_used(&self, jti: &str) -> Result<bool, MagicLinkStoreError> {
+        let key = format!("{}{}", USED_MAGIC_LINK_KEY_PREFIX, jti);
+        let mut conn = self.connection_manager.clone();
+        conn.exists(key)
+            .await
+            .wrap_err("Failed to look up magic link in Redis")
+            .map_err(MagicLinkStoreError::UnexpectedError)
+    }
+}
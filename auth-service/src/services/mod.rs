@@ -1,5 +1,17 @@
+pub mod breached_passwords;
 pub mod data_stores;
+pub mod dpop_replay;
+pub mod email_outbox;
+pub mod known_devices;
+pub mod magic_links;
+pub mod oidc;
+pub mod passkeys;
+pub mod password_hashing;
+pub mod phone_verification;
+pub mod rate_limiting;
+pub mod saml;
 
+pub use breached_passwords::{BreachedPasswordChecker, BreachedPasswordCheckerError};
 pub use data_stores::{
     BannedTokenStore, LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError, UserStore,
     UserStoreError,
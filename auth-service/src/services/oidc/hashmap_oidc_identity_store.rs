@@ -0,0 +1,73 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{OidcIdentityStore, OidcStoreError};
+use crate::domain::models::Email;
+
+#[derive(Clone, Default)]
+pub struct HashmapOidcIdentityStore {
+    identities: Arc<Mutex<HashMap<(String, String), Email>>>,
+}
+
+impl OidcIdentityStore for HashmapOidcIdentityStore {
+    async fn get_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<Email>, OidcStoreError> {
+        let identities = self
+            .identities
+            .lock()
+            .map_err(|e| OidcStoreError::UnexpectedError(eyre!("{}", e)))?;
+        Ok(identities
+            .get(&(provider.to_owned(), subject.to_owned()))
+            .cloned())
+    }
+
+    async fn link(
+        &self,
+        provider: &str,
+        subject: &str,
+        email: &Email,
+    ) -> Result<(), OidcStoreError> {
+        let mut identities = self
+            .identities
+            .lock()
+            .map_err(|e| OidcStoreError::UnexpectedError(eyre!("{}", e)))?;
+        identities
+            .entry((provider.to_owned(), subject.to_owned()))
+            .or_insert_with(|| email.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(address: &str) -> Email {
+        Email::new(address.to_owned().into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_identity_stays_linked_to_first_user() {
+        let store = HashmapOidcIdentityStore::default();
+        assert!(store.get_user("google", "123").await.unwrap().is_none());
+
+        store
+            .link("google", "123", &email("first@example.com"))
+            .await
+            .unwrap();
+        store
+            .link("google", "123", &email("second@example.com"))
+            .await
+            .unwrap();
+
+        assert!(store.get_user("google", "123").await.unwrap() == Some(email("first@example.com")));
+        assert!(store.get_user("github", "123").await.unwrap().is_none());
+    }
+}
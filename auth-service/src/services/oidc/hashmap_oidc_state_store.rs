@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{OidcLoginState, OidcStateStore, OidcStoreError};
+
+// Keeps the state in process memory, so the callback has to reach the instance the login started
+// on. Meant for single instance deployments and tests. Expired entries are evicted on every insert.
+#[derive(Clone, Default)]
+pub struct HashmapOidcStateStore {
+    states: Arc<Mutex<HashMap<String, (OidcLoginState, Instant)>>>,
+}
+
+impl OidcStateStore for HashmapOidcStateStore {
+    async fn add_state(
+        &self,
+        state: &str,
+        login: OidcLoginState,
+        ttl: Duration,
+    ) -> Result<(), OidcStoreError> {
+        let now = Instant::now();
+        let mut states = self
+            .states
+            .lock()
+            .map_err(|e| OidcStoreError::UnexpectedError(eyre!("{}", e)))?;
+
+        states.retain(|_, (_, expires_at)| *expires_at > now);
+        states.insert(state.to_owned(), (login, now + ttl));
+        Ok(())
+    }
+
+    async fn take_state(&self, state: &str) -> Result<Option<OidcLoginState>, OidcStoreError> {
+        let mut states = self
+            .states
+            .lock()
+            .map_err(|e| OidcStoreError::UnexpectedError(eyre!("{}", e)))?;
+
+        Ok(states
+            .remove(state)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(login, _)| login))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login() -> OidcLoginState {
+        OidcLoginState {
+            provider: "google".to_owned(),
+            nonce: "nonce".to_owned(),
+            code_verifier: "verifier".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_can_be_taken_once() {
+        let store = HashmapOidcStateStore::default();
+        store
+            .add_state("state", login(), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        assert_eq!(store.take_state("state").await.unwrap(), Some(login()));
+        assert_eq!(store.take_state("state").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_state_is_gone() {
+        let store = HashmapOidcStateStore::default();
+        store
+            .add_state("state", login(), Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(store.take_state("state").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,124 @@
+pub mod hashmap_oidc_identity_store;
+pub mod hashmap_oidc_state_store;
+pub mod postgres_oidc_identity_store;
+pub mod redis_oidc_state_store;
+
+use std::{future::Future, time::Duration};
+
+use color_eyre::eyre::Report;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::domain::models::Email;
+
+pub use hashmap_oidc_identity_store::HashmapOidcIdentityStore;
+pub use hashmap_oidc_state_store::HashmapOidcStateStore;
+pub use postgres_oidc_identity_store::PostgresOidcIdentityStore;
+pub use redis_oidc_state_store::RedisOidcStateStore;
+
+// Links the accounts of users at upstream providers to their account here
+pub trait OidcIdentityStore {
+    // The user the `subject` of `provider` is linked to, if any
+    fn get_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> impl Future<Output = Result<Option<Email>, OidcStoreError>> + Send;
+
+    // Links `subject` to the user, and records that it was used to sign in
+    fn link(
+        &self,
+        provider: &str,
+        subject: &str,
+        email: &Email,
+    ) -> impl Future<Output = Result<(), OidcStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum OidcStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// An authorization request in progress, until the provider redirects the browser back
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OidcLoginState {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+// Holds the state of authorization requests in progress. Each state can be taken only once.
+pub trait OidcStateStore {
+    fn add_state(
+        &self,
+        state: &str,
+        login: OidcLoginState,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<(), OidcStoreError>> + Send;
+
+    fn take_state(
+        &self,
+        state: &str,
+    ) -> impl Future<Output = Result<Option<OidcLoginState>, OidcStoreError>> + Send;
+}
+
+// Selects the store implementations at startup. The in-memory stores only suit tests and single
+// instance deployments without a database.
+#[derive(Clone)]
+pub enum OidcIdentityStoreBackend {
+    InMemory(HashmapOidcIdentityStore),
+    Postgres(PostgresOidcIdentityStore),
+}
+
+impl OidcIdentityStore for OidcIdentityStoreBackend {
+    async fn get_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<Email>, OidcStoreError> {
+        match self {
+            Self::InMemory(store) => store.get_user(provider, subject).await,
+            Self::Postgres(store) => store.get_user(provider, subject).await,
+        }
+    }
+
+    async fn link(
+        &self,
+        provider: &str,
+        subject: &str,
+        email: &Email,
+    ) -> Result<(), OidcStoreError> {
+        match self {
+            Self::InMemory(store) => store.link(provider, subject, email).await,
+            Self::Postgres(store) => store.link(provider, subject, email).await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum OidcStateStoreBackend {
+    InMemory(HashmapOidcStateStore),
+    Redis(RedisOidcStateStore),
+}
+
+impl OidcStateStore for OidcStateStoreBackend {
+    async fn add_state(
+        &self,
+        state: &str,
+        login: OidcLoginState,
+        ttl: Duration,
+    ) -> Result<(), OidcStoreError> {
+        match self {
+            Self::InMemory(store) => store.add_state(state, login, ttl).await,
+            Self::Redis(store) => store.add_state(state, login, ttl).await,
+        }
+    }
+
+    async fn take_state(&self, state: &str) -> Result<Option<OidcLoginState>, OidcStoreError> {
+        match self {
+            Self::InMemory(store) => store.take_state(state).await,
+            Self::Redis(store) => store.take_state(state).await,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+use super::{OidcIdentityStore, OidcStoreError};
+use crate::domain::models::Email;
+
+#[derive(Clone)]
+pub struct PostgresOidcIdentityStore {
+    pool: PgPool,
+}
+
+impl PostgresOidcIdentityStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl OidcIdentityStore for PostgresOidcIdentityStore {
+    #[tracing::instrument(name = "Retrieving OIDC identity from PostgreSQL", skip_all)]
+    async fn get_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<Email>, OidcStoreError> {
+        let email = sqlx::query_scalar!(
+            r#"
+            SELECT email
+            FROM oidc_identities
+            WHERE provider = $1 AND subject = $2
+            "#,
+            provider,
+            subject
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| OidcStoreError::UnexpectedError(e.into()))?;
+
+        email
+            .map(|email| Email::new(email.into()).map_err(OidcStoreError::UnexpectedError))
+            .transpose()
+    }
+
+    #[tracing::instrument(name = "Linking OIDC identity in PostgreSQL", skip_all)]
+    async fn link(
+        &self,
+        provider: &str,
+        subject: &str,
+        email: &Email,
+    ) -> Result<(), OidcStoreError> {
+        // An identity stays linked to the user it was first linked to
+        sqlx::query!(
+            r#"
+            INSERT INTO oidc_identities (provider, subject, email, last_used_at)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (provider, subject) DO UPDATE SET last_used_at = NOW()
+            "#,
+            provider,
+            subject,
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| OidcStoreError::UnexpectedError(e.into()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use tracing::instrument;
+
+use super::{OidcLoginState, OidcStateStore, OidcStoreError};
+
+// We are using a key prefix to prevent collisions and organize data!
+const OIDC_STATE_KEY_PREFIX: &str = "oidc_state:";
+
+// Shares the state between all instances, so the provider may redirect back to any of them
+#[derive(Clone)]
+pub struct RedisOidcStateStore {
+    connection_manager: MultiplexedConnection,
+}
+
+impl RedisOidcStateStore {
+    pub fn new(connection_manager: MultiplexedConnection) -> Self {
+        Self { connection_manager }
+    }
+}
+
+impl OidcStateStore for RedisOidcStateStore {
+    #[instrument(skip_all)]
+    async fn add_state(
+        &self,
+        state: &str,
+        login: OidcLoginState,
+        ttl: Duration,
+    ) -> Result<(), OidcStoreError> {
+        let value = serde_json::to_string(&login)
+            .wrap_err("Failed to serialize OIDC state")
+            .map_err(OidcStoreError::UnexpectedError)?;
+
+        let mut conn = self.connection_manager.clone();
+        let _: () = conn
+            .set_ex(get_key(state), value, ttl.as_secs().max(1))
+            .await
+            .wrap_err("Failed to set OIDC state in Redis")
+            .map_err(OidcStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn take_state(&self, state: &str) -> Result<Option<OidcLoginState>, OidcStoreError> {
+        // `GETDEL` is atomic, so a state cannot be used for two callbacks
+        let mut conn = self.connection_manager.clone();
+        let value: Option<String> = conn
+            .get_del(get_key(state))
+            .await
+            .wrap_err("Failed to take OIDC state from Redis")
+            .map_err(OidcStoreError::UnexpectedError)?;
+
+        value
+            .map(|value| {
+                serde_json::from_str(&value)
+                    .wrap_err("Failed to deserialize OIDC state")
+                    .map_err(OidcStoreError::UnexpectedError)
+            })
+            .transpose()
+    }
+}
+
+fn get_key(state: &str) -> String {
+    format!("{}{}", OIDC_STATE_KEY_PREFIX, state)
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{PasskeyChallenge, PasskeyChallengeStore, PasskeyStoreError};
+
+// Keeps challenges in process memory, so a ceremony has to finish on the instance it started on.
+// Meant for single instance deployments and tests. Expired entries are evicted on every insert.
+#[derive(Clone, Default)]
+pub struct HashmapPasskeyChallengeStore {
+    challenges: Arc<Mutex<HashMap<String, (PasskeyChallenge, Instant)>>>,
+}
+
+impl PasskeyChallengeStore for HashmapPasskeyChallengeStore {
+    async fn add_challenge(
+        &self,
+        id: &str,
+        challenge: PasskeyChallenge,
+        ttl: Duration,
+    ) -> Result<(), PasskeyStoreError> {
+        let now = Instant::now();
+        let mut challenges = self
+            .challenges
+            .lock()
+            .map_err(|e| PasskeyStoreError::UnexpectedError(eyre!("{}", e)))?;
+
+        challenges.retain(|_, (_, expires_at)| *expires_at > now);
+        challenges.insert(id.to_owned(), (challenge, now + ttl));
+        Ok(())
+    }
+
+    async fn take_challenge(
+        &self,
+        id: &str,
+    ) -> Result<Option<PasskeyChallenge>, PasskeyStoreError> {
+        let mut challenges = self
+            .challenges
+            .lock()
+            .map_err(|e| PasskeyStoreError::UnexpectedError(eyre!("{}", e)))?;
+
+        Ok(challenges
+            .remove(id)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(challenge, _)| challenge))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::passkeys::PasskeyCeremony;
+
+    fn challenge() -> PasskeyChallenge {
+        PasskeyChallenge {
+            challenge: vec![1, 2, 3],
+            ceremony: PasskeyCeremony::Authentication {
+                email: None,
+                login_attempt_id: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_challenge_can_be_taken_once() {
+        let store = HashmapPasskeyChallengeStore::default();
+        store
+            .add_challenge("id", challenge(), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        assert_eq!(store.take_challenge("id").await.unwrap(), Some(challenge()));
+        assert_eq!(store.take_challenge("id").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_challenge_is_gone() {
+        let store = HashmapPasskeyChallengeStore::default();
+        store
+            .add_challenge("id", challenge(), Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(store.take_challenge("id").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{PasskeyCredential, PasskeyStore, PasskeyStoreError};
+use crate::domain::models::Email;
+
+#[derive(Clone, Default)]
+pub struct HashmapPasskeyStore {
+    credentials: Arc<Mutex<HashMap<Vec<u8>, PasskeyCredential>>>,
+}
+
+impl HashmapPasskeyStore {
+    fn lock(
+        &self,
+    ) -> Result<std::sync::MutexGuard<'_, HashMap<Vec<u8>, PasskeyCredential>>, PasskeyStoreError>
+    {
+        self.credentials
+            .lock()
+            .map_err(|e| PasskeyStoreError::UnexpectedError(eyre!("{}", e)))
+    }
+}
+
+impl PasskeyStore for HashmapPasskeyStore {
+    async fn add_credential(&self, credential: PasskeyCredential) -> Result<(), PasskeyStoreError> {
+        let mut credentials = self.lock()?;
+        if credentials.contains_key(&credential.credential_id) {
+            return Err(PasskeyStoreError::CredentialAlreadyExists);
+        }
+        credentials.insert(credential.credential_id.clone(), credential);
+        Ok(())
+    }
+
+    async fn get_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> Result<PasskeyCredential, PasskeyStoreError> {
+        self.lock()?
+            .get(credential_id)
+            .cloned()
+            .ok_or(PasskeyStoreError::CredentialNotFound)
+    }
+
+    async fn get_credentials(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<PasskeyCredential>, PasskeyStoreError> {
+        Ok(self
+            .lock()?
+            .values()
+            .filter(|credential| credential.email == *email)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> Result<(), PasskeyStoreError> {
+        let mut credentials = self.lock()?;
+        let credential = credentials
+            .get_mut(credential_id)
+            .ok_or(PasskeyStoreError::CredentialNotFound)?;
+        if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+            return Err(PasskeyStoreError::SignCountRegression);
+        }
+        credential.sign_count = sign_count;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential(id: u8, email: &str) -> PasskeyCredential {
+        PasskeyCredential {
+            credential_id: vec![id],
+            email: Email::new(email.to_owned().into()).unwrap(),
+            user_handle: vec![0; 16],
+            public_key: vec![4; 65],
+            sign_count: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_credentials() {
+        let store = HashmapPasskeyStore::default();
+        store
+            .add_credential(credential(1, "a@example.com"))
+            .await
+            .unwrap();
+        store
+            .add_credential(credential(2, "a@example.com"))
+            .await
+            .unwrap();
+        store
+            .add_credential(credential(3, "b@example.com"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.add_credential(credential(1, "b@example.com")).await,
+            Err(PasskeyStoreError::CredentialAlreadyExists)
+        );
+        let email = Email::new("a@example.com".to_owned().into()).unwrap();
+        assert_eq!(store.get_credentials(&email).await.unwrap().len(), 2);
+
+        store.update_sign_count(&[1], 7).await.unwrap();
+        assert_eq!(store.get_credential(&[1]).await.unwrap().sign_count, 7);
+        assert_eq!(
+            store.update_sign_count(&[1], 7).await,
+            Err(PasskeyStoreError::SignCountRegression)
+        );
+        assert_eq!(store.get_credential(&[1]).await.unwrap().sign_count, 7);
+        assert_eq!(
+            store.get_credential(&[4]).await.err(),
+            Some(PasskeyStoreError::CredentialNotFound)
+        );
+    }
+}
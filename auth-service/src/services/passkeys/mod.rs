@@ -0,0 +1,186 @@
+pub mod hashmap_passkey_challenge_store;
+pub mod hashmap_passkey_store;
+pub mod postgres_passkey_store;
+pub mod redis_passkey_challenge_store;
+
+use std::{future::Future, time::Duration};
+
+use color_eyre::eyre::Report;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::domain::models::Email;
+
+pub use hashmap_passkey_challenge_store::HashmapPasskeyChallengeStore;
+pub use hashmap_passkey_store::HashmapPasskeyStore;
+pub use postgres_passkey_store::PostgresPasskeyStore;
+pub use redis_passkey_challenge_store::RedisPasskeyChallengeStore;
+
+// A registered WebAuthn credential. The public key is a SEC1 encoded P-256 point.
+#[derive(Clone)]
+pub struct PasskeyCredential {
+    pub credential_id: Vec<u8>,
+    pub email: Email,
+    pub user_handle: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+pub trait PasskeyStore {
+    fn add_credential(
+        &self,
+        credential: PasskeyCredential,
+    ) -> impl Future<Output = Result<(), PasskeyStoreError>> + Send;
+    fn get_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> impl Future<Output = Result<PasskeyCredential, PasskeyStoreError>> + Send;
+    fn get_credentials(
+        &self,
+        email: &Email,
+    ) -> impl Future<Output = Result<Vec<PasskeyCredential>, PasskeyStoreError>> + Send;
+    // Records a successful authentication with the counter the authenticator reported. The
+    // counter has to be higher than the stored one, unless the authenticator keeps none and both
+    // are zero, and is compared and stored in one step so concurrent logins cannot both pass.
+    fn update_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> impl Future<Output = Result<(), PasskeyStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum PasskeyStoreError {
+    #[error("Credential already exists")]
+    CredentialAlreadyExists,
+    #[error("Credential not found")]
+    CredentialNotFound,
+    #[error("Sign count did not increase")]
+    SignCountRegression,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for PasskeyStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::CredentialAlreadyExists, Self::CredentialAlreadyExists)
+                | (Self::CredentialNotFound, Self::CredentialNotFound)
+                | (Self::SignCountRegression, Self::SignCountRegression)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// What a challenge was issued for. Registrations are made by a signed in user; authentications
+// either stand on their own, or replace the emailed code of the login attempt they belong to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PasskeyCeremony {
+    Registration {
+        email: String,
+        user_handle: Vec<u8>,
+    },
+    Authentication {
+        email: Option<String>,
+        login_attempt_id: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasskeyChallenge {
+    pub challenge: Vec<u8>,
+    pub ceremony: PasskeyCeremony,
+}
+
+// Holds the challenges of ceremonies in progress. Each challenge can be taken only once.
+pub trait PasskeyChallengeStore {
+    fn add_challenge(
+        &self,
+        id: &str,
+        challenge: PasskeyChallenge,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<(), PasskeyStoreError>> + Send;
+    fn take_challenge(
+        &self,
+        id: &str,
+    ) -> impl Future<Output = Result<Option<PasskeyChallenge>, PasskeyStoreError>> + Send;
+}
+
+// Selects the store implementations at startup. The in-memory stores only suit tests and single
+// instance deployments without a database.
+#[derive(Clone)]
+pub enum PasskeyStoreBackend {
+    InMemory(HashmapPasskeyStore),
+    Postgres(PostgresPasskeyStore),
+}
+
+impl PasskeyStore for PasskeyStoreBackend {
+    async fn add_credential(&self, credential: PasskeyCredential) -> Result<(), PasskeyStoreError> {
+        match self {
+            Self::InMemory(store) => store.add_credential(credential).await,
+            Self::Postgres(store) => store.add_credential(credential).await,
+        }
+    }
+
+    async fn get_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> Result<PasskeyCredential, PasskeyStoreError> {
+        match self {
+            Self::InMemory(store) => store.get_credential(credential_id).await,
+            Self::Postgres(store) => store.get_credential(credential_id).await,
+        }
+    }
+
+    async fn get_credentials(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<PasskeyCredential>, PasskeyStoreError> {
+        match self {
+            Self::InMemory(store) => store.get_credentials(email).await,
+            Self::Postgres(store) => store.get_credentials(email).await,
+        }
+    }
+
+    async fn update_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> Result<(), PasskeyStoreError> {
+        match self {
+            Self::InMemory(store) => store.update_sign_count(credential_id, sign_count).await,
+            Self::Postgres(store) => store.update_sign_count(credential_id, sign_count).await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum PasskeyChallengeStoreBackend {
+    InMemory(HashmapPasskeyChallengeStore),
+    Redis(RedisPasskeyChallengeStore),
+}
+
+impl PasskeyChallengeStore for PasskeyChallengeStoreBackend {
+    async fn add_challenge(
+        &self,
+        id: &str,
+        challenge: PasskeyChallenge,
+        ttl: Duration,
+    ) -> Result<(), PasskeyStoreError> {
+        match self {
+            Self::InMemory(store) => store.add_challenge(id, challenge, ttl).await,
+            Self::Redis(store) => store.add_challenge(id, challenge, ttl).await,
+        }
+    }
+
+    async fn take_challenge(
+        &self,
+        id: &str,
+    ) -> Result<Option<PasskeyChallenge>, PasskeyStoreError> {
+        match self {
+            Self::InMemory(store) => store.take_challenge(id).await,
+            Self::Redis(store) => store.take_challenge(id).await,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use color_eyre::eyre::{eyre, Context};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+use super::{PasskeyCredential, PasskeyStore, PasskeyStoreError};
+use crate::domain::models::Email;
+
+#[derive(Clone)]
+pub struct PostgresPasskeyStore {
+    pool: PgPool,
+}
+
+impl PostgresPasskeyStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+struct PasskeyCredentialRow {
+    credential_id: Vec<u8>,
+    email: String,
+    user_handle: Vec<u8>,
+    public_key: Vec<u8>,
+    sign_count: i64,
+}
+
+impl TryFrom<PasskeyCredentialRow> for PasskeyCredential {
+    type Error = PasskeyStoreError;
+
+    fn try_from(row: PasskeyCredentialRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            credential_id: row.credential_id,
+            email: Email::new(row.email.into()).map_err(PasskeyStoreError::UnexpectedError)?,
+            user_handle: row.user_handle,
+            public_key: row.public_key,
+            sign_count: row
+                .sign_count
+                .try_into()
+                .map_err(|_| PasskeyStoreError::UnexpectedError(eyre!("Invalid sign count")))?,
+        })
+    }
+}
+
+impl PasskeyStore for PostgresPasskeyStore {
+    #[tracing::instrument(name = "Adding passkey credential to PostgreSQL", skip_all)]
+    async fn add_credential(&self, credential: PasskeyCredential) -> Result<(), PasskeyStoreError> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO passkey_credentials (credential_id, email, user_handle, public_key, sign_count)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            credential.credential_id,
+            credential.email.as_ref().expose_secret(),
+            credential.user_handle,
+            credential.public_key,
+            i64::from(credential.sign_count)
+        )
+        .execute(&self.pool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(db_err)) if db_err.code() == Some("23505".into()) => {
+                Err(PasskeyStoreError::CredentialAlreadyExists)
+            }
+            Err(e) => Err(PasskeyStoreError::UnexpectedError(e.into())),
+        }
+    }
+
+    #[tracing::instrument(name = "Retrieving passkey credential from PostgreSQL", skip_all)]
+    async fn get_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> Result<PasskeyCredential, PasskeyStoreError> {
+        sqlx::query_as!(
+            PasskeyCredentialRow,
+            r#"
+            SELECT credential_id, email, user_handle, public_key, sign_count
+            FROM passkey_credentials
+            WHERE credential_id = $1
+            "#,
+            credential_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| PasskeyStoreError::UnexpectedError(e.into()))?
+        .ok_or(PasskeyStoreError::CredentialNotFound)?
+        .try_into()
+    }
+
+    #[tracing::instrument(
+        name = "Retrieving passkey credentials of user from PostgreSQL",
+        skip_all
+    )]
+    async fn get_credentials(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<PasskeyCredential>, PasskeyStoreError> {
+        sqlx::query_as!(
+            PasskeyCredentialRow,
+            r#"
+            SELECT credential_id, email, user_handle, public_key, sign_count
+            FROM passkey_credentials
+            WHERE email = $1
+            ORDER BY created_at
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| PasskeyStoreError::UnexpectedError(e.into()))?
+        .into_iter()
+        .map(PasskeyCredential::try_from)
+        .collect()
+    }
+
+    #[tracing::instrument(name = "Updating passkey sign count in PostgreSQL", skip_all)]
+    async fn update_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> Result<(), PasskeyStoreError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE passkey_credentials
+            SET sign_count = $2, last_used_at = NOW()
+            WHERE credential_id = $1 AND (sign_count < $2 OR (sign_count = 0 AND $2 = 0))
+            "#,
+            credential_id,
+            i64::from(sign_count)
+        )
+        .execute(&self.pool)
+        .await
+        .wrap_err("Failed to update sign count")
+        .map_err(PasskeyStoreError::UnexpectedError)?;
+
+        // The credential was just read, so a missing row means its counter was not exceeded
+        match result.rows_affected() {
+            0 => Err(PasskeyStoreError::SignCountRegression),
+            _ => Ok(()),
+        }
+    }
+}
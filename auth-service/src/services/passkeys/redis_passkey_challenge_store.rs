@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use tracing::instrument;
+
+use super::{PasskeyChallenge, PasskeyChallengeStore, PasskeyStoreError};
+
+// We are using a key prefix to prevent collisions and organize data!
+const PASSKEY_CHALLENGE_KEY_PREFIX: &str = "passkey_challenge:";
+
+// Shares challenges between all instances, so a ceremony may finish on another instance
+#[derive(Clone)]
+pub struct RedisPasskeyChallengeStore {
+    connection_manager: MultiplexedConnection,
+}
+
+impl RedisPasskeyChallengeStore {
+    pub fn new(connection_manager: MultiplexedConnection) -> Self {
+        Self { connection_manager }
+    }
+}
+
+impl PasskeyChallengeStore for RedisPasskeyChallengeStore {
+    #[instrument(skip_all)]
+    async fn add_challenge(
+        &self,
+        id: &str,
+        challenge: PasskeyChallenge,
+        ttl: Duration,
+    ) -> Result<(), PasskeyStoreError> {
+        let value = serde_json::to_string(&challenge)
+            .wrap_err("Failed to serialize passkey challenge")
+            .map_err(PasskeyStoreError::UnexpectedError)?;
+
+        let mut conn = self.connection_manager.clone();
+        let _: () = conn
+            .set_ex(get_key(id), value, ttl.as_secs().max(1))
+            .await
+            .wrap_err("Failed to set passkey challenge in Redis")
+            .map_err(PasskeyStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn take_challenge(
+        &self,
+        id: &str,
+    ) -> Result<Option<PasskeyChallenge>, PasskeyStoreError> {
+        // `GETDEL` is atomic, so a challenge cannot be answered twice
+        let mut conn = self.connection_manager.clone();
+        let value: Option<String> = conn
+            .get_del(get_key(id))
+            .await
+            .wrap_err("Failed to take passkey challenge from Redis")
+            .map_err(PasskeyStoreError::UnexpectedError)?;
+
+        value
+            .map(|value| {
+                serde_json::from_str(&value)
+                    .wrap_err("Failed to deserialize passkey challenge")
+                    .map_err(PasskeyStoreError::UnexpectedError)
+            })
+            .transpose()
+    }
+}
+
+fn get_key(id: &str) -> String {
+    format!("{}{}", PASSKEY_CHALLENGE_KEY_PREFIX, id)
+}
@@ -0,0 +1,441 @@
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use hmac::{Hmac, Mac};
+use pbkdf2::Pbkdf2;
+use scrypt::Scrypt;
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+
+use crate::utils::constants::{
+    ARGON2_ITERATIONS, ARGON2_MEMORY_KIB, ARGON2_PARALLELISM, PASSWORD_PEPPER,
+    PASSWORD_PEPPER_VERSION, PASSWORD_PREVIOUS_PEPPERS,
+};
+
+// Modular crypt prefixes used by bcrypt implementations
+const BCRYPT_PREFIXES: [&str; 4] = ["$2a$", "$2b$", "$2x$", "$2y$"];
+
+// The Argon2id cost new password hashes are computed with. Stored hashes using any other cost,
+// or any other algorithm, are upgraded the next time their owner logs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordHashParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: *ARGON2_MEMORY_KIB,
+            iterations: *ARGON2_ITERATIONS,
+            parallelism: *ARGON2_PARALLELISM,
+        }
+    }
+}
+
+impl PasswordHashParams {
+    fn hasher(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    // Whether a stored hash was produced with something other than these parameters
+    pub fn needs_rehash(&self, password_hash: &str) -> Result<bool> {
+        if is_bcrypt(password_hash) {
+            return Ok(true);
+        }
+
+        let password_hash = PasswordHash::new(password_hash)?;
+        if password_hash.algorithm != Algorithm::Argon2id.ident()
+            || password_hash.version != Some(Version::V0x13.into())
+        {
+            return Ok(true);
+        }
+
+        let params = Params::try_from(&password_hash)?;
+        Ok(params.m_cost() != self.memory_kib
+            || params.t_cost() != self.iterations
+            || params.p_cost() != self.parallelism)
+    }
+}
+
+// A server-side secret mixed into passwords with HMAC-SHA256 before they are hashed, so a leaked
+// database alone is not enough to brute force them. The version is stored next to each hash.
+#[derive(Clone)]
+pub struct PasswordPepper {
+    pub version: i32,
+    secret: SecretString,
+}
+
+impl PasswordPepper {
+    pub fn new(version: i32, secret: SecretString) -> Self {
+        Self { version, secret }
+    }
+
+    fn apply(&self, password: &str) -> Result<SecretString> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.expose_secret().as_bytes())?;
+        mac.update(password.as_bytes());
+        Ok(hex::encode(mac.finalize().into_bytes()).into())
+    }
+}
+
+// The pepper new hashes are computed with, plus retired ones that existing hashes may still use.
+// Rotating means adding a new current pepper and moving the old one to `previous`; hashes are
+// migrated the next time their owner logs in, after which the old pepper can be dropped.
+#[derive(Clone, Default)]
+pub struct PasswordPeppers {
+    current: Option<PasswordPepper>,
+    previous: Vec<PasswordPepper>,
+}
+
+impl PasswordPeppers {
+    pub fn new(current: Option<PasswordPepper>, previous: Vec<PasswordPepper>) -> Self {
+        Self { current, previous }
+    }
+
+    // Builds the peppers from `PASSWORD_PEPPER`, `PASSWORD_PEPPER_VERSION` and
+    // `PASSWORD_PREVIOUS_PEPPERS`. Without a configured pepper, passwords are hashed as is.
+    pub fn from_config() -> Result<Self> {
+        let current = PASSWORD_PEPPER
+            .clone()
+            .map(|secret| PasswordPepper::new(*PASSWORD_PEPPER_VERSION, secret));
+        let previous = match PASSWORD_PREVIOUS_PEPPERS.as_ref() {
+            Some(previous) => parse_previous_peppers(previous.expose_secret())?,
+            None => Vec::new(),
+        };
+
+        Ok(Self::new(current, previous))
+    }
+
+    pub fn current(&self) -> Option<&PasswordPepper> {
+        self.current.as_ref()
+    }
+
+    pub fn current_version(&self) -> Option<i32> {
+        self.current.as_ref().map(|pepper| pepper.version)
+    }
+
+    // Looks up the pepper a stored hash was computed with; `None` means the hash is unpeppered
+    pub fn get(&self, version: Option<i32>) -> Result<Option<&PasswordPepper>> {
+        let Some(version) = version else {
+            return Ok(None);
+        };
+
+        self.current
+            .iter()
+            .chain(self.previous.iter())
+            .find(|pepper| pepper.version == version)
+            .map(Some)
+            .ok_or_else(|| eyre!("Password pepper version {} is not configured", version))
+    }
+}
+
+// Parses a comma separated list of `VERSION:SECRET` entries
+fn parse_previous_peppers(value: &str) -> Result<Vec<PasswordPepper>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (version, secret) = entry
+                .split_once(':')
+                .ok_or_else(|| eyre!("Expected VERSION:SECRET in previous password peppers"))?;
+            let version = version
+                .trim()
+                .parse()
+                .wrap_err("Invalid previous password pepper version")?;
+            if secret.is_empty() {
+                return Err(eyre!("Previous password pepper {} is empty", version));
+            }
+            Ok(PasswordPepper::new(version, secret.to_owned().into()))
+        })
+        .collect()
+}
+
+// Hash formats we can verify. Everything except Argon2 only exists in imported legacy data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHashAlgorithm {
+    Argon2,
+    Bcrypt,
+    Pbkdf2,
+    Scrypt,
+}
+
+impl PasswordHashAlgorithm {
+    // Dispatches on the modular crypt prefix for bcrypt, and on the PHC identifier otherwise
+    pub fn detect(password_hash: &str) -> Result<Self> {
+        if is_bcrypt(password_hash) {
+            return Ok(Self::Bcrypt);
+        }
+
+        let parsed = PasswordHash::new(password_hash)?;
+        match parsed.algorithm.as_str() {
+            "argon2id" | "argon2i" | "argon2d" => Ok(Self::Argon2),
+            "pbkdf2-sha256" | "pbkdf2-sha512" => Ok(Self::Pbkdf2),
+            "scrypt" => Ok(Self::Scrypt),
+            other => Err(eyre!("Unsupported password hash algorithm: {}", other)),
+        }
+    }
+}
+
+fn is_bcrypt(password_hash: &str) -> bool {
+    BCRYPT_PREFIXES
+        .iter()
+        .any(|prefix| password_hash.starts_with(prefix))
+}
+
+#[tracing::instrument(name = "Verify password hash", skip_all)]
+pub async fn verify_password_hash(
+    expected_password_hash: String,
+    password_candidate: String,
+    pepper: Option<&PasswordPepper>,
+) -> Result<()> {
+    let password_candidate = match pepper {
+        Some(pepper) => pepper
+            .apply(&password_candidate)?
+            .expose_secret()
+            .to_owned(),
+        None => password_candidate,
+    };
+
+    tokio::task::spawn_blocking(move || {
+        match PasswordHashAlgorithm::detect(&expected_password_hash)? {
+            PasswordHashAlgorithm::Bcrypt => {
+                if bcrypt::verify(password_candidate.as_bytes(), &expected_password_hash)? {
+                    Ok(())
+                } else {
+                    Err(eyre!("Password does not match bcrypt hash"))
+                }
+            }
+            PasswordHashAlgorithm::Argon2
+            | PasswordHashAlgorithm::Pbkdf2
+            | PasswordHashAlgorithm::Scrypt => {
+                let expected_password_hash: PasswordHash<'_> =
+                    PasswordHash::new(&expected_password_hash)?;
+
+                // Each verifier takes its cost parameters from the stored PHC string, and only
+                // accepts hashes carrying its own algorithm identifier
+                expected_password_hash
+                    .verify_password(
+                        &[
+                            &Argon2::default() as &dyn PasswordVerifier,
+                            &Pbkdf2,
+                            &Scrypt,
+                        ],
+                        password_candidate.as_bytes(),
+                    )
+                    .map_err(|e| eyre!(e))
+            }
+        }
+    })
+    .await?
+}
+
+#[tracing::instrument(name = "Computing password hash", skip_all)]
+pub async fn compute_password_hash(
+    password: SecretString,
+    hash_params: PasswordHashParams,
+    pepper: Option<&PasswordPepper>,
+) -> Result<String> {
+    let password = match pepper {
+        Some(pepper) => pepper.apply(password.expose_secret())?,
+        None => password,
+    };
+    let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
+    let hasher = hash_params.hasher()?;
+    let password_hash: Result<String, argon2::password_hash::Error> =
+        tokio::task::spawn_blocking(move || {
+            Ok(hasher
+                .hash_password(password.expose_secret().as_bytes(), &salt)?
+                .to_string())
+        })
+        .await?;
+
+    Ok(password_hash?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: PasswordHashParams = PasswordHashParams {
+        memory_kib: 8192,
+        iterations: 2,
+        parallelism: 1,
+    };
+
+    const PASSWORD: &str = "correct-Horse-battery-st4ple";
+
+    #[tokio::test]
+    async fn test_hash_with_target_params_does_not_need_rehash() {
+        let hash = compute_password_hash(PASSWORD.into(), PARAMS, None)
+            .await
+            .unwrap();
+        assert!(hash.contains("m=8192,t=2,p=1"));
+        assert!(!PARAMS.needs_rehash(&hash).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_hash_with_other_params_needs_rehash() {
+        let weaker = PasswordHashParams {
+            memory_kib: 4096,
+            ..PARAMS
+        };
+        let hash = compute_password_hash(PASSWORD.into(), weaker, None)
+            .await
+            .unwrap();
+        assert!(PARAMS.needs_rehash(&hash).unwrap());
+
+        // Verification still succeeds for hashes made with the old parameters
+        assert!(verify_password_hash(hash, PASSWORD.to_owned(), None)
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_other_argon2_variant_needs_rehash() {
+        let hash = Argon2::new(
+            Algorithm::Argon2i,
+            Version::V0x13,
+            PARAMS.hasher().unwrap().params().clone(),
+        )
+        .hash_password(b"password", &SaltString::generate(&mut rand::thread_rng()))
+        .unwrap()
+        .to_string();
+        assert!(PARAMS.needs_rehash(&hash).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verifies_bcrypt_hash() {
+        let hash = bcrypt::hash(PASSWORD, 4).unwrap();
+        assert_eq!(
+            PasswordHashAlgorithm::detect(&hash).unwrap(),
+            PasswordHashAlgorithm::Bcrypt
+        );
+        assert!(PARAMS.needs_rehash(&hash).unwrap());
+        assert!(
+            verify_password_hash(hash.clone(), PASSWORD.to_owned(), None)
+                .await
+                .is_ok()
+        );
+        assert!(
+            verify_password_hash(hash, "wrong-password".to_owned(), None)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verifies_pbkdf2_hash() {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let params = pbkdf2::Params {
+            rounds: 1000,
+            ..Default::default()
+        };
+        let hash = Pbkdf2
+            .hash_password_customized(PASSWORD.as_bytes(), None, None, params, &salt)
+            .unwrap()
+            .to_string();
+        assert!(hash.starts_with("$pbkdf2-sha256$"));
+        assert!(PARAMS.needs_rehash(&hash).unwrap());
+        assert!(
+            verify_password_hash(hash.clone(), PASSWORD.to_owned(), None)
+                .await
+                .is_ok()
+        );
+        assert!(
+            verify_password_hash(hash, "wrong-password".to_owned(), None)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verifies_scrypt_hash() {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let params = scrypt::Params::new(8, 8, 1, 32).unwrap();
+        let hash = Scrypt
+            .hash_password_customized(PASSWORD.as_bytes(), None, None, params, &salt)
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            PasswordHashAlgorithm::detect(&hash).unwrap(),
+            PasswordHashAlgorithm::Scrypt
+        );
+        assert!(
+            verify_password_hash(hash.clone(), PASSWORD.to_owned(), None)
+                .await
+                .is_ok()
+        );
+        assert!(
+            verify_password_hash(hash, "wrong-password".to_owned(), None)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_hash_format() {
+        assert!(PasswordHashAlgorithm::detect("5f4dcc3b5aa765d61d8327deb882cf99").is_err());
+        assert!(PasswordHashAlgorithm::detect("$md5$rounds=904$salt$hash").is_err());
+    }
+
+    fn pepper(version: i32, secret: &str) -> PasswordPepper {
+        PasswordPepper::new(version, secret.to_owned().into())
+    }
+
+    #[tokio::test]
+    async fn test_peppered_hash_requires_same_pepper() {
+        let current = pepper(2, "current-pepper");
+        let hash = compute_password_hash(PASSWORD.into(), PARAMS, Some(&current))
+            .await
+            .unwrap();
+
+        assert!(
+            verify_password_hash(hash.clone(), PASSWORD.to_owned(), Some(&current))
+                .await
+                .is_ok()
+        );
+        assert!(
+            verify_password_hash(hash.clone(), PASSWORD.to_owned(), None)
+                .await
+                .is_err()
+        );
+        assert!(verify_password_hash(
+            hash,
+            PASSWORD.to_owned(),
+            Some(&pepper(1, "previous-pepper"))
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn test_looks_up_peppers_by_version() {
+        let peppers = PasswordPeppers::new(
+            Some(pepper(2, "current-pepper")),
+            parse_previous_peppers("1:previous-pepper").unwrap(),
+        );
+
+        assert_eq!(peppers.current_version(), Some(2));
+        assert!(peppers.get(None).unwrap().is_none());
+        assert_eq!(peppers.get(Some(1)).unwrap().unwrap().version, 1);
+        assert_eq!(peppers.get(Some(2)).unwrap().unwrap().version, 2);
+        assert!(peppers.get(Some(3)).is_err());
+    }
+
+    #[test]
+    fn test_parse_previous_peppers() {
+        let peppers = parse_previous_peppers("1:first, 2:sec:ond,").unwrap();
+        assert_eq!(peppers.len(), 2);
+        assert_eq!(peppers[1].version, 2);
+        assert_eq!(peppers[1].secret.expose_secret(), "sec:ond");
+
+        assert!(parse_previous_peppers("first").is_err());
+        assert!(parse_previous_peppers("x:first").is_err());
+        assert!(parse_previous_peppers("1:").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{PendingPhoneNumber, PhoneVerificationStore, PhoneVerificationStoreError};
+use crate::domain::models::Email;
+
+// Keeps pending numbers in process memory. Meant for single instance deployments and tests.
+// Expired entries are evicted on every use.
+#[derive(Clone, Default)]
+pub struct HashmapPhoneVerificationStore {
+    pending: Arc<Mutex<HashMap<Email, (PendingPhoneNumber, Instant)>>>,
+}
+
+impl PhoneVerificationStore for HashmapPhoneVerificationStore {
+    async fn add(
+        &self,
+        email: &Email,
+        pending: PendingPhoneNumber,
+        ttl: Duration,
+    ) -> Result<(), PhoneVerificationStoreError> {
+        let now = Instant::now();
+        let mut entries = self
+            .pending
+            .lock()
+            .map_err(|e| PhoneVerificationStoreError::UnexpectedError(eyre!("{}", e)))?;
+        entries.retain(|_, (_, expires_at)| *expires_at > now);
+        entries.insert(email.clone(), (pending, now + ttl));
+        Ok(())
+    }
+
+    async fn take(
+        &self,
+        email: &Email,
+    ) -> Result<Option<PendingPhoneNumber>, PhoneVerificationStoreError> {
+        let now = Instant::now();
+        let mut entries = self
+            .pending
+            .lock()
+            .map_err(|e| PhoneVerificationStoreError::UnexpectedError(eyre!("{}", e)))?;
+        entries.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok(entries.remove(email).map(|(pending, _)| pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(phone_number: &str) -> PendingPhoneNumber {
+        PendingPhoneNumber {
+            phone_number: phone_number.to_owned(),
+            channel: "sms".to_owned(),
+            code: "123456".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_latest_number_can_be_taken_once() {
+        let store = HashmapPhoneVerificationStore::default();
+        let email = Email::new("user@example.com".to_owned().into()).unwrap();
+        let ttl = Duration::from_secs(60);
+
+        store
+            .add(&email, pending("+4930111111"), ttl)
+            .await
+            .unwrap();
+        store
+            .add(&email, pending("+4930222222"), ttl)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.take(&email).await.unwrap(),
+            Some(pending("+4930222222"))
+        );
+        assert_eq!(store.take(&email).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_number_is_gone() {
+        let store = HashmapPhoneVerificationStore::default();
+        let email = Email::new("user@example.com".to_owned().into()).unwrap();
+
+        store
+            .add(&email, pending("+4930111111"), Duration::ZERO)
+            .await
+            .unwrap();
+
+        assert_eq!(store.take(&email).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,75 @@
+pub mod hashmap_phone_verification_store;
+pub mod redis_phone_verification_store;
+
+use std::{future::Future, time::Duration};
+
+use color_eyre::eyre::Report;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::domain::models::Email;
+
+pub use hashmap_phone_verification_store::HashmapPhoneVerificationStore;
+pub use redis_phone_verification_store::RedisPhoneVerificationStore;
+
+// A phone number a user is adding, waiting for the code that was sent to it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingPhoneNumber {
+    // E.164 form
+    pub phone_number: String,
+    pub channel: String,
+    pub code: String,
+}
+
+// Holds one pending phone number per user. Taking it ends the verification, so every code gets a
+// single guess.
+pub trait PhoneVerificationStore {
+    // Replaces any number the user was adding before
+    fn add(
+        &self,
+        email: &Email,
+        pending: PendingPhoneNumber,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<(), PhoneVerificationStoreError>> + Send;
+    fn take(
+        &self,
+        email: &Email,
+    ) -> impl Future<Output = Result<Option<PendingPhoneNumber>, PhoneVerificationStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum PhoneVerificationStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// Redis shares pending numbers between all instances; the in-memory store only suits a single one
+#[derive(Clone)]
+pub enum PhoneVerificationStoreBackend {
+    InMemory(HashmapPhoneVerificationStore),
+    Redis(RedisPhoneVerificationStore),
+}
+
+impl PhoneVerificationStore for PhoneVerificationStoreBackend {
+    async fn add(
+        &self,
+        email: &Email,
+        pending: PendingPhoneNumber,
+        ttl: Duration,
+    ) -> Result<(), PhoneVerificationStoreError> {
+        match self {
+            Self::InMemory(store) => store.add(email, pending, ttl).await,
+            Self::Redis(store) => store.add(email, pending, ttl).await,
+        }
+    }
+
+    async fn take(
+        &self,
+        email: &Email,
+    ) -> Result<Option<PendingPhoneNumber>, PhoneVerificationStoreError> {
+        match self {
+            Self::InMemory(store) => store.take(email).await,
+            Self::Redis(store) => store.take(email).await,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use secrecy::ExposeSecret;
+use tracing::instrument;
+
+use super::{PendingPhoneNumber, PhoneVerificationStore, PhoneVerificationStoreError};
+use crate::domain::models::Email;
+
+// We are using a key prefix to prevent collisions and organize data!
+const PHONE_VERIFICATION_KEY_PREFIX: &str = "phone_verification:";
+
+// Shares pending numbers between all instances, so the code may be entered on another instance
+#[derive(Clone)]
+pub struct RedisPhoneVerificationStore {
+    connection_manager: MultiplexedConnection,
+}
+
+impl RedisPhoneVerificationStore {
+    pub fn new(connection_manager: MultiplexedConnection) -> Self {
+        Self { connection_manager }
+    }
+}
+
+impl PhoneVerificationStore for RedisPhoneVerificationStore {
+    #[instrument(skip_all)]
+    async fn add(
+        &self,
+        email: &Email,
+        pending: PendingPhoneNumber,
+        ttl: Duration,
+    ) -> Result<(), PhoneVerificationStoreError> {
+        let value = serde_json::to_string(&pending)
+            .wrap_err("Failed to serialize pending phone number")
+            .map_err(PhoneVerificationStoreError::UnexpectedError)?;
+
+        let mut conn = self.connection_manager.clone();
+        let _: () = conn
+            .set_ex(get_key(email), value, ttl.as_secs().max(1))
+            .await
+            .wrap_err("Failed to set pending phone number in Redis")
+            .map_err(PhoneVerificationStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn take(
+        &self,
+        email: &Email,
+    ) -> Result<Option<PendingPhoneNumber>, PhoneVerificationStoreError> {
+        // `GETDEL` is atomic, so a code cannot be guessed at twice
+        let mut conn = self.connection_manager.clone();
+        let value: Option<String> = conn
+            .get_del(get_key(email))
+            .await
+            .wrap_err("Failed to take pending phone number from Redis")
+            .map_err(PhoneVerificationStoreError::UnexpectedError)?;
+
+        value
+            .map(|value| {
+                serde_json::from_str(&value)
+                    .wrap_err("Failed to deserialize pending phone number")
+                    .map_err(PhoneVerificationStoreError::UnexpectedError)
+            })
+            .transpose()
+    }
+}
+
+fn get_key(email: &Email) -> String {
+    format!(
+        "{}{}",
+        PHONE_VERIFICATION_KEY_PREFIX,
+        email.as_ref().expose_secret()
+    )
+}
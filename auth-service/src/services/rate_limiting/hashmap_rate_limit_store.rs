@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{RateLimitDecision, RateLimitPolicy, RateLimitStore, RateLimitStoreError, TokenBucket};
+
+// Once this many buckets exist, buckets that have refilled completely are dropped, since a full
+// bucket behaves exactly like a missing one. Sweeping scans every bucket under the lock, so it
+// runs at most once per interval, however many distinct keys come in.
+const SWEEP_THRESHOLD: usize = 10_000;
+const SWEEP_INTERVAL_MS: u64 = 1_000;
+
+#[derive(Default)]
+struct Buckets {
+    buckets: HashMap<String, (RateLimitPolicy, TokenBucket)>,
+    last_sweep_ms: u64,
+}
+
+// Keeps buckets in process memory. Limits are not shared between instances, so this is meant for
+// single instance deployments and tests.
+#[derive(Clone)]
+pub struct HashmapRateLimitStore {
+    buckets: Arc<Mutex<Buckets>>,
+    started_at: Instant,
+}
+
+impl Default for HashmapRateLimitStore {
+    fn default() -> Self {
+        Self {
+            buckets: Arc::new(Mutex::new(Buckets::default())),
+            started_at: Instant::now(),
+        }
+    }
+}
+
+impl HashmapRateLimitStore {
+    fn now_ms(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+}
+
+impl RateLimitStore for HashmapRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitDecision, RateLimitStoreError> {
+        let now_ms = self.now_ms();
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|e| RateLimitStoreError::UnexpectedError(eyre!("{}", e)))?;
+
+        if buckets.buckets.len() >= SWEEP_THRESHOLD
+            && now_ms >= buckets.last_sweep_ms + SWEEP_INTERVAL_MS
+        {
+            buckets.buckets.retain(|_, (policy, bucket)| {
+                bucket.updated_at_ms + bucket.time_until_full(policy).as_millis() as u64 > now_ms
+            });
+            buckets.last_sweep_ms = now_ms;
+        }
+
+        let (_, bucket) = buckets
+            .buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| (*policy, TokenBucket::full(policy, now_ms)));
+
+        Ok(bucket.take(policy, now_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limits_each_key_separately() {
+        let store = HashmapRateLimitStore::default();
+        let policy = RateLimitPolicy::new(2, Duration::from_secs(60));
+
+        assert!(store.take("login:10.0.0.1", &policy).await.unwrap().allowed);
+        assert!(store.take("login:10.0.0.1", &policy).await.unwrap().allowed);
+        assert!(!store.take("login:10.0.0.1", &policy).await.unwrap().allowed);
+
+        let decision = store.take("login:10.0.0.2", &policy).await.unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sweeps_full_buckets_at_most_once_per_interval() {
+        let store = HashmapRateLimitStore::default();
+        let policy = RateLimitPolicy::new(1, Duration::from_millis(1));
+        let bucket_count = || store.buckets.lock().unwrap().buckets.len();
+
+        // The first sweep is due once a whole interval passed since the store started
+        tokio::time::sleep(Duration::from_millis(SWEEP_INTERVAL_MS)).await;
+        for i in 0..SWEEP_THRESHOLD {
+            store.take(&format!("key:{}", i), &policy).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        store.take("trigger", &policy).await.unwrap();
+        assert_eq!(bucket_count(), 1);
+
+        for i in 0..SWEEP_THRESHOLD {
+            store.take(&format!("key:{}", i), &policy).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        store.take("trigger", &policy).await.unwrap();
+        assert_eq!(bucket_count(), SWEEP_THRESHOLD + 1);
+    }
+}
@@ -0,0 +1,219 @@
+pub mod hashmap_rate_limit_store;
+pub mod redis_rate_limit_store;
+
+use std::{fmt, future::Future, str::FromStr, time::Duration};
+
+use color_eyre::eyre::Report;
+use thiserror::Error;
+
+pub use hashmap_rate_limit_store::HashmapRateLimitStore;
+pub use redis_rate_limit_store::RedisRateLimitStore;
+
+// Allows `requests` requests per `period`. The bucket holds up to `requests` tokens, so a client
+// may burst that many at once, and refills continuously at `requests / period`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl RateLimitPolicy {
+    pub fn new(requests: u32, period: Duration) -> Self {
+        Self { requests, period }
+    }
+
+    // Milliseconds it takes to refill a single token
+    fn refill_interval_ms(&self) -> f64 {
+        self.period.as_millis() as f64 / f64::from(self.requests)
+    }
+}
+
+// Parses `REQUESTS/SECONDS`, e.g. `10/60` for ten requests per minute
+impl FromStr for RateLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (requests, seconds) = s
+            .split_once('/')
+            .ok_or_else(|| format!("Expected REQUESTS/SECONDS, got {:?}", s))?;
+        let requests: u32 = requests
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid request count in {:?}", s))?;
+        let seconds: u64 = seconds
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid period in {:?}", s))?;
+        if requests == 0 || seconds == 0 {
+            return Err(format!(
+                "Request count and period must be positive in {:?}",
+                s
+            ));
+        }
+
+        Ok(Self::new(requests, Duration::from_secs(seconds)))
+    }
+}
+
+impl fmt::Display for RateLimitPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{};w={}", self.requests, self.period.as_secs())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // Time until the bucket is full again
+    pub reset_after: Duration,
+    // Time until the next request would be allowed, only set when this one was rejected
+    pub retry_after: Option<Duration>,
+}
+
+// The state of one client's bucket: fractional tokens left, and when they were last counted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub updated_at_ms: u64,
+}
+
+impl TokenBucket {
+    pub fn full(policy: &RateLimitPolicy, now_ms: u64) -> Self {
+        Self {
+            tokens: f64::from(policy.requests),
+            updated_at_ms: now_ms,
+        }
+    }
+
+    // Refills the tokens earned since the last update and tries to spend one
+    pub fn take(&mut self, policy: &RateLimitPolicy, now_ms: u64) -> RateLimitDecision {
+        let capacity = f64::from(policy.requests);
+        let refill_interval_ms = policy.refill_interval_ms();
+        let elapsed_ms = now_ms.saturating_sub(self.updated_at_ms) as f64;
+
+        self.tokens = (self.tokens + elapsed_ms / refill_interval_ms).min(capacity);
+        self.updated_at_ms = now_ms.max(self.updated_at_ms);
+
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+
+        let retry_after = (!allowed).then(|| {
+            Duration::from_millis(((1.0 - self.tokens) * refill_interval_ms).ceil() as u64)
+        });
+
+        RateLimitDecision {
+            allowed,
+            limit: policy.requests,
+            remaining: self.tokens.floor() as u32,
+            reset_after: self.time_until_full(policy),
+            retry_after,
+        }
+    }
+
+    pub fn time_until_full(&self, policy: &RateLimitPolicy) -> Duration {
+        let missing = (f64::from(policy.requests) - self.tokens).max(0.0);
+        Duration::from_millis((missing * policy.refill_interval_ms()).ceil() as u64)
+    }
+}
+
+// This trait represents the interface all rate limit stores should implement
+pub trait RateLimitStore {
+    // Spends one token from the bucket stored under `key`, creating a full bucket if none exists
+    fn take(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> impl Future<Output = Result<RateLimitDecision, RateLimitStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum RateLimitStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// Selects the store implementation at startup, so `main.rs` can pick one from configuration
+#[derive(Clone)]
+pub enum RateLimitStoreBackend {
+    InMemory(HashmapRateLimitStore),
+    Redis(RedisRateLimitStore),
+}
+
+impl RateLimitStore for RateLimitStoreBackend {
+    async fn take(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitDecision, RateLimitStoreError> {
+        match self {
+            Self::InMemory(store) => store.take(key, policy).await,
+            Self::Redis(store) => store.take(key, policy).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RateLimitPolicy {
+        RateLimitPolicy::new(3, Duration::from_secs(3))
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(
+            "10/60".parse::<RateLimitPolicy>().unwrap(),
+            RateLimitPolicy::new(10, Duration::from_secs(60))
+        );
+        assert!("10".parse::<RateLimitPolicy>().is_err());
+        assert!("0/60".parse::<RateLimitPolicy>().is_err());
+        assert!("10/0".parse::<RateLimitPolicy>().is_err());
+        assert!("ten/60".parse::<RateLimitPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_bucket_allows_burst_then_rejects() {
+        let policy = policy();
+        let mut bucket = TokenBucket::full(&policy, 0);
+
+        for remaining in [2, 1, 0] {
+            let decision = bucket.take(&policy, 0);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+            assert_eq!(decision.retry_after, None);
+        }
+
+        let decision = bucket.take(&policy, 0);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.retry_after, Some(Duration::from_secs(1)));
+        assert_eq!(decision.reset_after, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let policy = policy();
+        let mut bucket = TokenBucket::full(&policy, 0);
+        for _ in 0..3 {
+            bucket.take(&policy, 0);
+        }
+
+        // Half a token is not enough
+        let decision = bucket.take(&policy, 500);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, Some(Duration::from_millis(500)));
+
+        assert!(bucket.take(&policy, 1000).allowed);
+        assert!(!bucket.take(&policy, 1000).allowed);
+
+        // Never refills past capacity
+        let decision = bucket.take(&policy, 60_000);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 2);
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+
+use super::{SamlStore, SamlStoreError};
+
+// Keeps requests and used assertions in process memory. Meant for single instance deployments and
+// tests. Expired entries are evicted on every use.
+#[derive(Clone, Default)]
+pub struct HashmapSamlStore {
+    requests: Arc<Mutex<HashMap<String, Instant>>>,
+    used_assertions: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+fn lock(
+    entries: &Mutex<HashMap<String, Instant>>,
+) -> Result<MutexGuard<'_, HashMap<String, Instant>>, SamlStoreError> {
+    let mut entries = entries
+        .lock()
+        .map_err(|e| SamlStoreError::UnexpectedError(eyre!("{}", e)))?;
+    let now = Instant::now();
+    entries.retain(|_, expires_at| *expires_at > now);
+    Ok(entries)
+}
+
+impl SamlStore for HashmapSamlStore {
+    async fn add_request(&self, id: &str, ttl: Duration) -> Result<(), SamlStoreError> {
+        lock(&self.requests)?.insert(id.to_owned(), Instant::now() + ttl);
+        Ok(())
+    }
+
+    async fn take_request(&self, id: &str) -> Result<bool, SamlStoreError> {
+        Ok(lock(&self.requests)?.remove(id).is_some())
+    }
+
+    async fn mark_assertion_used(&self, id: &str, ttl: Duration) -> Result<bool, SamlStoreError> {
+        let mut used = lock(&self.used_assertions)?;
+        if used.contains_key(id) {
+            return Ok(false);
+        }
+        used.insert(id.to_owned(), Instant::now() + ttl);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_is_only_taken_once() {
+        let store = HashmapSamlStore::default();
+        store
+            .add_request("request", Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        assert!(store.take_request("request").await.unwrap());
+        assert!(!store.take_request("request").await.unwrap());
+        assert!(!store.take_request("other").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_assertion_is_only_used_once() {
+        let store = HashmapSamlStore::default();
+        let ttl = Duration::from_secs(60);
+
+        assert!(store.mark_assertion_used("assertion", ttl).await.unwrap());
+        assert!(!store.mark_assertion_used("assertion", ttl).await.unwrap());
+        assert!(store.mark_assertion_used("other", ttl).await.unwrap());
+    }
+}
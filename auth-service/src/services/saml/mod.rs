@@ -0,0 +1,66 @@
+pub mod hashmap_saml_store;
+pub mod redis_saml_store;
+
+use std::{future::Future, time::Duration};
+
+use color_eyre::eyre::Report;
+use thiserror::Error;
+
+pub use hashmap_saml_store::HashmapSamlStore;
+pub use redis_saml_store::RedisSamlStore;
+
+// Remembers the requests sent to the identity provider, so only responses to them are accepted,
+// and the assertions that were already used, so none signs in twice. Entries only need to outlive
+// the requests and assertions themselves.
+pub trait SamlStore {
+    fn add_request(
+        &self,
+        id: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<(), SamlStoreError>> + Send;
+    // Returns whether the request was outstanding, and forgets it
+    fn take_request(&self, id: &str) -> impl Future<Output = Result<bool, SamlStoreError>> + Send;
+    // Records the assertion `id` for `ttl`, returning false if it was already used
+    fn mark_assertion_used(
+        &self,
+        id: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<bool, SamlStoreError>> + Send;
+}
+
+#[derive(Debug, Error)]
+pub enum SamlStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// Redis lets the identity provider post back to any instance; the in-memory store only suits a
+// single one
+#[derive(Clone)]
+pub enum SamlStoreBackend {
+    InMemory(HashmapSamlStore),
+    Redis(RedisSamlStore),
+}
+
+impl SamlStore for SamlStoreBackend {
+    async fn add_request(&self, id: &str, ttl: Duration) -> Result<(), SamlStoreError> {
+        match self {
+            Self::InMemory(store) => store.add_request(id, ttl).await,
+            Self::Redis(store) => store.add_request(id, ttl).await,
+        }
+    }
+
+    async fn take_request(&self, id: &str) -> Result<bool, SamlStoreError> {
+        match self {
+            Self::InMemory(store) => store.take_request(id).await,
+            Self::Redis(store) => store.take_request(id).await,
+        }
+    }
+
+    async fn mark_assertion_used(&self, id: &str, ttl: Duration) -> Result<bool, SamlStoreError> {
+        match self {
+            Self::InMemory(store) => store.mark_assertion_used(id, ttl).await,
+            Self::Redis(store) => store.mark_assertion_used(id, ttl).await,
+        }
+    }
+}
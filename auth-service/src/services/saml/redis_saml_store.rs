@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Context;
+use redis::{aio::MultiplexedConnection, AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use tracing::instrument;
+
+use super::{SamlStore, SamlStoreError};
+
+// We are using a key prefix to prevent collisions and organize data!
+const SAML_REQUEST_KEY_PREFIX: &str = "saml_request:";
+const USED_SAML_ASSERTION_KEY_PREFIX: &str = "used_saml_assertion:";
+
+// Shares requests and used assertions between all instances
+#[derive(Clone)]
+pub struct RedisSamlStore {
+    connection_manager: MultiplexedConnection,
+}
+
+impl RedisSamlStore {
+    pub fn new(connection_manager: MultiplexedConnection) -> Self {
+        Self { connection_manager }
+    }
+}
+
+impl SamlStore for RedisSamlStore {
+    #[instrument(skip_all)]
+    async fn add_request(&self, id: &str, ttl: Duration) -> Result<(), SamlStoreError> {
+        let key = format!("{}{}", SAML_REQUEST_KEY_PREFIX, id);
+        let mut conn = self.connection_manager.clone();
+        let _: () = conn
+            .set_ex(key, true, ttl.as_secs().max(1))
+            .await
+            .wrap_err("Failed to set SAML request in Redis")
+            .map_err(SamlStoreError::UnexpectedError)?;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn take_request(&self, id: &str) -> Result<bool, SamlStoreError> {
+        let key = format!("{}{}", SAML_REQUEST_KEY_PREFIX, id);
+        // `GETDEL` is atomic, so a request cannot be answered twice
+        let mut conn = self.connection_manager.clone();
+        let value: Option<String> = conn
+            .get_del(key)
+            .await
+            .wrap_err("Failed to take SAML request from Redis")
+            .map_err(SamlStoreError::UnexpectedError)?;
+        Ok(value.is_some())
+    }
+
+    #[instrument(skip_all)]
+    async fn mark_assertion_used(&self, id: &str, ttl: Duration) -> Result<bool, SamlStoreError> {
+        let key = format!("{}{}", USED_SAML_ASSERTION_KEY_PREFIX, id);
+        // `SET NX` is atomic, so an assertion posted twice at once still only signs in once
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::PX(ttl.as_millis().max(1) as u64));
+
+        let mut conn = self.connection_manager.clone();
+        let inserted: Option<String> = conn
+            .set_options(key, true, options)
+            .await
+            .wrap_err("Failed to record SAML assertion in Redis")
+            .map_err(SamlStoreError::UnexpectedError)?;
+        Ok(inserted.is_some())
+    }
+}
@@ -1,30 +1,127 @@
-use axum_extra::extract::cookie::{Cookie, SameSite};
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{FromRef, FromRequestParts, OptionalFromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+};
+use axum_extra::extract::{cookie::Cookie, CookieJar};
 use chrono::Utc;
 use color_eyre::eyre::{eyre, Context, ContextCompat, Result};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Validation};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Validation};
 use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
-use tracing::instrument;
+use tracing::{instrument, warn};
+use uuid::Uuid;
 
-use crate::{domain::models::Email, services::BannedTokenStore, utils::constants::JWT_SECRET};
+use crate::{
+    domain::{models::Email, AuthAPIError},
+    services::BannedTokenStore,
+    utils::{
+        constants::{
+            BANNED_TOKEN_CHECK_FAILURE_POLICY, JWT_AUDIENCE, JWT_ISSUER, JWT_LEEWAY_SECONDS,
+            JWT_SECRET,
+        },
+        cookies::CookiePolicy,
+        dpop::{DpopRequest, DpopVerifier},
+    },
+};
 
-use super::constants::JWT_COOKIE_NAME;
+// Where the auth token of a request was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthTokenSource {
+    Bearer,
+    Dpop,
+    Cookie,
+}
 
-#[instrument(skip_all)]
-pub fn generate_auth_cookie(email: &Email) -> Result<Cookie<'static>> {
-    let token = generate_auth_token(email)?;
-    Ok(create_auth_cookie(token))
+// The auth token of a request, read from an `Authorization: Bearer` or `Authorization: DPoP`
+// header first, so native apps and CLIs do not need a cookie jar, and from the auth cookie
+// otherwise. Tokens sent with the DPoP scheme come with the proof of the request.
+#[derive(Debug, Clone)]
+pub struct AuthToken {
+    pub token: String,
+    pub source: AuthTokenSource,
+    pub dpop: Option<DpopRequest>,
 }
 
-#[instrument(skip_all)]
-fn create_auth_cookie(token: String) -> Cookie<'static> {
-    let cookie = Cookie::build((JWT_COOKIE_NAME, token))
-        .path("/") // apply cookie to all URLs on the server
-        .http_only(true) // prevent JavaScript from accessing the cookie
-        .same_site(SameSite::Lax) // send cookie with "same-site" requests, and with "cross-site" top-level navigations.
-        .build();
+impl<S> FromRequestParts<S> for AuthToken
+where
+    Arc<CookiePolicy>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        <Self as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+            .await?
+            .ok_or(AuthAPIError::MissingToken)
+    }
+}
 
-    cookie
+impl<S> OptionalFromRequestParts<S> for AuthToken
+where
+    Arc<CookiePolicy>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        if let Some((source, token)) = authorization_token(&parts.headers) {
+            let token = token.to_owned();
+            let dpop = match source {
+                AuthTokenSource::Dpop => {
+                    <DpopRequest as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+                        .await?
+                }
+                _ => None,
+            };
+            return Ok(Some(Self {
+                token,
+                source,
+                dpop,
+            }));
+        }
+
+        let cookie_policy = Arc::<CookiePolicy>::from_ref(state);
+        let jar = CookieJar::from_headers(&parts.headers);
+        Ok(jar
+            .get(&cookie_policy.auth_cookie_name())
+            .map(|cookie| Self {
+                token: cookie.value().to_owned(),
+                source: AuthTokenSource::Cookie,
+                dpop: None,
+            }))
+    }
+}
+
+// The token of an `Authorization: Bearer <token>` or `Authorization: DPoP <token>` header, if
+// there is one
+pub fn authorization_token(headers: &HeaderMap) -> Option<(AuthTokenSource, &str)> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    if token.is_empty() {
+        return None;
+    }
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some((AuthTokenSource::Bearer, token))
+    } else if scheme.eq_ignore_ascii_case("dpop") {
+        Some((AuthTokenSource::Dpop, token))
+    } else {
+        None
+    }
+}
+
+#[instrument(skip_all)]
+pub fn generate_auth_cookie(
+    email: &Email,
+    cookie_policy: &CookiePolicy,
+) -> Result<Cookie<'static>> {
+    let token = generate_auth_token(email)?;
+    Ok(cookie_policy.auth_cookie(token))
 }
 
 #[derive(Debug)]
@@ -37,13 +134,31 @@ pub enum GenerateTokenError {
 const TOKEN_TTL_MINS: i64 = 10; // 10 minutes
 pub const TOKEN_TTL_SECONDS: u64 = 600; // 10 minutes
 
+// Issues a token that is only accepted together with a DPoP proof signed by the key with the
+// thumbprint `jkt`
+#[instrument(skip_all)]
+pub fn generate_dpop_bound_token(email: &Email, jkt: &str) -> Result<String> {
+    let claims = Claims {
+        cnf: Some(Confirmation {
+            jkt: jkt.to_owned(),
+        }),
+        ..new_claims(email)?
+    };
+    create_token(&claims)
+}
+
 #[instrument(skip_all)]
 fn generate_auth_token(email: &Email) -> Result<String> {
+    create_token(&new_claims(email)?)
+}
+
+fn new_claims(email: &Email) -> Result<Claims> {
     let delta = chrono::Duration::try_minutes(TOKEN_TTL_MINS)
         .wrap_err("Failed to create 10min time delta")?;
 
+    let now = Utc::now();
     // Create JWT expiration time
-    let exp = Utc::now()
+    let exp = now
         .checked_add_signed(delta)
         .wrap_err("Failed to add 10 mins to time")?
         .timestamp();
@@ -53,30 +168,176 @@ fn generate_auth_token(email: &Email) -> Result<String> {
         "Failed to set exp time to usize, exp time: {}",
         exp
     ))?;
+    let iat: usize = now
+        .timestamp()
+        .try_into()
+        .wrap_err("Failed to set iat time to usize")?;
 
     let sub = email.as_ref().expose_secret().to_string();
 
-    let claims = Claims { sub, exp };
+    Ok(Claims {
+        sub,
+        iss: JWT_ISSUER.clone(),
+        aud: JWT_AUDIENCE.clone(),
+        exp,
+        iat,
+        nbf: iat,
+        jti: Uuid::new_v4().to_string(),
+        cnf: None,
+    })
+}
 
-    create_token(&claims)
+// The claims of a token this service just issued, such as the ID a session can be revoked by
+pub fn issued_token_claims(token: &str) -> Result<Claims> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(&JWT_AUDIENCE);
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
+        &validation,
+    )
+    .map(|data| data.claims)
+    .wrap_err("Failed to decode issued token")
 }
 
+// Accepts tokens issued for any of the configured audiences
 #[instrument(skip_all)]
-pub async fn validate_token<T>(token: &str, banned_token_store: &T) -> Result<Claims>
+pub async fn validate_token<T>(
+    token: &str,
+    banned_token_store: &T,
+    dpop_verifier: &DpopVerifier,
+    dpop: Option<&DpopRequest>,
+) -> Result<Claims>
 where
     T: BannedTokenStore + Send + Sync,
 {
-    if banned_token_store.is_token_banned(token).await {
-        return Err(eyre!("Token is banned"));
-    }
+    validate_token_for_audience(
+        token,
+        banned_token_store,
+        dpop_verifier,
+        dpop,
+        &JWT_AUDIENCE,
+    )
+    .await
+}
 
-    decode::<Claims>(
+// Accepts only tokens issued by this service for one of `audience`, so a token handed to one
+// service cannot be replayed against another. DPoP-bound tokens are accepted only with a proof
+// from their key, and proofs only with bound tokens.
+#[instrument(skip_all)]
+pub async fn validate_token_for_audience<T, A>(
+    token: &str,
+    banned_token_store: &T,
+    dpop_verifier: &DpopVerifier,
+    dpop: Option<&DpopRequest>,
+    audience: &[A],
+) -> Result<Claims>
+where
+    T: BannedTokenStore + Send + Sync,
+    A: ToString,
+{
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(audience);
+    validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+    validation.validate_nbf = true;
+    validation.leeway = *JWT_LEEWAY_SECONDS;
+
+    let claims = decode::<Claims>(
         token,
         &DecodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
-        &Validation::default(),
+        &validation,
     )
     .map(|data| data.claims)
-    .wrap_err("Failed to decode token")
+    .wrap_err("Failed to decode token")?;
+
+    check_token_not_banned(
+        &claims.jti,
+        banned_token_store,
+        *BANNED_TOKEN_CHECK_FAILURE_POLICY,
+    )
+    .await?;
+
+    match (&claims.cnf, dpop) {
+        (None, None) => {}
+        (Some(cnf), Some(dpop)) => {
+            let proof = dpop_verifier.verify(dpop, Some(token)).await?;
+            if proof.jkt != cnf.jkt {
+                return Err(eyre!("DPoP proof is signed by another key"));
+            }
+        }
+        (Some(_), None) => return Err(eyre!("DPoP-bound token sent without a proof")),
+        (None, Some(_)) => return Err(eyre!("DPoP proof sent with an unbound token")),
+    }
+
+    Ok(claims)
+}
+
+// Counts how often the banned token store could not be reached, labeled with the policy applied
+pub const BANNED_TOKEN_CHECK_FAILURES_METRIC: &str = "banned_token_check_failures_total";
+
+// What to do with a token when the banned token store cannot be reached. Failing open keeps users
+// logged in during an outage, at the cost of accepting tokens that were logged out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BannedTokenCheckFailurePolicy {
+    FailOpen,
+    FailClosed,
+}
+
+impl BannedTokenCheckFailurePolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::FailOpen => "open",
+            Self::FailClosed => "closed",
+        }
+    }
+}
+
+impl FromStr for BannedTokenCheckFailurePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "open" => Ok(Self::FailOpen),
+            "closed" => Ok(Self::FailClosed),
+            other => Err(format!(
+                "Unknown banned token check failure policy: {}",
+                other
+            )),
+        }
+    }
+}
+
+async fn check_token_not_banned<T>(
+    jti: &str,
+    banned_token_store: &T,
+    failure_policy: BannedTokenCheckFailurePolicy,
+) -> Result<()>
+where
+    T: BannedTokenStore + Send + Sync,
+{
+    match banned_token_store.is_token_banned(jti).await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(eyre!("Token is banned")),
+        Err(e) => {
+            metrics::counter!(
+                BANNED_TOKEN_CHECK_FAILURES_METRIC,
+                "policy" => failure_policy.as_str()
+            )
+            .increment(1);
+            match failure_policy {
+                BannedTokenCheckFailurePolicy::FailOpen => {
+                    warn!(error = ?e, "Banned token check failed, accepting token");
+                    Ok(())
+                }
+                BannedTokenCheckFailurePolicy::FailClosed => {
+                    warn!(error = ?e, "Banned token check failed, rejecting token");
+                    Err(eyre!(e).wrap_err("Failed to check whether token is banned"))
+                }
+            }
+        }
+    }
 }
 
 #[instrument(skip_all)]
@@ -92,19 +353,58 @@ fn create_token(claims: &Claims) -> Result<String> {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String,
+    pub iss: String,
+    pub aud: Vec<String>,
     pub exp: usize,
+    pub iat: usize,
+    pub nbf: usize,
+    pub jti: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+// Binds a token to the key of a DPoP client by the key's JWK thumbprint
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Confirmation {
+    pub jkt: String,
+}
+
+impl Claims {
+    // How long the token will still be accepted, which is as long as a ban on it has to last.
+    // Expired tokens are accepted for the clock skew leeway as well.
+    pub fn remaining_lifetime(&self) -> Duration {
+        let accepted_until = self.exp as u64 + *JWT_LEEWAY_SECONDS;
+        let now = Utc::now().timestamp().max(0) as u64;
+        Duration::from_secs(accepted_until.saturating_sub(now))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::services::data_stores::hashset_banned_store::HashsetBannedTokenStore;
+    use axum_extra::extract::cookie::SameSite;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+
+    use crate::{
+        services::data_stores::{
+            hashset_banned_store::HashsetBannedTokenStore, BannedTokenStoreError,
+        },
+        utils::{constants::JWT_COOKIE_NAME, dpop::test_keys::DpopKey},
+    };
 
     use super::*;
 
+    // Validates a token sent without a DPoP proof
+    async fn validate_unbound<T>(token: &str, banned_token_store: &T) -> Result<Claims>
+    where
+        T: BannedTokenStore + Send + Sync,
+    {
+        validate_token(token, banned_token_store, &DpopVerifier::default(), None).await
+    }
+
     #[tokio::test]
     async fn test_generate_auth_cookie() {
         let email = Email::new("test@example.com".into()).unwrap();
-        let cookie = generate_auth_cookie(&email).unwrap();
+        let cookie = generate_auth_cookie(&email, &CookiePolicy::default()).unwrap();
         assert_eq!(cookie.name(), JWT_COOKIE_NAME);
         assert_eq!(cookie.value().split('.').count(), 3);
         assert_eq!(cookie.path(), Some("/"));
@@ -112,15 +412,34 @@ mod tests {
         assert_eq!(cookie.same_site(), Some(SameSite::Lax));
     }
 
-    #[tokio::test]
-    async fn test_create_auth_cookie() {
-        let token = "test_token".to_owned();
-        let cookie = create_auth_cookie(token.clone());
-        assert_eq!(cookie.name(), JWT_COOKIE_NAME);
-        assert_eq!(cookie.value(), token);
-        assert_eq!(cookie.path(), Some("/"));
-        assert_eq!(cookie.http_only(), Some(true));
-        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+    #[test]
+    fn test_authorization_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(authorization_token(&headers), None);
+
+        headers.insert(AUTHORIZATION, "Bearer abc.def.ghi".parse().unwrap());
+        assert_eq!(
+            authorization_token(&headers),
+            Some((AuthTokenSource::Bearer, "abc.def.ghi"))
+        );
+
+        headers.insert(AUTHORIZATION, "bearer abc.def.ghi".parse().unwrap());
+        assert_eq!(
+            authorization_token(&headers),
+            Some((AuthTokenSource::Bearer, "abc.def.ghi"))
+        );
+
+        headers.insert(AUTHORIZATION, "DPoP abc.def.ghi".parse().unwrap());
+        assert_eq!(
+            authorization_token(&headers),
+            Some((AuthTokenSource::Dpop, "abc.def.ghi"))
+        );
+
+        headers.insert(AUTHORIZATION, "Basic dXNlcjpwYXNz".parse().unwrap());
+        assert_eq!(authorization_token(&headers), None);
+
+        headers.insert(AUTHORIZATION, "Bearer ".parse().unwrap());
+        assert_eq!(authorization_token(&headers), None);
     }
 
     #[tokio::test]
@@ -136,7 +455,7 @@ mod tests {
         let token = generate_auth_token(&email).unwrap();
 
         let banned_token_store = HashsetBannedTokenStore::new();
-        let result = validate_token(&token, &banned_token_store).await.unwrap();
+        let result = validate_unbound(&token, &banned_token_store).await.unwrap();
         assert_eq!(result.sub, "test@example.com");
 
         let exp = Utc::now()
@@ -151,7 +470,7 @@ mod tests {
     async fn test_validate_token_with_invalid_token() {
         let token = "invalid_token".to_owned();
         let banned_token_store = HashsetBannedTokenStore::new();
-        let result = validate_token(&token, &banned_token_store).await;
+        let result = validate_unbound(&token, &banned_token_store).await;
         assert!(result.is_err());
     }
 
@@ -161,9 +480,298 @@ mod tests {
         let token = generate_auth_token(&email).unwrap();
 
         let mut banned_token_store = HashsetBannedTokenStore::new();
-        banned_token_store.ban_token(&token).await;
+        let claims = validate_unbound(&token, &banned_token_store).await.unwrap();
+        banned_token_store
+            .ban_token(&claims.jti, claims.remaining_lifetime())
+            .await
+            .unwrap();
+
+        let result = validate_unbound(&token, &banned_token_store).await;
+        assert!(result.is_err());
+    }
+
+    fn valid_claims() -> Claims {
+        let now = Utc::now().timestamp() as usize;
+        Claims {
+            sub: "test@example.com".to_owned(),
+            iss: JWT_ISSUER.clone(),
+            aud: JWT_AUDIENCE.clone(),
+            exp: now + 600,
+            iat: now,
+            nbf: now,
+            jti: Uuid::new_v4().to_string(),
+            cnf: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_auth_token_claims() {
+        let email = Email::new("test@example.com".into()).unwrap();
+        let banned_token_store = HashsetBannedTokenStore::new();
+        let first = validate_unbound(&generate_auth_token(&email).unwrap(), &banned_token_store)
+            .await
+            .unwrap();
+        let second = validate_unbound(&generate_auth_token(&email).unwrap(), &banned_token_store)
+            .await
+            .unwrap();
+
+        assert_eq!(first.iss, *JWT_ISSUER);
+        assert_eq!(first.aud, *JWT_AUDIENCE);
+        assert_eq!(first.nbf, first.iat);
+        assert_eq!(first.exp, first.iat + TOKEN_TTL_SECONDS as usize);
+        assert_ne!(first.jti, second.jti);
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_other_issuer() {
+        let claims = Claims {
+            iss: "someone-else".to_owned(),
+            ..valid_claims()
+        };
+        let token = create_token(&claims).unwrap();
+        let result = validate_unbound(&token, &HashsetBannedTokenStore::new()).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_for_audience() {
+        let claims = Claims {
+            aud: vec!["billing-service".to_owned()],
+            ..valid_claims()
+        };
+        let token = create_token(&claims).unwrap();
+        let banned_token_store = HashsetBannedTokenStore::new();
+
+        let result = validate_token_for_audience(
+            &token,
+            &banned_token_store,
+            &DpopVerifier::default(),
+            None,
+            &["billing-service"],
+        )
+        .await;
+        assert!(result.is_ok());
+        let result = validate_token_for_audience(
+            &token,
+            &banned_token_store,
+            &DpopVerifier::default(),
+            None,
+            &["app-service"],
+        )
+        .await;
+        assert!(result.is_err());
+        // The configured audiences do not include it either
+        assert!(validate_unbound(&token, &banned_token_store).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_not_before_with_leeway() {
+        let now = Utc::now().timestamp() as usize;
+        let banned_token_store = HashsetBannedTokenStore::new();
+
+        let within_leeway = Claims {
+            nbf: now + *JWT_LEEWAY_SECONDS as usize / 2,
+            ..valid_claims()
+        };
+        let token = create_token(&within_leeway).unwrap();
+        assert!(validate_unbound(&token, &banned_token_store).await.is_ok());
+
+        let too_early = Claims {
+            nbf: now + *JWT_LEEWAY_SECONDS as usize + 60,
+            ..valid_claims()
+        };
+        let token = create_token(&too_early).unwrap();
+        assert!(validate_unbound(&token, &banned_token_store).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_legacy_claims() {
+        // Tokens from before `iss` and `aud` were added
+        #[derive(Serialize)]
+        struct LegacyClaims {
+            sub: String,
+            exp: usize,
+        }
+        let token = encode(
+            &jsonwebtoken::Header::default(),
+            &LegacyClaims {
+                sub: "test@example.com".to_owned(),
+                exp: Utc::now().timestamp() as usize + 600,
+            },
+            &EncodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
+        )
+        .unwrap();
+        let result = validate_unbound(&token, &HashsetBannedTokenStore::new()).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_dpop_bound_token() {
+        let email = Email::new("test@example.com".into()).unwrap();
+        let key = DpopKey::generate();
+        let token = generate_dpop_bound_token(&email, &key.jkt()).unwrap();
+        let banned_token_store = HashsetBannedTokenStore::new();
+        let verifier = DpopVerifier::default();
+        let url = "http://localhost/verify-token";
+        let dpop = |proof| DpopRequest {
+            proof,
+            method: "POST".to_owned(),
+            url: url.to_owned(),
+        };
+
+        let request = dpop(key.proof("POST", url, Some(&token)));
+        let claims = validate_token(&token, &banned_token_store, &verifier, Some(&request))
+            .await
+            .unwrap();
+        assert_eq!(claims.cnf.unwrap().jkt, key.jkt());
+
+        // Without a proof, the token is useless to whoever stole it
+        assert!(validate_unbound(&token, &banned_token_store).await.is_err());
+
+        let request = dpop(DpopKey::generate().proof("POST", url, Some(&token)));
+        let result = validate_token(&token, &banned_token_store, &verifier, Some(&request)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_unbound_token_rejects_proof() {
+        let email = Email::new("test@example.com".into()).unwrap();
+        let token = generate_auth_token(&email).unwrap();
+        let url = "http://localhost/verify-token";
+        let request = DpopRequest {
+            proof: DpopKey::generate().proof("POST", url, Some(&token)),
+            method: "POST".to_owned(),
+            url: url.to_owned(),
+        };
 
-        let result = validate_token(&token, &banned_token_store).await;
+        let result = validate_token(
+            &token,
+            &HashsetBannedTokenStore::new(),
+            &DpopVerifier::default(),
+            Some(&request),
+        )
+        .await;
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_remaining_lifetime_covers_leeway() {
+        let claims = valid_claims();
+        let remaining = claims.remaining_lifetime().as_secs();
+        assert!(remaining <= 600 + *JWT_LEEWAY_SECONDS);
+        assert!(remaining >= 599 + *JWT_LEEWAY_SECONDS);
+
+        let expired = Claims {
+            exp: Utc::now().timestamp() as usize - *JWT_LEEWAY_SECONDS as usize - 1,
+            ..valid_claims()
+        };
+        assert_eq!(expired.remaining_lifetime(), Duration::ZERO);
+    }
+
+    // Behaves like a banned token store whose backend is down
+    struct UnavailableBannedTokenStore;
+
+    impl BannedTokenStore for UnavailableBannedTokenStore {
+        async fn ban_token(&mut self, _: &str, _: Duration) -> Result<(), BannedTokenStoreError> {
+            Err(BannedTokenStoreError::UnexpectedError(eyre!("unavailable")))
+        }
+
+        async fn is_token_banned(&self, _: &str) -> Result<bool, BannedTokenStoreError> {
+            Err(BannedTokenStoreError::UnexpectedError(eyre!("unavailable")))
+        }
+    }
+
+    fn fallback_count(snapshotter: &Snapshotter, policy: &str) -> u64 {
+        snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .find(|(key, ..)| {
+                key.key().name() == BANNED_TOKEN_CHECK_FAILURES_METRIC
+                    && key
+                        .key()
+                        .labels()
+                        .any(|label| label.key() == "policy" && label.value() == policy)
+            })
+            .map(|(.., value)| match value {
+                DebugValue::Counter(count) => count,
+                other => panic!("Unexpected metric value {:?}", other),
+            })
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_store_fails_open() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let result = check_token_not_banned(
+            "jti",
+            &UnavailableBannedTokenStore,
+            BannedTokenCheckFailurePolicy::FailOpen,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(fallback_count(&snapshotter, "open"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_store_fails_closed() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let result = check_token_not_banned(
+            "jti",
+            &UnavailableBannedTokenStore,
+            BannedTokenCheckFailurePolicy::FailClosed,
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(fallback_count(&snapshotter, "closed"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_available_store_takes_no_fallback() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+        let mut banned_token_store = HashsetBannedTokenStore::new();
+        banned_token_store
+            .ban_token("banned", Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        for policy in [
+            BannedTokenCheckFailurePolicy::FailOpen,
+            BannedTokenCheckFailurePolicy::FailClosed,
+        ] {
+            assert!(check_token_not_banned("other", &banned_token_store, policy)
+                .await
+                .is_ok());
+            assert!(
+                check_token_not_banned("banned", &banned_token_store, policy)
+                    .await
+                    .is_err()
+            );
+        }
+        assert_eq!(fallback_count(&snapshotter, "open"), 0);
+        assert_eq!(fallback_count(&snapshotter, "closed"), 0);
+    }
+
+    #[test]
+    fn test_parse_banned_token_check_failure_policy() {
+        assert_eq!(
+            "open".parse::<BannedTokenCheckFailurePolicy>(),
+            Ok(BannedTokenCheckFailurePolicy::FailOpen)
+        );
+        assert_eq!(
+            "Closed".parse::<BannedTokenCheckFailurePolicy>(),
+            Ok(BannedTokenCheckFailurePolicy::FailClosed)
+        );
+        assert!("maybe".parse::<BannedTokenCheckFailurePolicy>().is_err());
+    }
 }
@@ -1,10 +1,111 @@
+use axum::http::HeaderName;
 use dotenvy::dotenv;
+use ipnet::IpNet;
 use lazy_static::lazy_static;
 use secrecy::SecretString;
-use std::env as std_env;
+use std::{env as std_env, str::FromStr, time::Duration};
+
+use crate::{
+    domain::{
+        http_sms_client::SmsGatewayConfig,
+        smtp_email_client::{SmtpConfig, SmtpTls},
+        Locale,
+    },
+    services::{data_stores::LdapConfig, rate_limiting::RateLimitPolicy},
+    utils::{
+        auth::BannedTokenCheckFailurePolicy, email_templates::EmailBranding,
+        oidc::OidcProviderConfig, rate_limit::parse_trusted_proxies, saml::SamlIdpConfig,
+    },
+};
 
 pub const JWT_COOKIE_NAME: &str = "jwt";
+pub const CSRF_COOKIE_NAME: &str = "csrf_token";
+pub const CSRF_HEADER_NAME: &str = "x-csrf-token";
+pub const MAGIC_LINK_NONCE_COOKIE_NAME: &str = "magic_link_nonce";
+pub const OIDC_STATE_COOKIE_NAME: &str = "oidc_state";
+pub const SAML_REQUEST_COOKIE_NAME: &str = "saml_request";
+pub const DEVICE_COOKIE_NAME: &str = "device_id";
 pub const DEFAULT_REDIS_HOSTNAME: &str = "127.0.0.1";
+pub const DEFAULT_PASSWORD_MIN_LENGTH: usize = 8;
+pub const DEFAULT_PASSWORD_MAX_LENGTH: usize = 128;
+pub const DEFAULT_PASSWORD_MIN_STRENGTH_SCORE: u8 = 2;
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 15000;
+pub const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+pub const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
+pub const DEFAULT_PASSWORD_PEPPER_VERSION: i32 = 1;
+pub const DEFAULT_AUTH_COOKIE_SAME_SITE: &str = "lax";
+pub const DEFAULT_JWT_ISSUER: &str = "auth-service";
+// A comma separated list of the services tokens are issued for
+pub const DEFAULT_JWT_AUDIENCE: &str = "app-service";
+pub const DEFAULT_JWT_LEEWAY_SECONDS: u64 = 60;
+pub const DEFAULT_DPOP_PROOF_MAX_AGE_SECONDS: u64 = 60;
+// The relying party ID is the domain passkeys are scoped to, and the origins are the pages that
+// run the WebAuthn ceremonies, given as a comma separated list
+pub const DEFAULT_WEBAUTHN_RP_ID: &str = "localhost";
+pub const DEFAULT_WEBAUTHN_RP_NAME: &str = "Auth Service";
+pub const DEFAULT_WEBAUTHN_ORIGINS: &str = "http://localhost:3000";
+pub const DEFAULT_WEBAUTHN_CHALLENGE_TTL_SECONDS: u64 = 300;
+// Where the emailed sign-in links point to, with the link token appended as `?token=`
+pub const DEFAULT_MAGIC_LINK_URL: &str = "http://localhost:3000/login/magic-link/consume";
+pub const DEFAULT_MAGIC_LINK_TTL_SECONDS: u64 = 900;
+// Providers redirect back to `<base URL>/oidc/<provider>/callback`
+pub const DEFAULT_OIDC_REDIRECT_BASE_URL: &str = "http://localhost:3000";
+pub const DEFAULT_OIDC_SCOPES: &str = "openid email profile";
+pub const DEFAULT_OIDC_STATE_TTL_SECONDS: u64 = 600;
+// The identity provider posts its responses back to the assertion consumer service URL
+pub const DEFAULT_SAML_SP_ENTITY_ID: &str = "http://localhost:3000/saml/metadata";
+pub const DEFAULT_SAML_ACS_URL: &str = "http://localhost:3000/saml/acs";
+pub const DEFAULT_SAML_REQUEST_TTL_SECONDS: u64 = 600;
+pub const DEFAULT_SAML_CLOCK_SKEW_SECONDS: u64 = 60;
+// Users are found by their email, with group memberships read from the directory's `memberOf`
+pub const DEFAULT_LDAP_USER_FILTER: &str = "(&(objectClass=person)(mail={email}))";
+pub const DEFAULT_LDAP_EMAIL_ATTRIBUTE: &str = "mail";
+pub const DEFAULT_LDAP_GROUP_ATTRIBUTE: &str = "memberOf";
+pub const DEFAULT_LDAP_TIMEOUT_SECONDS: u64 = 5;
+// One of `resend`, `smtp` or `mock`, which only logs emails
+pub const DEFAULT_EMAIL_BACKEND: &str = "resend";
+pub const DEFAULT_SMTP_TLS: &str = "starttls";
+pub const DEFAULT_SMTP_POOL_SIZE: u32 = 4;
+pub const DEFAULT_SMTP_TIMEOUT_SECONDS: u64 = 10;
+// Failed deliveries are retried after 5s, 10s, 20s... up to an hour apart, and given up on after
+// the last attempt
+pub const DEFAULT_EMAIL_OUTBOX_MAX_ATTEMPTS: u32 = 8;
+pub const DEFAULT_EMAIL_OUTBOX_BASE_BACKOFF_SECONDS: u64 = 5;
+pub const DEFAULT_EMAIL_OUTBOX_MAX_BACKOFF_SECONDS: u64 = 3600;
+// Emails enqueued by this instance are sent right away, this is how often others' are looked for
+pub const DEFAULT_EMAIL_OUTBOX_POLL_INTERVAL_SECONDS: u64 = 5;
+// One of `http`, `mock`, which only logs messages, or `disabled`, which keeps 2FA codes on email
+pub const DEFAULT_SMS_BACKEND: &str = "disabled";
+pub const DEFAULT_SMS_GATEWAY_TIMEOUT_SECONDS: u64 = 10;
+// How long the code sent to a phone number being added stays valid
+pub const DEFAULT_PHONE_VERIFICATION_TTL_SECONDS: u64 = 600;
+// The "this wasn't me" link of a login alert opens this page, with the link's token appended
+pub const DEFAULT_LOGIN_REPORT_URL: &str = "http://localhost:3000/";
+pub const DEFAULT_LOGIN_REPORT_TTL_SECONDS: u64 = 604800;
+// Users who did not pick a language get emails in this one
+pub const DEFAULT_EMAIL_DEFAULT_LOCALE: &str = "en";
+pub const DEFAULT_EMAIL_BRAND_NAME: &str = "Auth Service";
+pub const DEFAULT_EMAIL_BRAND_URL: &str = "http://localhost:3000";
+pub const DEFAULT_EMAIL_BRAND_COLOR: &str = "#2563eb";
+pub const DEFAULT_RATE_LIMIT_BACKEND: &str = "redis";
+// Rate limits are given as REQUESTS/SECONDS
+pub const DEFAULT_RATE_LIMIT_SIGNUP: &str = "10/60";
+pub const DEFAULT_RATE_LIMIT_LOGIN: &str = "10/60";
+pub const DEFAULT_RATE_LIMIT_VERIFY_2FA: &str = "10/60";
+// Every new number is texted or called, which costs money per message
+pub const DEFAULT_RATE_LIMIT_PHONE_NUMBER: &str = "5/60";
+// Security headers can be turned off by setting them to `off`. `{nonce}` in the
+// Content-Security-Policy is replaced with a fresh nonce on every request.
+pub const DEFAULT_SECURITY_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+    script-src 'nonce-{nonce}' 'strict-dynamic'; \
+    style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+    img-src 'self' data:; object-src 'none'; base-uri 'none'; \
+    form-action 'self'; frame-ancestors 'none'";
+pub const DEFAULT_SECURITY_STRICT_TRANSPORT_SECURITY: &str = "max-age=63072000; includeSubDomains";
+pub const DEFAULT_SECURITY_FRAME_OPTIONS: &str = "DENY";
+pub const DEFAULT_SECURITY_REFERRER_POLICY: &str = "no-referrer";
+pub const DEFAULT_SECURITY_PERMISSIONS_POLICY: &str =
+    "camera=(), microphone=(), geolocation=(), payment=(), usb=()";
 
 // Define a lazily evaluated static. lazy_static is needed because std_env::var is not a const function.
 lazy_static! {
@@ -15,6 +116,221 @@ lazy_static! {
     pub static ref SENDER_EMAIL: SecretString = set_sender_email();
 }
 
+// Password policy and hashing
+lazy_static! {
+    pub static ref PASSWORD_MIN_LENGTH: usize = set_parsed_or_default(
+        env::PASSWORD_MIN_LENGTH_ENV_VAR,
+        DEFAULT_PASSWORD_MIN_LENGTH
+    );
+    pub static ref PASSWORD_MAX_LENGTH: usize = set_parsed_or_default(
+        env::PASSWORD_MAX_LENGTH_ENV_VAR,
+        DEFAULT_PASSWORD_MAX_LENGTH
+    );
+    pub static ref PASSWORD_MIN_STRENGTH_SCORE: u8 = set_parsed_or_default(
+        env::PASSWORD_MIN_STRENGTH_SCORE_ENV_VAR,
+        DEFAULT_PASSWORD_MIN_STRENGTH_SCORE
+    );
+    pub static ref BREACHED_PASSWORDS_FILE: Option<String> =
+        set_optional(env::BREACHED_PASSWORDS_FILE_ENV_VAR);
+    pub static ref BREACHED_PASSWORDS_API_URL: Option<String> =
+        set_optional(env::BREACHED_PASSWORDS_API_URL_ENV_VAR);
+    pub static ref ARGON2_MEMORY_KIB: u32 =
+        set_parsed_or_default(env::ARGON2_MEMORY_KIB_ENV_VAR, DEFAULT_ARGON2_MEMORY_KIB);
+    pub static ref ARGON2_ITERATIONS: u32 =
+        set_parsed_or_default(env::ARGON2_ITERATIONS_ENV_VAR, DEFAULT_ARGON2_ITERATIONS);
+    pub static ref ARGON2_PARALLELISM: u32 =
+        set_parsed_or_default(env::ARGON2_PARALLELISM_ENV_VAR, DEFAULT_ARGON2_PARALLELISM);
+    pub static ref PASSWORD_PEPPER: Option<SecretString> =
+        set_optional(env::PASSWORD_PEPPER_ENV_VAR).map(Into::into);
+    pub static ref PASSWORD_PEPPER_VERSION: i32 = set_parsed_or_default(
+        env::PASSWORD_PEPPER_VERSION_ENV_VAR,
+        DEFAULT_PASSWORD_PEPPER_VERSION
+    );
+    pub static ref PASSWORD_PREVIOUS_PEPPERS: Option<SecretString> =
+        set_optional(env::PASSWORD_PREVIOUS_PEPPERS_ENV_VAR).map(Into::into);
+}
+
+// Tokens and DPoP
+lazy_static! {
+    pub static ref JWT_ISSUER: String =
+        set_optional(env::JWT_ISSUER_ENV_VAR).unwrap_or(DEFAULT_JWT_ISSUER.to_owned());
+    pub static ref JWT_AUDIENCE: Vec<String> = set_optional(env::JWT_AUDIENCE_ENV_VAR)
+        .unwrap_or(DEFAULT_JWT_AUDIENCE.to_owned())
+        .split(',')
+        .map(str::trim)
+        .filter(|audience| !audience.is_empty())
+        .map(str::to_owned)
+        .collect();
+    pub static ref JWT_LEEWAY_SECONDS: u64 =
+        set_parsed_or_default(env::JWT_LEEWAY_SECONDS_ENV_VAR, DEFAULT_JWT_LEEWAY_SECONDS);
+    pub static ref DPOP_PROOF_MAX_AGE_SECONDS: u64 = set_parsed_or_default(
+        env::DPOP_PROOF_MAX_AGE_SECONDS_ENV_VAR,
+        DEFAULT_DPOP_PROOF_MAX_AGE_SECONDS
+    );
+    // The externally visible origin of this service, e.g. `https://auth.example.com`, which DPoP
+    // proofs are made out to. Defaults to the Host header of the request over plain HTTP.
+    pub static ref AUTH_SERVICE_PUBLIC_URL: Option<String> =
+        set_optional(env::AUTH_SERVICE_PUBLIC_URL_ENV_VAR);
+}
+
+// Passkeys, magic links and external identity providers
+lazy_static! {
+    pub static ref WEBAUTHN_RP_ID: String =
+        set_optional(env::WEBAUTHN_RP_ID_ENV_VAR).unwrap_or(DEFAULT_WEBAUTHN_RP_ID.to_owned());
+    pub static ref WEBAUTHN_RP_NAME: String = set_optional(env::WEBAUTHN_RP_NAME_ENV_VAR)
+        .unwrap_or(DEFAULT_WEBAUTHN_RP_NAME.to_owned());
+    pub static ref WEBAUTHN_ORIGINS: Vec<String> = set_optional(env::WEBAUTHN_ORIGINS_ENV_VAR)
+        .unwrap_or(DEFAULT_WEBAUTHN_ORIGINS.to_owned())
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/'))
+        .filter(|origin| !origin.is_empty())
+        .map(str::to_owned)
+        .collect();
+    pub static ref WEBAUTHN_CHALLENGE_TTL_SECONDS: u64 = set_parsed_or_default(
+        env::WEBAUTHN_CHALLENGE_TTL_SECONDS_ENV_VAR,
+        DEFAULT_WEBAUTHN_CHALLENGE_TTL_SECONDS
+    );
+    pub static ref MAGIC_LINK_URL: String =
+        set_optional(env::MAGIC_LINK_URL_ENV_VAR).unwrap_or(DEFAULT_MAGIC_LINK_URL.to_owned());
+    pub static ref MAGIC_LINK_TTL_SECONDS: u64 = set_parsed_or_default(
+        env::MAGIC_LINK_TTL_SECONDS_ENV_VAR,
+        DEFAULT_MAGIC_LINK_TTL_SECONDS
+    );
+    // Upstream OpenID Connect providers users can log in with, configured per provider name
+    pub static ref OIDC_PROVIDERS: Vec<OidcProviderConfig> = set_oidc_providers();
+    pub static ref OIDC_REDIRECT_BASE_URL: String = set_optional(env::OIDC_REDIRECT_BASE_URL_ENV_VAR)
+        .unwrap_or(DEFAULT_OIDC_REDIRECT_BASE_URL.to_owned())
+        .trim_end_matches('/')
+        .to_owned();
+    pub static ref OIDC_STATE_TTL_SECONDS: u64 = set_parsed_or_default(
+        env::OIDC_STATE_TTL_SECONDS_ENV_VAR,
+        DEFAULT_OIDC_STATE_TTL_SECONDS
+    );
+    pub static ref SAML_IDP: Option<SamlIdpConfig> = set_saml_idp();
+    pub static ref SAML_SP_ENTITY_ID: String = set_optional(env::SAML_SP_ENTITY_ID_ENV_VAR)
+        .unwrap_or(DEFAULT_SAML_SP_ENTITY_ID.to_owned());
+    pub static ref SAML_ACS_URL: String =
+        set_optional(env::SAML_ACS_URL_ENV_VAR).unwrap_or(DEFAULT_SAML_ACS_URL.to_owned());
+    pub static ref SAML_REQUEST_TTL_SECONDS: u64 = set_parsed_or_default(
+        env::SAML_REQUEST_TTL_SECONDS_ENV_VAR,
+        DEFAULT_SAML_REQUEST_TTL_SECONDS
+    );
+    pub static ref SAML_CLOCK_SKEW_SECONDS: u64 = set_parsed_or_default(
+        env::SAML_CLOCK_SKEW_SECONDS_ENV_VAR,
+        DEFAULT_SAML_CLOCK_SKEW_SECONDS
+    );
+    pub static ref LDAP_CONFIG: Option<LdapConfig> = set_ldap_config();
+}
+
+// Email and SMS delivery
+lazy_static! {
+    pub static ref EMAIL_BACKEND: String =
+        set_optional(env::EMAIL_BACKEND_ENV_VAR).unwrap_or(DEFAULT_EMAIL_BACKEND.to_owned());
+    pub static ref SMTP_CONFIG: Option<SmtpConfig> = set_smtp_config();
+    // Serves what the mock email client sent at `/_dev/mailbox`. Never turn on in production.
+    pub static ref DEV_MAILBOX: bool = set_parsed_or_default(env::DEV_MAILBOX_ENV_VAR, false);
+    pub static ref EMAIL_OUTBOX_MAX_ATTEMPTS: u32 = set_parsed_or_default(
+        env::EMAIL_OUTBOX_MAX_ATTEMPTS_ENV_VAR,
+        DEFAULT_EMAIL_OUTBOX_MAX_ATTEMPTS
+    );
+    pub static ref EMAIL_OUTBOX_BASE_BACKOFF_SECONDS: u64 = set_parsed_or_default(
+        env::EMAIL_OUTBOX_BASE_BACKOFF_SECONDS_ENV_VAR,
+        DEFAULT_EMAIL_OUTBOX_BASE_BACKOFF_SECONDS
+    );
+    pub static ref EMAIL_OUTBOX_MAX_BACKOFF_SECONDS: u64 = set_parsed_or_default(
+        env::EMAIL_OUTBOX_MAX_BACKOFF_SECONDS_ENV_VAR,
+        DEFAULT_EMAIL_OUTBOX_MAX_BACKOFF_SECONDS
+    );
+    pub static ref EMAIL_OUTBOX_POLL_INTERVAL_SECONDS: u64 = set_parsed_or_default(
+        env::EMAIL_OUTBOX_POLL_INTERVAL_SECONDS_ENV_VAR,
+        DEFAULT_EMAIL_OUTBOX_POLL_INTERVAL_SECONDS
+    );
+    pub static ref SMS_BACKEND: String =
+        set_optional(env::SMS_BACKEND_ENV_VAR).unwrap_or(DEFAULT_SMS_BACKEND.to_owned());
+    pub static ref SMS_GATEWAY_CONFIG: Option<SmsGatewayConfig> = set_sms_gateway_config();
+    pub static ref PHONE_VERIFICATION_TTL_SECONDS: u64 = set_parsed_or_default(
+        env::PHONE_VERIFICATION_TTL_SECONDS_ENV_VAR,
+        DEFAULT_PHONE_VERIFICATION_TTL_SECONDS
+    );
+}
+
+// Login alerts and email templates
+lazy_static! {
+    // Header a proxy or CDN sets to the country code of the client, such as `CF-IPCountry`.
+    // Without it, logins are only told apart by device.
+    pub static ref LOGIN_ALERT_COUNTRY_HEADER: Option<HeaderName> =
+        set_optional(env::LOGIN_ALERT_COUNTRY_HEADER_ENV_VAR).map(|value| {
+            HeaderName::try_from(value).unwrap_or_else(|e| {
+                panic!(
+                    "{} has an invalid value: {}",
+                    env::LOGIN_ALERT_COUNTRY_HEADER_ENV_VAR,
+                    e
+                )
+            })
+        });
+    pub static ref LOGIN_REPORT_URL: String = set_optional(env::LOGIN_REPORT_URL_ENV_VAR)
+        .unwrap_or(DEFAULT_LOGIN_REPORT_URL.to_owned());
+    pub static ref LOGIN_REPORT_TTL_SECONDS: u64 = set_parsed_or_default(
+        env::LOGIN_REPORT_TTL_SECONDS_ENV_VAR,
+        DEFAULT_LOGIN_REPORT_TTL_SECONDS
+    );
+    pub static ref EMAIL_DEFAULT_LOCALE: Locale = set_email_default_locale();
+    // Product name, links and colors the email templates are rendered with
+    pub static ref EMAIL_BRANDING: EmailBranding = EmailBranding {
+        name: set_optional(env::EMAIL_BRAND_NAME_ENV_VAR)
+            .unwrap_or(DEFAULT_EMAIL_BRAND_NAME.to_owned()),
+        url: set_optional(env::EMAIL_BRAND_URL_ENV_VAR)
+            .unwrap_or(DEFAULT_EMAIL_BRAND_URL.to_owned()),
+        logo_url: set_optional(env::EMAIL_BRAND_LOGO_URL_ENV_VAR),
+        color: set_optional(env::EMAIL_BRAND_COLOR_ENV_VAR)
+            .unwrap_or(DEFAULT_EMAIL_BRAND_COLOR.to_owned()),
+        support_email: set_optional(env::EMAIL_SUPPORT_ADDRESS_ENV_VAR),
+    };
+}
+
+// Sessions, cookies and rate limiting
+lazy_static! {
+    pub static ref BANNED_TOKEN_CHECK_FAILURE_POLICY: BannedTokenCheckFailurePolicy =
+        set_parsed_or_default(
+            env::BANNED_TOKEN_CHECK_FAILURE_POLICY_ENV_VAR,
+            BannedTokenCheckFailurePolicy::FailOpen
+        );
+    pub static ref AUTH_COOKIE_SECURE: bool =
+        set_parsed_or_default(env::AUTH_COOKIE_SECURE_ENV_VAR, false);
+    pub static ref AUTH_COOKIE_DOMAIN: Option<String> =
+        set_optional(env::AUTH_COOKIE_DOMAIN_ENV_VAR);
+    pub static ref AUTH_COOKIE_HOST_PREFIX: bool =
+        set_parsed_or_default(env::AUTH_COOKIE_HOST_PREFIX_ENV_VAR, false);
+    pub static ref AUTH_COOKIE_SAME_SITE: String = set_optional(env::AUTH_COOKIE_SAME_SITE_ENV_VAR)
+        .unwrap_or(DEFAULT_AUTH_COOKIE_SAME_SITE.to_owned());
+    pub static ref RATE_LIMIT_BACKEND: String = set_optional(env::RATE_LIMIT_BACKEND_ENV_VAR)
+        .unwrap_or(DEFAULT_RATE_LIMIT_BACKEND.to_owned());
+    pub static ref RATE_LIMIT_SIGNUP: RateLimitPolicy =
+        set_rate_limit_policy(env::RATE_LIMIT_SIGNUP_ENV_VAR, DEFAULT_RATE_LIMIT_SIGNUP);
+    pub static ref RATE_LIMIT_LOGIN: RateLimitPolicy =
+        set_rate_limit_policy(env::RATE_LIMIT_LOGIN_ENV_VAR, DEFAULT_RATE_LIMIT_LOGIN);
+    pub static ref RATE_LIMIT_VERIFY_2FA: RateLimitPolicy = set_rate_limit_policy(
+        env::RATE_LIMIT_VERIFY_2FA_ENV_VAR,
+        DEFAULT_RATE_LIMIT_VERIFY_2FA
+    );
+    pub static ref RATE_LIMIT_PHONE_NUMBER: RateLimitPolicy = set_rate_limit_policy(
+        env::RATE_LIMIT_PHONE_NUMBER_ENV_VAR,
+        DEFAULT_RATE_LIMIT_PHONE_NUMBER
+    );
+    pub static ref RATE_LIMIT_TRUSTED_PROXIES: Vec<IpNet> =
+        set_optional(env::RATE_LIMIT_TRUSTED_PROXIES_ENV_VAR)
+            .map(|value| {
+                parse_trusted_proxies(&value).unwrap_or_else(|e| {
+                    panic!(
+                        "{} has an invalid value: {}",
+                        env::RATE_LIMIT_TRUSTED_PROXIES_ENV_VAR,
+                        e
+                    )
+                })
+            })
+            .unwrap_or_default();
+}
+
 fn set_sender_email() -> SecretString {
     dotenv().ok(); // Load environment variables
     let email = std_env::var(env::SENDER_EMAIL_ENV_VAR).expect("SENDER_EMAIL must be set.");
@@ -56,12 +372,242 @@ fn set_redis_host() -> String {
     std_env::var(env::REDIS_HOST_NAME_ENV_VAR).unwrap_or(DEFAULT_REDIS_HOSTNAME.to_owned())
 }
 
+fn set_optional(name: &str) -> Option<String> {
+    dotenv().ok();
+    std_env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+// Reads an optional environment variable, falling back to `default` when it is unset or empty.
+fn set_parsed_or_default<T: FromStr>(name: &str, default: T) -> T {
+    dotenv().ok();
+    match std_env::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value.", name)),
+        _ => default,
+    }
+}
+
+fn set_rate_limit_policy(name: &str, default: &str) -> RateLimitPolicy {
+    let default = default.parse().expect("Invalid default rate limit policy");
+    set_parsed_or_default(name, default)
+}
+
+// Reads the providers listed in `OIDC_PROVIDERS`, each configured by `OIDC_<NAME>_DISCOVERY_URL`,
+// `OIDC_<NAME>_CLIENT_ID`, `OIDC_<NAME>_CLIENT_SECRET` and optionally `OIDC_<NAME>_SCOPES`
+fn set_oidc_providers() -> Vec<OidcProviderConfig> {
+    let Some(names) = set_optional(env::OIDC_PROVIDERS_ENV_VAR) else {
+        return Vec::new();
+    };
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let prefix = format!("OIDC_{}_", name.to_ascii_uppercase().replace('-', "_"));
+            let required = |suffix: &str| {
+                let var = format!("{}{}", prefix, suffix);
+                set_optional(&var).unwrap_or_else(|| panic!("{} must be set.", var))
+            };
+            OidcProviderConfig {
+                name: name.to_ascii_lowercase(),
+                discovery_url: required("DISCOVERY_URL"),
+                client_id: required("CLIENT_ID"),
+                client_secret: required("CLIENT_SECRET").into(),
+                scopes: set_optional(&format!("{}SCOPES", prefix))
+                    .unwrap_or(DEFAULT_OIDC_SCOPES.to_owned())
+                    .split_whitespace()
+                    .map(str::to_owned)
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+// Reads the identity provider, if `SAML_IDP_ENTITY_ID` is set. `SAML_IDP_SSO_URL` and
+// `SAML_IDP_CERTIFICATE` are required with it.
+fn set_saml_idp() -> Option<SamlIdpConfig> {
+    let entity_id = set_optional(env::SAML_IDP_ENTITY_ID_ENV_VAR)?;
+    let required =
+        |name: &str| set_optional(name).unwrap_or_else(|| panic!("{} must be set.", name));
+    let idp = SamlIdpConfig::new(
+        entity_id,
+        required(env::SAML_IDP_SSO_URL_ENV_VAR),
+        &required(env::SAML_IDP_CERTIFICATE_ENV_VAR),
+    )
+    .unwrap_or_else(|e| panic!("{} is invalid: {}", env::SAML_IDP_CERTIFICATE_ENV_VAR, e));
+    Some(idp)
+}
+
+// Reads the directory users are looked up in, if `LDAP_URL` is set. Groups are given by their
+// DNs, with `LDAP_2FA_GROUPS` as a `;` separated list since DNs contain commas.
+fn set_ldap_config() -> Option<LdapConfig> {
+    let url = set_optional(env::LDAP_URL_ENV_VAR)?;
+    Some(LdapConfig {
+        url,
+        base_dn: set_optional(env::LDAP_BASE_DN_ENV_VAR)
+            .unwrap_or_else(|| panic!("{} must be set.", env::LDAP_BASE_DN_ENV_VAR)),
+        bind_dn: set_optional(env::LDAP_BIND_DN_ENV_VAR),
+        bind_password: set_optional(env::LDAP_BIND_PASSWORD_ENV_VAR).map(Into::into),
+        user_filter: set_optional(env::LDAP_USER_FILTER_ENV_VAR)
+            .unwrap_or(DEFAULT_LDAP_USER_FILTER.to_owned()),
+        email_attribute: set_optional(env::LDAP_EMAIL_ATTRIBUTE_ENV_VAR)
+            .unwrap_or(DEFAULT_LDAP_EMAIL_ATTRIBUTE.to_owned()),
+        group_attribute: set_optional(env::LDAP_GROUP_ATTRIBUTE_ENV_VAR)
+            .unwrap_or(DEFAULT_LDAP_GROUP_ATTRIBUTE.to_owned()),
+        required_group: set_optional(env::LDAP_REQUIRED_GROUP_ENV_VAR),
+        two_fa_groups: set_optional(env::LDAP_2FA_GROUPS_ENV_VAR)
+            .unwrap_or_default()
+            .split(';')
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        starttls: set_parsed_or_default(env::LDAP_STARTTLS_ENV_VAR, false),
+        timeout: Duration::from_secs(set_parsed_or_default(
+            env::LDAP_TIMEOUT_SECONDS_ENV_VAR,
+            DEFAULT_LDAP_TIMEOUT_SECONDS,
+        )),
+    })
+}
+
+// Reads the SMTP server, if `SMTP_HOST` is set. The port defaults to the usual one of the TLS
+// mode: 587 for STARTTLS, 465 for implicit TLS and 25 without TLS.
+fn set_smtp_config() -> Option<SmtpConfig> {
+    let host = set_optional(env::SMTP_HOST_ENV_VAR)?;
+    let tls: SmtpTls = set_optional(env::SMTP_TLS_ENV_VAR)
+        .unwrap_or(DEFAULT_SMTP_TLS.to_owned())
+        .parse()
+        .unwrap_or_else(|e| panic!("{} is invalid: {}", env::SMTP_TLS_ENV_VAR, e));
+    Some(SmtpConfig {
+        host,
+        port: set_parsed_or_default(env::SMTP_PORT_ENV_VAR, tls.default_port()),
+        tls,
+        username: set_optional(env::SMTP_USERNAME_ENV_VAR),
+        password: set_optional(env::SMTP_PASSWORD_ENV_VAR).map(Into::into),
+        pool_size: set_parsed_or_default(env::SMTP_POOL_SIZE_ENV_VAR, DEFAULT_SMTP_POOL_SIZE),
+        timeout: Duration::from_secs(set_parsed_or_default(
+            env::SMTP_TIMEOUT_SECONDS_ENV_VAR,
+            DEFAULT_SMTP_TIMEOUT_SECONDS,
+        )),
+    })
+}
+
+// Reads the SMS gateway, if `SMS_GATEWAY_URL` is set
+fn set_sms_gateway_config() -> Option<SmsGatewayConfig> {
+    let url = set_optional(env::SMS_GATEWAY_URL_ENV_VAR)?;
+    Some(SmsGatewayConfig {
+        url,
+        token: set_optional(env::SMS_GATEWAY_TOKEN_ENV_VAR).map(Into::into),
+        sender: set_optional(env::SMS_SENDER_ENV_VAR),
+        timeout: Duration::from_secs(set_parsed_or_default(
+            env::SMS_GATEWAY_TIMEOUT_SECONDS_ENV_VAR,
+            DEFAULT_SMS_GATEWAY_TIMEOUT_SECONDS,
+        )),
+    })
+}
+
+fn set_email_default_locale() -> Locale {
+    let tag = set_optional(env::EMAIL_DEFAULT_LOCALE_ENV_VAR)
+        .unwrap_or(DEFAULT_EMAIL_DEFAULT_LOCALE.to_owned());
+    Locale::negotiate(&tag).unwrap_or_else(|| {
+        panic!(
+            "{} must be a supported language, got {}",
+            env::EMAIL_DEFAULT_LOCALE_ENV_VAR,
+            tag
+        )
+    })
+}
+
 pub mod env {
     pub const JWT_SECRET_ENV_VAR: &str = "JWT_SECRET";
+    pub const JWT_ISSUER_ENV_VAR: &str = "JWT_ISSUER";
+    pub const JWT_AUDIENCE_ENV_VAR: &str = "JWT_AUDIENCE";
+    pub const JWT_LEEWAY_SECONDS_ENV_VAR: &str = "JWT_LEEWAY_SECONDS";
+    pub const DPOP_PROOF_MAX_AGE_SECONDS_ENV_VAR: &str = "DPOP_PROOF_MAX_AGE_SECONDS";
+    pub const AUTH_SERVICE_PUBLIC_URL_ENV_VAR: &str = "AUTH_SERVICE_PUBLIC_URL";
+    pub const WEBAUTHN_RP_ID_ENV_VAR: &str = "WEBAUTHN_RP_ID";
+    pub const WEBAUTHN_RP_NAME_ENV_VAR: &str = "WEBAUTHN_RP_NAME";
+    pub const WEBAUTHN_ORIGINS_ENV_VAR: &str = "WEBAUTHN_ORIGINS";
+    pub const WEBAUTHN_CHALLENGE_TTL_SECONDS_ENV_VAR: &str = "WEBAUTHN_CHALLENGE_TTL_SECONDS";
+    pub const MAGIC_LINK_URL_ENV_VAR: &str = "MAGIC_LINK_URL";
+    pub const MAGIC_LINK_TTL_SECONDS_ENV_VAR: &str = "MAGIC_LINK_TTL_SECONDS";
+    pub const OIDC_PROVIDERS_ENV_VAR: &str = "OIDC_PROVIDERS";
+    pub const OIDC_REDIRECT_BASE_URL_ENV_VAR: &str = "OIDC_REDIRECT_BASE_URL";
+    pub const OIDC_STATE_TTL_SECONDS_ENV_VAR: &str = "OIDC_STATE_TTL_SECONDS";
+    pub const SAML_IDP_ENTITY_ID_ENV_VAR: &str = "SAML_IDP_ENTITY_ID";
+    pub const SAML_IDP_SSO_URL_ENV_VAR: &str = "SAML_IDP_SSO_URL";
+    pub const SAML_IDP_CERTIFICATE_ENV_VAR: &str = "SAML_IDP_CERTIFICATE";
+    pub const SAML_SP_ENTITY_ID_ENV_VAR: &str = "SAML_SP_ENTITY_ID";
+    pub const SAML_ACS_URL_ENV_VAR: &str = "SAML_ACS_URL";
+    pub const SAML_REQUEST_TTL_SECONDS_ENV_VAR: &str = "SAML_REQUEST_TTL_SECONDS";
+    pub const SAML_CLOCK_SKEW_SECONDS_ENV_VAR: &str = "SAML_CLOCK_SKEW_SECONDS";
+    pub const LDAP_URL_ENV_VAR: &str = "LDAP_URL";
+    pub const LDAP_BASE_DN_ENV_VAR: &str = "LDAP_BASE_DN";
+    pub const LDAP_BIND_DN_ENV_VAR: &str = "LDAP_BIND_DN";
+    pub const LDAP_BIND_PASSWORD_ENV_VAR: &str = "LDAP_BIND_PASSWORD";
+    pub const LDAP_USER_FILTER_ENV_VAR: &str = "LDAP_USER_FILTER";
+    pub const LDAP_EMAIL_ATTRIBUTE_ENV_VAR: &str = "LDAP_EMAIL_ATTRIBUTE";
+    pub const LDAP_GROUP_ATTRIBUTE_ENV_VAR: &str = "LDAP_GROUP_ATTRIBUTE";
+    pub const LDAP_REQUIRED_GROUP_ENV_VAR: &str = "LDAP_REQUIRED_GROUP";
+    pub const LDAP_2FA_GROUPS_ENV_VAR: &str = "LDAP_2FA_GROUPS";
+    pub const LDAP_STARTTLS_ENV_VAR: &str = "LDAP_STARTTLS";
+    pub const LDAP_TIMEOUT_SECONDS_ENV_VAR: &str = "LDAP_TIMEOUT_SECONDS";
+    pub const EMAIL_BACKEND_ENV_VAR: &str = "EMAIL_BACKEND";
+    pub const SMTP_HOST_ENV_VAR: &str = "SMTP_HOST";
+    pub const SMTP_PORT_ENV_VAR: &str = "SMTP_PORT";
+    pub const SMTP_TLS_ENV_VAR: &str = "SMTP_TLS";
+    pub const SMTP_USERNAME_ENV_VAR: &str = "SMTP_USERNAME";
+    pub const SMTP_PASSWORD_ENV_VAR: &str = "SMTP_PASSWORD";
+    pub const SMTP_POOL_SIZE_ENV_VAR: &str = "SMTP_POOL_SIZE";
+    pub const SMTP_TIMEOUT_SECONDS_ENV_VAR: &str = "SMTP_TIMEOUT_SECONDS";
+    pub const DEV_MAILBOX_ENV_VAR: &str = "DEV_MAILBOX";
+    pub const EMAIL_OUTBOX_MAX_ATTEMPTS_ENV_VAR: &str = "EMAIL_OUTBOX_MAX_ATTEMPTS";
+    pub const EMAIL_OUTBOX_BASE_BACKOFF_SECONDS_ENV_VAR: &str = "EMAIL_OUTBOX_BASE_BACKOFF_SECONDS";
+    pub const EMAIL_OUTBOX_MAX_BACKOFF_SECONDS_ENV_VAR: &str = "EMAIL_OUTBOX_MAX_BACKOFF_SECONDS";
+    pub const EMAIL_OUTBOX_POLL_INTERVAL_SECONDS_ENV_VAR: &str =
+        "EMAIL_OUTBOX_POLL_INTERVAL_SECONDS";
+    pub const SMS_BACKEND_ENV_VAR: &str = "SMS_BACKEND";
+    pub const SMS_GATEWAY_URL_ENV_VAR: &str = "SMS_GATEWAY_URL";
+    pub const SMS_GATEWAY_TOKEN_ENV_VAR: &str = "SMS_GATEWAY_TOKEN";
+    pub const SMS_SENDER_ENV_VAR: &str = "SMS_SENDER";
+    pub const SMS_GATEWAY_TIMEOUT_SECONDS_ENV_VAR: &str = "SMS_GATEWAY_TIMEOUT_SECONDS";
+    pub const PHONE_VERIFICATION_TTL_SECONDS_ENV_VAR: &str = "PHONE_VERIFICATION_TTL_SECONDS";
+    pub const LOGIN_ALERT_COUNTRY_HEADER_ENV_VAR: &str = "LOGIN_ALERT_COUNTRY_HEADER";
+    pub const LOGIN_REPORT_URL_ENV_VAR: &str = "LOGIN_REPORT_URL";
+    pub const LOGIN_REPORT_TTL_SECONDS_ENV_VAR: &str = "LOGIN_REPORT_TTL_SECONDS";
+    pub const EMAIL_DEFAULT_LOCALE_ENV_VAR: &str = "EMAIL_DEFAULT_LOCALE";
+    pub const EMAIL_BRAND_NAME_ENV_VAR: &str = "EMAIL_BRAND_NAME";
+    pub const EMAIL_BRAND_URL_ENV_VAR: &str = "EMAIL_BRAND_URL";
+    pub const EMAIL_BRAND_LOGO_URL_ENV_VAR: &str = "EMAIL_BRAND_LOGO_URL";
+    pub const EMAIL_BRAND_COLOR_ENV_VAR: &str = "EMAIL_BRAND_COLOR";
+    pub const EMAIL_SUPPORT_ADDRESS_ENV_VAR: &str = "EMAIL_SUPPORT_ADDRESS";
+    pub const BANNED_TOKEN_CHECK_FAILURE_POLICY_ENV_VAR: &str = "BANNED_TOKEN_CHECK_FAILURE_POLICY";
     pub const RESEND_SECRET_ENV_VAR: &str = "RESEND_API_KEY";
     pub const DATABASE_URL_ENV_VAR: &str = "DATABASE_URL";
     pub const REDIS_HOST_NAME_ENV_VAR: &str = "REDIS_HOST_NAME";
     pub const SENDER_EMAIL_ENV_VAR: &str = "SENDER_EMAIL";
+    pub const PASSWORD_MIN_LENGTH_ENV_VAR: &str = "PASSWORD_MIN_LENGTH";
+    pub const PASSWORD_MAX_LENGTH_ENV_VAR: &str = "PASSWORD_MAX_LENGTH";
+    pub const PASSWORD_MIN_STRENGTH_SCORE_ENV_VAR: &str = "PASSWORD_MIN_STRENGTH_SCORE";
+    pub const BREACHED_PASSWORDS_FILE_ENV_VAR: &str = "BREACHED_PASSWORDS_FILE";
+    pub const BREACHED_PASSWORDS_API_URL_ENV_VAR: &str = "BREACHED_PASSWORDS_API_URL";
+    pub const ARGON2_MEMORY_KIB_ENV_VAR: &str = "ARGON2_MEMORY_KIB";
+    pub const ARGON2_ITERATIONS_ENV_VAR: &str = "ARGON2_ITERATIONS";
+    pub const ARGON2_PARALLELISM_ENV_VAR: &str = "ARGON2_PARALLELISM";
+    pub const PASSWORD_PEPPER_ENV_VAR: &str = "PASSWORD_PEPPER";
+    pub const PASSWORD_PEPPER_VERSION_ENV_VAR: &str = "PASSWORD_PEPPER_VERSION";
+    pub const PASSWORD_PREVIOUS_PEPPERS_ENV_VAR: &str = "PASSWORD_PREVIOUS_PEPPERS";
+    pub const AUTH_COOKIE_SECURE_ENV_VAR: &str = "AUTH_COOKIE_SECURE";
+    pub const AUTH_COOKIE_DOMAIN_ENV_VAR: &str = "AUTH_COOKIE_DOMAIN";
+    pub const AUTH_COOKIE_HOST_PREFIX_ENV_VAR: &str = "AUTH_COOKIE_HOST_PREFIX";
+    pub const AUTH_COOKIE_SAME_SITE_ENV_VAR: &str = "AUTH_COOKIE_SAME_SITE";
+    pub const RATE_LIMIT_BACKEND_ENV_VAR: &str = "RATE_LIMIT_BACKEND";
+    pub const RATE_LIMIT_SIGNUP_ENV_VAR: &str = "RATE_LIMIT_SIGNUP";
+    pub const RATE_LIMIT_LOGIN_ENV_VAR: &str = "RATE_LIMIT_LOGIN";
+    pub const RATE_LIMIT_VERIFY_2FA_ENV_VAR: &str = "RATE_LIMIT_VERIFY_2FA";
+    pub const RATE_LIMIT_PHONE_NUMBER_ENV_VAR: &str = "RATE_LIMIT_PHONE_NUMBER";
+    pub const RATE_LIMIT_TRUSTED_PROXIES_ENV_VAR: &str = "RATE_LIMIT_TRUSTED_PROXIES";
 }
 
 pub mod prod {
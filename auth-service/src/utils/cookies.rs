@@ -0,0 +1,285 @@
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use color_eyre::eyre::{eyre, Result};
+
+use crate::utils::{
+    auth::TOKEN_TTL_SECONDS,
+    constants::{
+        AUTH_COOKIE_DOMAIN, AUTH_COOKIE_HOST_PREFIX, AUTH_COOKIE_SAME_SITE, AUTH_COOKIE_SECURE,
+        CSRF_COOKIE_NAME, DEVICE_COOKIE_NAME, JWT_COOKIE_NAME, MAGIC_LINK_NONCE_COOKIE_NAME,
+        OIDC_STATE_COOKIE_NAME, SAML_REQUEST_COOKIE_NAME,
+    },
+};
+
+// Browsers only accept `__Host-` cookies that are Secure, have path `/` and no Domain, which
+// pins them to the exact host that set them
+const HOST_PREFIX: &str = "__Host-";
+// Browsers cap the lifetime of cookies at 400 days
+const DEVICE_COOKIE_TTL_SECONDS: i64 = 400 * 24 * 60 * 60;
+
+// Attributes shared by the auth and CSRF cookies, configured per deployment
+#[derive(Debug, Clone, PartialEq)]
+pub struct CookiePolicy {
+    secure: bool,
+    domain: Option<String>,
+    host_prefix: bool,
+    same_site: SameSite,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        let same_site =
+            parse_same_site(&AUTH_COOKIE_SAME_SITE).expect("Invalid auth cookie SameSite value");
+        Self::new(
+            *AUTH_COOKIE_SECURE,
+            AUTH_COOKIE_DOMAIN.clone(),
+            *AUTH_COOKIE_HOST_PREFIX,
+            same_site,
+        )
+        .expect("Invalid auth cookie configuration")
+    }
+}
+
+impl CookiePolicy {
+    pub fn new(
+        secure: bool,
+        domain: Option<String>,
+        host_prefix: bool,
+        same_site: SameSite,
+    ) -> Result<Self> {
+        if host_prefix && (!secure || domain.is_some()) {
+            return Err(eyre!(
+                "The __Host- cookie prefix requires Secure cookies without a Domain"
+            ));
+        }
+        if same_site == SameSite::None && !secure {
+            return Err(eyre!("SameSite=None cookies must be Secure"));
+        }
+
+        Ok(Self {
+            secure,
+            domain,
+            host_prefix,
+            same_site,
+        })
+    }
+
+    pub fn auth_cookie_name(&self) -> String {
+        self.cookie_name(JWT_COOKIE_NAME)
+    }
+
+    pub fn csrf_cookie_name(&self) -> String {
+        self.cookie_name(CSRF_COOKIE_NAME)
+    }
+
+    pub fn magic_link_nonce_cookie_name(&self) -> String {
+        self.cookie_name(MAGIC_LINK_NONCE_COOKIE_NAME)
+    }
+
+    pub fn oidc_state_cookie_name(&self) -> String {
+        self.cookie_name(OIDC_STATE_COOKIE_NAME)
+    }
+
+    pub fn saml_request_cookie_name(&self) -> String {
+        self.cookie_name(SAML_REQUEST_COOKIE_NAME)
+    }
+
+    pub fn device_cookie_name(&self) -> String {
+        self.cookie_name(DEVICE_COOKIE_NAME)
+    }
+
+    fn cookie_name(&self, name: &str) -> String {
+        if self.host_prefix {
+            format!("{}{}", HOST_PREFIX, name)
+        } else {
+            name.to_owned()
+        }
+    }
+
+    // Lives exactly as long as the token inside it
+    pub fn auth_cookie(&self, token: String) -> Cookie<'static> {
+        let mut cookie = self.build(self.auth_cookie_name(), token);
+        cookie.set_http_only(true); // prevent JavaScript from accessing the cookie
+        cookie.set_same_site(self.same_site);
+        cookie
+    }
+
+    // Readable from JavaScript so same-origin pages can copy it into the CSRF header, and never
+    // sent cross-site since it is only ever read locally
+    pub fn csrf_cookie(&self, token: String) -> Cookie<'static> {
+        let mut cookie = self.build(self.csrf_cookie_name(), token);
+        cookie.set_same_site(SameSite::Strict);
+        cookie
+    }
+
+    // Lives as long as the magic link it binds to the browser
+    pub fn magic_link_nonce_cookie(&self, nonce: String, ttl: u64) -> Cookie<'static> {
+        self.login_flow_cookie(self.magic_link_nonce_cookie_name(), nonce, ttl)
+    }
+
+    // Lives as long as the login at the identity provider it binds to the browser
+    pub fn oidc_state_cookie(&self, state: String, ttl: u64) -> Cookie<'static> {
+        self.login_flow_cookie(self.oidc_state_cookie_name(), state, ttl)
+    }
+
+    // Lives as long as the SAML request it binds to the browser. The identity provider posts its
+    // response back cross-site, which browsers only send SameSite=None cookies with. Those have to
+    // be Secure, so without Secure cookies the browser's default is left to apply.
+    pub fn saml_request_cookie(&self, request_id: String, ttl: u64) -> Cookie<'static> {
+        let mut cookie = self.login_flow_cookie(self.saml_request_cookie_name(), request_id, ttl);
+        cookie.set_same_site(self.secure.then_some(SameSite::None));
+        cookie
+    }
+
+    // Recognizes the browser on later logins. It is set again on every login, so it only expires
+    // for browsers that stop being used.
+    pub fn device_cookie(&self, device: String) -> Cookie<'static> {
+        let mut cookie = self.login_flow_cookie(self.device_cookie_name(), device, 0);
+        cookie.set_max_age(time::Duration::seconds(DEVICE_COOKIE_TTL_SECONDS));
+        cookie
+    }
+
+    // Binds a login that continues elsewhere to the browser it started in. The browser comes back
+    // from a mail client or an identity provider, so the cookie has to be sent on top-level
+    // navigations from other sites, and is never Strict.
+    fn login_flow_cookie(&self, name: String, value: String, ttl: u64) -> Cookie<'static> {
+        let mut cookie = self.build(name, value);
+        cookie.set_http_only(true);
+        cookie.set_max_age(time::Duration::seconds(ttl as i64));
+        cookie.set_same_site(match self.same_site {
+            SameSite::None => SameSite::None,
+            _ => SameSite::Lax,
+        });
+        cookie
+    }
+
+    // Browsers only delete a cookie when the path and domain of the removal match the original
+    pub fn removal_cookie(&self, name: String) -> Cookie<'static> {
+        self.build(name, String::new())
+    }
+
+    fn build(&self, name: String, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build((name, value))
+            .path("/") // apply cookie to all URLs on the server
+            .secure(self.secure)
+            .max_age(time::Duration::seconds(TOKEN_TTL_SECONDS as i64))
+            .build();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+}
+
+pub fn parse_same_site(value: &str) -> Result<SameSite> {
+    match value.to_ascii_lowercase().as_str() {
+        "strict" => Ok(SameSite::Strict),
+        "lax" => Ok(SameSite::Lax),
+        "none" => Ok(SameSite::None),
+        other => Err(eyre!("Unknown SameSite value: {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_cookie_attributes() {
+        let policy = CookiePolicy::new(
+            true,
+            Some("example.com".to_owned()),
+            false,
+            SameSite::Strict,
+        )
+        .unwrap();
+        let cookie = policy.auth_cookie("token".to_owned());
+
+        assert_eq!(cookie.name(), JWT_COOKIE_NAME);
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.domain(), Some("example.com"));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        assert_eq!(
+            cookie.max_age(),
+            Some(time::Duration::seconds(TOKEN_TTL_SECONDS as i64))
+        );
+    }
+
+    #[test]
+    fn test_host_prefix() {
+        let policy = CookiePolicy::new(true, None, true, SameSite::Lax).unwrap();
+        assert_eq!(policy.auth_cookie_name(), "__Host-jwt");
+        assert_eq!(policy.csrf_cookie_name(), "__Host-csrf_token");
+        assert_eq!(policy.auth_cookie("token".to_owned()).domain(), None);
+
+        assert!(CookiePolicy::new(false, None, true, SameSite::Lax).is_err());
+        assert!(
+            CookiePolicy::new(true, Some("example.com".to_owned()), true, SameSite::Lax).is_err()
+        );
+    }
+
+    #[test]
+    fn test_magic_link_nonce_cookie_is_sent_from_mail_clients() {
+        let policy = CookiePolicy::new(true, None, false, SameSite::Strict).unwrap();
+        let cookie = policy.magic_link_nonce_cookie("nonce".to_owned(), 900);
+
+        assert_eq!(cookie.name(), MAGIC_LINK_NONCE_COOKIE_NAME);
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.max_age(), Some(time::Duration::seconds(900)));
+    }
+
+    #[test]
+    fn test_saml_request_cookie_is_sent_with_identity_provider_posts() {
+        let policy = CookiePolicy::new(true, None, true, SameSite::Strict).unwrap();
+        let cookie = policy.saml_request_cookie("_request".to_owned(), 300);
+
+        assert_eq!(cookie.name(), "__Host-saml_request");
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::None));
+        assert_eq!(cookie.max_age(), Some(time::Duration::seconds(300)));
+
+        let policy = CookiePolicy::new(false, None, false, SameSite::Lax).unwrap();
+        let cookie = policy.saml_request_cookie("_request".to_owned(), 300);
+        assert_eq!(cookie.same_site(), None);
+    }
+
+    #[test]
+    fn test_device_cookie_outlives_sessions() {
+        let policy = CookiePolicy::new(true, None, true, SameSite::Strict).unwrap();
+        let cookie = policy.device_cookie("device".to_owned());
+
+        assert_eq!(cookie.name(), "__Host-device_id");
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(
+            cookie.max_age(),
+            Some(time::Duration::seconds(DEVICE_COOKIE_TTL_SECONDS))
+        );
+    }
+
+    #[test]
+    fn test_same_site_none_requires_secure() {
+        assert!(CookiePolicy::new(false, None, false, SameSite::None).is_err());
+        assert!(CookiePolicy::new(true, None, false, SameSite::None).is_ok());
+    }
+
+    #[test]
+    fn test_removal_cookie_matches_attributes() {
+        let policy =
+            CookiePolicy::new(true, Some("example.com".to_owned()), false, SameSite::Lax).unwrap();
+        let cookie = policy.removal_cookie(policy.auth_cookie_name());
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.domain(), Some("example.com"));
+        assert_eq!(cookie.secure(), Some(true));
+    }
+
+    #[test]
+    fn test_parse_same_site() {
+        assert_eq!(parse_same_site("Strict").unwrap(), SameSite::Strict);
+        assert_eq!(parse_same_site("lax").unwrap(), SameSite::Lax);
+        assert_eq!(parse_same_site("NONE").unwrap(), SameSite::None);
+        assert!(parse_same_site("sometimes").is_err());
+    }
+}
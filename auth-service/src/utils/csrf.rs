@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::{cookie::Cookie, CookieJar};
+use color_eyre::eyre::Result;
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+use tracing::instrument;
+
+use crate::{
+    domain::AuthAPIError,
+    utils::{
+        auth::authorization_token,
+        constants::{CSRF_HEADER_NAME, JWT_SECRET},
+        cookies::CookiePolicy,
+    },
+};
+
+// Keeps CSRF tokens from ever colliding with other values signed with the JWT secret
+const CSRF_TOKEN_CONTEXT: &[u8] = b"csrf-token:";
+
+// The CSRF token is an HMAC of the session token, so it needs no server side storage, changes
+// with every login, and a token planted by a sibling subdomain cannot match someone else's session
+#[instrument(skip_all)]
+pub fn generate_csrf_token(auth_token: &str) -> Result<String> {
+    Ok(hex::encode(csrf_mac(auth_token)?.finalize().into_bytes()))
+}
+
+#[instrument(skip_all)]
+pub fn verify_csrf_token(auth_token: &str, csrf_token: &str) -> bool {
+    let (Ok(mac), Ok(csrf_token)) = (csrf_mac(auth_token), hex::decode(csrf_token)) else {
+        return false;
+    };
+    mac.verify_slice(&csrf_token).is_ok()
+}
+
+fn csrf_mac(auth_token: &str) -> Result<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(JWT_SECRET.expose_secret().as_bytes())?;
+    mac.update(CSRF_TOKEN_CONTEXT);
+    mac.update(auth_token.as_bytes());
+    Ok(mac)
+}
+
+// Issued next to the auth cookie
+#[instrument(skip_all)]
+pub fn generate_csrf_cookie(
+    auth_token: &str,
+    cookie_policy: &CookiePolicy,
+) -> Result<Cookie<'static>> {
+    let token = generate_csrf_token(auth_token)?;
+    Ok(cookie_policy.csrf_cookie(token))
+}
+
+// Rejects state-changing requests that authenticate with the auth cookie unless they carry the
+// matching CSRF token header. Requests with a bearer or DPoP token come from API clients that
+// attach their credentials explicitly, which a cross-site form cannot do, so they are exempt.
+// Such requests are authenticated by that token even if a cookie is present as well.
+pub async fn csrf_protect(
+    State(cookie_policy): State<Arc<CookiePolicy>>,
+    jar: CookieJar,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_safe_method = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if is_safe_method || authorization_token(request.headers()).is_some() {
+        return next.run(request).await;
+    }
+
+    let Some(auth_cookie) = jar.get(&cookie_policy.auth_cookie_name()) else {
+        return next.run(request).await;
+    };
+
+    let csrf_token = request
+        .headers()
+        .get(CSRF_HEADER_NAME)
+        .and_then(|value| value.to_str().ok());
+
+    match csrf_token {
+        Some(csrf_token) if verify_csrf_token(auth_cookie.value(), csrf_token) => {
+            next.run(request).await
+        }
+        _ => AuthAPIError::InvalidCsrfToken.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_extra::extract::cookie::SameSite;
+
+    use crate::utils::constants::CSRF_COOKIE_NAME;
+
+    use super::*;
+
+    #[test]
+    fn test_csrf_token_matches_its_session() {
+        let token = generate_csrf_token("session-a").unwrap();
+        assert!(verify_csrf_token("session-a", &token));
+        assert!(!verify_csrf_token("session-b", &token));
+    }
+
+    #[test]
+    fn test_rejects_malformed_csrf_token() {
+        assert!(!verify_csrf_token("session-a", ""));
+        assert!(!verify_csrf_token("session-a", "not-hex"));
+        assert!(!verify_csrf_token("session-a", "00ff"));
+    }
+
+    #[test]
+    fn test_generate_csrf_cookie() {
+        let cookie = generate_csrf_cookie("session-a", &CookiePolicy::default()).unwrap();
+        assert_eq!(cookie.name(), CSRF_COOKIE_NAME);
+        assert!(verify_csrf_token("session-a", cookie.value()));
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.http_only(), None);
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+    }
+}
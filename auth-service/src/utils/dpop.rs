@@ -0,0 +1,478 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::{header::HOST, request::Parts},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Context, ContextCompat, Result};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk, ThumbprintHash},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+
+use crate::{
+    domain::AuthAPIError,
+    services::dpop_replay::{DpopReplayStore, DpopReplayStoreBackend, HashmapDpopReplayStore},
+    utils::constants::{AUTH_SERVICE_PUBLIC_URL, DPOP_PROOF_MAX_AGE_SECONDS, JWT_LEEWAY_SECONDS},
+};
+
+pub const DPOP_HEADER_NAME: &str = "dpop";
+const DPOP_PROOF_TYPE: &str = "dpop+jwt";
+// Members of a JWK that only a private key has. Proofs must never carry them.
+const PRIVATE_KEY_MEMBERS: [&str; 6] = ["d", "p", "q", "dp", "dq", "qi"];
+
+// A DPoP proof together with the request it was sent with, which the proof has to match. Resource
+// servers forward these to `/verify-token` for the requests they receive.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DpopRequest {
+    pub proof: String,
+    pub method: String,
+    pub url: String,
+}
+
+impl<S> OptionalFromRequestParts<S> for DpopRequest
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        let mut proofs = parts.headers.get_all(DPOP_HEADER_NAME).iter();
+        let Some(proof) = proofs.next() else {
+            return Ok(None);
+        };
+        // A request carries exactly one proof
+        if proofs.next().is_some() {
+            return Err(AuthAPIError::InvalidDpopProof);
+        }
+        let proof = proof.to_str().map_err(|_| AuthAPIError::InvalidDpopProof)?;
+
+        Ok(Some(Self {
+            proof: proof.to_owned(),
+            method: parts.method.to_string(),
+            url: request_url(parts),
+        }))
+    }
+}
+
+impl<S> FromRequestParts<S> for DpopRequest
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        <Self as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+            .await?
+            .ok_or(AuthAPIError::InvalidDpopProof)
+    }
+}
+
+// The URL clients address this service by. Behind a proxy that terminates TLS, the public URL
+// has to be configured, since the request itself does not tell.
+fn request_url(parts: &Parts) -> String {
+    let path = parts.uri.path();
+    match AUTH_SERVICE_PUBLIC_URL.as_ref() {
+        Some(base) => format!("{}{}", base.trim_end_matches('/'), path),
+        None => {
+            let host = parts
+                .headers
+                .get(HOST)
+                .and_then(|host| host.to_str().ok())
+                .or_else(|| parts.uri.authority().map(|authority| authority.as_str()))
+                .unwrap_or_default();
+            format!("http://{}{}", host, path)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DpopClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: i64,
+    ath: Option<String>,
+}
+
+// A verified proof, identified by the RFC 7638 thumbprint of the key that signed it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DpopProof {
+    pub jkt: String,
+}
+
+// Verifies DPoP proofs (RFC 9449). Proofs are accepted for `max_age` after they were created, plus
+// the clock skew leeway, and only once.
+#[derive(Clone)]
+pub struct DpopVerifier {
+    replay_store: DpopReplayStoreBackend,
+    max_age: Duration,
+    leeway: Duration,
+}
+
+impl Default for DpopVerifier {
+    fn default() -> Self {
+        Self::new(DpopReplayStoreBackend::InMemory(
+            HashmapDpopReplayStore::default(),
+        ))
+    }
+}
+
+impl DpopVerifier {
+    pub fn new(replay_store: DpopReplayStoreBackend) -> Self {
+        Self {
+            replay_store,
+            max_age: Duration::from_secs(*DPOP_PROOF_MAX_AGE_SECONDS),
+            leeway: Duration::from_secs(*JWT_LEEWAY_SECONDS),
+        }
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    // Checks the proof of `request`. Proofs sent along with an access token must also commit to
+    // that token through their `ath` claim.
+    #[instrument(skip_all)]
+    pub async fn verify(
+        &self,
+        request: &DpopRequest,
+        access_token: Option<&str>,
+    ) -> Result<DpopProof> {
+        let header = decode_header(&request.proof).wrap_err("Malformed DPoP proof")?;
+        if header.typ.as_deref() != Some(DPOP_PROOF_TYPE) {
+            return Err(eyre!("DPoP proof has the wrong type"));
+        }
+        let jwk = header.jwk.wrap_err("DPoP proof has no key")?;
+        check_key(header.alg, &jwk)?;
+        check_public_key_only(&request.proof)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        validation.validate_aud = false;
+        let claims = decode::<DpopClaims>(
+            &request.proof,
+            &DecodingKey::from_jwk(&jwk).wrap_err("Invalid DPoP key")?,
+            &validation,
+        )
+        .wrap_err("Invalid DPoP proof")?
+        .claims;
+
+        if claims.htm != request.method {
+            return Err(eyre!("DPoP proof is for another method"));
+        }
+        if !same_url(&claims.htu, &request.url) {
+            return Err(eyre!("DPoP proof is for another URL"));
+        }
+        self.check_issued_at(claims.iat)?;
+        if let Some(access_token) = access_token {
+            let ath = URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes()));
+            if claims.ath.as_deref() != Some(ath.as_str()) {
+                return Err(eyre!("DPoP proof is for another access token"));
+            }
+        }
+
+        let jkt = jwk.thumbprint(ThumbprintHash::SHA256);
+        let first_use = self
+            .replay_store
+            .insert_if_absent(
+                &format!("{}:{}", jkt, claims.jti),
+                self.max_age + self.leeway * 2,
+            )
+            .await
+            .wrap_err("Failed to check DPoP proof for replay")?;
+        if !first_use {
+            return Err(eyre!("DPoP proof was already used"));
+        }
+
+        Ok(DpopProof { jkt })
+    }
+
+    fn check_issued_at(&self, iat: i64) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let leeway = self.leeway.as_secs() as i64;
+        let oldest = now - self.max_age.as_secs() as i64 - leeway;
+        if iat < oldest || iat > now + leeway {
+            return Err(eyre!("DPoP proof is expired or from the future"));
+        }
+        Ok(())
+    }
+}
+
+// Only asymmetric keys on well supported curves are accepted. A symmetric key would let anyone
+// who sees the proof forge new ones.
+fn check_key(alg: Algorithm, jwk: &Jwk) -> Result<()> {
+    let supported = match (alg, &jwk.algorithm) {
+        (Algorithm::ES256, AlgorithmParameters::EllipticCurve(params)) => {
+            params.curve == EllipticCurve::P256
+        }
+        (Algorithm::RS256 | Algorithm::PS256, AlgorithmParameters::RSA(_)) => true,
+        _ => false,
+    };
+    if !supported {
+        return Err(eyre!("Unsupported DPoP proof algorithm {:?}", alg));
+    }
+    Ok(())
+}
+
+// The parsed JWK drops unknown members, so the raw header is checked for private key material
+fn check_public_key_only(proof: &str) -> Result<()> {
+    let header = proof.split('.').next().unwrap_or_default();
+    let header: serde_json::Value = URL_SAFE_NO_PAD
+        .decode(header)
+        .ok()
+        .and_then(|header| serde_json::from_slice(&header).ok())
+        .wrap_err("Malformed DPoP proof header")?;
+    let has_private_members = header["jwk"].as_object().is_some_and(|jwk| {
+        PRIVATE_KEY_MEMBERS
+            .iter()
+            .any(|member| jwk.contains_key(*member))
+    });
+    if has_private_members {
+        return Err(eyre!("DPoP proof contains a private key"));
+    }
+    Ok(())
+}
+
+// Compares URLs without their query and fragment, after normalizing case and default ports
+fn same_url(htu: &str, url: &str) -> bool {
+    let (Ok(htu), Ok(url)) = (Url::parse(htu), Url::parse(url)) else {
+        return false;
+    };
+    htu.scheme() == url.scheme()
+        && htu.host_str() == url.host_str()
+        && htu.port_or_known_default() == url.port_or_known_default()
+        && htu.path() == url.path()
+}
+
+#[cfg(test)]
+pub(crate) mod test_keys {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use p256::pkcs8::EncodePrivateKey;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    // Signs proofs with a P-256 key, like a DPoP client would
+    pub(crate) struct DpopKey {
+        key: EncodingKey,
+        pub(crate) jwk: Jwk,
+    }
+
+    impl DpopKey {
+        pub(crate) fn generate() -> Self {
+            let secret = p256::SecretKey::random(&mut rand::rngs::OsRng);
+            let der = secret.to_pkcs8_der().unwrap();
+            let key = EncodingKey::from_ec_der(der.as_bytes());
+            let jwk = Jwk::from_encoding_key(&key, Algorithm::ES256).unwrap();
+            Self { key, jwk }
+        }
+
+        pub(crate) fn proof(&self, method: &str, url: &str, access_token: Option<&str>) -> String {
+            let ath =
+                access_token.map(|token| URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes())));
+            self.sign(json!({
+                "jti": Uuid::new_v4().to_string(),
+                "htm": method,
+                "htu": url,
+                "iat": Utc::now().timestamp(),
+                "ath": ath,
+            }))
+        }
+
+        pub(crate) fn sign(&self, claims: serde_json::Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.typ = Some(DPOP_PROOF_TYPE.to_owned());
+            header.jwk = Some(self.jwk.clone());
+            encode(&header, &claims, &self.key).unwrap()
+        }
+
+        pub(crate) fn jkt(&self) -> String {
+            self.jwk.thumbprint(ThumbprintHash::SHA256)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::{test_keys::DpopKey, *};
+
+    const URL: &str = "https://auth.example.com/login";
+
+    fn request(proof: String) -> DpopRequest {
+        DpopRequest {
+            proof,
+            method: "POST".to_owned(),
+            url: URL.to_owned(),
+        }
+    }
+
+    // Replaces the header of `proof`, keeping its payload and signature
+    fn with_header(proof: &str, header: &Header) -> String {
+        let (_, rest) = proof.split_once('.').unwrap();
+        let header = URL_SAFE_NO_PAD.encode(serde_json::to_vec(header).unwrap());
+        format!("{}.{}", header, rest)
+    }
+
+    #[tokio::test]
+    async fn test_verifies_proof() {
+        let key = DpopKey::generate();
+        let proof = DpopVerifier::default()
+            .verify(&request(key.proof("POST", URL, None)), None)
+            .await
+            .unwrap();
+        assert_eq!(proof.jkt, key.jkt());
+    }
+
+    #[tokio::test]
+    async fn test_ignores_query_and_normalizes_url() {
+        let key = DpopKey::generate();
+        let proof = key.proof("POST", "HTTPS://Auth.Example.com:443/login?next=%2F", None);
+        assert!(DpopVerifier::default()
+            .verify(&request(proof), None)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_other_method_or_url() {
+        let key = DpopKey::generate();
+        let verifier = DpopVerifier::default();
+
+        let proof = key.proof("GET", URL, None);
+        assert!(verifier.verify(&request(proof), None).await.is_err());
+
+        let proof = key.proof("POST", "https://auth.example.com/logout", None);
+        assert!(verifier.verify(&request(proof), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_replayed_proof() {
+        let key = DpopKey::generate();
+        let verifier = DpopVerifier::default();
+        let proof = key.proof("POST", URL, None);
+
+        assert!(verifier.verify(&request(proof.clone()), None).await.is_ok());
+        assert!(verifier.verify(&request(proof), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_stale_or_future_proof() {
+        let key = DpopKey::generate();
+        let verifier = DpopVerifier::default().with_max_age(Duration::from_secs(60));
+        let leeway = *JWT_LEEWAY_SECONDS as i64;
+
+        for iat in [
+            Utc::now().timestamp() - 60 - leeway - 10,
+            Utc::now().timestamp() + leeway + 10,
+        ] {
+            let proof = key.sign(json!({
+                "jti": Uuid::new_v4().to_string(),
+                "htm": "POST",
+                "htu": URL,
+                "iat": iat,
+            }));
+            assert!(verifier.verify(&request(proof), None).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checks_access_token_hash() {
+        let key = DpopKey::generate();
+        let verifier = DpopVerifier::default();
+
+        let proof = key.proof("POST", URL, Some("access-token"));
+        assert!(verifier
+            .verify(&request(proof), Some("access-token"))
+            .await
+            .is_ok());
+
+        let proof = key.proof("POST", URL, Some("access-token"));
+        assert!(verifier
+            .verify(&request(proof), Some("other-token"))
+            .await
+            .is_err());
+
+        let proof = key.proof("POST", URL, None);
+        assert!(verifier
+            .verify(&request(proof), Some("access-token"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_proof_signed_by_another_key() {
+        let proof = DpopKey::generate().proof("POST", URL, None);
+        let mut header = decode_header(&proof).unwrap();
+        header.jwk = Some(DpopKey::generate().jwk);
+
+        assert!(DpopVerifier::default()
+            .verify(&request(with_header(&proof, &header)), None)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_wrong_type() {
+        let proof = DpopKey::generate().proof("POST", URL, None);
+        let mut header = decode_header(&proof).unwrap();
+        header.typ = Some("JWT".to_owned());
+
+        assert!(DpopVerifier::default()
+            .verify(&request(with_header(&proof, &header)), None)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_symmetric_key() {
+        // An HMAC key published in the header would let anyone sign proofs with it
+        let key = EncodingKey::from_secret(b"secret");
+        let mut header = Header::new(Algorithm::HS256);
+        header.typ = Some(DPOP_PROOF_TYPE.to_owned());
+        header.jwk = Some(Jwk::from_encoding_key(&key, Algorithm::HS256).unwrap());
+        let claims = json!({
+            "jti": Uuid::new_v4().to_string(),
+            "htm": "POST",
+            "htu": URL,
+            "iat": Utc::now().timestamp(),
+        });
+        let proof = encode(&header, &claims, &key).unwrap();
+
+        assert!(DpopVerifier::default()
+            .verify(&request(proof), None)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_rejects_private_key_in_header() {
+        let header = URL_SAFE_NO_PAD.encode(
+            json!({"typ": "dpop+jwt", "alg": "ES256", "jwk": {"kty": "EC", "d": "secret"}})
+                .to_string(),
+        );
+        assert!(check_public_key_only(&format!("{}.e30.sig", header)).is_err());
+
+        let header = URL_SAFE_NO_PAD.encode(
+            json!({"typ": "dpop+jwt", "alg": "ES256", "jwk": {"kty": "EC", "x": "x"}}).to_string(),
+        );
+        assert!(check_public_key_only(&format!("{}.e30.sig", header)).is_ok());
+    }
+}
@@ -0,0 +1,400 @@
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::eyre;
+use sqlx::PgConnection;
+use tokio::sync::{Notify, RwLock};
+use tracing::{error, instrument, warn};
+
+use crate::{
+    domain::{models::Email, EmailClient, EmailMessage},
+    services::email_outbox::{
+        EmailOutboxStore, EmailOutboxStoreBackend, EmailOutboxStoreError, HashmapEmailOutboxStore,
+        OutboxEmail, OutboxEntry, OutboxStatus, PostgresEmailOutboxStore,
+    },
+    utils::constants::{
+        EMAIL_OUTBOX_BASE_BACKOFF_SECONDS, EMAIL_OUTBOX_MAX_ATTEMPTS,
+        EMAIL_OUTBOX_MAX_BACKOFF_SECONDS, EMAIL_OUTBOX_POLL_INTERVAL_SECONDS,
+    },
+};
+
+// How many emails a worker claims at once
+const BATCH_SIZE: u32 = 10;
+// How long a claimed email stays hidden from other workers. It must outlast a delivery, or a slow
+// one could be sent twice.
+const DELIVERY_LEASE: Duration = Duration::from_secs(60);
+
+// When failed deliveries are tried again. The wait doubles with every attempt, up to a limit, and
+// emails are dead-lettered after the last attempt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: *EMAIL_OUTBOX_MAX_ATTEMPTS,
+            base_backoff: Duration::from_secs(*EMAIL_OUTBOX_BASE_BACKOFF_SECONDS),
+            max_backoff: Duration::from_secs(*EMAIL_OUTBOX_MAX_BACKOFF_SECONDS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // The wait after `attempts` failed attempts, or None if the email should be given up on
+    pub fn backoff(&self, attempts: u32) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        Some(
+            self.base_backoff
+                .saturating_mul(factor)
+                .min(self.max_backoff),
+        )
+    }
+}
+
+// Sends emails in the background, so a slow or failing email provider never holds up a request.
+// Handlers enqueue emails and return; a worker delivers them through the email client, retrying
+// failures. Enqueueing wakes the worker of this instance, and it also polls for emails enqueued
+// elsewhere and for retries that came due.
+#[derive(Clone)]
+pub struct EmailOutbox {
+    store: EmailOutboxStoreBackend,
+    retry_policy: RetryPolicy,
+    poll_interval: Duration,
+    wake: Arc<Notify>,
+}
+
+impl Default for EmailOutbox {
+    fn default() -> Self {
+        Self::new(EmailOutboxStoreBackend::InMemory(
+            HashmapEmailOutboxStore::default(),
+        ))
+    }
+}
+
+impl EmailOutbox {
+    pub fn new(store: EmailOutboxStoreBackend) -> Self {
+        Self {
+            store,
+            retry_policy: RetryPolicy::default(),
+            poll_interval: Duration::from_secs(*EMAIL_OUTBOX_POLL_INTERVAL_SECONDS),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    // Queues `message` for `recipient`. Emails enqueued again under the same key are dropped.
+    #[instrument(skip_all)]
+    pub async fn enqueue(
+        &self,
+        idempotency_key: String,
+        recipient: &Email,
+        message: EmailMessage,
+    ) -> Result<(), EmailOutboxStoreError> {
+        self.store
+            .enqueue(OutboxEmail {
+                idempotency_key,
+                recipient: recipient.clone(),
+                message,
+            })
+            .await?;
+        self.wake();
+        Ok(())
+    }
+
+    // Queues `message` for `recipient` in the caller's transaction, so it is only sent if the
+    // change it is about gets committed. Only an outbox kept in Postgres can join the
+    // transaction, and its worker has to be woken once the transaction is committed.
+    #[instrument(skip_all)]
+    pub async fn enqueue_in(
+        &self,
+        conn: &mut PgConnection,
+        idempotency_key: String,
+        recipient: &Email,
+        message: EmailMessage,
+    ) -> Result<(), EmailOutboxStoreError> {
+        let email = OutboxEmail {
+            idempotency_key,
+            recipient: recipient.clone(),
+            message,
+        };
+        match &self.store {
+            EmailOutboxStoreBackend::Postgres(_) => {
+                PostgresEmailOutboxStore::enqueue_in(conn, &email).await
+            }
+            EmailOutboxStoreBackend::InMemory(_) => Err(EmailOutboxStoreError::UnexpectedError(
+                eyre!("The in-memory outbox cannot join a Postgres transaction"),
+            )),
+        }
+    }
+
+    // Has the worker look for emails right away, e.g. after committing a transaction that
+    // enqueued some with `enqueue_in`
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    pub async fn status(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<OutboxStatus>, EmailOutboxStoreError> {
+        self.store.get_status(idempotency_key).await
+    }
+
+    // Delivers one batch of due emails, returning how many were attempted
+    pub async fn deliver_due<W: EmailClient>(
+        &self,
+        email_client: &RwLock<W>,
+    ) -> Result<usize, EmailOutboxStoreError> {
+        let entries = self.store.claim_due(BATCH_SIZE, DELIVERY_LEASE).await?;
+        for entry in &entries {
+            self.deliver(entry, email_client).await?;
+        }
+        Ok(entries.len())
+    }
+
+    #[instrument(skip_all, fields(idempotency_key = %entry.email.idempotency_key, attempts = entry.attempts))]
+    async fn deliver<W: EmailClient>(
+        &self,
+        entry: &OutboxEntry,
+        email_client: &RwLock<W>,
+    ) -> Result<(), EmailOutboxStoreError> {
+        let result = email_client
+            .read()
+            .await
+            .send_multipart_email(&entry.email.recipient, &entry.email.message)
+            .await;
+
+        match result {
+            Ok(()) => self.store.mark_sent(entry.id).await,
+            Err(e) => {
+                let retry_in = self.retry_policy.backoff(entry.attempts);
+                match retry_in {
+                    Some(retry_in) => warn!(error = ?e, ?retry_in, "Failed to send email"),
+                    None => error!(error = ?e, "Failed to send email, giving up"),
+                }
+                self.store
+                    .mark_failed(entry.id, &format!("{:#}", e), retry_in)
+                    .await
+            }
+        }
+    }
+
+    // Delivers emails until the process ends
+    pub async fn run<W: EmailClient>(self, email_client: Arc<RwLock<W>>) {
+        loop {
+            match self.deliver_due(&email_client).await {
+                // A full batch suggests more are waiting
+                Ok(delivered) if delivered == BATCH_SIZE as usize => continue,
+                Ok(_) => {}
+                Err(e) => error!(error = ?e, "Failed to deliver emails from the outbox"),
+            }
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(self.poll_interval) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use color_eyre::eyre::{eyre, Result};
+    use secrecy::ExposeSecret;
+
+    use super::*;
+
+    // Fails the first `failures` sends and keeps the subjects of the ones that went through
+    #[derive(Clone, Default)]
+    struct FlakyEmailClient {
+        failures: Arc<Mutex<u32>>,
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FlakyEmailClient {
+        fn failing(failures: u32) -> Self {
+            Self {
+                failures: Arc::new(Mutex::new(failures)),
+                ..Default::default()
+            }
+        }
+
+        fn sent(&self) -> Vec<String> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    impl EmailClient for FlakyEmailClient {
+        async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()> {
+            let message = EmailMessage {
+                subject: subject.to_owned(),
+                html: content.to_owned(),
+                text: content.to_owned(),
+            };
+            self.send_multipart_email(recipient, &message).await
+        }
+
+        async fn send_multipart_email(
+            &self,
+            recipient: &Email,
+            message: &EmailMessage,
+        ) -> Result<()> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(eyre!("Provider unavailable"));
+            }
+            assert_eq!(recipient.as_ref().expose_secret(), "test@example.com");
+            self.sent.lock().unwrap().push(message.subject.clone());
+            Ok(())
+        }
+    }
+
+    fn recipient() -> Email {
+        Email::new("test@example.com".to_owned().into()).unwrap()
+    }
+
+    fn message(subject: &str) -> EmailMessage {
+        EmailMessage {
+            subject: subject.to_owned(),
+            html: "<p>Body</p>".to_owned(),
+            text: "Body".to_owned(),
+        }
+    }
+
+    fn outbox(store: HashmapEmailOutboxStore, max_attempts: u32) -> EmailOutbox {
+        EmailOutbox::new(EmailOutboxStoreBackend::InMemory(store)).with_retry_policy(RetryPolicy {
+            max_attempts,
+            base_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        })
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_limit() {
+        let policy = RetryPolicy {
+            max_attempts: 6,
+            base_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(30),
+        };
+
+        let waits: Vec<_> = (1..=6).map(|attempts| policy.backoff(attempts)).collect();
+        assert_eq!(
+            waits,
+            [
+                Some(Duration::from_secs(5)),
+                Some(Duration::from_secs(10)),
+                Some(Duration::from_secs(20)),
+                Some(Duration::from_secs(30)),
+                Some(Duration::from_secs(30)),
+                None,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delivers_each_key_once() {
+        let client = RwLock::new(FlakyEmailClient::default());
+        let outbox = outbox(HashmapEmailOutboxStore::default(), 3);
+
+        outbox
+            .enqueue("a".to_owned(), &recipient(), message("First"))
+            .await
+            .unwrap();
+        outbox
+            .enqueue("a".to_owned(), &recipient(), message("Again"))
+            .await
+            .unwrap();
+
+        assert_eq!(outbox.deliver_due(&client).await.unwrap(), 1);
+        assert_eq!(outbox.deliver_due(&client).await.unwrap(), 0);
+        assert_eq!(client.read().await.sent(), ["First"]);
+        assert_eq!(outbox.status("a").await.unwrap(), Some(OutboxStatus::Sent));
+    }
+
+    #[tokio::test]
+    async fn test_retries_failed_deliveries() {
+        let client = RwLock::new(FlakyEmailClient::failing(2));
+        let store = HashmapEmailOutboxStore::default();
+        let outbox = outbox(store.clone(), 3);
+        outbox
+            .enqueue("a".to_owned(), &recipient(), message("Code"))
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            outbox.deliver_due(&client).await.unwrap();
+            assert_eq!(
+                outbox.status("a").await.unwrap(),
+                Some(OutboxStatus::Pending)
+            );
+            assert_eq!(
+                store.last_error("a").as_deref(),
+                Some("Provider unavailable")
+            );
+        }
+        outbox.deliver_due(&client).await.unwrap();
+
+        assert_eq!(client.read().await.sent(), ["Code"]);
+        assert_eq!(outbox.status("a").await.unwrap(), Some(OutboxStatus::Sent));
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_after_last_attempt() {
+        let client = RwLock::new(FlakyEmailClient::failing(u32::MAX));
+        let outbox = outbox(HashmapEmailOutboxStore::default(), 3);
+        outbox
+            .enqueue("a".to_owned(), &recipient(), message("Code"))
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(outbox.deliver_due(&client).await.unwrap(), 1);
+        }
+
+        assert_eq!(outbox.deliver_due(&client).await.unwrap(), 0);
+        assert_eq!(outbox.status("a").await.unwrap(), Some(OutboxStatus::Dead));
+        assert!(client.read().await.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_enqueueing_wakes_worker() {
+        let client = FlakyEmailClient::default();
+        // The worker would not poll again during the test
+        let outbox = outbox(HashmapEmailOutboxStore::default(), 3)
+            .with_poll_interval(Duration::from_secs(3600));
+        tokio::spawn(outbox.clone().run(Arc::new(RwLock::new(client.clone()))));
+        // Let the worker find the outbox empty and go to sleep
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        outbox
+            .enqueue("a".to_owned(), &recipient(), message("Code"))
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while client.sent().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Email was not delivered");
+        assert_eq!(client.sent(), ["Code"]);
+    }
+}
@@ -0,0 +1,536 @@
+use std::time::Duration;
+
+use askama::Template;
+use color_eyre::eyre::{Context, Result};
+
+use crate::{
+    domain::{EmailMessage, Locale},
+    services::known_devices::KnownDevice,
+    utils::{
+        constants::{EMAIL_BRANDING, EMAIL_DEFAULT_LOCALE},
+        login_alerts::LoginAlertReason,
+    },
+};
+
+// How emails present the product they are sent for
+#[derive(Debug, Clone)]
+pub struct EmailBranding {
+    pub name: String,
+    pub url: String,
+    pub logo_url: Option<String>,
+    // CSS color of the accents and buttons of HTML emails
+    pub color: String,
+    pub support_email: Option<String>,
+}
+
+// The wording of the emails, text messages and calls in one language. `{name}` stands for the
+// product name, `{code}` for a 2FA code, `{minutes}` and `{days}` for how long a link is valid and
+// `{email}` for the support address.
+struct Translations {
+    two_fa_subject: &'static str,
+    two_fa_intro: &'static str,
+    two_fa_notice: &'static str,
+    two_fa_sms: &'static str,
+    two_fa_voice: &'static str,
+    magic_link_subject: &'static str,
+    magic_link_intro: &'static str,
+    magic_link_button: &'static str,
+    magic_link_expiry: &'static str,
+    magic_link_notice: &'static str,
+    login_alert_device_subject: &'static str,
+    login_alert_country_subject: &'static str,
+    login_alert_device_intro: &'static str,
+    login_alert_country_intro: &'static str,
+    login_alert_browser: &'static str,
+    login_alert_ip_address: &'static str,
+    login_alert_country: &'static str,
+    login_alert_unknown: &'static str,
+    login_alert_notice: &'static str,
+    login_alert_button: &'static str,
+    login_alert_report: &'static str,
+    footer: &'static str,
+    support: &'static str,
+}
+
+const EN: Translations = Translations {
+    two_fa_subject: "Your {name} verification code",
+    two_fa_intro: "Use this code to finish signing in to {name}:",
+    two_fa_notice: "If you did not try to sign in, someone may know your password. Change it as \
+                    soon as you can.",
+    two_fa_sms: "{code} is your {name} verification code. Do not share it with anyone.",
+    two_fa_voice: "Your {name} verification code is {code}. Once again, your code is {code}.",
+    magic_link_subject: "Your {name} sign-in link",
+    magic_link_intro: "Use this link to sign in to {name}:",
+    magic_link_button: "Sign in",
+    magic_link_expiry: "It expires in {minutes} minutes and only works in the browser you \
+                        requested it from.",
+    magic_link_notice: "If you did not request this link, you can ignore this email.",
+    login_alert_device_subject: "New sign-in to your {name} account",
+    login_alert_country_subject: "Sign-in to your {name} account from a new country",
+    login_alert_device_intro: "Your {name} account was just signed in to from a device it was \
+                               not used on before:",
+    login_alert_country_intro: "Your {name} account was just signed in to from a country it \
+                                was not used in before:",
+    login_alert_browser: "Browser",
+    login_alert_ip_address: "IP address",
+    login_alert_country: "Country",
+    login_alert_unknown: "Unknown",
+    login_alert_notice: "If this was you, you can ignore this email.",
+    login_alert_button: "This wasn't me",
+    login_alert_report: "If it was not you, use this link to sign the device out and choose a \
+                         new password. It works for {days} days.",
+    footer: "You received this email because of your {name} account.",
+    support: "Questions? Write to {email}.",
+};
+
+const DE: Translations = Translations {
+    two_fa_subject: "Ihr Bestätigungscode für {name}",
+    two_fa_intro: "Verwenden Sie diesen Code, um die Anmeldung bei {name} abzuschließen:",
+    two_fa_notice: "Falls Sie sich nicht anmelden wollten, kennt möglicherweise jemand Ihr \
+                    Passwort. Ändern Sie es so bald wie möglich.",
+    two_fa_sms: "{code} ist Ihr Bestätigungscode für {name}. Geben Sie ihn an niemanden weiter.",
+    two_fa_voice: "Ihr Bestätigungscode für {name} lautet {code}. Noch einmal, Ihr Code lautet \
+                   {code}.",
+    magic_link_subject: "Ihr Anmeldelink für {name}",
+    magic_link_intro: "Verwenden Sie diesen Link, um sich bei {name} anzumelden:",
+    magic_link_button: "Anmelden",
+    magic_link_expiry: "Er läuft in {minutes} Minuten ab und funktioniert nur in dem Browser, in \
+                        dem Sie ihn angefordert haben.",
+    magic_link_notice: "Falls Sie diesen Link nicht angefordert haben, können Sie diese E-Mail \
+                        ignorieren.",
+    login_alert_device_subject: "Neue Anmeldung bei Ihrem Konto bei {name}",
+    login_alert_country_subject: "Anmeldung bei Ihrem Konto bei {name} aus einem neuen Land",
+    login_alert_device_intro: "Bei Ihrem Konto bei {name} hat sich soeben ein Gerät angemeldet, \
+                               das bisher nicht verwendet wurde:",
+    login_alert_country_intro: "Bei Ihrem Konto bei {name} hat sich soeben jemand aus einem Land \
+                                angemeldet, aus dem es bisher nicht verwendet wurde:",
+    login_alert_browser: "Browser",
+    login_alert_ip_address: "IP-Adresse",
+    login_alert_country: "Land",
+    login_alert_unknown: "Unbekannt",
+    login_alert_notice: "Falls Sie das waren, können Sie diese E-Mail ignorieren.",
+    login_alert_button: "Das war ich nicht",
+    login_alert_report: "Falls nicht, melden Sie das Gerät mit diesem Link ab und wählen ein \
+                         neues Passwort. Er ist {days} Tage lang gültig.",
+    footer: "Sie erhalten diese E-Mail wegen Ihres Kontos bei {name}.",
+    support: "Fragen? Schreiben Sie an {email}.",
+};
+
+const FR: Translations = Translations {
+    two_fa_subject: "Votre code de vérification {name}",
+    two_fa_intro: "Utilisez ce code pour terminer votre connexion à {name} :",
+    two_fa_notice: "Si vous n'avez pas essayé de vous connecter, quelqu'un connaît peut-être \
+                    votre mot de passe. Changez-le dès que possible.",
+    two_fa_sms: "{code} est votre code de vérification {name}. Ne le communiquez à personne.",
+    two_fa_voice: "Votre code de vérification {name} est {code}. Je répète, votre code est \
+                   {code}.",
+    magic_link_subject: "Votre lien de connexion {name}",
+    magic_link_intro: "Utilisez ce lien pour vous connecter à {name} :",
+    magic_link_button: "Se connecter",
+    magic_link_expiry: "Il expire dans {minutes} minutes et ne fonctionne que dans le navigateur \
+                        depuis lequel vous l'avez demandé.",
+    magic_link_notice: "Si vous n'avez pas demandé ce lien, vous pouvez ignorer cet e-mail.",
+    login_alert_device_subject: "Nouvelle connexion à votre compte {name}",
+    login_alert_country_subject: "Connexion à votre compte {name} depuis un nouveau pays",
+    login_alert_device_intro: "Quelqu'un vient de se connecter à votre compte {name} depuis un \
+                               appareil qui n'avait encore jamais été utilisé :",
+    login_alert_country_intro: "Quelqu'un vient de se connecter à votre compte {name} depuis un \
+                                pays d'où il n'avait encore jamais été utilisé :",
+    login_alert_browser: "Navigateur",
+    login_alert_ip_address: "Adresse IP",
+    login_alert_country: "Pays",
+    login_alert_unknown: "Inconnu",
+    login_alert_notice: "Si c'était vous, vous pouvez ignorer cet e-mail.",
+    login_alert_button: "Ce n'était pas moi",
+    login_alert_report: "Sinon, utilisez ce lien pour déconnecter l'appareil et choisir un \
+                         nouveau mot de passe. Il est valable {days} jours.",
+    footer: "Vous recevez cet e-mail en raison de votre compte {name}.",
+    support: "Des questions ? Écrivez à {email}.",
+};
+
+fn translations(locale: Locale) -> &'static Translations {
+    match locale {
+        Locale::En => &EN,
+        Locale::De => &DE,
+        Locale::Fr => &FR,
+    }
+}
+
+// What the HTML and text layouts around every message show
+struct Layout<'a> {
+    lang: &'static str,
+    subject: &'a str,
+    branding: &'a EmailBranding,
+    footer: String,
+    support: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "emails/two_fa_code.html")]
+struct TwoFACodeHtml<'a> {
+    layout: &'a Layout<'a>,
+    intro: &'a str,
+    code: &'a str,
+    notice: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/two_fa_code.txt")]
+struct TwoFACodeText<'a> {
+    layout: &'a Layout<'a>,
+    intro: &'a str,
+    code: &'a str,
+    notice: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/magic_link.html")]
+struct MagicLinkHtml<'a> {
+    layout: &'a Layout<'a>,
+    intro: &'a str,
+    link: &'a str,
+    button: &'a str,
+    expiry: &'a str,
+    notice: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/magic_link.txt")]
+struct MagicLinkText<'a> {
+    layout: &'a Layout<'a>,
+    intro: &'a str,
+    link: &'a str,
+    expiry: &'a str,
+    notice: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/login_alert.html")]
+struct LoginAlertHtml<'a> {
+    layout: &'a Layout<'a>,
+    intro: &'a str,
+    details: &'a [(&'a str, &'a str)],
+    notice: &'a str,
+    report: &'a str,
+    link: &'a str,
+    button: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/login_alert.txt")]
+struct LoginAlertText<'a> {
+    layout: &'a Layout<'a>,
+    intro: &'a str,
+    details: &'a [(&'a str, &'a str)],
+    notice: &'a str,
+    report: &'a str,
+    link: &'a str,
+}
+
+// Renders the emails the service sends, and the text of its text messages and calls, in the user's
+// language or the default one. HTML is escaped, plain text is not, so values such as links are
+// passed to the templates as they are.
+#[derive(Clone)]
+pub struct EmailTemplates {
+    branding: EmailBranding,
+    default_locale: Locale,
+}
+
+impl Default for EmailTemplates {
+    fn default() -> Self {
+        Self::new(EMAIL_BRANDING.clone(), *EMAIL_DEFAULT_LOCALE)
+    }
+}
+
+impl EmailTemplates {
+    pub fn new(branding: EmailBranding, default_locale: Locale) -> Self {
+        Self {
+            branding,
+            default_locale,
+        }
+    }
+
+    pub fn two_fa_code(&self, locale: Option<Locale>, code: &str) -> Result<EmailMessage> {
+        let (locale, t) = self.translations(locale);
+        let subject = self.fill(t.two_fa_subject);
+        let layout = self.layout(locale, t, &subject);
+        let intro = self.fill(t.two_fa_intro);
+
+        let html = TwoFACodeHtml {
+            layout: &layout,
+            intro: &intro,
+            code,
+            notice: t.two_fa_notice,
+        };
+        let text = TwoFACodeText {
+            layout: &layout,
+            intro: &intro,
+            code,
+            notice: t.two_fa_notice,
+        };
+        render(&subject, &html, &text)
+    }
+
+    pub fn two_fa_sms(&self, locale: Option<Locale>, code: &str) -> String {
+        let (_, t) = self.translations(locale);
+        self.fill(t.two_fa_sms).replace("{code}", code)
+    }
+
+    // Spells the code out digit by digit, so text-to-speech does not read it as one number
+    pub fn two_fa_voice(&self, locale: Option<Locale>, code: &str) -> String {
+        let (_, t) = self.translations(locale);
+        let digits = code
+            .chars()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.fill(t.two_fa_voice).replace("{code}", &digits)
+    }
+
+    pub fn magic_link(
+        &self,
+        locale: Option<Locale>,
+        link: &str,
+        ttl: Duration,
+    ) -> Result<EmailMessage> {
+        let (locale, t) = self.translations(locale);
+        let subject = self.fill(t.magic_link_subject);
+        let layout = self.layout(locale, t, &subject);
+        let intro = self.fill(t.magic_link_intro);
+        let expiry = t
+            .magic_link_expiry
+            .replace("{minutes}", &(ttl.as_secs() / 60).to_string());
+
+        let html = MagicLinkHtml {
+            layout: &layout,
+            intro: &intro,
+            link,
+            button: t.magic_link_button,
+            expiry: &expiry,
+            notice: t.magic_link_notice,
+        };
+        let text = MagicLinkText {
+            layout: &layout,
+            intro: &intro,
+            link,
+            expiry: &expiry,
+            notice: t.magic_link_notice,
+        };
+        render(&subject, &html, &text)
+    }
+
+    // Tells the user where the login came from, with the link to report it if it was not them
+    pub fn login_alert<'a>(
+        &self,
+        locale: Option<Locale>,
+        reason: LoginAlertReason,
+        device: &'a KnownDevice,
+        link: &str,
+        ttl: Duration,
+    ) -> Result<EmailMessage> {
+        let (locale, t) = self.translations(locale);
+        let (subject, intro) = match reason {
+            LoginAlertReason::NewDevice => {
+                (t.login_alert_device_subject, t.login_alert_device_intro)
+            }
+            LoginAlertReason::NewCountry => {
+                (t.login_alert_country_subject, t.login_alert_country_intro)
+            }
+        };
+        let subject = self.fill(subject);
+        let layout = self.layout(locale, t, &subject);
+        let intro = self.fill(intro);
+        let detail = |value: &'a Option<String>| value.as_deref().unwrap_or(t.login_alert_unknown);
+        let details = [
+            (t.login_alert_browser, detail(&device.user_agent)),
+            (t.login_alert_ip_address, detail(&device.ip_address)),
+            (t.login_alert_country, detail(&device.country)),
+        ];
+        let report = t
+            .login_alert_report
+            .replace("{days}", &(ttl.as_secs() / 86400).to_string());
+
+        let html = LoginAlertHtml {
+            layout: &layout,
+            intro: &intro,
+            details: &details,
+            notice: t.login_alert_notice,
+            report: &report,
+            link,
+            button: t.login_alert_button,
+        };
+        let text = LoginAlertText {
+            layout: &layout,
+            intro: &intro,
+            details: &details,
+            notice: t.login_alert_notice,
+            report: &report,
+            link,
+        };
+        render(&subject, &html, &text)
+    }
+
+    fn translations(&self, locale: Option<Locale>) -> (Locale, &'static Translations) {
+        let locale = locale.unwrap_or(self.default_locale);
+        (locale, translations(locale))
+    }
+
+    fn layout<'a>(&'a self, locale: Locale, t: &Translations, subject: &'a str) -> Layout<'a> {
+        Layout {
+            lang: locale.as_str(),
+            subject,
+            branding: &self.branding,
+            footer: self.fill(t.footer),
+            support: self
+                .branding
+                .support_email
+                .as_ref()
+                .map(|email| t.support.replace("{email}", email)),
+        }
+    }
+
+    fn fill(&self, text: &str) -> String {
+        text.replace("{name}", &self.branding.name)
+    }
+}
+
+fn render(subject: &str, html: &impl Template, text: &impl Template) -> Result<EmailMessage> {
+    Ok(EmailMessage {
+        subject: subject.to_owned(),
+        html: html.render().wrap_err("Failed to render HTML email")?,
+        text: text.render().wrap_err("Failed to render text email")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branding() -> EmailBranding {
+        EmailBranding {
+            name: "Acme <Cloud>".to_owned(),
+            url: "https://acme.example.com".to_owned(),
+            logo_url: None,
+            color: "#ff6600".to_owned(),
+            support_email: None,
+        }
+    }
+
+    #[test]
+    fn test_renders_two_fa_code_in_default_locale() {
+        let message = EmailTemplates::new(branding(), Locale::En)
+            .two_fa_code(None, "123456")
+            .unwrap();
+
+        assert_eq!(message.subject, "Your Acme <Cloud> verification code");
+        assert!(message.text.contains("123456"));
+        assert!(message.text.contains("finish signing in to Acme <Cloud>:"));
+        assert!(message.html.contains("123456"));
+        assert!(message.html.contains("<html lang=\"en\">"));
+        assert!(message.html.contains("#ff6600"));
+    }
+
+    #[test]
+    fn test_renders_in_locale_of_user() {
+        let templates = EmailTemplates::new(branding(), Locale::En);
+
+        let message = templates.two_fa_code(Some(Locale::De), "123456").unwrap();
+        assert_eq!(message.subject, "Ihr Bestätigungscode für Acme <Cloud>");
+        assert!(message.html.contains("<html lang=\"de\">"));
+
+        let message = templates
+            .magic_link(
+                Some(Locale::Fr),
+                "https://acme.example.com/link",
+                Duration::from_secs(900),
+            )
+            .unwrap();
+        assert!(message.text.contains("Il expire dans 15 minutes"));
+        assert!(message.html.contains("Se connecter"));
+    }
+
+    #[test]
+    fn test_renders_phone_messages() {
+        let templates = EmailTemplates::new(branding(), Locale::En);
+
+        assert_eq!(
+            templates.two_fa_sms(None, "123456"),
+            "123456 is your Acme <Cloud> verification code. Do not share it with anyone."
+        );
+        assert!(templates
+            .two_fa_voice(Some(Locale::De), "123456")
+            .starts_with("Ihr Bestätigungscode für Acme <Cloud> lautet 1, 2, 3, 4, 5, 6."));
+    }
+
+    #[test]
+    fn test_renders_login_alert() {
+        let device = KnownDevice {
+            device_id: "device".to_owned(),
+            user_agent: Some("Mozilla/5.0 <Firefox>".to_owned()),
+            ip_address: Some("192.0.2.1".to_owned()),
+            country: None,
+        };
+        let message = EmailTemplates::new(branding(), Locale::En)
+            .login_alert(
+                Some(Locale::De),
+                LoginAlertReason::NewCountry,
+                &device,
+                "https://acme.example.com/?reportToken=a&b",
+                Duration::from_secs(604800),
+            )
+            .unwrap();
+
+        assert_eq!(
+            message.subject,
+            "Anmeldung bei Ihrem Konto bei Acme <Cloud> aus einem neuen Land"
+        );
+        assert!(message.text.contains("Browser: Mozilla/5.0 <Firefox>"));
+        assert!(message.text.contains("IP-Adresse: 192.0.2.1"));
+        assert!(message.text.contains("Land: Unbekannt"));
+        assert!(message.text.contains("Er ist 7 Tage lang gültig."));
+        assert!(message.html.contains("Mozilla/5.0 &lt;Firefox&gt;"));
+        assert!(message.html.contains("reportToken=a&amp;b"));
+        assert!(message.html.contains("Das war ich nicht"));
+    }
+
+    #[test]
+    fn test_escapes_html_only() {
+        let message = EmailTemplates::new(branding(), Locale::En)
+            .magic_link(
+                None,
+                "https://acme.example.com/link?token=a&b",
+                Duration::from_secs(600),
+            )
+            .unwrap();
+
+        assert!(message.html.contains("Acme &lt;Cloud&gt;"));
+        assert!(!message.html.contains("Acme <Cloud>"));
+        assert!(message.html.contains("token=a&amp;b"));
+        assert!(message
+            .text
+            .contains("https://acme.example.com/link?token=a&b"));
+        assert!(message.text.contains("in 10 minutes"));
+    }
+
+    #[test]
+    fn test_renders_logo_and_support_address() {
+        let branding = EmailBranding {
+            logo_url: Some("https://acme.example.com/logo.png".to_owned()),
+            support_email: Some("help@acme.example.com".to_owned()),
+            ..branding()
+        };
+        let message = EmailTemplates::new(branding, Locale::En)
+            .two_fa_code(None, "123456")
+            .unwrap();
+
+        assert!(message
+            .html
+            .contains("<img src=\"https://acme.example.com/logo.png\""));
+        assert!(message
+            .html
+            .contains("Questions? Write to help@acme.example.com."));
+        assert!(message
+            .text
+            .contains("Questions? Write to help@acme.example.com."));
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts, HeaderMap, HeaderName},
+};
+use axum_extra::extract::{cookie::Cookie, CookieJar};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Context, Report, Result};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    domain::models::Email,
+    services::{
+        known_devices::{
+            HashmapKnownDeviceStore, KnownDevice, KnownDeviceStore, KnownDeviceStoreBackend,
+        },
+        magic_links::{HashmapMagicLinkStore, MagicLinkStore, MagicLinkStoreBackend},
+    },
+    utils::{
+        auth::Claims,
+        constants::{
+            JWT_ISSUER, JWT_LEEWAY_SECONDS, JWT_SECRET, LOGIN_ALERT_COUNTRY_HEADER,
+            LOGIN_REPORT_TTL_SECONDS, LOGIN_REPORT_URL,
+        },
+        cookies::CookiePolicy,
+        rate_limit::RateLimiter,
+    },
+};
+
+// Report tokens are made out to their own audience, so they cannot be used as access tokens or
+// magic links, nor those as report tokens
+const LOGIN_REPORT_AUDIENCE: &str = "login-report";
+const DEVICE_COOKIE_LENGTH: usize = 32;
+// Longer user agents are cut off before they are stored
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+#[derive(Debug, Error)]
+pub enum LoginReportError {
+    #[error("Invalid or expired link")]
+    InvalidReport(#[source] Report),
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+// Why the user is told about a login
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginAlertReason {
+    NewDevice,
+    NewCountry,
+}
+
+// Where a login comes from: the browser's device cookie, its user agent, the client's IP address
+// and, if a proxy in front of the service reports it, its country
+#[derive(Debug, Clone)]
+pub struct LoginDevice {
+    // Value of the device cookie, newly made up for browsers that sent none
+    cookie: String,
+    user_agent: Option<String>,
+    ip_address: Option<IpAddr>,
+    country: Option<String>,
+}
+
+impl LoginDevice {
+    pub fn new(
+        cookie: Option<String>,
+        user_agent: Option<String>,
+        ip_address: Option<IpAddr>,
+        country: Option<String>,
+    ) -> Self {
+        Self {
+            cookie: cookie
+                .filter(|cookie| !cookie.is_empty())
+                .unwrap_or_else(new_device_cookie),
+            user_agent: user_agent
+                .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+            ip_address,
+            country,
+        }
+    }
+
+    // Set on every login, so the browser is recognized next time
+    pub fn cookie(&self, cookie_policy: &CookiePolicy) -> Cookie<'static> {
+        cookie_policy.device_cookie(self.cookie.clone())
+    }
+
+    // Only a hash of the cookie is stored, so the table cannot be used to impersonate a device
+    fn known_device(&self) -> KnownDevice {
+        KnownDevice {
+            device_id: URL_SAFE_NO_PAD.encode(Sha256::digest(self.cookie.as_bytes())),
+            user_agent: self.user_agent.clone(),
+            ip_address: self.ip_address.map(|ip| ip.to_string()),
+            country: self.country.clone(),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for LoginDevice
+where
+    Arc<CookiePolicy>: FromRef<S>,
+    Arc<RateLimiter>: FromRef<S>,
+    Arc<LoginAlerts>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let cookie_policy = Arc::<CookiePolicy>::from_ref(state);
+        let cookie = CookieJar::from_headers(&parts.headers)
+            .get(&cookie_policy.device_cookie_name())
+            .map(|cookie| cookie.value().to_owned());
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        // The client's address, not the proxy's, when the request came through a trusted proxy
+        let ip_address =
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(peer)| {
+                    Arc::<RateLimiter>::from_ref(state).client_ip(peer.ip(), &parts.headers)
+                });
+        let country = Arc::<LoginAlerts>::from_ref(state).country(&parts.headers);
+
+        Ok(Self::new(cookie, user_agent, ip_address, country))
+    }
+}
+
+// A login compared with the user's known devices
+#[derive(Debug, Clone)]
+pub struct LoginCheck {
+    pub alert: Option<LoginAlertReason>,
+    pub device: KnownDevice,
+    // Known device the login was recognized as by its user agent and IP address, after its
+    // cookie was lost
+    replaces: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginReportClaims {
+    sub: String,
+    iss: String,
+    aud: String,
+    exp: usize,
+    iat: usize,
+    jti: String,
+    // ID and expiry of the session the login started
+    sid: String,
+    sid_exp: usize,
+    // Known device the login came from
+    did: String,
+}
+
+// A "this wasn't me" link that was opened
+pub struct LoginReport {
+    pub email: Email,
+    pub session_id: String,
+    pub device_id: String,
+    claims: LoginReportClaims,
+}
+
+impl LoginReport {
+    // How long the reported session will still be accepted, which is as long as its ban has to
+    // last
+    pub fn session_ttl(&self) -> Duration {
+        let accepted_until = self.claims.sid_exp as u64 + *JWT_LEEWAY_SECONDS;
+        let now = Utc::now().timestamp().max(0) as u64;
+        Duration::from_secs(accepted_until.saturating_sub(now).max(1))
+    }
+}
+
+// Tells users about logins from devices or countries their account was not used from before.
+// The first login of an account sends no alert. Each alert has a signed, single-use link for the
+// user to report the login, which ends its session and has them choose a new password.
+#[derive(Clone)]
+pub struct LoginAlerts {
+    devices: KnownDeviceStoreBackend,
+    used_reports: MagicLinkStoreBackend,
+    country_header: Option<HeaderName>,
+    report_url: String,
+    report_ttl: Duration,
+}
+
+impl Default for LoginAlerts {
+    fn default() -> Self {
+        Self::new(
+            KnownDeviceStoreBackend::InMemory(HashmapKnownDeviceStore::default()),
+            MagicLinkStoreBackend::InMemory(HashmapMagicLinkStore::default()),
+        )
+    }
+}
+
+impl LoginAlerts {
+    pub fn new(devices: KnownDeviceStoreBackend, used_reports: MagicLinkStoreBackend) -> Self {
+        Self {
+            devices,
+            used_reports,
+            country_header: LOGIN_ALERT_COUNTRY_HEADER.clone(),
+            report_url: LOGIN_REPORT_URL.clone(),
+            report_ttl: Duration::from_secs(*LOGIN_REPORT_TTL_SECONDS),
+        }
+    }
+
+    pub fn with_country_header(mut self, country_header: Option<HeaderName>) -> Self {
+        self.country_header = country_header;
+        self
+    }
+
+    pub fn with_report_ttl(mut self, report_ttl: Duration) -> Self {
+        self.report_ttl = report_ttl;
+        self
+    }
+
+    pub fn report_ttl(&self) -> Duration {
+        self.report_ttl
+    }
+
+    // The country code the proxy sent. `XX` and codes such as `T1` for Tor stand for unknown
+    // countries and are ignored.
+    fn country(&self, headers: &HeaderMap) -> Option<String> {
+        let value = headers.get(self.country_header.as_ref()?)?.to_str().ok()?;
+        let country = value.trim().to_ascii_uppercase();
+        (country.len() == 2 && country.bytes().all(|b| b.is_ascii_uppercase()) && country != "XX")
+            .then_some(country)
+    }
+
+    #[instrument(skip_all)]
+    pub async fn check(&self, email: &Email, device: &LoginDevice) -> Result<LoginCheck> {
+        let known = self
+            .devices
+            .devices(email)
+            .await
+            .wrap_err("Failed to load known devices")?;
+        Ok(assess(&known, device.known_device()))
+    }
+
+    // Call once the alert, if any, was sent, so a failed alert is sent again on the next login
+    #[instrument(skip_all)]
+    pub async fn remember(&self, email: &Email, check: &LoginCheck) -> Result<()> {
+        self.devices
+            .remember(email, &check.device, check.replaces.as_deref())
+            .await
+            .wrap_err("Failed to remember device")
+    }
+
+    // Returns the "this wasn't me" link of the alert for the login that started `session`
+    #[instrument(skip_all)]
+    pub fn issue_report_link(
+        &self,
+        email: &Email,
+        session: &Claims,
+        check: &LoginCheck,
+    ) -> Result<String> {
+        let now = Utc::now().timestamp();
+        let claims = LoginReportClaims {
+            sub: email.as_ref().expose_secret().to_owned(),
+            iss: JWT_ISSUER.clone(),
+            aud: LOGIN_REPORT_AUDIENCE.to_owned(),
+            exp: (now + self.report_ttl.as_secs() as i64)
+                .try_into()
+                .wrap_err("Failed to set report link expiry")?,
+            iat: now
+                .try_into()
+                .wrap_err("Failed to set report link issue time")?,
+            jti: Uuid::new_v4().to_string(),
+            sid: session.jti.clone(),
+            sid_exp: session.exp,
+            did: check.device.device_id.clone(),
+        };
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
+        )
+        .wrap_err("Failed to sign report link")?;
+
+        let mut url = Url::parse(&self.report_url).wrap_err("Invalid login report URL")?;
+        url.query_pairs_mut().append_pair("reportToken", &token);
+        Ok(url.into())
+    }
+
+    // Checks a report token without using it up, so the user can retry a rejected password
+    #[instrument(skip_all)]
+    pub async fn verify_report(&self, token: &str) -> Result<LoginReport, LoginReportError> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[JWT_ISSUER.as_str()]);
+        validation.set_audience(&[LOGIN_REPORT_AUDIENCE]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = 0;
+
+        let claims = decode::<LoginReportClaims>(
+            token,
+            &DecodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
+            &validation,
+        )
+        .map(|data| data.claims)
+        .wrap_err("Failed to decode report token")
+        .map_err(LoginReportError::InvalidReport)?;
+
+        let email = Email::new(claims.sub.clone().into()).map_err(|e| {
+            LoginReportError::InvalidReport(eyre!("Invalid email in report token: {}", e))
+        })?;
+        if self
+            .used_reports
+            .is_used(&claims.jti)
+            .await
+            .map_err(|e| LoginReportError::UnexpectedError(e.into()))?
+        {
+            return Err(LoginReportError::InvalidReport(eyre!(
+                "Report link was already used"
+            )));
+        }
+        Ok(LoginReport {
+            email,
+            session_id: claims.sid.clone(),
+            device_id: claims.did.clone(),
+            claims,
+        })
+    }
+
+    // Uses the report link up, failing if it was used before, even by a request that verified it
+    // at the same time
+    #[instrument(skip_all)]
+    pub async fn consume_report(&self, report: &LoginReport) -> Result<(), LoginReportError> {
+        let remaining = (report.claims.exp as i64 - Utc::now().timestamp()).max(1) as u64;
+        let first_use = self
+            .used_reports
+            .mark_used(&report.claims.jti, Duration::from_secs(remaining))
+            .await
+            .map_err(|e| LoginReportError::UnexpectedError(e.into()))?;
+        if !first_use {
+            return Err(LoginReportError::InvalidReport(eyre!(
+                "Report link was already used"
+            )));
+        }
+        Ok(())
+    }
+
+    // The reported device has to sign in again before it is trusted
+    #[instrument(skip_all)]
+    pub async fn forget_device(&self, email: &Email, device_id: &str) -> Result<()> {
+        self.devices
+            .forget(email, device_id)
+            .await
+            .wrap_err("Failed to forget device")
+    }
+}
+
+fn new_device_cookie() -> String {
+    let mut value = [0u8; DEVICE_COOKIE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut value);
+    URL_SAFE_NO_PAD.encode(value)
+}
+
+// A login is from a known device if its cookie is known, or, for browsers that lost the cookie, if
+// a known device had the same user agent and IP address. It is from a new country if the user
+// logged in from known countries only so far, and not from this one.
+fn assess(known: &[KnownDevice], device: KnownDevice) -> LoginCheck {
+    if known.is_empty() {
+        return LoginCheck {
+            alert: None,
+            device,
+            replaces: None,
+        };
+    }
+
+    let matched = known
+        .iter()
+        .find(|known| known.device_id == device.device_id)
+        .or_else(|| {
+            known.iter().find(|known| {
+                known.user_agent.is_some()
+                    && known.ip_address.is_some()
+                    && known.user_agent == device.user_agent
+                    && known.ip_address == device.ip_address
+            })
+        });
+    let new_country = device.country.as_ref().is_some_and(|country| {
+        known.iter().any(|known| known.country.is_some())
+            && !known
+                .iter()
+                .any(|known| known.country.as_ref() == Some(country))
+    });
+
+    let alert = if new_country {
+        Some(LoginAlertReason::NewCountry)
+    } else if matched.is_none() {
+        Some(LoginAlertReason::NewDevice)
+    } else {
+        None
+    };
+    let replaces = matched
+        .filter(|known| known.device_id != device.device_id)
+        .map(|known| known.device_id.clone());
+    LoginCheck {
+        alert,
+        device,
+        replaces,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use axum::http::HeaderValue;
+
+    use crate::utils::auth::{generate_auth_cookie, issued_token_claims};
+
+    use super::*;
+
+    fn email() -> Email {
+        Email::new("test@example.com".to_owned().into()).unwrap()
+    }
+
+    fn device(cookie: &str, user_agent: &str, ip: u8, country: Option<&str>) -> LoginDevice {
+        LoginDevice::new(
+            Some(cookie.to_owned()),
+            Some(user_agent.to_owned()),
+            Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, ip))),
+            country.map(str::to_owned),
+        )
+    }
+
+    fn token(link: &str) -> String {
+        let url = Url::parse(link).unwrap();
+        let (_, token) = url
+            .query_pairs()
+            .find(|(key, _)| key == "reportToken")
+            .unwrap();
+        token.into_owned()
+    }
+
+    #[test]
+    fn test_first_login_sends_no_alert() {
+        let check = assess(&[], device("a", "Firefox", 1, Some("DE")).known_device());
+        assert_eq!(check.alert, None);
+    }
+
+    #[test]
+    fn test_alerts_on_unknown_device() {
+        let known = [device("a", "Firefox", 1, None).known_device()];
+
+        let check = assess(&known, device("a", "Safari", 2, None).known_device());
+        assert_eq!(check.alert, None);
+        assert_eq!(check.replaces, None);
+
+        let check = assess(&known, device("b", "Safari", 1, None).known_device());
+        assert_eq!(check.alert, Some(LoginAlertReason::NewDevice));
+    }
+
+    #[test]
+    fn test_recognizes_device_that_lost_its_cookie() {
+        let known = [device("a", "Firefox", 1, None).known_device()];
+
+        let check = assess(&known, device("b", "Firefox", 1, None).known_device());
+        assert_eq!(check.alert, None);
+        assert_eq!(check.replaces, Some(known[0].device_id.clone()));
+    }
+
+    #[test]
+    fn test_alerts_on_new_country() {
+        let known = [device("a", "Firefox", 1, Some("DE")).known_device()];
+
+        let check = assess(&known, device("a", "Firefox", 1, Some("FR")).known_device());
+        assert_eq!(check.alert, Some(LoginAlertReason::NewCountry));
+
+        let check = assess(&known, device("a", "Firefox", 1, None).known_device());
+        assert_eq!(check.alert, None);
+
+        // Countries only count once the user was seen in one
+        let known = [device("a", "Firefox", 1, None).known_device()];
+        let check = assess(&known, device("a", "Firefox", 1, Some("FR")).known_device());
+        assert_eq!(check.alert, None);
+    }
+
+    #[test]
+    fn test_reads_country_from_configured_header() {
+        let alerts = LoginAlerts::default()
+            .with_country_header(Some(HeaderName::from_static("cf-ipcountry")));
+        let country = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("cf-ipcountry", HeaderValue::from_static(value));
+            alerts.country(&headers)
+        };
+
+        assert_eq!(country("de"), Some("DE".to_owned()));
+        assert_eq!(country("XX"), None);
+        assert_eq!(country("T1"), None);
+        assert_eq!(country("Germany"), None);
+        assert_eq!(alerts.country(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_report_link_is_single_use() {
+        let alerts = LoginAlerts::default();
+        let session = session();
+        let login = device("a", "Firefox", 1, None);
+        let check = alerts.check(&email(), &login).await.unwrap();
+        let link = alerts
+            .issue_report_link(&email(), &session, &check)
+            .unwrap();
+        assert!(link.starts_with(LOGIN_REPORT_URL.as_str()));
+
+        let report = alerts.verify_report(&token(&link)).await.unwrap();
+        assert!(report.email == email());
+        assert_eq!(report.session_id, session.jti);
+        assert_eq!(report.device_id, check.device.device_id);
+
+        assert!(alerts.consume_report(&report).await.is_ok());
+        assert!(matches!(
+            alerts.consume_report(&report).await,
+            Err(LoginReportError::InvalidReport(_))
+        ));
+        assert!(matches!(
+            alerts.verify_report(&token(&link)).await,
+            Err(LoginReportError::InvalidReport(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_access_token_as_report() {
+        let access_token = generate_auth_cookie(&email(), &CookiePolicy::default())
+            .unwrap()
+            .value()
+            .to_owned();
+
+        assert!(matches!(
+            LoginAlerts::default().verify_report(&access_token).await,
+            Err(LoginReportError::InvalidReport(_))
+        ));
+    }
+
+    fn session() -> Claims {
+        let cookie = generate_auth_cookie(&email(), &CookiePolicy::default()).unwrap();
+        issued_token_claims(cookie.value()).unwrap()
+    }
+}
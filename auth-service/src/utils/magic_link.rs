@@ -0,0 +1,254 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Context, Report, Result};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    domain::models::Email,
+    services::magic_links::{
+        HashmapMagicLinkStore, MagicLinkStore, MagicLinkStoreBackend, MagicLinkStoreError,
+    },
+    utils::constants::{JWT_ISSUER, JWT_SECRET, MAGIC_LINK_TTL_SECONDS, MAGIC_LINK_URL},
+};
+
+// Link tokens are made out to this audience, which no service accepts access tokens for, so a
+// link cannot be used as an access token and an access token cannot be used as a link
+const MAGIC_LINK_AUDIENCE: &str = "magic-link";
+const NONCE_LENGTH: usize = 32;
+
+#[derive(Debug, Error)]
+pub enum MagicLinkError {
+    #[error("Invalid or expired link")]
+    InvalidLink(#[source] Report),
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl From<MagicLinkStoreError> for MagicLinkError {
+    fn from(e: MagicLinkStoreError) -> Self {
+        match e {
+            MagicLinkStoreError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MagicLinkClaims {
+    sub: String,
+    iss: String,
+    aud: String,
+    exp: usize,
+    iat: usize,
+    jti: String,
+    // Hash of the nonce in the cookie of the browser that asked for the link, so neither the link
+    // nor the cookie alone signs anyone in
+    nonce: String,
+}
+
+// Issues and consumes the signed, single-use sign-in links that are emailed for passwordless
+// logins. A link only works in the browser it was requested from, which holds the matching nonce
+// cookie, and asking for a new link there replaces that nonce, so only the latest link works.
+#[derive(Clone)]
+pub struct MagicLinks {
+    used_links: MagicLinkStoreBackend,
+    url: String,
+    ttl: Duration,
+}
+
+impl Default for MagicLinks {
+    fn default() -> Self {
+        Self::new(MagicLinkStoreBackend::InMemory(
+            HashmapMagicLinkStore::default(),
+        ))
+    }
+}
+
+impl MagicLinks {
+    pub fn new(used_links: MagicLinkStoreBackend) -> Self {
+        Self {
+            used_links,
+            url: MAGIC_LINK_URL.clone(),
+            ttl: Duration::from_secs(*MAGIC_LINK_TTL_SECONDS),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    // A fresh nonce for the cookie that binds a link to the browser
+    pub fn new_nonce() -> String {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        URL_SAFE_NO_PAD.encode(nonce)
+    }
+
+    // Returns the link to email to the user, for the browser holding `nonce`
+    #[instrument(skip_all)]
+    pub fn issue(&self, email: &Email, nonce: &str) -> Result<String> {
+        let now = Utc::now().timestamp();
+        let claims = MagicLinkClaims {
+            sub: email.as_ref().expose_secret().to_owned(),
+            iss: JWT_ISSUER.clone(),
+            aud: MAGIC_LINK_AUDIENCE.to_owned(),
+            exp: (now + self.ttl.as_secs() as i64)
+                .try_into()
+                .wrap_err("Failed to set link expiry")?,
+            iat: now.try_into().wrap_err("Failed to set link issue time")?,
+            jti: Uuid::new_v4().to_string(),
+            nonce: hash_nonce(nonce),
+        };
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
+        )
+        .wrap_err("Failed to sign magic link")?;
+
+        let mut url = Url::parse(&self.url).wrap_err("Invalid magic link URL")?;
+        url.query_pairs_mut().append_pair("token", &token);
+        Ok(url.into())
+    }
+
+    // Checks the link `token` against the nonce cookie of the browser that opened it, and uses it
+    // up. The nonce is checked first, so a mail scanner that follows the link cannot spend it.
+    #[instrument(skip_all)]
+    pub async fn consume(&self, token: &str, nonce: Option<&str>) -> Result<Email, MagicLinkError> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[JWT_ISSUER.as_str()]);
+        validation.set_audience(&[MAGIC_LINK_AUDIENCE]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        // Links are only ever checked by the service that signed them, so no clock skew is allowed
+        validation.leeway = 0;
+
+        let claims = decode::<MagicLinkClaims>(
+            token,
+            &DecodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
+            &validation,
+        )
+        .map(|data| data.claims)
+        .wrap_err("Failed to decode magic link")
+        .map_err(MagicLinkError::InvalidLink)?;
+
+        let nonce =
+            nonce.ok_or_else(|| MagicLinkError::InvalidLink(eyre!("Missing magic link nonce")))?;
+        if hash_nonce(nonce) != claims.nonce {
+            return Err(MagicLinkError::InvalidLink(eyre!(
+                "Magic link was requested from another browser"
+            )));
+        }
+
+        let email = Email::new(claims.sub.into()).map_err(|e| {
+            MagicLinkError::InvalidLink(eyre!("Invalid email in magic link: {}", e))
+        })?;
+
+        let remaining = (claims.exp as i64 - Utc::now().timestamp()).max(1) as u64;
+        if !self
+            .used_links
+            .mark_used(&claims.jti, Duration::from_secs(remaining))
+            .await?
+        {
+            return Err(MagicLinkError::InvalidLink(eyre!(
+                "Magic link was already used"
+            )));
+        }
+        Ok(email)
+    }
+}
+
+fn hash_nonce(nonce: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(nonce.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::{auth::generate_auth_cookie, cookies::CookiePolicy};
+
+    use super::*;
+
+    fn email() -> Email {
+        Email::new("test@example.com".to_owned().into()).unwrap()
+    }
+
+    fn token(link: &str) -> String {
+        let url = Url::parse(link).unwrap();
+        let (_, token) = url.query_pairs().find(|(key, _)| key == "token").unwrap();
+        token.into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_link_signs_in_once() {
+        let magic_links = MagicLinks::default();
+        let nonce = MagicLinks::new_nonce();
+        let link = magic_links.issue(&email(), &nonce).unwrap();
+        assert!(link.starts_with(MAGIC_LINK_URL.as_str()));
+
+        let consumed = magic_links.consume(&token(&link), Some(&nonce)).await;
+        assert!(consumed.unwrap() == email());
+
+        assert!(matches!(
+            magic_links.consume(&token(&link), Some(&nonce)).await,
+            Err(MagicLinkError::InvalidLink(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_link_only_works_in_requesting_browser() {
+        let magic_links = MagicLinks::default();
+        let nonce = MagicLinks::new_nonce();
+        let token = token(&magic_links.issue(&email(), &nonce).unwrap());
+
+        for other in [None, Some(MagicLinks::new_nonce())] {
+            assert!(matches!(
+                magic_links.consume(&token, other.as_deref()).await,
+                Err(MagicLinkError::InvalidLink(_))
+            ));
+        }
+        // Failed attempts do not use the link up
+        assert!(magic_links.consume(&token, Some(&nonce)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_expired_link() {
+        let magic_links = MagicLinks::default().with_ttl(Duration::ZERO);
+        let nonce = MagicLinks::new_nonce();
+        let token = token(&magic_links.issue(&email(), &nonce).unwrap());
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(matches!(
+            magic_links.consume(&token, Some(&nonce)).await,
+            Err(MagicLinkError::InvalidLink(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_access_token_as_link() {
+        let magic_links = MagicLinks::default();
+        let access_token = generate_auth_cookie(&email(), &CookiePolicy::default())
+            .unwrap()
+            .value()
+            .to_owned();
+
+        assert!(matches!(
+            magic_links
+                .consume(&access_token, Some(&MagicLinks::new_nonce()))
+                .await,
+            Err(MagicLinkError::InvalidLink(_))
+        ));
+    }
+}
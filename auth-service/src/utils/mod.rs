@@ -1,3 +1,19 @@
-pub mod constants;
 pub mod auth;
-pub mod tracing;
\ No newline at end of file
+pub mod constants;
+pub mod cookies;
+pub mod csrf;
+pub mod dpop;
+pub mod email_outbox;
+pub mod email_templates;
+pub mod login_alerts;
+pub mod magic_link;
+pub mod oidc;
+pub mod password;
+pub mod phone_verification;
+pub mod rate_limit;
+pub mod saml;
+pub mod security_headers;
+pub mod tracing;
+pub mod user_import;
+pub mod webauthn;
+pub mod xml_dsig;
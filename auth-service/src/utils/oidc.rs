@@ -0,0 +1,456 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use color_eyre::eyre::{eyre, Context, ContextCompat, Report};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use rand::RngCore;
+use reqwest::Url;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::sync::{OnceCell, RwLock};
+use tracing::instrument;
+
+use crate::{
+    domain::models::Email,
+    services::oidc::{
+        HashmapOidcIdentityStore, HashmapOidcStateStore, OidcIdentityStore,
+        OidcIdentityStoreBackend, OidcLoginState, OidcStateStore, OidcStateStoreBackend,
+        OidcStoreError,
+    },
+    utils::constants::{
+        JWT_LEEWAY_SECONDS, OIDC_PROVIDERS, OIDC_REDIRECT_BASE_URL, OIDC_STATE_TTL_SECONDS,
+    },
+};
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+// ID tokens have to be signed by the provider's keys. Symmetric algorithms are refused, as they
+// would make the client secret a signing key.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+const RANDOM_VALUE_LENGTH: usize = 32;
+
+// An upstream OpenID Connect provider, registered with this service as a confidential client
+#[derive(Debug, Clone)]
+pub struct OidcProviderConfig {
+    pub name: String,
+    pub discovery_url: String,
+    pub client_id: String,
+    pub client_secret: SecretString,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("Unknown provider")]
+    UnknownProvider,
+    #[error("Invalid login state")]
+    InvalidState(#[source] Report),
+    #[error("Invalid ID token")]
+    InvalidIdToken(#[source] Report),
+    #[error("Provider error")]
+    ProviderError(#[source] Report),
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl From<OidcStoreError> for OidcError {
+    fn from(e: OidcStoreError) -> Self {
+        match e {
+            OidcStoreError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
+// Where to send the browser to log in at the provider, and the state to bind to the browser
+pub struct OidcAuthorization {
+    pub url: String,
+    pub state: String,
+}
+
+// The account at a provider that an ID token was issued for. The email is only given when the
+// provider verified it.
+#[derive(Clone)]
+pub struct OidcIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub verified_email: Option<Email>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    aud: serde_json::Value,
+    azp: Option<String>,
+    nonce: Option<String>,
+    email: Option<String>,
+    // Some providers send this as a string
+    email_verified: Option<serde_json::Value>,
+}
+
+// The provider's metadata and keys are fetched on first use and cached. The keys are fetched
+// again when a token is signed by a key that is not cached, which picks up rotated keys.
+#[derive(Clone)]
+struct OidcProvider {
+    config: OidcProviderConfig,
+    metadata: Arc<OnceCell<ProviderMetadata>>,
+    keys: Arc<RwLock<JwkSet>>,
+}
+
+impl OidcProvider {
+    fn new(config: OidcProviderConfig) -> Self {
+        Self {
+            config,
+            metadata: Arc::new(OnceCell::new()),
+            keys: Arc::new(RwLock::new(JwkSet { keys: Vec::new() })),
+        }
+    }
+}
+
+// Logs users in with upstream OpenID Connect providers, using the authorization code flow with
+// PKCE. The state and nonce of each login are kept until the provider redirects back.
+#[derive(Clone)]
+pub struct OidcRelyingParty {
+    providers: HashMap<String, OidcProvider>,
+    states: OidcStateStoreBackend,
+    identities: OidcIdentityStoreBackend,
+    redirect_base_url: String,
+    state_ttl: Duration,
+    http_client: reqwest::Client,
+}
+
+impl Default for OidcRelyingParty {
+    fn default() -> Self {
+        Self::new(
+            OidcStateStoreBackend::InMemory(HashmapOidcStateStore::default()),
+            OidcIdentityStoreBackend::InMemory(HashmapOidcIdentityStore::default()),
+        )
+    }
+}
+
+impl OidcRelyingParty {
+    pub fn new(states: OidcStateStoreBackend, identities: OidcIdentityStoreBackend) -> Self {
+        let relying_party = Self {
+            providers: HashMap::new(),
+            states,
+            identities,
+            redirect_base_url: OIDC_REDIRECT_BASE_URL.clone(),
+            state_ttl: Duration::from_secs(*OIDC_STATE_TTL_SECONDS),
+            http_client: reqwest::Client::new(),
+        };
+        OIDC_PROVIDERS
+            .iter()
+            .cloned()
+            .fold(relying_party, Self::with_provider)
+    }
+
+    pub fn with_provider(mut self, config: OidcProviderConfig) -> Self {
+        self.providers
+            .insert(config.name.clone(), OidcProvider::new(config));
+        self
+    }
+
+    pub fn state_ttl(&self) -> Duration {
+        self.state_ttl
+    }
+
+    // Starts a login at `provider`, returning where to redirect the browser
+    #[instrument(skip_all, fields(provider = provider))]
+    pub async fn start_login(&self, provider: &str) -> Result<OidcAuthorization, OidcError> {
+        let provider = self.provider(provider)?;
+        let metadata = self.metadata(provider).await?;
+
+        let state = random_value();
+        let nonce = random_value();
+        let code_verifier = random_value();
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .wrap_err("Invalid authorization endpoint")
+            .map_err(OidcError::ProviderError)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.config.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri(provider))
+            .append_pair("scope", &provider.config.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        self.states
+            .add_state(
+                &state,
+                OidcLoginState {
+                    provider: provider.config.name.clone(),
+                    nonce,
+                    code_verifier,
+                },
+                self.state_ttl,
+            )
+            .await?;
+
+        Ok(OidcAuthorization {
+            url: url.into(),
+            state,
+        })
+    }
+
+    // Redeems the authorization `code` the provider redirected back with, and returns the
+    // identity its ID token was issued for. The state is used up, whether or not this succeeds.
+    #[instrument(skip_all, fields(provider = provider))]
+    pub async fn finish_login(
+        &self,
+        provider: &str,
+        state: &str,
+        code: &str,
+    ) -> Result<OidcIdentity, OidcError> {
+        let provider = self.provider(provider)?;
+        let login = self
+            .states
+            .take_state(state)
+            .await?
+            .ok_or_else(|| OidcError::InvalidState(eyre!("Unknown or expired state")))?;
+        if login.provider != provider.config.name {
+            return Err(OidcError::InvalidState(eyre!(
+                "State was issued for another provider"
+            )));
+        }
+
+        let metadata = self.metadata(provider).await?;
+        let id_token = self
+            .exchange_code(provider, metadata, code, &login.code_verifier)
+            .await?;
+        let claims = self
+            .validate_id_token(provider, metadata, &id_token, &login.nonce)
+            .await?;
+
+        let verified = match claims.email_verified {
+            Some(serde_json::Value::Bool(verified)) => verified,
+            Some(serde_json::Value::String(verified)) => verified == "true",
+            _ => false,
+        };
+        let verified_email = match (claims.email, verified) {
+            (Some(email), true) => Email::new(email.into()).ok(),
+            _ => None,
+        };
+
+        Ok(OidcIdentity {
+            provider: provider.config.name.clone(),
+            subject: claims.sub,
+            verified_email,
+        })
+    }
+
+    // The user that `identity` was linked to by an earlier login
+    pub async fn linked_user(&self, identity: &OidcIdentity) -> Result<Option<Email>, OidcError> {
+        Ok(self
+            .identities
+            .get_user(&identity.provider, &identity.subject)
+            .await?)
+    }
+
+    pub async fn link(&self, identity: &OidcIdentity, email: &Email) -> Result<(), OidcError> {
+        Ok(self
+            .identities
+            .link(&identity.provider, &identity.subject, email)
+            .await?)
+    }
+
+    fn provider(&self, name: &str) -> Result<&OidcProvider, OidcError> {
+        self.providers.get(name).ok_or(OidcError::UnknownProvider)
+    }
+
+    fn redirect_uri(&self, provider: &OidcProvider) -> String {
+        format!(
+            "{}/oidc/{}/callback",
+            self.redirect_base_url, provider.config.name
+        )
+    }
+
+    async fn metadata<'a>(
+        &self,
+        provider: &'a OidcProvider,
+    ) -> Result<&'a ProviderMetadata, OidcError> {
+        provider
+            .metadata
+            .get_or_try_init(|| async {
+                let metadata: ProviderMetadata = self
+                    .http_client
+                    .get(&provider.config.discovery_url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .wrap_err("Failed to fetch provider metadata")?
+                    .json()
+                    .await
+                    .wrap_err("Failed to parse provider metadata")?;
+
+                // The metadata has to come from the issuer it describes (OpenID Connect Discovery
+                // 1.0, section 4.3)
+                let expected = format!(
+                    "{}{}",
+                    metadata.issuer.trim_end_matches('/'),
+                    DISCOVERY_PATH
+                );
+                if provider.config.discovery_url != expected {
+                    return Err(eyre!("Provider metadata is for issuer {}", metadata.issuer));
+                }
+                Ok(metadata)
+            })
+            .await
+            .map_err(OidcError::ProviderError)
+    }
+
+    async fn exchange_code(
+        &self,
+        provider: &OidcProvider,
+        metadata: &ProviderMetadata,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String, OidcError> {
+        let redirect_uri = self.redirect_uri(provider);
+        let response: TokenResponse = self
+            .http_client
+            .post(&metadata.token_endpoint)
+            .basic_auth(
+                &provider.config.client_id,
+                Some(provider.config.client_secret.expose_secret()),
+            )
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &redirect_uri),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .wrap_err("Failed to redeem authorization code")
+            .map_err(OidcError::ProviderError)?
+            .json()
+            .await
+            .wrap_err("Failed to parse token response")
+            .map_err(OidcError::ProviderError)?;
+        Ok(response.id_token)
+    }
+
+    async fn validate_id_token(
+        &self,
+        provider: &OidcProvider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let header = decode_header(id_token)
+            .wrap_err("Failed to decode ID token header")
+            .map_err(OidcError::InvalidIdToken)?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(OidcError::InvalidIdToken(eyre!(
+                "ID token is signed with {:?}",
+                header.alg
+            )));
+        }
+        let jwk = self
+            .signing_key(provider, metadata, header.kid.as_deref())
+            .await?;
+        let key = DecodingKey::from_jwk(&jwk)
+            .wrap_err("Unsupported provider key")
+            .map_err(OidcError::InvalidIdToken)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[metadata.issuer.as_str()]);
+        validation.set_audience(&[provider.config.client_id.as_str()]);
+        validation.set_required_spec_claims(&["exp", "iat", "iss", "aud", "sub"]);
+        validation.leeway = *JWT_LEEWAY_SECONDS;
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .wrap_err("Failed to validate ID token")
+            .map_err(OidcError::InvalidIdToken)?;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OidcError::InvalidIdToken(eyre!(
+                "ID token was issued for another login"
+            )));
+        }
+        // A token for several audiences has to name this client as the party it was issued to
+        let audiences = claims.aud.as_array().map_or(1, Vec::len);
+        if audiences > 1 && claims.azp.as_deref() != Some(provider.config.client_id.as_str()) {
+            return Err(OidcError::InvalidIdToken(eyre!(
+                "ID token was issued to another party"
+            )));
+        }
+        Ok(claims)
+    }
+
+    async fn signing_key(
+        &self,
+        provider: &OidcProvider,
+        metadata: &ProviderMetadata,
+        kid: Option<&str>,
+    ) -> Result<Jwk, OidcError> {
+        if let Some(jwk) = find_key(&*provider.keys.read().await, kid) {
+            return Ok(jwk);
+        }
+
+        let keys: JwkSet = self
+            .http_client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .wrap_err("Failed to fetch provider keys")
+            .map_err(OidcError::ProviderError)?
+            .json()
+            .await
+            .wrap_err("Failed to parse provider keys")
+            .map_err(OidcError::ProviderError)?;
+        let jwk = find_key(&keys, kid);
+        *provider.keys.write().await = keys;
+
+        jwk.wrap_err("ID token is signed by an unknown key")
+            .map_err(OidcError::InvalidIdToken)
+    }
+}
+
+// Tokens without a key ID can only be matched when the provider has a single key
+fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None,
+    }
+}
+
+fn random_value() -> String {
+    let mut bytes = [0u8; RANDOM_VALUE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
@@ -0,0 +1,38 @@
+use secrecy::SecretString;
+use tracing::{instrument, warn};
+
+use crate::{
+    domain::{models::Email, AuthAPIError, PasswordPolicy, PasswordPolicyViolation},
+    services::BreachedPasswordChecker,
+};
+
+// Runs every check a newly chosen password has to pass, so all routes that let a user pick a
+// password report the same per-rule feedback.
+#[instrument(skip_all)]
+pub async fn validate_new_password<X>(
+    password: &SecretString,
+    email: &Email,
+    password_policy: &PasswordPolicy,
+    breached_password_checker: &X,
+) -> Result<(), AuthAPIError>
+where
+    X: BreachedPasswordChecker,
+{
+    let mut violations = password_policy
+        .check(password, email)
+        .err()
+        .unwrap_or_default();
+
+    // An unavailable breach corpus should not lock users out of signing up, so we fail open
+    match breached_password_checker.breach_count(password).await {
+        Ok(0) => {}
+        Ok(count) => violations.push(PasswordPolicyViolation::Breached { count }),
+        Err(e) => warn!(error = ?e, "Breached password check failed, skipping it"),
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(AuthAPIError::WeakPassword(violations))
+    }
+}
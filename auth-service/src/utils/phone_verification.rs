@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Report, Result};
+use thiserror::Error;
+use tracing::instrument;
+
+use crate::{
+    domain::{models::Email, Locale, PhoneNumber, SmsClient, TwoFAChannel},
+    services::{
+        phone_verification::{
+            HashmapPhoneVerificationStore, PendingPhoneNumber, PhoneVerificationStore,
+            PhoneVerificationStoreBackend, PhoneVerificationStoreError,
+        },
+        TwoFACode,
+    },
+    utils::{constants::PHONE_VERIFICATION_TTL_SECONDS, email_templates::EmailTemplates},
+};
+
+#[derive(Debug, Error)]
+pub enum PhoneVerificationError {
+    #[error("Invalid or expired code")]
+    InvalidCode,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl From<PhoneVerificationStoreError> for PhoneVerificationError {
+    fn from(e: PhoneVerificationStoreError) -> Self {
+        match e {
+            PhoneVerificationStoreError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
+// Proves a user receives messages at a phone number before 2FA codes are sent there. Starting
+// again replaces the number being added, and a wrong code ends the verification, so the code has
+// to be asked for anew.
+#[derive(Clone)]
+pub struct PhoneVerification {
+    pending: PhoneVerificationStoreBackend,
+    ttl: Duration,
+}
+
+impl Default for PhoneVerification {
+    fn default() -> Self {
+        Self::new(PhoneVerificationStoreBackend::InMemory(
+            HashmapPhoneVerificationStore::default(),
+        ))
+    }
+}
+
+impl PhoneVerification {
+    pub fn new(pending: PhoneVerificationStoreBackend) -> Self {
+        Self {
+            pending,
+            ttl: Duration::from_secs(*PHONE_VERIFICATION_TTL_SECONDS),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    // Returns the code to send to `phone_number` over `channel`
+    #[instrument(skip_all)]
+    pub async fn start(
+        &self,
+        email: &Email,
+        phone_number: &PhoneNumber,
+        channel: TwoFAChannel,
+    ) -> Result<TwoFACode, PhoneVerificationError> {
+        let code = TwoFACode::default();
+        self.pending
+            .add(
+                email,
+                PendingPhoneNumber {
+                    phone_number: phone_number.as_str().to_owned(),
+                    channel: channel.as_str().to_owned(),
+                    code: code.as_ref().to_owned(),
+                },
+                self.ttl,
+            )
+            .await?;
+        Ok(code)
+    }
+
+    // Returns the number and channel the user verified with `code`
+    #[instrument(skip_all)]
+    pub async fn finish(
+        &self,
+        email: &Email,
+        code: &str,
+    ) -> Result<(PhoneNumber, TwoFAChannel), PhoneVerificationError> {
+        let pending = self
+            .pending
+            .take(email)
+            .await?
+            .ok_or(PhoneVerificationError::InvalidCode)?;
+        if pending.code != code {
+            return Err(PhoneVerificationError::InvalidCode);
+        }
+
+        let phone_number = PhoneNumber::parse(&pending.phone_number)
+            .map_err(PhoneVerificationError::UnexpectedError)?;
+        let channel = pending
+            .channel
+            .parse()
+            .map_err(PhoneVerificationError::UnexpectedError)?;
+        Ok((phone_number, channel))
+    }
+}
+
+// Texts `code` to the user or calls them to read it out, in their language
+#[instrument(skip_all)]
+pub async fn send_code(
+    sms_client: &impl SmsClient,
+    templates: &EmailTemplates,
+    locale: Option<Locale>,
+    phone_number: &PhoneNumber,
+    channel: TwoFAChannel,
+    code: &TwoFACode,
+) -> Result<()> {
+    match channel {
+        TwoFAChannel::Sms => {
+            let message = templates.two_fa_sms(locale, code.as_ref());
+            sms_client.send_sms(phone_number, &message).await
+        }
+        TwoFAChannel::Voice => {
+            let message = templates.two_fa_voice(locale, code.as_ref());
+            sms_client.send_voice_message(phone_number, &message).await
+        }
+        TwoFAChannel::Email => Err(eyre!("Codes sent by email are not sent to phones")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email() -> Email {
+        Email::new("test@example.com".to_owned().into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_code_verifies_number_once() {
+        let verification = PhoneVerification::default();
+        let phone_number = PhoneNumber::parse("+4930123456").unwrap();
+
+        let code = verification
+            .start(&email(), &phone_number, TwoFAChannel::Voice)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            verification.finish(&email(), code.as_ref()).await.unwrap(),
+            (phone_number, TwoFAChannel::Voice)
+        );
+        assert!(matches!(
+            verification.finish(&email(), code.as_ref()).await,
+            Err(PhoneVerificationError::InvalidCode)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_code_ends_verification() {
+        let verification = PhoneVerification::default();
+        let phone_number = PhoneNumber::parse("+4930123456").unwrap();
+
+        let code = verification
+            .start(&email(), &phone_number, TwoFAChannel::Sms)
+            .await
+            .unwrap();
+        let wrong_code = if code.as_ref() == "000000" {
+            "111111"
+        } else {
+            "000000"
+        };
+
+        assert!(matches!(
+            verification.finish(&email(), wrong_code).await,
+            Err(PhoneVerificationError::InvalidCode)
+        ));
+        assert!(matches!(
+            verification.finish(&email(), code.as_ref()).await,
+            Err(PhoneVerificationError::InvalidCode)
+        ));
+    }
+}
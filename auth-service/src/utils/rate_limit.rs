@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ipnet::IpNet;
+use tracing::warn;
+
+use crate::{
+    services::rate_limiting::{
+        HashmapRateLimitStore, RateLimitDecision, RateLimitPolicy, RateLimitStore,
+        RateLimitStoreBackend,
+    },
+    utils::constants::{
+        RATE_LIMIT_LOGIN, RATE_LIMIT_PHONE_NUMBER, RATE_LIMIT_SIGNUP, RATE_LIMIT_TRUSTED_PROXIES,
+        RATE_LIMIT_VERIFY_2FA,
+    },
+    ErrorResponse,
+};
+
+const RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+const RATE_LIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+// Holds one token bucket policy per route, keyed by the route path as registered on the router.
+// Routes without a policy are not limited.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: RateLimitStoreBackend,
+    policies: HashMap<String, RateLimitPolicy>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimitStoreBackend::InMemory(
+            HashmapRateLimitStore::default(),
+        ))
+    }
+}
+
+impl RateLimiter {
+    // Limits `/signup`, `/login`, `/verify-2fa` and the phone number routes with the configured
+    // policies. Passkey logins, logins with magic links, external providers or SAML, and login
+    // reports count as logins.
+    pub fn new(store: RateLimitStoreBackend) -> Self {
+        Self {
+            store,
+            policies: HashMap::new(),
+            trusted_proxies: RATE_LIMIT_TRUSTED_PROXIES.clone(),
+        }
+        .with_policy("/signup", *RATE_LIMIT_SIGNUP)
+        .with_policy("/login", *RATE_LIMIT_LOGIN)
+        .with_policy("/login/magic-link", *RATE_LIMIT_LOGIN)
+        .with_policy("/oidc/{provider}/callback", *RATE_LIMIT_LOGIN)
+        .with_policy("/saml/acs", *RATE_LIMIT_LOGIN)
+        .with_policy("/verify-2fa", *RATE_LIMIT_VERIFY_2FA)
+        .with_policy("/passkeys/login/finish", *RATE_LIMIT_LOGIN)
+        .with_policy("/login/report", *RATE_LIMIT_LOGIN)
+        .with_policy("/phone-number", *RATE_LIMIT_PHONE_NUMBER)
+        .with_policy("/phone-number/verify", *RATE_LIMIT_PHONE_NUMBER)
+    }
+
+    pub fn with_policy(mut self, route: &str, policy: RateLimitPolicy) -> Self {
+        self.policies.insert(route.to_owned(), policy);
+        self
+    }
+
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpNet>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    // The address of the client as seen by the first proxy we trust. `X-Forwarded-For` is only
+    // honored when the request came from a trusted proxy, and is read from the right, since
+    // anything left of the last trusted hop can be forged by the client.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted_proxy(&peer) {
+            return peer;
+        }
+
+        let forwarded_for = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+
+        let mut client = peer;
+        for hop in forwarded_for.into_iter().rev() {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !self.is_trusted_proxy(&ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+// Accepts a comma separated list of addresses or CIDR ranges, e.g. `10.0.0.0/8, 192.168.1.10`
+pub fn parse_trusted_proxies(value: &str) -> Result<Vec<IpNet>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("Invalid trusted proxy {:?}", entry))
+        })
+        .collect()
+}
+
+pub async fn rate_limit(
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some((route, policy)) = matched_path.and_then(|path| {
+        rate_limiter
+            .policies
+            .get_key_value(path.as_str())
+            .map(|(route, policy)| (route.clone(), *policy))
+    }) else {
+        return next.run(request).await;
+    };
+
+    let client_ip = rate_limiter.client_ip(peer.ip(), request.headers());
+    let key = format!("{}:{}", route, client_ip);
+
+    // An unavailable store should not lock everyone out, so requests are let through
+    let decision = match rate_limiter.store.take(&key, &policy).await {
+        Ok(decision) => decision,
+        Err(e) => {
+            warn!(error = ?e, "Rate limit check failed, allowing request");
+            return next.run(request).await;
+        }
+    };
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        let body = Json(ErrorResponse {
+            error: "Too many requests".to_owned(),
+        });
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, body).into_response();
+        if let Some(retry_after) = decision.retry_after {
+            response
+                .headers_mut()
+                .insert(axum::http::header::RETRY_AFTER, ceil_secs(retry_after));
+        }
+        response
+    };
+
+    insert_rate_limit_headers(response.headers_mut(), &policy, &decision);
+    response
+}
+
+fn insert_rate_limit_headers(
+    headers: &mut HeaderMap,
+    policy: &RateLimitPolicy,
+    decision: &RateLimitDecision,
+) {
+    headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(decision.limit));
+    headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(decision.remaining));
+    headers.insert(RATE_LIMIT_RESET, ceil_secs(decision.reset_after));
+    if let Ok(value) = HeaderValue::from_str(&policy.to_string()) {
+        headers.insert(RATE_LIMIT_POLICY, value);
+    }
+}
+
+fn ceil_secs(duration: Duration) -> HeaderValue {
+    HeaderValue::from(duration.as_millis().div_ceil(1000) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limiter() -> RateLimiter {
+        RateLimiter::default()
+            .with_trusted_proxies(parse_trusted_proxies("10.0.0.0/8, 192.168.1.10").unwrap())
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_ignores_forwarded_for_from_untrusted_peer() {
+        let peer: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(
+            rate_limiter().client_ip(peer, &forwarded_for("198.51.100.1")),
+            peer
+        );
+    }
+
+    #[test]
+    fn test_uses_rightmost_untrusted_forwarded_address() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        // The leftmost entry was supplied by the client and must not be trusted
+        let headers = forwarded_for("1.1.1.1, 198.51.100.1, 192.168.1.10");
+        assert_eq!(
+            rate_limiter().client_ip(peer, &headers),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_falls_back_to_peer_without_forwarded_for() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        assert_eq!(rate_limiter().client_ip(peer, &HeaderMap::new()), peer);
+    }
+
+    #[test]
+    fn test_stops_at_malformed_forwarded_address() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        let headers = forwarded_for("198.51.100.1, not-an-ip, 10.0.0.3");
+        assert_eq!(
+            rate_limiter().client_ip(peer, &headers),
+            "10.0.0.3".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_trusted_proxies() {
+        assert_eq!(parse_trusted_proxies("").unwrap(), vec![]);
+        assert_eq!(parse_trusted_proxies("10.0.0.0/8, ::1").unwrap().len(), 2);
+        assert!(parse_trusted_proxies("10.0.0.0/33").is_err());
+        assert!(parse_trusted_proxies("proxy.internal").is_err());
+    }
+}
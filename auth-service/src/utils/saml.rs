@@ -0,0 +1,815 @@
+use std::{io::Write, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use color_eyre::eyre::{eyre, Context, ContextCompat, Report, Result};
+use flate2::{write::DeflateEncoder, Compression};
+use reqwest::Url;
+use roxmltree::{Document, Node};
+use rsa::{pkcs1v15::VerifyingKey, pkcs8::DecodePublicKey, RsaPublicKey};
+use sha2::Sha256;
+use thiserror::Error;
+use tracing::instrument;
+use uuid::Uuid;
+use x509_cert::{
+    der::{Decode, DecodePem, Encode},
+    Certificate,
+};
+
+use crate::{
+    domain::models::Email,
+    services::saml::{HashmapSamlStore, SamlStore, SamlStoreBackend, SamlStoreError},
+    utils::{
+        constants::{
+            SAML_ACS_URL, SAML_CLOCK_SKEW_SECONDS, SAML_IDP, SAML_REQUEST_TTL_SECONDS,
+            SAML_SP_ENTITY_ID,
+        },
+        xml_dsig::{signature_of, verify_enveloped_signature},
+    },
+};
+
+const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const HTTP_POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const NAME_ID_EMAIL: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+const NAME_ID_UNSPECIFIED: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:unspecified";
+
+// The identity provider users log in at, which signs its assertions with the key in `certificate`
+#[derive(Debug, Clone)]
+pub struct SamlIdpConfig {
+    pub entity_id: String,
+    pub sso_url: String,
+    key: VerifyingKey<Sha256>,
+}
+
+impl SamlIdpConfig {
+    // The certificate is given in PEM, or as the bare base64 DER found in the provider's metadata
+    pub fn new(entity_id: String, sso_url: String, certificate: &str) -> Result<Self> {
+        let certificate = if certificate.contains("-----BEGIN") {
+            Certificate::from_pem(certificate.trim().as_bytes())
+        } else {
+            let der: String = certificate
+                .chars()
+                .filter(|c| !c.is_ascii_whitespace())
+                .collect();
+            Certificate::from_der(&STANDARD.decode(der).wrap_err("Invalid certificate")?)
+        }
+        .wrap_err("Invalid certificate")?;
+        let public_key = certificate
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()
+            .wrap_err("Invalid certificate public key")?;
+        let public_key = RsaPublicKey::from_public_key_der(&public_key)
+            .wrap_err("Certificate does not hold an RSA key")?;
+        Ok(Self {
+            entity_id,
+            sso_url,
+            key: VerifyingKey::new(public_key),
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SamlError {
+    #[error("SAML is not configured")]
+    NotConfigured,
+    #[error("Invalid SAML response")]
+    InvalidResponse(#[source] Report),
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl From<SamlStoreError> for SamlError {
+    fn from(e: SamlStoreError) -> Self {
+        match e {
+            SamlStoreError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
+// Logs users in with a SAML 2.0 identity provider. Logins start here with an AuthnRequest sent
+// with the HTTP-Redirect binding, and the provider posts its signed response back to the
+// assertion consumer service. Responses the provider sends unasked are refused, so a response
+// captured elsewhere cannot be played to this service.
+// Where to send the browser to log in at the identity provider, and the ID of the request, which
+// the response has to come back to the same browser with
+pub struct SamlAuthnRequest {
+    pub url: String,
+    pub request_id: String,
+}
+
+#[derive(Clone)]
+pub struct SamlServiceProvider {
+    idp: Option<SamlIdpConfig>,
+    store: SamlStoreBackend,
+    entity_id: String,
+    acs_url: String,
+    request_ttl: Duration,
+    clock_skew: Duration,
+}
+
+impl Default for SamlServiceProvider {
+    fn default() -> Self {
+        Self::new(SamlStoreBackend::InMemory(HashmapSamlStore::default()))
+    }
+}
+
+impl SamlServiceProvider {
+    pub fn new(store: SamlStoreBackend) -> Self {
+        Self {
+            idp: SAML_IDP.clone(),
+            store,
+            entity_id: SAML_SP_ENTITY_ID.clone(),
+            acs_url: SAML_ACS_URL.clone(),
+            request_ttl: Duration::from_secs(*SAML_REQUEST_TTL_SECONDS),
+            clock_skew: Duration::from_secs(*SAML_CLOCK_SKEW_SECONDS),
+        }
+    }
+
+    pub fn with_idp(mut self, idp: SamlIdpConfig) -> Self {
+        self.idp = Some(idp);
+        self
+    }
+
+    pub fn entity_id(&self) -> &str {
+        &self.entity_id
+    }
+
+    pub fn acs_url(&self) -> &str {
+        &self.acs_url
+    }
+
+    pub fn request_ttl(&self) -> Duration {
+        self.request_ttl
+    }
+
+    fn idp(&self) -> Result<&SamlIdpConfig, SamlError> {
+        self.idp.as_ref().ok_or(SamlError::NotConfigured)
+    }
+
+    // The metadata to register this service with the identity provider
+    pub fn metadata(&self) -> Result<String, SamlError> {
+        self.idp()?;
+        Ok(format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" entityID="{}">"#,
+                r#"<md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{}">"#,
+                r#"<md:NameIDFormat>{}</md:NameIDFormat>"#,
+                r#"<md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/>"#,
+                r#"</md:SPSSODescriptor>"#,
+                r#"</md:EntityDescriptor>"#
+            ),
+            escape(&self.entity_id),
+            PROTOCOL_NS,
+            NAME_ID_EMAIL,
+            HTTP_POST_BINDING,
+            escape(&self.acs_url),
+        ))
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start_login(&self) -> Result<SamlAuthnRequest, SamlError> {
+        let idp = self.idp()?;
+        // IDs have to start with a letter or underscore
+        let id = format!("_{}", Uuid::new_v4().simple());
+        let request = format!(
+            concat!(
+                r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="{}" Version="2.0" "#,
+                r#"IssueInstant="{}" Destination="{}" AssertionConsumerServiceURL="{}" ProtocolBinding="{}">"#,
+                r#"<saml:Issuer>{}</saml:Issuer>"#,
+                r#"<samlp:NameIDPolicy Format="{}" AllowCreate="false"/>"#,
+                r#"</samlp:AuthnRequest>"#
+            ),
+            PROTOCOL_NS,
+            ASSERTION_NS,
+            id,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            escape(&idp.sso_url),
+            escape(&self.acs_url),
+            HTTP_POST_BINDING,
+            escape(&self.entity_id),
+            NAME_ID_EMAIL,
+        );
+
+        // The redirect binding sends the request DEFLATE compressed
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        let deflated = encoder
+            .write_all(request.as_bytes())
+            .and_then(|_| encoder.finish())
+            .wrap_err("Failed to compress AuthnRequest")
+            .map_err(SamlError::UnexpectedError)?;
+        let mut url = Url::parse(&idp.sso_url)
+            .wrap_err("Invalid identity provider SSO URL")
+            .map_err(SamlError::UnexpectedError)?;
+        url.query_pairs_mut()
+            .append_pair("SAMLRequest", &STANDARD.encode(deflated));
+
+        self.store.add_request(&id, self.request_ttl).await?;
+        Ok(SamlAuthnRequest {
+            url: url.into(),
+            request_id: id,
+        })
+    }
+
+    // Validates the base64 `SAMLResponse` the identity provider posted, and returns the email of
+    // the user it vouches for. `request_id` is the ID of the request the posting browser started.
+    #[instrument(skip_all)]
+    pub async fn finish_login(
+        &self,
+        saml_response: &str,
+        request_id: Option<&str>,
+    ) -> Result<Email, SamlError> {
+        let idp = self.idp()?;
+        let xml: String = saml_response
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect();
+        let xml = STANDARD
+            .decode(xml)
+            .wrap_err("Invalid base64")
+            .and_then(|xml| String::from_utf8(xml).wrap_err("Invalid UTF-8"))
+            .map_err(SamlError::InvalidResponse)?;
+        // Documents with a DTD are refused by the parser, which rules out entity expansion
+        let document = Document::parse(&xml)
+            .wrap_err("Invalid XML")
+            .map_err(SamlError::InvalidResponse)?;
+
+        let assertion = self
+            .validate(&document, idp, Utc::now())
+            .map_err(SamlError::InvalidResponse)?;
+
+        // Otherwise an attacker could post a response to their own request from a victim's
+        // browser, and log the victim into the attacker's account
+        if request_id != Some(assertion.in_response_to.as_str()) {
+            return Err(SamlError::InvalidResponse(eyre!(
+                "Response answers a request of another browser"
+            )));
+        }
+        if !self.store.take_request(&assertion.in_response_to).await? {
+            return Err(SamlError::InvalidResponse(eyre!(
+                "Response does not answer an outstanding request"
+            )));
+        }
+        let ttl = (assertion.expires_at - Utc::now())
+            .to_std()
+            .unwrap_or_default()
+            + self.clock_skew;
+        if !self.store.mark_assertion_used(&assertion.id, ttl).await? {
+            return Err(SamlError::InvalidResponse(eyre!(
+                "Assertion was already used"
+            )));
+        }
+        Ok(assertion.email)
+    }
+
+    fn validate(
+        &self,
+        document: &Document,
+        idp: &SamlIdpConfig,
+        now: DateTime<Utc>,
+    ) -> Result<ValidAssertion> {
+        let response = document.root_element();
+        if !response.has_tag_name((PROTOCOL_NS, "Response")) {
+            return Err(eyre!("Document is not a SAML response"));
+        }
+        reject_duplicate_ids(document)?;
+        if let Some(destination) = response.attribute("Destination") {
+            if destination != self.acs_url {
+                return Err(eyre!("Response is for another destination"));
+            }
+        }
+        let status = child(response, PROTOCOL_NS, "Status")
+            .and_then(|status| child(status, PROTOCOL_NS, "StatusCode"))
+            .and_then(|code| code.attribute("Value"));
+        if status != Some(STATUS_SUCCESS) {
+            return Err(eyre!("Identity provider reported status {:?}", status));
+        }
+
+        // Exactly one assertion, right in the response, so the one read is the one signed
+        let mut assertions = document
+            .descendants()
+            .filter(|node| node.has_tag_name((ASSERTION_NS, "Assertion")));
+        let assertion = assertions.next().wrap_err("Response has no assertion")?;
+        if assertions.next().is_some() || assertion.parent() != Some(response) {
+            return Err(eyre!("Response must hold exactly one assertion"));
+        }
+
+        // Either the assertion or the whole response has to be signed
+        if signature_of(assertion).is_some() {
+            verify_enveloped_signature(assertion, &idp.key)
+        } else if signature_of(response).is_some() {
+            verify_enveloped_signature(response, &idp.key)
+        } else {
+            Err(eyre!("Neither the assertion nor the response is signed"))
+        }
+        .wrap_err("Invalid signature")?;
+
+        for issuer in [assertion, response]
+            .into_iter()
+            .filter_map(|node| child(node, ASSERTION_NS, "Issuer"))
+        {
+            if issuer.text().map(str::trim) != Some(idp.entity_id.as_str()) {
+                return Err(eyre!("Assertion was issued by another identity provider"));
+            }
+        }
+        if child(assertion, ASSERTION_NS, "Issuer").is_none() {
+            return Err(eyre!("Assertion has no issuer"));
+        }
+
+        let skew = chrono::Duration::from_std(self.clock_skew).wrap_err("Invalid clock skew")?;
+        let conditions =
+            child(assertion, ASSERTION_NS, "Conditions").wrap_err("Assertion has no conditions")?;
+        if let Some(not_before) = timestamp(conditions, "NotBefore")? {
+            if now + skew < not_before {
+                return Err(eyre!("Assertion is not valid yet"));
+            }
+        }
+        let not_on_or_after = timestamp(conditions, "NotOnOrAfter")?;
+        if let Some(not_on_or_after) = not_on_or_after {
+            if now - skew >= not_on_or_after {
+                return Err(eyre!("Assertion has expired"));
+            }
+        }
+        let mut restrictions = children(conditions, ASSERTION_NS, "AudienceRestriction").peekable();
+        if restrictions.peek().is_none() {
+            return Err(eyre!("Assertion is not restricted to an audience"));
+        }
+        for restriction in restrictions {
+            if !children(restriction, ASSERTION_NS, "Audience")
+                .any(|audience| audience.text().map(str::trim) == Some(self.entity_id.as_str()))
+            {
+                return Err(eyre!("Assertion is for another audience"));
+            }
+        }
+
+        let subject =
+            child(assertion, ASSERTION_NS, "Subject").wrap_err("Assertion has no subject")?;
+        let name_id = child(subject, ASSERTION_NS, "NameID").wrap_err("Subject has no NameID")?;
+        match name_id.attribute("Format") {
+            None | Some(NAME_ID_EMAIL) | Some(NAME_ID_UNSPECIFIED) => {}
+            Some(format) => return Err(eyre!("Unsupported NameID format {}", format)),
+        }
+        let email = Email::new(name_id.text().unwrap_or_default().trim().to_owned().into())
+            .wrap_err("NameID is not an email")?;
+
+        // The bearer confirmation says who may present the assertion, where, and until when
+        let confirmation = children(subject, ASSERTION_NS, "SubjectConfirmation")
+            .filter(|confirmation| confirmation.attribute("Method") == Some(BEARER))
+            .find_map(|confirmation| child(confirmation, ASSERTION_NS, "SubjectConfirmationData"))
+            .wrap_err("Subject has no bearer confirmation")?;
+        if confirmation.attribute("Recipient") != Some(self.acs_url.as_str()) {
+            return Err(eyre!("Assertion is for another recipient"));
+        }
+        let confirmation_expiry = timestamp(confirmation, "NotOnOrAfter")?
+            .wrap_err("Bearer confirmation does not expire")?;
+        if now - skew >= confirmation_expiry {
+            return Err(eyre!("Bearer confirmation has expired"));
+        }
+        let in_response_to = confirmation
+            .attribute("InResponseTo")
+            .wrap_err("Assertion does not answer a request")?;
+        if response
+            .attribute("InResponseTo")
+            .is_some_and(|id| id != in_response_to)
+        {
+            return Err(eyre!("Response and assertion answer different requests"));
+        }
+
+        Ok(ValidAssertion {
+            id: assertion
+                .attribute("ID")
+                .wrap_err("Assertion has no ID")?
+                .to_owned(),
+            email,
+            in_response_to: in_response_to.to_owned(),
+            expires_at: not_on_or_after.map_or(confirmation_expiry, |expiry| {
+                expiry.min(confirmation_expiry)
+            }),
+        })
+    }
+}
+
+struct ValidAssertion {
+    id: String,
+    email: Email,
+    in_response_to: String,
+    expires_at: DateTime<Utc>,
+}
+
+fn child<'a, 'input>(
+    node: Node<'a, 'input>,
+    namespace: &str,
+    name: &str,
+) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.has_tag_name((namespace, name)))
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    namespace: &'a str,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.has_tag_name((namespace, name)))
+}
+
+fn timestamp(node: Node, attribute: &str) -> Result<Option<DateTime<Utc>>> {
+    node.attribute(attribute)
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .wrap_err_with(|| format!("Invalid {}", attribute))
+        })
+        .transpose()
+}
+
+// Signatures reference elements by ID, so an ID used twice could point the check at another
+// element than the one that is read
+fn reject_duplicate_ids(document: &Document) -> Result<()> {
+    let mut ids = std::collections::HashSet::new();
+    for id in document
+        .descendants()
+        .filter_map(|node| node.attribute("ID"))
+    {
+        if !ids.insert(id) {
+            return Err(eyre!("Duplicate ID {}", id));
+        }
+    }
+    Ok(())
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::DeflateDecoder;
+    use rsa::{
+        pkcs1v15::SigningKey,
+        pkcs8::DecodePrivateKey,
+        signature::{SignatureEncoding, Signer},
+        RsaPrivateKey,
+    };
+    use secrecy::ExposeSecret;
+    use sha2::Digest;
+    use std::io::Read;
+
+    use super::*;
+
+    const IDP_ENTITY_ID: &str = "https://idp.example.com/metadata";
+    const IDP_SSO_URL: &str = "https://idp.example.com/sso";
+    const IDP_CERT: &str = include_str!("../../tests/fixtures/saml/idp_cert.pem");
+    const IDP_KEY: &str = include_str!("../../tests/fixtures/saml/idp_key.pem");
+    const OTHER_KEY: &str = include_str!("../../tests/fixtures/saml/other_key.pem");
+    const ASSERTION: &str = include_str!("../../tests/fixtures/saml/assertion.xml");
+    const RESPONSE: &str = include_str!("../../tests/fixtures/saml/response.xml");
+    const SIGNED_INFO: &str = include_str!("../../tests/fixtures/saml/signed_info.xml");
+    const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+    const EMAIL: &str = "saml@example.com";
+
+    fn service_provider() -> SamlServiceProvider {
+        SamlServiceProvider::default().with_idp(
+            SamlIdpConfig::new(IDP_ENTITY_ID.to_owned(), IDP_SSO_URL.to_owned(), IDP_CERT).unwrap(),
+        )
+    }
+
+    fn time(timestamp: DateTime<Utc>) -> String {
+        timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    // What the identity provider puts in its assertion, valid for `service_provider()` by default
+    struct Assertion {
+        id: String,
+        issuer: String,
+        name_id: String,
+        in_response_to: String,
+        recipient: String,
+        audience: String,
+        not_before: DateTime<Utc>,
+        not_on_or_after: DateTime<Utc>,
+    }
+
+    impl Assertion {
+        fn new(sp: &SamlServiceProvider, in_response_to: &str) -> Self {
+            Self {
+                id: format!("_{}", Uuid::new_v4().simple()),
+                issuer: IDP_ENTITY_ID.to_owned(),
+                name_id: EMAIL.to_owned(),
+                in_response_to: in_response_to.to_owned(),
+                recipient: sp.acs_url().to_owned(),
+                audience: sp.entity_id().to_owned(),
+                not_before: Utc::now() - chrono::Duration::seconds(30),
+                not_on_or_after: Utc::now() + chrono::Duration::seconds(300),
+            }
+        }
+
+        // The assertion on its own, which the fixture already has in canonical form
+        fn xml(&self) -> String {
+            ASSERTION
+                .replace("{assertion_id}", &self.id)
+                .replace("{issue_instant}", &time(Utc::now()))
+                .replace("{issuer}", &self.issuer)
+                .replace("{name_id}", &self.name_id)
+                .replace("{in_response_to}", &self.in_response_to)
+                .replace("{recipient}", &self.recipient)
+                .replace("{audience}", &self.audience)
+                .replace("{not_before}", &time(self.not_before))
+                .replace("{not_on_or_after}", &time(self.not_on_or_after))
+        }
+
+        fn signed(&self, key: &str) -> String {
+            sign(&self.xml(), &self.id, key)
+        }
+
+        // The assertion signed by the identity provider, in a successful response
+        fn response(&self) -> String {
+            response(&self.signed(IDP_KEY), &self.in_response_to)
+        }
+    }
+
+    // Adds an enveloped signature to the canonical `xml`, right after its issuer
+    fn sign(xml: &str, id: &str, key: &str) -> String {
+        let digest = STANDARD.encode(Sha256::digest(xml.as_bytes()));
+        let signed_info = SIGNED_INFO
+            .replace("{reference_id}", id)
+            .replace("{digest}", &digest);
+        let canonical = signed_info.replacen(
+            "<ds:SignedInfo>",
+            &format!("<ds:SignedInfo xmlns:ds=\"{}\">", DSIG_NS),
+            1,
+        );
+        let key = SigningKey::<Sha256>::new(RsaPrivateKey::from_pkcs8_pem(key).unwrap());
+        let signature_value = STANDARD.encode(key.sign(canonical.as_bytes()).to_bytes());
+        let signature = format!(
+            "<ds:Signature xmlns:ds=\"{}\">{}<ds:SignatureValue>{}</ds:SignatureValue></ds:Signature>",
+            DSIG_NS, signed_info, signature_value
+        );
+        xml.replacen("</saml:Issuer>", &format!("</saml:Issuer>{}", signature), 1)
+    }
+
+    // Wraps `assertion` in a response, where the namespace it declares is declared for it
+    fn response(assertion: &str, in_response_to: &str) -> String {
+        let assertion = assertion.replacen(
+            &format!("<saml:Assertion xmlns:saml=\"{}\" ", ASSERTION_NS),
+            "<saml:Assertion ",
+            1,
+        );
+        RESPONSE
+            .replace("{response_id}", &format!("_{}", Uuid::new_v4().simple()))
+            .replace("{issue_instant}", &time(Utc::now()))
+            .replace("{recipient}", SAML_ACS_URL.as_str())
+            .replace("{in_response_to}", in_response_to)
+            .replace("{issuer}", IDP_ENTITY_ID)
+            .replace("{status}", STATUS_SUCCESS)
+            .replace("{assertion}", &assertion)
+    }
+
+    fn validate(sp: &SamlServiceProvider, response: &str) -> Result<ValidAssertion> {
+        let document = Document::parse(response).unwrap();
+        sp.validate(&document, sp.idp().unwrap(), Utc::now())
+    }
+
+    // Starts a login and returns the ID of the AuthnRequest
+    async fn start_login(sp: &SamlServiceProvider) -> String {
+        let login = sp.start_login().await.unwrap();
+        let url = Url::parse(&login.url).unwrap();
+        let (_, request) = url
+            .query_pairs()
+            .find(|(name, _)| name == "SAMLRequest")
+            .unwrap();
+        let mut xml = String::new();
+        DeflateDecoder::new(STANDARD.decode(request.as_ref()).unwrap().as_slice())
+            .read_to_string(&mut xml)
+            .unwrap();
+        let document = Document::parse(&xml).unwrap();
+        let request_id = document.root_element().attribute("ID").unwrap();
+        assert_eq!(request_id, login.request_id);
+        login.request_id
+    }
+
+    #[test]
+    fn test_accepts_signed_assertion() {
+        let sp = service_provider();
+        let assertion = Assertion::new(&sp, "_request");
+
+        let valid = validate(&sp, &assertion.response()).unwrap();
+        assert_eq!(valid.email.as_ref().expose_secret(), EMAIL);
+        assert_eq!(valid.id, assertion.id);
+        assert_eq!(valid.in_response_to, "_request");
+    }
+
+    #[test]
+    fn test_accepts_signed_response() {
+        let sp = service_provider();
+        let assertion = Assertion::new(&sp, "_request");
+        // Signs the response instead, with the same canonical form the assertion fixture has
+        let response = response(&assertion.xml(), "_request");
+        let document = Document::parse(&response).unwrap();
+        let canonical = crate::utils::xml_dsig::canonicalize(document.root_element(), None, &[]);
+        let id = document.root_element().attribute("ID").unwrap();
+        let signed = sign(&canonical, id, IDP_KEY);
+
+        assert!(validate(&sp, &signed).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_unsigned_or_foreign_signature() {
+        let sp = service_provider();
+        let assertion = Assertion::new(&sp, "_request");
+
+        assert!(validate(&sp, &response(&assertion.xml(), "_request")).is_err());
+        assert!(validate(&sp, &response(&assertion.signed(OTHER_KEY), "_request")).is_err());
+    }
+
+    #[test]
+    fn test_rejects_tampered_assertion() {
+        let sp = service_provider();
+        let assertion = Assertion::new(&sp, "_request");
+        let tampered = assertion.response().replace(EMAIL, "admin@example.com");
+
+        assert!(validate(&sp, &tampered).is_err());
+    }
+
+    #[test]
+    fn test_rejects_assertion_for_someone_else() {
+        let sp = service_provider();
+        let mut assertions = Vec::new();
+        for change in 0..3 {
+            let mut assertion = Assertion::new(&sp, "_request");
+            match change {
+                0 => assertion.audience = "https://other.example.com".to_owned(),
+                1 => assertion.recipient = "https://other.example.com/acs".to_owned(),
+                _ => assertion.issuer = "https://evil.example.com".to_owned(),
+            }
+            assertions.push(assertion);
+        }
+
+        for assertion in assertions {
+            assert!(validate(&sp, &assertion.response()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_rejects_expired_or_future_assertion() {
+        let sp = service_provider();
+        let skew = chrono::Duration::seconds(*SAML_CLOCK_SKEW_SECONDS as i64);
+
+        let mut expired = Assertion::new(&sp, "_request");
+        expired.not_before = Utc::now() - chrono::Duration::seconds(600);
+        expired.not_on_or_after = Utc::now() - skew - chrono::Duration::seconds(10);
+        assert!(validate(&sp, &expired.response()).is_err());
+
+        let mut future = Assertion::new(&sp, "_request");
+        future.not_before = Utc::now() + skew + chrono::Duration::seconds(10);
+        assert!(validate(&sp, &future.response()).is_err());
+
+        // Clocks a little apart are tolerated
+        let mut skewed = Assertion::new(&sp, "_request");
+        skewed.not_before = Utc::now() + skew - chrono::Duration::seconds(10);
+        assert!(validate(&sp, &skewed.response()).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_wrapped_assertions() {
+        let sp = service_provider();
+        let assertion = Assertion::new(&sp, "_request");
+        let signed = assertion.signed(IDP_KEY);
+
+        // A second, forged assertion next to the signed one
+        let mut forged = Assertion::new(&sp, "_request");
+        forged.name_id = "admin@example.com".to_owned();
+        let wrapped = response(&format!("{}{}", forged.xml(), signed), "_request");
+        assert!(validate(&sp, &wrapped).is_err());
+
+        // Another element carrying the ID the signature references
+        let duplicated = response(
+            &format!(
+                "<samlp:Extensions ID=\"{}\"></samlp:Extensions>{}",
+                assertion.id, signed
+            ),
+            "_request",
+        );
+        assert!(validate(&sp, &duplicated).is_err());
+    }
+
+    #[test]
+    fn test_rejects_failed_status() {
+        let sp = service_provider();
+        let response = Assertion::new(&sp, "_request").response().replace(
+            STATUS_SUCCESS,
+            "urn:oasis:names:tc:SAML:2.0:status:Requester",
+        );
+
+        assert!(validate(&sp, &response).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_login_answers_request_only_once() {
+        let sp = service_provider();
+        let request_id = start_login(&sp).await;
+        let response = STANDARD.encode(Assertion::new(&sp, &request_id).response());
+
+        let email = sp.finish_login(&response, Some(&request_id)).await.unwrap();
+        assert_eq!(email.as_ref().expose_secret(), EMAIL);
+        assert!(matches!(
+            sp.finish_login(&response, Some(&request_id)).await,
+            Err(SamlError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unsolicited_response() {
+        let sp = service_provider();
+        let response = STANDARD.encode(Assertion::new(&sp, "_unknown").response());
+
+        assert!(matches!(
+            sp.finish_login(&response, Some("_unknown")).await,
+            Err(SamlError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_response_to_request_of_other_browser() {
+        let sp = service_provider();
+        let request_id = start_login(&sp).await;
+        let other_request_id = start_login(&sp).await;
+        let response = STANDARD.encode(Assertion::new(&sp, &request_id).response());
+
+        for browser_request_id in [None, Some(other_request_id.as_str())] {
+            assert!(matches!(
+                sp.finish_login(&response, browser_request_id).await,
+                Err(SamlError::InvalidResponse(_))
+            ));
+        }
+        // The request stays open for the browser that started it
+        assert!(sp.finish_login(&response, Some(&request_id)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_document_type_definitions() {
+        let sp = service_provider();
+        let response = STANDARD.encode(concat!(
+            r#"<?xml version="1.0"?><!DOCTYPE Response [<!ENTITY a "aaaa">]>"#,
+            r#"<Response>&a;</Response>"#
+        ));
+
+        assert!(matches!(
+            sp.finish_login(&response, None).await,
+            Err(SamlError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_start_login_redirects_to_identity_provider() {
+        let sp = service_provider();
+        let login = sp.start_login().await.unwrap();
+        assert!(login.url.starts_with(IDP_SSO_URL));
+        assert!(!start_login(&sp).await.is_empty());
+    }
+
+    #[test]
+    fn test_metadata_names_assertion_consumer_service() {
+        let sp = service_provider();
+        let metadata = sp.metadata().unwrap();
+        let document = Document::parse(&metadata).unwrap();
+
+        assert_eq!(
+            document.root_element().attribute("entityID"),
+            Some(sp.entity_id())
+        );
+        let acs = document
+            .descendants()
+            .find(|node| node.tag_name().name() == "AssertionConsumerService")
+            .unwrap();
+        assert_eq!(acs.attribute("Location"), Some(sp.acs_url()));
+        assert_eq!(acs.attribute("Binding"), Some(HTTP_POST_BINDING));
+    }
+
+    #[tokio::test]
+    async fn test_not_configured_without_identity_provider() {
+        let sp = SamlServiceProvider::default();
+        if SAML_IDP.is_some() {
+            return;
+        }
+
+        assert!(matches!(sp.metadata(), Err(SamlError::NotConfigured)));
+        assert!(matches!(
+            sp.start_login().await,
+            Err(SamlError::NotConfigured)
+        ));
+        assert!(matches!(
+            sp.finish_login("", None).await,
+            Err(SamlError::NotConfigured)
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+// The security headers themselves live in the `security-headers` crate, which the app service
+// shares
+pub use ::security_headers::{security_headers, CspNonce, SecurityHeaders, SecurityHeadersPolicy};
+
+use crate::utils::constants::{
+    DEFAULT_SECURITY_CONTENT_SECURITY_POLICY, DEFAULT_SECURITY_FRAME_OPTIONS,
+    DEFAULT_SECURITY_PERMISSIONS_POLICY, DEFAULT_SECURITY_REFERRER_POLICY,
+    DEFAULT_SECURITY_STRICT_TRANSPORT_SECURITY,
+};
+
+// Responses from these routes carry credentials or session state and must never be cached
+const NO_STORE_ROUTES: [&str; 20] = [
+    "/signup",
+    "/login",
+    "/login/magic-link",
+    "/login/magic-link/consume",
+    "/login/report",
+    "/oidc/{provider}/login",
+    "/oidc/{provider}/callback",
+    "/saml/login",
+    "/saml/acs",
+    "/logout",
+    "/csrf-token",
+    "/verify-2fa",
+    "/verify-token",
+    "/passkeys/register/start",
+    "/passkeys/register/finish",
+    "/passkeys/login/start",
+    "/passkeys/login/finish",
+    "/phone-number",
+    "/phone-number/verify",
+    "/_dev/mailbox",
+];
+
+pub fn headers_from_config() -> SecurityHeaders {
+    dotenvy::dotenv().ok();
+    SecurityHeaders::from_env(SecurityHeaders {
+        content_security_policy: Some(DEFAULT_SECURITY_CONTENT_SECURITY_POLICY.to_owned()),
+        strict_transport_security: Some(DEFAULT_SECURITY_STRICT_TRANSPORT_SECURITY.to_owned()),
+        frame_options: Some(DEFAULT_SECURITY_FRAME_OPTIONS.to_owned()),
+        referrer_policy: Some(DEFAULT_SECURITY_REFERRER_POLICY.to_owned()),
+        permissions_policy: Some(DEFAULT_SECURITY_PERMISSIONS_POLICY.to_owned()),
+        cache_control: None,
+    })
+}
+
+// Sends `headers` everywhere, and additionally `Cache-Control: no-store` from the auth routes
+pub fn auth_routes_policy(headers: SecurityHeaders) -> SecurityHeadersPolicy {
+    NO_STORE_ROUTES.iter().fold(
+        SecurityHeadersPolicy::new(headers.clone()),
+        |policy, route| policy.with_route(route, headers.clone().no_store()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_routes_are_not_cached() {
+        let policy = auth_routes_policy(SecurityHeaders::default());
+
+        assert_eq!(
+            policy.headers_for(Some("/login")).cache_control.as_deref(),
+            Some("no-store")
+        );
+        assert_eq!(policy.headers_for(Some("/")).cache_control, None);
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::BufRead;
+
+use color_eyre::eyre::{Context, Result};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::{
+    domain::models::Email,
+    services::{
+        data_stores::postgres_user_store::PostgresUserStore,
+        password_hashing::PasswordHashAlgorithm, UserStoreError,
+    },
+};
+
+// One line of a legacy user export
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyUser {
+    email: SecretString,
+    password_hash: SecretString,
+    #[serde(rename = "requires2FA", default)]
+    requires_2fa: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    // Users that an earlier run imported, or that signed up since
+    pub already_existing: usize,
+    // Lines that are no valid user, or carry a hash in an unsupported format
+    pub rejected: usize,
+}
+
+// Imports users migrated from a legacy system out of a JSON Lines export, one
+// `{"email", "passwordHash", "requires2FA"}` object per line. Hashes are stored as is and upgraded
+// to Argon2id on each user's first login. Bad lines are logged and skipped, so a run imports
+// everything it can, while database errors abort it.
+#[instrument(name = "Importing legacy users", skip_all)]
+pub async fn import_users(
+    user_store: &PostgresUserStore,
+    export: impl BufRead,
+) -> Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+
+    for (index, line) in export.lines().enumerate() {
+        let line = line.wrap_err("Failed to read user export")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (email, user) = match parse_user(&line) {
+            Ok(user) => user,
+            Err(e) => {
+                warn!(line = index + 1, error = ?e, "Skipping invalid user");
+                summary.rejected += 1;
+                continue;
+            }
+        };
+
+        match user_store
+            .import_user(&email, &user.password_hash, user.requires_2fa)
+            .await
+        {
+            Ok(()) => summary.imported += 1,
+            Err(UserStoreError::UserAlreadyExists) => summary.already_existing += 1,
+            Err(e) => {
+                return Err(e).wrap_err_with(|| format!("Failed to import line {}", index + 1))
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+fn parse_user(line: &str) -> Result<(Email, LegacyUser)> {
+    let user: LegacyUser = serde_json::from_str(line).wrap_err("Malformed user")?;
+    let email = Email::new(user.email.clone())?;
+    PasswordHashAlgorithm::detect(user.password_hash.expose_secret())?;
+    Ok((email, user))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const BCRYPT_HASH: &str = "$2b$04$Qz0V7nzM1K8m5Qb5n1g8UeQYkYy1mVQ8M6YVqzv3mQ8bO8r9vZC7y";
+
+    #[test]
+    fn test_parses_legacy_user() {
+        let line = json!({
+            "email": "user@example.com",
+            "passwordHash": BCRYPT_HASH,
+            "requires2FA": true
+        });
+        let (email, user) = parse_user(&line.to_string()).unwrap();
+
+        assert_eq!(email.as_ref().expose_secret(), "user@example.com");
+        assert!(user.requires_2fa);
+    }
+
+    #[test]
+    fn test_defaults_to_no_2fa() {
+        let line = json!({ "email": "user@example.com", "passwordHash": BCRYPT_HASH });
+        let (_, user) = parse_user(&line.to_string()).unwrap();
+
+        assert!(!user.requires_2fa);
+    }
+
+    #[test]
+    fn test_rejects_invalid_users() {
+        let lines = [
+            "not json".to_owned(),
+            json!({ "email": "user@example.com" }).to_string(),
+            json!({ "email": "not-an-email", "passwordHash": BCRYPT_HASH }).to_string(),
+            json!({ "email": "user@example.com", "passwordHash": "5f4dcc3b5aa765d6" }).to_string(),
+        ];
+
+        for line in lines {
+            assert!(parse_user(&line).is_err(), "accepted {}", line);
+        }
+    }
+}
@@ -0,0 +1,960 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use color_eyre::eyre::{eyre, Context, ContextCompat, Report, Result};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand::RngCore;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    domain::models::Email,
+    services::{
+        passkeys::{
+            HashmapPasskeyChallengeStore, HashmapPasskeyStore, PasskeyCeremony, PasskeyChallenge,
+            PasskeyChallengeStore, PasskeyChallengeStoreBackend, PasskeyCredential, PasskeyStore,
+            PasskeyStoreBackend, PasskeyStoreError,
+        },
+        LoginAttemptId,
+    },
+    utils::constants::{
+        WEBAUTHN_CHALLENGE_TTL_SECONDS, WEBAUTHN_ORIGINS, WEBAUTHN_RP_ID, WEBAUTHN_RP_NAME,
+    },
+};
+
+const PUBLIC_KEY_CREDENTIAL_TYPE: &str = "public-key";
+// COSE algorithm identifier of ECDSA with P-256 and SHA-256, which every passkey provider supports
+const COSE_ALGORITHM_ES256: i64 = -7;
+const COSE_KEY_TYPE_EC2: i64 = 2;
+const COSE_CURVE_P256: i64 = 1;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+const CHALLENGE_LENGTH: usize = 32;
+const USER_HANDLE_LENGTH: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum PasskeyError {
+    #[error("Unknown or expired challenge")]
+    UnknownChallenge,
+    #[error("Invalid credential")]
+    InvalidCredential(#[source] Report),
+    #[error("Sign count did not increase")]
+    SignCountRegression,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl From<PasskeyStoreError> for PasskeyError {
+    fn from(e: PasskeyStoreError) -> Self {
+        match e {
+            PasskeyStoreError::CredentialNotFound => {
+                Self::InvalidCredential(eyre!("Credential not found"))
+            }
+            PasskeyStoreError::CredentialAlreadyExists => {
+                Self::InvalidCredential(eyre!("Credential is already registered"))
+            }
+            PasskeyStoreError::SignCountRegression => Self::SignCountRegression,
+            PasskeyStoreError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
+// The options passed to `navigator.credentials.create()`, in the JSON form accepted by
+// `PublicKeyCredential.parseCreationOptionsFromJSON()`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyRegistrationOptions {
+    pub challenge_id: String,
+    pub public_key: PublicKeyCredentialCreationOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialCreationOptions {
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+// The options passed to `navigator.credentials.get()`, in the JSON form accepted by
+// `PublicKeyCredential.parseRequestOptionsFromJSON()`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyAuthenticationOptions {
+    pub challenge_id: String,
+    pub public_key: PublicKeyCredentialRequestOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialRequestOptions {
+    pub challenge: String,
+    pub timeout: u64,
+    pub rp_id: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: String,
+}
+
+// The result of `navigator.credentials.create()`, as serialized by `PublicKeyCredential.toJSON()`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+// The result of `navigator.credentials.get()`, as serialized by `PublicKeyCredential.toJSON()`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthenticationCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+// A successful authentication. Without a login attempt, the passkey was used on its own, with
+// user verification standing in for the password.
+pub struct PasskeyAuthentication {
+    pub email: Email,
+    pub login_attempt_id: Option<LoginAttemptId>,
+}
+
+// Runs the WebAuthn (Level 2) registration and authentication ceremonies of this service.
+// Attestation is not requested, so the authenticator model is not verified, only that the
+// credential's key signs the challenges.
+#[derive(Clone)]
+pub struct WebauthnRelyingParty {
+    credentials: PasskeyStoreBackend,
+    challenges: PasskeyChallengeStoreBackend,
+    rp_id: String,
+    rp_name: String,
+    origins: Vec<String>,
+    challenge_ttl: Duration,
+}
+
+impl Default for WebauthnRelyingParty {
+    fn default() -> Self {
+        Self::new(
+            PasskeyStoreBackend::InMemory(HashmapPasskeyStore::default()),
+            PasskeyChallengeStoreBackend::InMemory(HashmapPasskeyChallengeStore::default()),
+        )
+    }
+}
+
+impl WebauthnRelyingParty {
+    pub fn new(credentials: PasskeyStoreBackend, challenges: PasskeyChallengeStoreBackend) -> Self {
+        Self {
+            credentials,
+            challenges,
+            rp_id: WEBAUTHN_RP_ID.clone(),
+            rp_name: WEBAUTHN_RP_NAME.clone(),
+            origins: WEBAUTHN_ORIGINS.clone(),
+            challenge_ttl: Duration::from_secs(*WEBAUTHN_CHALLENGE_TTL_SECONDS),
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start_registration(
+        &self,
+        email: &Email,
+    ) -> Result<PasskeyRegistrationOptions, PasskeyError> {
+        let existing = self.credentials.get_credentials(email).await?;
+        // All passkeys of a user share a handle, so authenticators replace rather than duplicate
+        // the user's passkey when it is registered again
+        let user_handle = existing
+            .first()
+            .map(|credential| credential.user_handle.clone())
+            .unwrap_or_else(|| random_bytes(USER_HANDLE_LENGTH));
+        let name = email.as_ref().expose_secret().to_owned();
+
+        let (challenge_id, challenge) = self
+            .add_challenge(PasskeyCeremony::Registration {
+                email: name.clone(),
+                user_handle: user_handle.clone(),
+            })
+            .await?;
+
+        Ok(PasskeyRegistrationOptions {
+            challenge_id,
+            public_key: PublicKeyCredentialCreationOptions {
+                rp: RelyingPartyEntity {
+                    id: self.rp_id.clone(),
+                    name: self.rp_name.clone(),
+                },
+                user: UserEntity {
+                    id: URL_SAFE_NO_PAD.encode(&user_handle),
+                    name: name.clone(),
+                    display_name: name,
+                },
+                challenge,
+                pub_key_cred_params: vec![CredentialParameters {
+                    credential_type: PUBLIC_KEY_CREDENTIAL_TYPE.to_owned(),
+                    alg: COSE_ALGORITHM_ES256,
+                }],
+                timeout: self.timeout_ms(),
+                exclude_credentials: existing.iter().map(descriptor).collect(),
+                authenticator_selection: AuthenticatorSelection {
+                    resident_key: "required".to_owned(),
+                    user_verification: "required".to_owned(),
+                },
+                attestation: "none".to_owned(),
+            },
+        })
+    }
+
+    #[instrument(skip_all)]
+    pub async fn finish_registration(
+        &self,
+        email: &Email,
+        challenge_id: &str,
+        credential: &RegistrationCredential,
+    ) -> Result<(), PasskeyError> {
+        let challenge = self.take_challenge(challenge_id).await?;
+        let PasskeyCeremony::Registration {
+            email: challenged_email,
+            user_handle,
+        } = challenge.ceremony
+        else {
+            return Err(PasskeyError::UnknownChallenge);
+        };
+        if challenged_email != *email.as_ref().expose_secret() {
+            return Err(PasskeyError::UnknownChallenge);
+        }
+
+        let response = &credential.response;
+        self.check_client_data(
+            &response.client_data_json,
+            "webauthn.create",
+            &challenge.challenge,
+        )
+        .map_err(PasskeyError::InvalidCredential)?;
+        let authenticator_data = parse_attestation_object(&response.attestation_object)
+            .and_then(|auth_data| AuthenticatorData::parse(&auth_data));
+        let authenticator_data = authenticator_data.map_err(PasskeyError::InvalidCredential)?;
+        self.check_authenticator_data(&authenticator_data, true)
+            .map_err(PasskeyError::InvalidCredential)?;
+
+        let attested = authenticator_data
+            .attested_credential
+            .ok_or_else(|| PasskeyError::InvalidCredential(eyre!("No credential was attested")))?;
+        if decode(&credential.id).ok().as_ref() != Some(&attested.credential_id) {
+            return Err(PasskeyError::InvalidCredential(eyre!(
+                "Credential ID does not match the attested credential"
+            )));
+        }
+
+        self.credentials
+            .add_credential(PasskeyCredential {
+                credential_id: attested.credential_id,
+                email: email.clone(),
+                user_handle,
+                public_key: attested.public_key,
+                sign_count: authenticator_data.sign_count,
+            })
+            .await?;
+        Ok(())
+    }
+
+    // Starts a passwordless login, or, given the login attempt of a user who entered their
+    // password, one that replaces the emailed 2FA code. Passwordless logins accept any
+    // discoverable passkey of this service and require user verification.
+    #[instrument(skip_all)]
+    pub async fn start_authentication(
+        &self,
+        second_factor: Option<(&Email, &LoginAttemptId)>,
+    ) -> Result<PasskeyAuthenticationOptions, PasskeyError> {
+        let allow_credentials = match second_factor {
+            Some((email, _)) => self
+                .credentials
+                .get_credentials(email)
+                .await?
+                .iter()
+                .map(descriptor)
+                .collect(),
+            None => Vec::new(),
+        };
+        let user_verification = match second_factor {
+            Some(_) => "preferred",
+            None => "required",
+        };
+
+        let (challenge_id, challenge) = self
+            .add_challenge(PasskeyCeremony::Authentication {
+                email: second_factor.map(|(email, _)| email.as_ref().expose_secret().to_owned()),
+                login_attempt_id: second_factor.map(|(_, id)| id.as_ref().to_owned()),
+            })
+            .await?;
+
+        Ok(PasskeyAuthenticationOptions {
+            challenge_id,
+            public_key: PublicKeyCredentialRequestOptions {
+                challenge,
+                timeout: self.timeout_ms(),
+                rp_id: self.rp_id.clone(),
+                allow_credentials,
+                user_verification: user_verification.to_owned(),
+            },
+        })
+    }
+
+    #[instrument(skip_all)]
+    pub async fn finish_authentication(
+        &self,
+        challenge_id: &str,
+        credential: &AuthenticationCredential,
+    ) -> Result<PasskeyAuthentication, PasskeyError> {
+        let challenge = self.take_challenge(challenge_id).await?;
+        let PasskeyCeremony::Authentication {
+            email,
+            login_attempt_id,
+        } = challenge.ceremony
+        else {
+            return Err(PasskeyError::UnknownChallenge);
+        };
+
+        let credential_id = decode(&credential.id).map_err(PasskeyError::InvalidCredential)?;
+        let stored = self.credentials.get_credential(&credential_id).await?;
+        if email.is_some_and(|email| email != *stored.email.as_ref().expose_secret()) {
+            return Err(PasskeyError::InvalidCredential(eyre!(
+                "Credential belongs to another user"
+            )));
+        }
+
+        let response = &credential.response;
+        if let Some(user_handle) = &response.user_handle {
+            if decode(user_handle).ok().as_ref() != Some(&stored.user_handle) {
+                return Err(PasskeyError::InvalidCredential(eyre!(
+                    "User handle does not match the credential"
+                )));
+            }
+        }
+        let client_data = self
+            .check_client_data(
+                &response.client_data_json,
+                "webauthn.get",
+                &challenge.challenge,
+            )
+            .map_err(PasskeyError::InvalidCredential)?;
+        let raw_authenticator_data =
+            decode(&response.authenticator_data).map_err(PasskeyError::InvalidCredential)?;
+        let authenticator_data = AuthenticatorData::parse(&raw_authenticator_data)
+            .map_err(PasskeyError::InvalidCredential)?;
+        self.check_authenticator_data(&authenticator_data, login_attempt_id.is_none())
+            .map_err(PasskeyError::InvalidCredential)?;
+
+        verify_signature(
+            &stored.public_key,
+            &raw_authenticator_data,
+            &client_data,
+            &response.signature,
+        )
+        .map_err(PasskeyError::InvalidCredential)?;
+
+        // Authenticators that keep a counter increase it on every use. A counter that did not
+        // increase means the credential's key may have been cloned.
+        let sign_count = authenticator_data.sign_count;
+        match self
+            .credentials
+            .update_sign_count(&credential_id, sign_count)
+            .await
+        {
+            Err(PasskeyStoreError::SignCountRegression) => {
+                warn!(
+                    stored = stored.sign_count,
+                    received = sign_count,
+                    "Passkey sign count did not increase, the authenticator may be cloned"
+                );
+                return Err(PasskeyError::SignCountRegression);
+            }
+            result => result?,
+        }
+
+        let login_attempt_id = login_attempt_id
+            .map(LoginAttemptId::new)
+            .transpose()
+            .map_err(PasskeyError::UnexpectedError)?;
+        Ok(PasskeyAuthentication {
+            email: stored.email,
+            login_attempt_id,
+        })
+    }
+
+    async fn add_challenge(
+        &self,
+        ceremony: PasskeyCeremony,
+    ) -> Result<(String, String), PasskeyError> {
+        let id = Uuid::new_v4().to_string();
+        let challenge = random_bytes(CHALLENGE_LENGTH);
+        let encoded = URL_SAFE_NO_PAD.encode(&challenge);
+        self.challenges
+            .add_challenge(
+                &id,
+                PasskeyChallenge {
+                    challenge,
+                    ceremony,
+                },
+                self.challenge_ttl,
+            )
+            .await?;
+        Ok((id, encoded))
+    }
+
+    async fn take_challenge(&self, id: &str) -> Result<PasskeyChallenge, PasskeyError> {
+        self.challenges
+            .take_challenge(id)
+            .await?
+            .ok_or(PasskeyError::UnknownChallenge)
+    }
+
+    fn timeout_ms(&self) -> u64 {
+        self.challenge_ttl.as_millis() as u64
+    }
+
+    // Checks that the browser signed the expected challenge on one of our pages, returning the
+    // raw client data, whose hash the authenticator signs
+    fn check_client_data(
+        &self,
+        client_data_json: &str,
+        ceremony_type: &str,
+        challenge: &[u8],
+    ) -> Result<Vec<u8>> {
+        let raw = decode(client_data_json)?;
+        let client_data: CollectedClientData =
+            serde_json::from_slice(&raw).wrap_err("Malformed client data")?;
+
+        if client_data.ceremony_type != ceremony_type {
+            return Err(eyre!("Client data is for another ceremony"));
+        }
+        if decode(&client_data.challenge)? != challenge {
+            return Err(eyre!("Client data is for another challenge"));
+        }
+        if !self.origins.contains(&client_data.origin) {
+            return Err(eyre!("Unexpected origin {}", client_data.origin));
+        }
+        if client_data.cross_origin {
+            return Err(eyre!("Cross-origin ceremonies are not allowed"));
+        }
+        Ok(raw)
+    }
+
+    fn check_authenticator_data(
+        &self,
+        authenticator_data: &AuthenticatorData,
+        require_user_verification: bool,
+    ) -> Result<()> {
+        if authenticator_data.rp_id_hash[..] != Sha256::digest(self.rp_id.as_bytes())[..] {
+            return Err(eyre!("Credential is scoped to another relying party"));
+        }
+        if authenticator_data.flags & FLAG_USER_PRESENT == 0 {
+            return Err(eyre!("User was not present"));
+        }
+        if require_user_verification && authenticator_data.flags & FLAG_USER_VERIFIED == 0 {
+            return Err(eyre!("User was not verified"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct CollectedClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+    #[serde(default, rename = "crossOrigin")]
+    cross_origin: bool,
+}
+
+struct AttestedCredential {
+    credential_id: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    attested_credential: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    // rpIdHash (32) | flags (1) | signCount (4) | attestedCredentialData? | extensions?
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 37 {
+            return Err(eyre!("Authenticator data is too short"));
+        }
+        let flags = bytes[32];
+        let sign_count = u32::from_be_bytes(bytes[33..37].try_into()?);
+
+        let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+            // aaguid (16) | credentialIdLength (2) | credentialId | credentialPublicKey
+            let rest = bytes.get(37..).unwrap_or_default();
+            let id_length = rest
+                .get(16..18)
+                .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+                .wrap_err("Attested credential data is too short")?;
+            let credential_id = rest
+                .get(18..18 + id_length)
+                .wrap_err("Credential ID is truncated")?
+                .to_vec();
+            let public_key: Value = ciborium::from_reader(&rest[18 + id_length..])
+                .wrap_err("Malformed credential public key")?;
+            Some(AttestedCredential {
+                credential_id,
+                public_key: parse_cose_key(&public_key)?,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            rp_id_hash: bytes[..32].to_vec(),
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+}
+
+// Returns the authenticator data of an attestation object. The attestation statement is not
+// checked, since attestation is not requested.
+fn parse_attestation_object(attestation_object: &str) -> Result<Vec<u8>> {
+    let attestation: Value = ciborium::from_reader(&decode(attestation_object)?[..])
+        .wrap_err("Malformed attestation object")?;
+    attestation
+        .as_map()
+        .and_then(|entries| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_text() == Some("authData"))
+        })
+        .and_then(|(_, value)| value.as_bytes())
+        .cloned()
+        .wrap_err("Attestation object has no authenticator data")
+}
+
+// Converts an ES256 COSE key to a SEC1 encoded point
+fn parse_cose_key(key: &Value) -> Result<Vec<u8>> {
+    let entries = key.as_map().wrap_err("COSE key is not a map")?;
+    let get = |label: i64| {
+        entries
+            .iter()
+            .find(|(key, _)| key.as_integer().map(i128::from) == Some(label.into()))
+            .map(|(_, value)| value)
+    };
+    let get_integer = |label: i64| {
+        get(label)
+            .and_then(Value::as_integer)
+            .map(i128::from)
+            .and_then(|value| i64::try_from(value).ok())
+    };
+
+    if get_integer(1) != Some(COSE_KEY_TYPE_EC2)
+        || get_integer(3) != Some(COSE_ALGORITHM_ES256)
+        || get_integer(-1) != Some(COSE_CURVE_P256)
+    {
+        return Err(eyre!("Only ES256 keys on P-256 are supported"));
+    }
+    let x = get(-2)
+        .and_then(Value::as_bytes)
+        .wrap_err("COSE key has no x")?;
+    let y = get(-3)
+        .and_then(Value::as_bytes)
+        .wrap_err("COSE key has no y")?;
+    if x.len() != 32 || y.len() != 32 {
+        return Err(eyre!("Invalid P-256 coordinates"));
+    }
+
+    let mut point = Vec::with_capacity(65);
+    point.push(0x04);
+    point.extend_from_slice(x);
+    point.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&point).wrap_err("Point is not on the curve")?;
+    Ok(point)
+}
+
+// The authenticator signs its data followed by the hash of the client data
+fn verify_signature(
+    public_key: &[u8],
+    authenticator_data: &[u8],
+    client_data: &[u8],
+    signature: &str,
+) -> Result<()> {
+    let key = VerifyingKey::from_sec1_bytes(public_key).wrap_err("Invalid stored public key")?;
+    let signature = Signature::from_der(&decode(signature)?).wrap_err("Malformed signature")?;
+    let mut message = authenticator_data.to_vec();
+    message.extend_from_slice(&Sha256::digest(client_data));
+    key.verify(&message, &signature)
+        .wrap_err("Invalid signature")
+}
+
+fn descriptor(credential: &PasskeyCredential) -> CredentialDescriptor {
+    CredentialDescriptor {
+        credential_type: PUBLIC_KEY_CREDENTIAL_TYPE.to_owned(),
+        id: URL_SAFE_NO_PAD.encode(&credential.credential_id),
+    }
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .wrap_err("Invalid base64url")
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use ciborium::Value;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use serde_json::json;
+
+    use super::*;
+
+    const ORIGIN: &str = "http://localhost:3000";
+
+    // Behaves like a platform authenticator holding a single ES256 passkey
+    struct SoftwareAuthenticator {
+        key: SigningKey,
+        credential_id: Vec<u8>,
+        rp_id: String,
+        origin: String,
+        sign_count: u32,
+        has_counter: bool,
+        flags: u8,
+    }
+
+    impl SoftwareAuthenticator {
+        fn new() -> Self {
+            Self {
+                key: SigningKey::random(&mut rand::rngs::OsRng),
+                credential_id: random_bytes(16),
+                rp_id: WEBAUTHN_RP_ID.clone(),
+                origin: ORIGIN.to_owned(),
+                sign_count: 0,
+                has_counter: true,
+                flags: FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+            }
+        }
+
+        fn client_data(&self, ceremony_type: &str, challenge: &str) -> Vec<u8> {
+            json!({
+                "type": ceremony_type,
+                "challenge": challenge,
+                "origin": self.origin,
+                "crossOrigin": false,
+            })
+            .to_string()
+            .into_bytes()
+        }
+
+        fn authenticator_data(&self, flags: u8) -> Vec<u8> {
+            let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            data
+        }
+
+        fn create(&self, options: &PasskeyRegistrationOptions) -> RegistrationCredential {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let cose_key = Value::Map(vec![
+                (Value::from(1), Value::from(COSE_KEY_TYPE_EC2)),
+                (Value::from(3), Value::from(COSE_ALGORITHM_ES256)),
+                (Value::from(-1), Value::from(COSE_CURVE_P256)),
+                (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+            let mut auth_data = self.authenticator_data(self.flags | FLAG_ATTESTED_CREDENTIAL_DATA);
+            auth_data.extend_from_slice(&[0; 16]);
+            auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.credential_id);
+            ciborium::into_writer(&cose_key, &mut auth_data).unwrap();
+
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(vec![])),
+                (Value::from("authData"), Value::Bytes(auth_data)),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+            RegistrationCredential {
+                id: URL_SAFE_NO_PAD.encode(&self.credential_id),
+                response: AttestationResponse {
+                    client_data_json: URL_SAFE_NO_PAD
+                        .encode(self.client_data("webauthn.create", &options.public_key.challenge)),
+                    attestation_object: URL_SAFE_NO_PAD.encode(attestation_object),
+                },
+            }
+        }
+
+        fn get(&mut self, options: &PasskeyAuthenticationOptions) -> AuthenticationCredential {
+            if self.has_counter {
+                self.sign_count += 1;
+            }
+            let auth_data = self.authenticator_data(self.flags);
+            let client_data = self.client_data("webauthn.get", &options.public_key.challenge);
+            let mut message = auth_data.clone();
+            message.extend_from_slice(&Sha256::digest(&client_data));
+            let signature: p256::ecdsa::Signature = self.key.sign(&message);
+
+            AuthenticationCredential {
+                id: URL_SAFE_NO_PAD.encode(&self.credential_id),
+                response: AssertionResponse {
+                    client_data_json: URL_SAFE_NO_PAD.encode(client_data),
+                    authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+                    signature: URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes()),
+                    user_handle: None,
+                },
+            }
+        }
+    }
+
+    fn email() -> Email {
+        Email::new("test@example.com".to_owned().into()).unwrap()
+    }
+
+    async fn register(
+        relying_party: &WebauthnRelyingParty,
+        authenticator: &SoftwareAuthenticator,
+    ) -> Result<(), PasskeyError> {
+        let options = relying_party.start_registration(&email()).await?;
+        let credential = authenticator.create(&options);
+        relying_party
+            .finish_registration(&email(), &options.challenge_id, &credential)
+            .await
+    }
+
+    async fn authenticate(
+        relying_party: &WebauthnRelyingParty,
+        authenticator: &mut SoftwareAuthenticator,
+    ) -> Result<PasskeyAuthentication, PasskeyError> {
+        let options = relying_party.start_authentication(None).await?;
+        let credential = authenticator.get(&options);
+        relying_party
+            .finish_authentication(&options.challenge_id, &credential)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_register_and_authenticate() {
+        let relying_party = WebauthnRelyingParty::default();
+        let mut authenticator = SoftwareAuthenticator::new();
+
+        register(&relying_party, &authenticator).await.unwrap();
+        let authentication = authenticate(&relying_party, &mut authenticator)
+            .await
+            .unwrap();
+
+        assert!(authentication.email == email());
+        assert!(authentication.login_attempt_id.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_registration_options() {
+        let relying_party = WebauthnRelyingParty::default();
+        let authenticator = SoftwareAuthenticator::new();
+        register(&relying_party, &authenticator).await.unwrap();
+
+        let options = relying_party.start_registration(&email()).await.unwrap();
+        let public_key = &options.public_key;
+        assert_eq!(public_key.rp.id, *WEBAUTHN_RP_ID);
+        assert_eq!(public_key.pub_key_cred_params[0].alg, COSE_ALGORITHM_ES256);
+        assert_eq!(
+            decode(&public_key.challenge).unwrap().len(),
+            CHALLENGE_LENGTH
+        );
+        // The registered passkey is excluded, so it is not created twice
+        assert_eq!(
+            public_key.exclude_credentials[0].id,
+            URL_SAFE_NO_PAD.encode(&authenticator.credential_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_registering_same_credential_twice() {
+        let relying_party = WebauthnRelyingParty::default();
+        let authenticator = SoftwareAuthenticator::new();
+
+        register(&relying_party, &authenticator).await.unwrap();
+        assert!(matches!(
+            register(&relying_party, &authenticator).await,
+            Err(PasskeyError::InvalidCredential(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_other_origin_or_relying_party() {
+        let relying_party = WebauthnRelyingParty::default();
+
+        let mut phished = SoftwareAuthenticator::new();
+        phished.origin = "https://evil.example.com".to_owned();
+        assert!(register(&relying_party, &phished).await.is_err());
+
+        let mut other_rp = SoftwareAuthenticator::new();
+        other_rp.rp_id = "evil.example.com".to_owned();
+        assert!(register(&relying_party, &other_rp).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_challenge_is_single_use() {
+        let relying_party = WebauthnRelyingParty::default();
+        let mut authenticator = SoftwareAuthenticator::new();
+        register(&relying_party, &authenticator).await.unwrap();
+
+        let options = relying_party.start_authentication(None).await.unwrap();
+        let credential = authenticator.get(&options);
+        assert!(relying_party
+            .finish_authentication(&options.challenge_id, &credential)
+            .await
+            .is_ok());
+        assert!(matches!(
+            relying_party
+                .finish_authentication(&options.challenge_id, &credential)
+                .await,
+            Err(PasskeyError::UnknownChallenge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_sign_count_regression() {
+        let relying_party = WebauthnRelyingParty::default();
+        let mut authenticator = SoftwareAuthenticator::new();
+        register(&relying_party, &authenticator).await.unwrap();
+
+        authenticator.sign_count = 10;
+        assert!(authenticate(&relying_party, &mut authenticator)
+            .await
+            .is_ok());
+
+        // A clone of the key that has fallen behind the original
+        authenticator.sign_count = 5;
+        assert!(matches!(
+            authenticate(&relying_party, &mut authenticator).await,
+            Err(PasskeyError::SignCountRegression)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_accepts_authenticators_without_counter() {
+        let relying_party = WebauthnRelyingParty::default();
+        let mut authenticator = SoftwareAuthenticator::new();
+        authenticator.has_counter = false;
+        register(&relying_party, &authenticator).await.unwrap();
+
+        // Synced passkeys always report zero, which cannot be told apart from a clone
+        for _ in 0..2 {
+            assert!(authenticate(&relying_party, &mut authenticator)
+                .await
+                .is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passwordless_requires_user_verification() {
+        let relying_party = WebauthnRelyingParty::default();
+        let mut authenticator = SoftwareAuthenticator::new();
+        register(&relying_party, &authenticator).await.unwrap();
+
+        authenticator.flags = FLAG_USER_PRESENT;
+        assert!(matches!(
+            authenticate(&relying_party, &mut authenticator).await,
+            Err(PasskeyError::InvalidCredential(_))
+        ));
+
+        // As a second factor, presence is enough
+        let login_attempt_id = LoginAttemptId::default();
+        let options = relying_party
+            .start_authentication(Some((&email(), &login_attempt_id)))
+            .await
+            .unwrap();
+        assert_eq!(options.public_key.allow_credentials.len(), 1);
+        let credential = authenticator.get(&options);
+        let authentication = relying_party
+            .finish_authentication(&options.challenge_id, &credential)
+            .await
+            .unwrap();
+        assert_eq!(authentication.login_attempt_id, Some(login_attempt_id));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_forged_signature() {
+        let relying_party = WebauthnRelyingParty::default();
+        let authenticator = SoftwareAuthenticator::new();
+        register(&relying_party, &authenticator).await.unwrap();
+
+        let mut impostor = SoftwareAuthenticator::new();
+        impostor.credential_id = authenticator.credential_id.clone();
+        assert!(matches!(
+            authenticate(&relying_party, &mut impostor).await,
+            Err(PasskeyError::InvalidCredential(_))
+        ));
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::{BTreeSet, HashMap};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use color_eyre::eyre::{eyre, Context, ContextCompat, Result};
+use roxmltree::{Node, NodeId, NodeType};
+use rsa::{
+    pkcs1v15::{Signature, VerifyingKey},
+    signature::Verifier,
+};
+use sha2::{Digest, Sha256};
+
+const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+const EXCLUSIVE_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+// XML Signature, as far as identity providers use it: an enveloped RSA-SHA256 signature over the
+// element it is a child of, referenced by that element's `ID`, with exclusive canonicalization.
+// Anything else is refused rather than half supported.
+
+// The `Signature` child of `element`, if it is signed at all
+pub fn signature_of<'a, 'input>(element: Node<'a, 'input>) -> Option<Node<'a, 'input>> {
+    element
+        .children()
+        .find(|child| child.has_tag_name((DSIG_NS, "Signature")))
+}
+
+// Checks the enveloped signature of `element` against `key`. Only `element` itself is vouched for,
+// so callers must read the signed data from this very node, never look it up again by its ID.
+pub fn verify_enveloped_signature(element: Node, key: &VerifyingKey<Sha256>) -> Result<()> {
+    let signature = signature_of(element).wrap_err("Element is not signed")?;
+    let signed_info = dsig_child(signature, "SignedInfo")?;
+
+    let c14n_method = dsig_child(signed_info, "CanonicalizationMethod")?;
+    expect_algorithm(c14n_method, EXCLUSIVE_C14N)?;
+    expect_algorithm(dsig_child(signed_info, "SignatureMethod")?, RSA_SHA256)?;
+
+    let mut references = signed_info
+        .children()
+        .filter(|child| child.has_tag_name((DSIG_NS, "Reference")));
+    let reference = references.next().wrap_err("Signature has no reference")?;
+    if references.next().is_some() {
+        return Err(eyre!("Signature has more than one reference"));
+    }
+    let id = element
+        .attribute("ID")
+        .wrap_err("Signed element has no ID")?;
+    if reference.attribute("URI") != Some(&format!("#{}", id)) {
+        return Err(eyre!("Signature does not reference the element it is in"));
+    }
+
+    // The enveloped signature transform only takes the signature itself out, which the
+    // canonicalization below always does
+    let mut prefixes = None;
+    for transform in dsig_child(reference, "Transforms")?
+        .children()
+        .filter(Node::is_element)
+    {
+        match transform.attribute("Algorithm") {
+            Some(ENVELOPED_SIGNATURE) => {}
+            Some(EXCLUSIVE_C14N) => prefixes = Some(inclusive_prefixes(transform)),
+            algorithm => return Err(eyre!("Unsupported transform {:?}", algorithm)),
+        }
+    }
+    let prefixes = prefixes.wrap_err("Reference is not canonicalized")?;
+    expect_algorithm(dsig_child(reference, "DigestMethod")?, SHA256)?;
+    let digest = decode_base64(dsig_child(reference, "DigestValue")?)?;
+
+    let canonical = canonicalize(element, Some(signature.id()), &prefixes);
+    if Sha256::digest(canonical.as_bytes())[..] != digest[..] {
+        return Err(eyre!("Digest of the signed element does not match"));
+    }
+
+    let signature_value = decode_base64(dsig_child(signature, "SignatureValue")?)?;
+    let signature_value =
+        Signature::try_from(signature_value.as_slice()).wrap_err("Malformed signature value")?;
+    let canonical = canonicalize(signed_info, None, &inclusive_prefixes(c14n_method));
+    key.verify(canonical.as_bytes(), &signature_value)
+        .wrap_err("Signature does not verify")
+}
+
+fn dsig_child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Result<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.has_tag_name((DSIG_NS, name)))
+        .wrap_err_with(|| format!("Signature has no {}", name))
+}
+
+fn expect_algorithm(node: Node, algorithm: &str) -> Result<()> {
+    match node.attribute("Algorithm") {
+        Some(actual) if actual == algorithm => Ok(()),
+        actual => Err(eyre!(
+            "Unsupported {} algorithm {:?}",
+            node.tag_name().name(),
+            actual
+        )),
+    }
+}
+
+fn decode_base64(node: Node) -> Result<Vec<u8>> {
+    let text: String = node
+        .text()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    STANDARD.decode(text).wrap_err("Invalid base64")
+}
+
+// The `PrefixList` of the `InclusiveNamespaces` parameter of an exclusive canonicalization, with
+// `#default` standing for the default namespace
+fn inclusive_prefixes(method: Node) -> Vec<String> {
+    method
+        .children()
+        .find(|child| child.has_tag_name((EXCLUSIVE_C14N, "InclusiveNamespaces")))
+        .and_then(|node| node.attribute("PrefixList"))
+        .map(|list| {
+            list.split_whitespace()
+                .map(|prefix| match prefix {
+                    "#default" => String::new(),
+                    prefix => prefix.to_owned(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Exclusive XML Canonicalization 1.0, without comments, of the subtree of `node` without the
+// `excluded` subtree. Namespaces are declared where they are visibly used, plus those in
+// `inclusive_prefixes` wherever they are in scope.
+pub fn canonicalize(node: Node, excluded: Option<NodeId>, inclusive_prefixes: &[String]) -> String {
+    let mut output = String::new();
+    write_element(
+        node,
+        excluded,
+        inclusive_prefixes,
+        &HashMap::new(),
+        &mut output,
+    );
+    output
+}
+
+fn write_element(
+    node: Node,
+    excluded: Option<NodeId>,
+    inclusive_prefixes: &[String],
+    rendered: &HashMap<String, String>,
+    output: &mut String,
+) {
+    let input = node.document().input_text();
+    let qname = element_qname(node);
+
+    let mut prefixes: BTreeSet<&str> = inclusive_prefixes.iter().map(String::as_str).collect();
+    prefixes.insert(qname.split_once(':').map_or("", |(prefix, _)| prefix));
+    for attribute in node.attributes() {
+        if let Some((prefix, _)) = input[attribute.range_qname()].split_once(':') {
+            prefixes.insert(prefix);
+        }
+    }
+
+    let mut rendered = rendered.clone();
+    output.push('<');
+    output.push_str(qname);
+    // The set is ordered by prefix, with the default namespace first, as canonical order requires
+    for prefix in prefixes {
+        let uri = match prefix {
+            "xml" => continue,
+            "" => node.default_namespace().unwrap_or_default(),
+            prefix => match node.lookup_namespace_uri(Some(prefix)) {
+                Some(uri) => uri,
+                None => continue,
+            },
+        };
+        if rendered.get(prefix).map_or("", String::as_str) == uri {
+            continue;
+        }
+        match prefix {
+            "" => output.push_str(" xmlns=\""),
+            prefix => {
+                output.push_str(" xmlns:");
+                output.push_str(prefix);
+                output.push_str("=\"");
+            }
+        }
+        escape_attribute(uri, output);
+        output.push('"');
+        rendered.insert(prefix.to_owned(), uri.to_owned());
+    }
+
+    let mut attributes: Vec<_> = node.attributes().collect();
+    attributes
+        .sort_by_key(|attribute| (attribute.namespace().unwrap_or_default(), attribute.name()));
+    for attribute in attributes {
+        output.push(' ');
+        output.push_str(&input[attribute.range_qname()]);
+        output.push_str("=\"");
+        escape_attribute(attribute.value(), output);
+        output.push('"');
+    }
+    output.push('>');
+
+    for child in node.children() {
+        if Some(child.id()) == excluded {
+            continue;
+        }
+        match child.node_type() {
+            NodeType::Element => {
+                write_element(child, excluded, inclusive_prefixes, &rendered, output)
+            }
+            NodeType::Text => escape_text(child.text().unwrap_or_default(), output),
+            NodeType::PI => {
+                if let Some(pi) = child.pi() {
+                    output.push_str("<?");
+                    output.push_str(pi.target);
+                    if let Some(value) = pi.value {
+                        output.push(' ');
+                        output.push_str(value);
+                    }
+                    output.push_str("?>");
+                }
+            }
+            NodeType::Comment | NodeType::Root => {}
+        }
+    }
+
+    output.push_str("</");
+    output.push_str(qname);
+    output.push('>');
+}
+
+// The element name as written, prefix included, which the parsed node does not keep
+fn element_qname<'input>(node: Node<'_, 'input>) -> &'input str {
+    let input = node.document().input_text();
+    let tag = &input[node.range().start + 1..];
+    let end = tag
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+fn escape_attribute(value: &str, output: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '"' => output.push_str("&quot;"),
+            '\t' => output.push_str("&#x9;"),
+            '\n' => output.push_str("&#xA;"),
+            '\r' => output.push_str("&#xD;"),
+            c => output.push(c),
+        }
+    }
+}
+
+fn escape_text(text: &str, output: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '\r' => output.push_str("&#xD;"),
+            c => output.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use roxmltree::Document;
+
+    use super::*;
+
+    const DOCUMENT: &str = concat!(
+        r#"<?xml version="1.0"?>"#,
+        r#"<a:Root xmlns:a="urn:a" xmlns:b="urn:b" xmlns:unused="urn:unused" z="1" b:y="2" a="3">"#,
+        r#"<!-- comment --><a:Empty/>"#,
+        r#"<Child xmlns="urn:default" text="&quot;x&quot;&#9;">1 &lt; 2 &amp; 3 &gt; 0</Child>"#,
+        r#"<a:Signed ID="_1"><a:Signature/><b:Data>data</b:Data></a:Signed>"#,
+        r#"</a:Root>"#
+    );
+
+    fn element<'a, 'input>(document: &'a Document<'input>, name: &str) -> Node<'a, 'input> {
+        document
+            .descendants()
+            .find(|node| node.tag_name().name() == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_canonicalizes_document() {
+        let document = Document::parse(DOCUMENT).unwrap();
+
+        assert_eq!(
+            canonicalize(document.root_element(), None, &[]),
+            concat!(
+                r#"<a:Root xmlns:a="urn:a" xmlns:b="urn:b" a="3" z="1" b:y="2">"#,
+                r#"<a:Empty></a:Empty>"#,
+                r#"<Child xmlns="urn:default" text="&quot;x&quot;&#x9;">1 &lt; 2 &amp; 3 &gt; 0</Child>"#,
+                r#"<a:Signed ID="_1"><a:Signature></a:Signature><b:Data>data</b:Data></a:Signed>"#,
+                r#"</a:Root>"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_declares_namespaces_of_subtree_only() {
+        let document = Document::parse(DOCUMENT).unwrap();
+        let signed = element(&document, "Signed");
+        let signature = element(&document, "Signature");
+
+        assert_eq!(
+            canonicalize(signed, Some(signature.id()), &[]),
+            r#"<a:Signed xmlns:a="urn:a" ID="_1"><b:Data xmlns:b="urn:b">data</b:Data></a:Signed>"#
+        );
+        assert_eq!(
+            canonicalize(
+                signed,
+                Some(signature.id()),
+                &["b".to_owned(), "unused".to_owned()]
+            ),
+            concat!(
+                r#"<a:Signed xmlns:a="urn:a" xmlns:b="urn:b" xmlns:unused="urn:unused" ID="_1">"#,
+                r#"<b:Data>data</b:Data></a:Signed>"#
+            )
+        );
+    }
+}
@@ -1,55 +1,242 @@
 use std::{str::FromStr, sync::Arc};
 
 use auth_service::{
-    domain::mock_email_client::MockEmailClient,
+    app_state::AppState,
+    domain::{
+        mock_email_client::{MockEmail, MockEmailClient, MockInbox},
+        mock_sms_client::MockSmsClient,
+        SmsClientBackend,
+    },
     get_postgres_pool, get_redis_client,
     services::{
+        breached_passwords::{BreachedPasswordCheckerBackend, RangeFileBreachedPasswordChecker},
         data_stores::{
-            hashset_banned_store::HashsetBannedTokenStore, postgres_user_store::PostgresUserStore,
+            postgres_two_fa_code_store::PostgresTwoFACodeStore,
+            postgres_user_store::PostgresUserStore,
             redis_banned_token_store::RedisBannedTokenStore,
-            redis_two_fa_code_store::RedisTwoFACodeStore, HashMapUserStore, HashmapTwoFACodeStore,
         },
-        BannedTokenStore,
+        dpop_replay::{DpopReplayStoreBackend, RedisDpopReplayStore},
+        email_outbox::{EmailOutboxStoreBackend, OutboxStatus, PostgresEmailOutboxStore},
+        known_devices::{KnownDeviceStoreBackend, PostgresKnownDeviceStore},
+        magic_links::{MagicLinkStoreBackend, RedisMagicLinkStore},
+        oidc::{
+            OidcIdentityStoreBackend, OidcStateStoreBackend, PostgresOidcIdentityStore,
+            RedisOidcStateStore,
+        },
+        passkeys::{
+            PasskeyChallengeStoreBackend, PasskeyStoreBackend, PostgresPasskeyStore,
+            RedisPasskeyChallengeStore,
+        },
+        phone_verification::{PhoneVerificationStoreBackend, RedisPhoneVerificationStore},
+        saml::{RedisSamlStore, SamlStoreBackend},
+    },
+    utils::{
+        auth::Claims,
+        constants::{
+            test, CSRF_COOKIE_NAME, CSRF_HEADER_NAME, DATABASE_URL, DEFAULT_SAML_ACS_URL,
+            DEFAULT_SAML_SP_ENTITY_ID, REDIS_HOST_NAME,
+        },
+        cookies::CookiePolicy,
+        dpop::{DpopVerifier, DPOP_HEADER_NAME},
+        email_outbox::EmailOutbox,
+        login_alerts::LoginAlerts,
+        magic_link::MagicLinks,
+        oidc::{OidcProviderConfig, OidcRelyingParty},
+        phone_verification::PhoneVerification,
+        rate_limit::RateLimiter,
+        saml::{SamlIdpConfig, SamlServiceProvider},
+        security_headers::SecurityHeadersPolicy,
+        webauthn::WebauthnRelyingParty,
     },
-    utils::constants::{test, DATABASE_URL, REDIS_HOST_NAME},
     Application,
 };
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use ciborium::Value;
+use jsonwebtoken::{
+    encode,
+    jwk::{Jwk, ThumbprintHash},
+    Algorithm, EncodingKey, Header,
+};
+use p256::{
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    pkcs8::EncodePrivateKey,
+};
+use reqwest::{cookie::CookieStore, header::HeaderName, Url};
+use rsa::{
+    pkcs1v15::SigningKey as RsaSigningKey, pkcs8::DecodePrivateKey, signature::SignatureEncoding,
+    RsaPrivateKey,
+};
 use secrecy::{ExposeSecret, SecretString};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     Connection, Executor, PgConnection, PgPool,
 };
 use uuid::Uuid;
+use wiremock::{
+    matchers::{basic_auth, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+// A tiny sorted range file holding the SHA-1 hashes of a handful of passwords
+const BREACHED_PASSWORDS_FIXTURE: &str = "tests/fixtures/pwned_passwords.txt";
+// A SAML identity provider's key and certificate, and an assertion and response to fill in
+const SAML_IDP_KEY_FIXTURE: &str = "tests/fixtures/saml/idp_key.pem";
+const SAML_IDP_CERT_FIXTURE: &str = "tests/fixtures/saml/idp_cert.pem";
+const SAML_ASSERTION_FIXTURE: &str = "tests/fixtures/saml/assertion.xml";
+const SAML_RESPONSE_FIXTURE: &str = "tests/fixtures/saml/response.xml";
+const SAML_SIGNED_INFO_FIXTURE: &str = "tests/fixtures/saml/signed_info.xml";
 
 pub struct TestApp {
     pub address: String,
     pub cookie_jar: Arc<reqwest::cookie::Jar>,
     pub http_client: reqwest::Client,
     pub banned_token_store: Arc<tokio::sync::RwLock<RedisBannedTokenStore>>,
-    pub two_fa_code_store: Arc<tokio::sync::RwLock<RedisTwoFACodeStore>>,
+    pub two_fa_code_store: Arc<tokio::sync::RwLock<PostgresTwoFACodeStore>>,
     pub user_store: Arc<tokio::sync::RwLock<PostgresUserStore>>,
+    pub email_outbox: EmailOutbox,
+    // What the app emailed, read the way users read their mail
+    pub inbox: MockInbox,
+    // What the app texted or read out over the phone
+    pub sms: MockSmsClient,
     db_name: String,
 }
 
+type TestAppState =
+    AppState<PostgresUserStore, RedisBannedTokenStore, PostgresTwoFACodeStore, MockEmailClient>;
+
 impl TestApp {
     pub async fn new() -> Self {
+        Self::build(|app_state| app_state).await
+    }
+
+    pub async fn with_rate_limiter(rate_limiter: RateLimiter) -> Self {
+        Self::build(|app_state| app_state.with_rate_limiter(rate_limiter)).await
+    }
+
+    pub async fn with_cookie_policy(cookie_policy: CookiePolicy) -> Self {
+        Self::build(|app_state| app_state.with_cookie_policy(cookie_policy)).await
+    }
+
+    pub async fn with_security_headers(security_headers: SecurityHeadersPolicy) -> Self {
+        Self::build(|app_state| app_state.with_security_headers(security_headers)).await
+    }
+
+    pub async fn with_oidc_provider(provider: OidcProviderConfig) -> Self {
+        Self::build(|app_state| {
+            let oidc = (*app_state.oidc).clone().with_provider(provider);
+            app_state.with_oidc(oidc)
+        })
+        .await
+    }
+
+    pub async fn with_dev_mailbox() -> Self {
+        Self::build(|app_state| {
+            let inbox = app_state
+                .email_client
+                .try_read()
+                .expect("Email client is not in use yet")
+                .inbox();
+            app_state.with_dev_mailbox(inbox)
+        })
+        .await
+    }
+
+    pub async fn with_sms_client(sms_client: SmsClientBackend) -> Self {
+        Self::build(|app_state| app_state.with_sms_client(sms_client)).await
+    }
+
+    pub async fn with_saml_idp(idp: SamlIdpConfig) -> Self {
+        Self::build(|app_state| {
+            let saml = (*app_state.saml).clone().with_idp(idp);
+            app_state.with_saml(saml)
+        })
+        .await
+    }
+
+    pub async fn with_login_country_header(country_header: &'static str) -> Self {
+        Self::build(|app_state| {
+            let login_alerts = (*app_state.login_alerts)
+                .clone()
+                .with_country_header(Some(HeaderName::from_static(country_header)));
+            app_state.with_login_alerts(login_alerts)
+        })
+        .await
+    }
+
+    // Builds the app with the default configuration, adjusted by `configure`
+    async fn build(configure: impl FnOnce(TestAppState) -> TestAppState) -> Self {
         let (pg_pool, db_name) = configure_postgresql().await;
         let redis_connection = configure_redis().await;
 
-        let user_store = Arc::new(tokio::sync::RwLock::new(PostgresUserStore::new(pg_pool)));
-        let banned_token_store = Arc::new(tokio::sync::RwLock::new(RedisBannedTokenStore::new(
+        let webauthn = WebauthnRelyingParty::new(
+            PasskeyStoreBackend::Postgres(PostgresPasskeyStore::new(pg_pool.clone())),
+            PasskeyChallengeStoreBackend::Redis(RedisPasskeyChallengeStore::new(
+                redis_connection.clone(),
+            )),
+        );
+        let oidc = OidcRelyingParty::new(
+            OidcStateStoreBackend::Redis(RedisOidcStateStore::new(redis_connection.clone())),
+            OidcIdentityStoreBackend::Postgres(PostgresOidcIdentityStore::new(pg_pool.clone())),
+        );
+        let saml = SamlServiceProvider::new(SamlStoreBackend::Redis(RedisSamlStore::new(
             redis_connection.clone(),
         )));
-        let two_fa_code_store = Arc::new(tokio::sync::RwLock::new(RedisTwoFACodeStore::new(
+        let login_alerts = LoginAlerts::new(
+            KnownDeviceStoreBackend::Postgres(PostgresKnownDeviceStore::new(pg_pool.clone())),
+            MagicLinkStoreBackend::Redis(RedisMagicLinkStore::new(redis_connection.clone())),
+        );
+        let email_outbox = EmailOutbox::new(EmailOutboxStoreBackend::Postgres(
+            PostgresEmailOutboxStore::new(pg_pool.clone()),
+        ));
+        let user_store = Arc::new(tokio::sync::RwLock::new(PostgresUserStore::new(
+            pg_pool.clone(),
+        )));
+        let banned_token_store = Arc::new(tokio::sync::RwLock::new(RedisBannedTokenStore::new(
             redis_connection.clone(),
         )));
-        let email_client = Arc::new(tokio::sync::RwLock::new(MockEmailClient {}));
+        let two_fa_code_store = Arc::new(tokio::sync::RwLock::new(PostgresTwoFACodeStore::new(
+            pg_pool,
+        )));
+        let mock_email_client = MockEmailClient::default();
+        let inbox = mock_email_client.inbox();
+        let email_client = Arc::new(tokio::sync::RwLock::new(mock_email_client));
+        let sms = MockSmsClient::default();
+        let phone_verification = PhoneVerification::new(PhoneVerificationStoreBackend::Redis(
+            RedisPhoneVerificationStore::new(redis_connection.clone()),
+        ));
+        let breached_password_checker = BreachedPasswordCheckerBackend::RangeFile(
+            RangeFileBreachedPasswordChecker::open(BREACHED_PASSWORDS_FIXTURE)
+                .expect("Failed to open breached passwords fixture"),
+        );
+
+        let dpop_verifier = DpopVerifier::new(DpopReplayStoreBackend::Redis(
+            RedisDpopReplayStore::new(redis_connection.clone()),
+        ));
 
-        let app_state = auth_service::app_state::AppState::new(
-            user_store.clone(),
-            banned_token_store.clone(),
-            two_fa_code_store.clone(),
-            email_client.clone(),
+        let app_state = configure(
+            AppState::new(
+                user_store.clone(),
+                banned_token_store.clone(),
+                two_fa_code_store.clone(),
+                email_client.clone(),
+            )
+            .with_breached_password_checker(breached_password_checker)
+            .with_email_outbox(email_outbox.clone())
+            .with_sms_client(SmsClientBackend::Mock(sms.clone()))
+            .with_phone_verification(phone_verification)
+            .with_dpop_verifier(dpop_verifier)
+            .with_webauthn(webauthn)
+            .with_magic_links(MagicLinks::new(MagicLinkStoreBackend::Redis(
+                RedisMagicLinkStore::new(redis_connection.clone()),
+            )))
+            .with_oidc(oidc)
+            .with_saml(saml)
+            .with_login_alerts(login_alerts),
         );
 
         let app = Application::build(app_state, test::APP_ADDRESS)
@@ -73,10 +260,47 @@ impl TestApp {
             banned_token_store,
             two_fa_code_store,
             user_store,
+            email_outbox,
+            inbox,
+            sms,
             db_name,
         }
     }
 
+    // Waits for the outbox worker to get the email with `idempotency_key` to `status`
+    pub async fn wait_for_email(&self, idempotency_key: &str, status: OutboxStatus) {
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while self.email_outbox.status(idempotency_key).await.unwrap() != Some(status) {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("Email {} never became {:?}", idempotency_key, status));
+    }
+
+    // Waits until `recipient` got `count` emails and returns the newest
+    pub async fn wait_for_emails_to(&self, recipient: &str, count: usize) -> MockEmail {
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let messages = self.inbox.messages_to(recipient);
+                if messages.len() >= count {
+                    return messages[0].clone();
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("{} never got {} emails", recipient, count))
+    }
+
+    pub async fn get_dev_mailbox(&self, query: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/_dev/mailbox{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_root(&self) -> reqwest::Response {
         self.http_client
             .get(&self.address)
@@ -109,14 +333,74 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    // Sends the CSRF token from the cookie jar as a header, the way the frontend does
+    // Logs in from another browser, which keeps cookies of its own
+    pub async fn post_login_from<Body>(
+        &self,
+        http_client: &reqwest::Client,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        http_client
+            .post(format!("{}/login", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_report<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/login/report", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
+        let mut request = self.http_client.post(format!("{}/logout", &self.address));
+        if let Some(csrf_token) = self.get_cookie(CSRF_COOKIE_NAME) {
+            request = request.header(CSRF_HEADER_NAME, csrf_token);
+        }
+        request.send().await.expect("Failed to execute request.")
+    }
+
+    // Authenticates like an API client, with no CSRF header
+    pub async fn post_logout_with_bearer(&self, token: &str) -> reqwest::Response {
         self.http_client
             .post(format!("{}/logout", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_csrf_token(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/csrf-token", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub fn get_cookie(&self, name: &str) -> Option<String> {
+        let url = Url::parse(&self.address).expect("Failed to parse URL");
+        let cookies = self.cookie_jar.cookies(&url)?;
+        cookies
+            .to_str()
+            .ok()?
+            .split("; ")
+            .filter_map(|cookie| cookie.split_once('='))
+            .find(|(cookie_name, _)| *cookie_name == name)
+            .map(|(_, value)| value.to_owned())
+    }
+
     pub async fn post_verify_2fa<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -141,11 +425,519 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
-    pub async fn clean_up(&self) {
-        delete_database(&self.db_name).await;
+    pub async fn post_verify_token_with_bearer(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/verify-token", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_with_dpop<Body>(&self, body: &Body, proof: &str) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/login", &self.address))
+            .header(DPOP_HEADER_NAME, proof)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_verify_2fa_with_dpop<Body>(
+        &self,
+        body: &Body,
+        proof: &str,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/verify-2fa", &self.address))
+            .header(DPOP_HEADER_NAME, proof)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Presents a DPoP-bound token the way a resource request would
+    pub async fn post_verify_token_with_dpop(&self, token: &str, proof: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/verify-token", &self.address))
+            .header("Authorization", format!("DPoP {}", token))
+            .header(DPOP_HEADER_NAME, proof)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Registration is for signed in users, who are authenticated like an API client
+    pub async fn post_passkeys_register<Body>(
+        &self,
+        path: &str,
+        body: &Body,
+        token: &str,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/passkeys/register/{}", &self.address, path))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Phone numbers are added by signed in users, who are authenticated like an API client
+    pub async fn post_phone_number<Body>(
+        &self,
+        path: &str,
+        body: &Body,
+        token: &str,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/phone-number{}", &self.address, path))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_passkeys_login<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/passkeys/login/{}", &self.address, path))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_magic_link<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/login/magic-link", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Opens an emailed sign-in link, like a click from the mail client would
+    pub async fn get_magic_link(&self, link: &str) -> reqwest::Response {
+        self.http_client
+            .get(link)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Does not follow the redirect to the provider, like a browser that has not gone there yet
+    pub async fn get_oidc_login(&self, provider: &str) -> reqwest::Response {
+        reqwest::Client::builder()
+            .cookie_provider(self.cookie_jar.clone())
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build HTTP client")
+            .get(format!("{}/oidc/{}/login", &self.address, provider))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Comes back from the provider, like the browser would after logging in there
+    pub async fn get_oidc_callback(
+        &self,
+        provider: &str,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/oidc/{}/callback", &self.address, provider))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_saml_metadata(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/saml/metadata", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Does not follow the redirect to the identity provider, like `get_oidc_login`
+    pub async fn get_saml_login(&self) -> reqwest::Response {
+        reqwest::Client::builder()
+            .cookie_provider(self.cookie_jar.clone())
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build HTTP client")
+            .get(format!("{}/saml/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Posts the identity provider's response, like the browser does with its auto-submitting form
+    pub async fn post_saml_acs(&self, saml_response: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/saml/acs", &self.address))
+            .form(&[("SAMLResponse", saml_response), ("RelayState", "")])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.address, path)
+    }
+}
+
+// Signs DPoP proofs with a P-256 key, like a DPoP client would
+pub struct DpopKey {
+    key: EncodingKey,
+    jwk: Jwk,
+}
+
+impl DpopKey {
+    pub fn generate() -> Self {
+        let secret = p256::SecretKey::random(&mut rand::rngs::OsRng);
+        let der = secret.to_pkcs8_der().expect("Failed to encode key");
+        let key = EncodingKey::from_ec_der(der.as_bytes());
+        let jwk = Jwk::from_encoding_key(&key, Algorithm::ES256).expect("Failed to build JWK");
+        Self { key, jwk }
+    }
+
+    pub fn jkt(&self) -> String {
+        self.jwk.thumbprint(ThumbprintHash::SHA256)
+    }
+
+    pub fn proof(&self, method: &str, url: &str, access_token: Option<&str>) -> String {
+        let ath =
+            access_token.map(|token| URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes())));
+        let claims = json!({
+            "jti": Uuid::new_v4().to_string(),
+            "htm": method,
+            "htu": url,
+            "iat": chrono::Utc::now().timestamp(),
+            "ath": ath,
+        });
+        let mut header = Header::new(Algorithm::ES256);
+        header.typ = Some("dpop+jwt".to_owned());
+        header.jwk = Some(self.jwk.clone());
+        encode(&header, &claims, &self.key).expect("Failed to sign proof")
+    }
+}
+
+// Creates and uses a single ES256 passkey, like a browser and platform authenticator would
+pub struct SoftwareAuthenticator {
+    key: SigningKey,
+    pub credential_id: Vec<u8>,
+    pub sign_count: u32,
+}
+
+impl SoftwareAuthenticator {
+    const ORIGIN: &str = "http://localhost:3000";
+    const RP_ID: &str = "localhost";
+    // User present and user verified
+    const FLAGS: u8 = 0x05;
+
+    pub fn generate() -> Self {
+        Self {
+            key: SigningKey::random(&mut rand::rngs::OsRng),
+            credential_id: Uuid::new_v4().as_bytes().to_vec(),
+            sign_count: 0,
+        }
+    }
+
+    // Answers the options of `/passkeys/register/start` with a credential for `finish`
+    pub fn create(&self, options: &serde_json::Value) -> serde_json::Value {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        let cose_key = Value::Map(vec![
+            (Value::from(1), Value::from(2)),
+            (Value::from(3), Value::from(-7)),
+            (Value::from(-1), Value::from(1)),
+            (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+            (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+        ]);
+        // Attested credential data follows the flags and counter
+        let mut auth_data = self.authenticator_data(Self::FLAGS | 0x40);
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        ciborium::into_writer(&cose_key, &mut auth_data).unwrap();
+
+        let attestation = Value::Map(vec![
+            (Value::from("fmt"), Value::from("none")),
+            (Value::from("attStmt"), Value::Map(vec![])),
+            (Value::from("authData"), Value::Bytes(auth_data)),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        json!({
+            "id": URL_SAFE_NO_PAD.encode(&self.credential_id),
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(
+                    Self::client_data("webauthn.create", options),
+                ),
+                "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+            }
+        })
+    }
+
+    // Answers the options of `/passkeys/login/start` with an assertion for `finish`
+    pub fn get(&mut self, options: &serde_json::Value) -> serde_json::Value {
+        self.sign_count += 1;
+        let auth_data = self.authenticator_data(Self::FLAGS);
+        let client_data = Self::client_data("webauthn.get", options);
+        let mut message = auth_data.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data));
+        let signature: Signature = self.key.sign(&message);
+
+        json!({
+            "id": URL_SAFE_NO_PAD.encode(&self.credential_id),
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes()),
+            }
+        })
+    }
+
+    fn authenticator_data(&self, flags: u8) -> Vec<u8> {
+        let mut data = Sha256::digest(Self::RP_ID.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        data
+    }
+
+    fn client_data(ceremony_type: &str, options: &serde_json::Value) -> Vec<u8> {
+        json!({
+            "type": ceremony_type,
+            "challenge": options["publicKey"]["challenge"],
+            "origin": Self::ORIGIN,
+            "crossOrigin": false,
+        })
+        .to_string()
+        .into_bytes()
     }
 }
 
+// A local OpenID Connect provider, which signs ID tokens with an ES256 key
+pub struct MockIdp {
+    pub server: MockServer,
+    key: EncodingKey,
+    jwk: Jwk,
+}
+
+impl MockIdp {
+    pub const CLIENT_ID: &str = "auth-service";
+    const KEY_ID: &str = "mock-idp-key";
+
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        let secret = p256::SecretKey::random(&mut rand::rngs::OsRng);
+        let der = secret.to_pkcs8_der().expect("Failed to encode key");
+        let key = EncodingKey::from_ec_der(der.as_bytes());
+        let mut jwk = Jwk::from_encoding_key(&key, Algorithm::ES256).expect("Failed to build JWK");
+        jwk.common.key_id = Some(Self::KEY_ID.to_owned());
+
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": server.uri(),
+                "authorization_endpoint": format!("{}/authorize", server.uri()),
+                "token_endpoint": format!("{}/token", server.uri()),
+                "jwks_uri": format!("{}/jwks", server.uri()),
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "keys": [jwk] })))
+            .mount(&server)
+            .await;
+
+        Self { server, key, jwk }
+    }
+
+    pub fn provider(&self, name: &str) -> OidcProviderConfig {
+        OidcProviderConfig {
+            name: name.to_owned(),
+            discovery_url: format!("{}/.well-known/openid-configuration", self.server.uri()),
+            client_id: Self::CLIENT_ID.to_owned(),
+            client_secret: SecretString::from("mock-idp-secret".to_owned()),
+            scopes: vec!["openid".to_owned(), "email".to_owned()],
+        }
+    }
+
+    // Claims of an ID token for `subject`, issued to this service
+    pub fn claims(
+        &self,
+        nonce: &str,
+        subject: &str,
+        email: &str,
+        verified: bool,
+    ) -> serde_json::Value {
+        let now = chrono::Utc::now().timestamp();
+        json!({
+            "iss": self.server.uri(),
+            "aud": Self::CLIENT_ID,
+            "sub": subject,
+            "iat": now,
+            "exp": now + 300,
+            "nonce": nonce,
+            "email": email,
+            "email_verified": verified,
+        })
+    }
+
+    pub fn id_token(&self, claims: &serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = self.jwk.common.key_id.clone();
+        encode(&header, claims, &self.key).expect("Failed to sign ID token")
+    }
+
+    // Answers the next authorization code redemption with `id_token`
+    pub async fn issue_id_token(&self, id_token: String) {
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(basic_auth(Self::CLIENT_ID, "mock-idp-secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "mock-access-token",
+                "token_type": "Bearer",
+                "id_token": id_token,
+            })))
+            .up_to_n_times(1)
+            .mount(&self.server)
+            .await;
+    }
+}
+
+// A SAML identity provider, which signs its assertions with the fixture key
+pub struct MockSamlIdp {
+    key: RsaSigningKey<Sha256>,
+}
+
+impl MockSamlIdp {
+    pub const ENTITY_ID: &str = "https://idp.example.com/metadata";
+    pub const SSO_URL: &str = "https://idp.example.com/sso";
+    const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+    pub fn new() -> Self {
+        let key = read_fixture(SAML_IDP_KEY_FIXTURE);
+        let key =
+            RsaPrivateKey::from_pkcs8_pem(&key).expect("Failed to read identity provider key");
+        Self {
+            key: RsaSigningKey::new(key),
+        }
+    }
+
+    pub fn config(&self) -> SamlIdpConfig {
+        SamlIdpConfig::new(
+            Self::ENTITY_ID.to_owned(),
+            Self::SSO_URL.to_owned(),
+            &read_fixture(SAML_IDP_CERT_FIXTURE),
+        )
+        .expect("Failed to read identity provider certificate")
+    }
+
+    // The ID of the AuthnRequest the app redirected to the identity provider with
+    pub fn request_id(location: &str) -> String {
+        let url = Url::parse(location).expect("Invalid redirect");
+        let (_, request) = url
+            .query_pairs()
+            .find(|(name, _)| name == "SAMLRequest")
+            .expect("No SAMLRequest in the redirect");
+        let request = STANDARD.decode(request.as_ref()).expect("Invalid base64");
+        let mut xml = String::new();
+        std::io::Read::read_to_string(
+            &mut flate2::read::DeflateDecoder::new(request.as_slice()),
+            &mut xml,
+        )
+        .expect("Failed to inflate AuthnRequest");
+        roxmltree::Document::parse(&xml)
+            .expect("Invalid AuthnRequest")
+            .root_element()
+            .attribute("ID")
+            .expect("AuthnRequest has no ID")
+            .to_owned()
+    }
+
+    // A signed response to `request_id` vouching for `email`, base64 encoded as it is posted
+    pub fn response(&self, request_id: &str, email: &str) -> String {
+        let now = chrono::Utc::now();
+        let time = |offset: i64| {
+            (now + chrono::Duration::seconds(offset))
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        };
+        let assertion_id = format!("_{}", Uuid::new_v4().simple());
+        // The fixture is in canonical form already, so its digest is that of the assertion
+        let assertion = read_fixture(SAML_ASSERTION_FIXTURE)
+            .replace("{assertion_id}", &assertion_id)
+            .replace("{issue_instant}", &time(0))
+            .replace("{issuer}", Self::ENTITY_ID)
+            .replace("{name_id}", email)
+            .replace("{in_response_to}", request_id)
+            .replace("{recipient}", DEFAULT_SAML_ACS_URL)
+            .replace("{audience}", DEFAULT_SAML_SP_ENTITY_ID)
+            .replace("{not_before}", &time(-30))
+            .replace("{not_on_or_after}", &time(300));
+
+        let signed_info = read_fixture(SAML_SIGNED_INFO_FIXTURE)
+            .replace("{reference_id}", &assertion_id)
+            .replace(
+                "{digest}",
+                &STANDARD.encode(Sha256::digest(assertion.as_bytes())),
+            );
+        let canonical = signed_info.replacen(
+            "<ds:SignedInfo>",
+            &format!("<ds:SignedInfo xmlns:ds=\"{}\">", Self::DSIG_NS),
+            1,
+        );
+        let signature = STANDARD.encode(self.key.sign(canonical.as_bytes()).to_bytes());
+        let assertion = assertion.replacen(
+            "</saml:Issuer>",
+            &format!(
+                "</saml:Issuer><ds:Signature xmlns:ds=\"{}\">{}<ds:SignatureValue>{}</ds:SignatureValue></ds:Signature>",
+                Self::DSIG_NS, signed_info, signature
+            ),
+            1,
+        );
+
+        let response = read_fixture(SAML_RESPONSE_FIXTURE)
+            .replace("{response_id}", &format!("_{}", Uuid::new_v4().simple()))
+            .replace("{issue_instant}", &time(0))
+            .replace("{recipient}", DEFAULT_SAML_ACS_URL)
+            .replace("{in_response_to}", request_id)
+            .replace("{issuer}", Self::ENTITY_ID)
+            .replace("{status}", "urn:oasis:names:tc:SAML:2.0:status:Success")
+            .replace("{assertion}", &assertion);
+        STANDARD.encode(response)
+    }
+}
+
+fn read_fixture(path: &str) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+}
+
 impl Drop for TestApp {
     fn drop(&mut self) {
         let db_name = self.db_name.clone();
@@ -155,6 +947,13 @@ impl Drop for TestApp {
     }
 }
 
+// Reads the claims of a token issued by the app without validating it, e.g. to look up its `jti`
+pub fn get_token_claims(token: &str) -> Claims {
+    jsonwebtoken::dangerous::insecure_decode::<Claims>(token)
+        .expect("Failed to decode token")
+        .claims
+}
+
 pub fn get_random_email() -> String {
     format!("{}@example.com", Uuid::new_v4())
 }
@@ -210,7 +1009,7 @@ async fn configure_database(db_conn_string: &SecretString, db_name: &str) {
 async fn delete_database(db_name: &str) {
     let postgresql_conn_url = DATABASE_URL.to_owned();
 
-    let connection_options = PgConnectOptions::from_str(&postgresql_conn_url.expose_secret())
+    let connection_options = PgConnectOptions::from_str(postgresql_conn_url.expose_secret())
         .expect("Failed to parse PostgreSQL connection string");
 
     let mut connection = PgConnection::connect_with(&connection_options)
@@ -241,10 +1040,20 @@ async fn delete_database(db_name: &str) {
         .expect("Failed to drop the database.");
 }
 
-async fn configure_redis() -> redis::aio::MultiplexedConnection {
+pub async fn configure_redis() -> redis::aio::MultiplexedConnection {
     get_redis_client(REDIS_HOST_NAME.to_owned())
         .expect("Failed to get Redis client")
         .get_multiplexed_async_connection()
         .await
         .expect("Failed to get Redis connection")
 }
+
+// Reads the code out of a 2FA email the way a user would
+pub fn two_fa_code_from(email: &MockEmail) -> String {
+    email
+        .text
+        .split_whitespace()
+        .find(|word| word.len() == 6 && word.chars().all(|c| c.is_ascii_digit()))
+        .expect("No 2FA code in email")
+        .to_owned()
+}
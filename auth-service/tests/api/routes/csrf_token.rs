@@ -0,0 +1,57 @@
+use auth_service::{
+    routes::CsrfTokenResponse,
+    utils::constants::{CSRF_COOKIE_NAME, JWT_COOKIE_NAME},
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+#[tokio::test]
+async fn should_issue_csrf_cookie_on_login() {
+    let app = TestApp::new().await;
+    let email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "correct-Horse-battery-st4ple",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": email,
+            "password": "correct-Horse-battery-st4ple",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let csrf_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == CSRF_COOKIE_NAME)
+        .expect("No CSRF cookie found");
+    assert!(!csrf_cookie.http_only());
+
+    // The token endpoint hands out the same token for frontends on other origins
+    let response = app.get_csrf_token().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response
+        .json::<CsrfTokenResponse>()
+        .await
+        .expect("Could not deserialize response body to CsrfTokenResponse");
+    assert_eq!(body.csrf_token, csrf_cookie.value());
+
+    // Logging out clears both cookies
+    let response = app.post_logout().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.get_cookie(JWT_COOKIE_NAME), None);
+    assert_eq!(app.get_cookie(CSRF_COOKIE_NAME), None);
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let app = TestApp::new().await;
+
+    let response = app.get_csrf_token().await;
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,55 @@
+use auth_service::routes::DevMailboxResponse;
+
+use crate::helpers::{get_random_email, two_fa_code_from, TestApp};
+
+async fn login_with_2fa(app: &TestApp) -> String {
+    let email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "correct-Horse-battery-st4ple",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": email,
+            "password": "correct-Horse-battery-st4ple",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    email
+}
+
+#[tokio::test]
+async fn should_list_sent_emails() {
+    let app = TestApp::with_dev_mailbox().await;
+    let first = login_with_2fa(&app).await;
+    let second = login_with_2fa(&app).await;
+    let code = two_fa_code_from(&app.wait_for_emails_to(&second, 1).await);
+    app.wait_for_emails_to(&first, 1).await;
+
+    let response = app.get_dev_mailbox("").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.json::<DevMailboxResponse>().await.unwrap();
+    assert_eq!(body.messages.len(), 2);
+
+    let response = app
+        .get_dev_mailbox(&format!("?recipient={}&limit=1", second))
+        .await;
+    let body = response.json::<DevMailboxResponse>().await.unwrap();
+    assert_eq!(body.messages.len(), 1);
+    assert_eq!(body.messages[0].recipient, second);
+    assert!(body.messages[0].text.contains(&code));
+}
+
+#[tokio::test]
+async fn should_not_serve_mailbox_unless_enabled() {
+    let app = TestApp::new().await;
+    login_with_2fa(&app).await;
+
+    let response = app.get_dev_mailbox("").await;
+    assert_eq!(response.status().as_u16(), 404);
+}
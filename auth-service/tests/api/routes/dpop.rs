@@ -0,0 +1,203 @@
+use auth_service::{
+    domain::models::Email,
+    routes::{TokenResponse, TwoFactorAuthResponse},
+    services::TwoFACodeStore,
+    utils::constants::JWT_COOKIE_NAME,
+};
+
+use crate::helpers::{get_random_email, get_token_claims, DpopKey, TestApp};
+
+const PASSWORD: &str = "correct-Horse-battery-st4ple";
+
+async fn signup(app: &TestApp, requires_2fa: bool) -> String {
+    let email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "requires2FA": requires_2fa
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    email
+}
+
+// Logs in with a proof from `key`, returning the bound token
+async fn login_with_dpop(app: &TestApp, key: &DpopKey) -> String {
+    let email = signup(app, false).await;
+    let proof = key.proof("POST", &app.url("/login"), None);
+    let response = app
+        .post_login_with_dpop(
+            &serde_json::json!({ "email": email, "password": PASSWORD }),
+            &proof,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+
+    let body = response
+        .json::<TokenResponse>()
+        .await
+        .expect("Failed to parse JSON response");
+    assert_eq!(body.token_type, "DPoP");
+    body.token
+}
+
+#[tokio::test]
+async fn login_with_proof_returns_bound_token() {
+    let app = TestApp::new().await;
+    let key = DpopKey::generate();
+
+    let token = login_with_dpop(&app, &key).await;
+
+    let claims = get_token_claims(&token);
+    assert_eq!(claims.cnf.expect("Token is not bound").jkt, key.jkt());
+}
+
+#[tokio::test]
+async fn login_rejects_invalid_proof() {
+    let app = TestApp::new().await;
+    let email = signup(&app, false).await;
+    let key = DpopKey::generate();
+
+    for proof in [
+        key.proof("GET", &app.url("/login"), None),
+        key.proof("POST", &app.url("/logout"), None),
+        "not.a.proof".to_owned(),
+    ] {
+        let response = app
+            .post_login_with_dpop(
+                &serde_json::json!({ "email": email, "password": PASSWORD }),
+                &proof,
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn login_rejects_replayed_proof() {
+    let app = TestApp::new().await;
+    let email = signup(&app, false).await;
+    let proof = DpopKey::generate().proof("POST", &app.url("/login"), None);
+    let body = serde_json::json!({ "email": email, "password": PASSWORD });
+
+    let response = app.post_login_with_dpop(&body, &proof).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_login_with_dpop(&body, &proof).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn verify_2fa_with_proof_returns_bound_token() {
+    let app = TestApp::new().await;
+    let email = signup(&app, true).await;
+
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Failed to parse JSON response")
+        .login_attempt_id;
+    let (_, code) = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&Email::new(email.clone().into()).unwrap())
+        .await
+        .unwrap();
+
+    let key = DpopKey::generate();
+    let response = app
+        .post_verify_2fa_with_dpop(
+            &serde_json::json!({
+                "email": email,
+                "loginAttemptId": login_attempt_id,
+                "2FACode": code.as_ref()
+            }),
+            &key.proof("POST", &app.url("/verify-2fa"), None),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = response
+        .json::<TokenResponse>()
+        .await
+        .expect("Failed to parse JSON response");
+    assert_eq!(body.token_type, "DPoP");
+    let claims = get_token_claims(&body.token);
+    assert_eq!(claims.sub, email);
+    assert_eq!(claims.cnf.expect("Token is not bound").jkt, key.jkt());
+}
+
+#[tokio::test]
+async fn verify_token_accepts_bound_token_with_proof() {
+    let app = TestApp::new().await;
+    let key = DpopKey::generate();
+    let token = login_with_dpop(&app, &key).await;
+
+    let proof = key.proof("POST", &app.url("/verify-token"), Some(&token));
+    let response = app.post_verify_token_with_dpop(&token, &proof).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Resource servers forward the proof of the request they received
+    let url = "https://api.example.com/orders";
+    let response = app
+        .post_verify_token(&serde_json::json!({
+            "token": token,
+            "dpop": {
+                "proof": key.proof("GET", url, Some(&token)),
+                "method": "GET",
+                "url": url
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn verify_token_rejects_bound_token_without_valid_proof() {
+    let app = TestApp::new().await;
+    let key = DpopKey::generate();
+    let token = login_with_dpop(&app, &key).await;
+    let url = app.url("/verify-token");
+
+    // A stolen token is useless without the key
+    let response = app.post_verify_token_with_bearer(&token).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    for proof in [
+        DpopKey::generate().proof("POST", &url, Some(&token)),
+        key.proof("GET", &url, Some(&token)),
+        key.proof("POST", &app.url("/logout"), Some(&token)),
+        key.proof("POST", &url, None),
+        key.proof("POST", &url, Some("another.access.token")),
+    ] {
+        let response = app.post_verify_token_with_dpop(&token, &proof).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+}
+
+#[tokio::test]
+async fn verify_token_rejects_replayed_proof() {
+    let app = TestApp::new().await;
+    let key = DpopKey::generate();
+    let token = login_with_dpop(&app, &key).await;
+    let proof = key.proof("POST", &app.url("/verify-token"), Some(&token));
+
+    let response = app.post_verify_token_with_dpop(&token, &proof).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_verify_token_with_dpop(&token, &proof).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -1,6 +1,22 @@
+use std::time::Duration;
+
 use auth_service::{
-    routes::TwoFactorAuthResponse, services::TwoFACodeStore, utils::constants::JWT_COOKIE_NAME,
+    domain::models::Email,
+    routes::{TokenResponse, TwoFactorAuthResponse},
+    services::{
+        email_outbox::OutboxStatus,
+        password_hashing::{PasswordHashParams, PasswordPepper, PasswordPeppers},
+        TwoFACodeStore, UserStore,
+    },
+    utils::{
+        auth::TOKEN_TTL_SECONDS,
+        constants::JWT_COOKIE_NAME,
+        cookies::CookiePolicy,
+        user_import::{import_users, ImportSummary},
+    },
 };
+use axum_extra::extract::cookie::SameSite;
+use secrecy::ExposeSecret;
 
 use crate::helpers::TestApp;
 
@@ -55,7 +71,7 @@ async fn should_return_200_if_valid_credentials_and_2fa_disabled() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "correct-Horse-battery-st4ple",
         "requires2FA": false
     });
 
@@ -65,7 +81,7 @@ async fn should_return_200_if_valid_credentials_and_2fa_disabled() {
 
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "correct-Horse-battery-st4ple",
     });
 
     let response = app.post_login(&login_body).await;
@@ -87,7 +103,7 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
     let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "correct-Horse-battery-st4ple",
         "requires2FA": true
     });
     let response = app.post_signup(&signup_body).await;
@@ -95,7 +111,7 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
 
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "correct-Horse-battery-st4ple",
     });
     let response = app.post_login(&login_body).await;
     assert_eq!(response.status().as_u16(), 206);
@@ -109,10 +125,354 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
 
     let store = app.two_fa_code_store.read().await;
     let result = store
-        .get_code(&auth_service::domain::models::Email::new(random_email).unwrap())
+        .get_code(&auth_service::domain::models::Email::new(random_email.into()).unwrap())
         .await;
     assert!(result.is_ok());
 
     let (stored_login_attempt_id, _) = result.unwrap();
     assert_eq!(stored_login_attempt_id.as_ref(), login_attempt_id.as_str());
 }
+
+#[tokio::test]
+async fn should_send_2fa_code_through_email_outbox() {
+    let app = TestApp::new().await;
+
+    let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let response = response.json::<TwoFactorAuthResponse>().await.unwrap();
+
+    // The code is enqueued with the login attempt and delivered by the worker afterwards
+    app.wait_for_email(
+        &format!("two-fa-code:{}", response.login_attempt_id),
+        OutboxStatus::Sent,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn should_upgrade_password_hash_parameters_after_login() {
+    let app = TestApp::new().await;
+
+    let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    // Raise the configured cost after the user signed up with the old one
+    let target = PasswordHashParams {
+        memory_kib: 19456,
+        iterations: 2,
+        parallelism: 1,
+    };
+    {
+        let mut user_store = app.user_store.write().await;
+        *user_store = user_store.clone().with_hash_params(target);
+    }
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The rehash runs in the background, so poll until it lands
+    let email = Email::new(random_email.into()).unwrap();
+    let mut upgraded = false;
+    for _ in 0..50 {
+        let user = app.user_store.read().await.get(&email).await.unwrap();
+        if user
+            .password
+            .as_ref()
+            .expose_secret()
+            .contains("m=19456,t=2,p=1")
+        {
+            upgraded = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(upgraded, "password hash was not upgraded");
+
+    // The upgraded hash still verifies
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_rehash_imported_bcrypt_password_to_argon2id() {
+    let app = TestApp::new().await;
+
+    let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
+    let email = Email::new(random_email.clone().into()).unwrap();
+    let legacy_hash = bcrypt::hash("correct-Horse-battery-st4ple", 4).unwrap();
+    app.user_store
+        .read()
+        .await
+        .import_user(&email, &legacy_hash.into(), false)
+        .await
+        .unwrap();
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let mut upgraded = false;
+    for _ in 0..50 {
+        let user = app.user_store.read().await.get(&email).await.unwrap();
+        if user
+            .password
+            .as_ref()
+            .expose_secret()
+            .starts_with("$argon2id$")
+        {
+            upgraded = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(upgraded, "bcrypt hash was not upgraded to Argon2id");
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_401_for_wrong_password_on_imported_user() {
+    let app = TestApp::new().await;
+
+    let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
+    let email = Email::new(random_email.clone().into()).unwrap();
+    let legacy_hash = bcrypt::hash("correct-Horse-battery-st4ple", 4).unwrap();
+    app.user_store
+        .read()
+        .await
+        .import_user(&email, &legacy_hash.into(), false)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "wrong-Horse-battery-st4ple",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_log_in_users_imported_from_legacy_export() {
+    let app = TestApp::new().await;
+
+    let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
+    let legacy_hash = bcrypt::hash("correct-Horse-battery-st4ple", 4).unwrap();
+    let export = [
+        serde_json::json!({ "email": random_email, "passwordHash": legacy_hash }).to_string(),
+        String::new(),
+        serde_json::json!({ "email": random_email, "passwordHash": legacy_hash }).to_string(),
+        serde_json::json!({ "email": "other@example.com", "passwordHash": "md5:5f4dcc3b" })
+            .to_string(),
+    ]
+    .join("\n");
+
+    let summary = import_users(&*app.user_store.read().await, export.as_bytes())
+        .await
+        .unwrap();
+    assert_eq!(
+        summary,
+        ImportSummary {
+            imported: 1,
+            already_existing: 1,
+            rejected: 1,
+        }
+    );
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "correct-Horse-battery-st4ple",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_migrate_password_hash_to_rotated_pepper_after_login() {
+    let app = TestApp::new().await;
+    let first_pepper = PasswordPepper::new(1, "first-pepper-secret".to_owned().into());
+    let second_pepper = PasswordPepper::new(2, "second-pepper-secret".to_owned().into());
+    {
+        let mut user_store = app.user_store.write().await;
+        *user_store = user_store
+            .clone()
+            .with_peppers(PasswordPeppers::new(Some(first_pepper.clone()), vec![]));
+    }
+
+    let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
+    let email = Email::new(random_email.clone().into()).unwrap();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(
+        app.user_store
+            .read()
+            .await
+            .password_pepper_version(&email)
+            .await
+            .unwrap(),
+        Some(1)
+    );
+
+    // Rotate: the new pepper becomes current, the old one is kept for verification only
+    {
+        let mut user_store = app.user_store.write().await;
+        *user_store = user_store.clone().with_peppers(PasswordPeppers::new(
+            Some(second_pepper.clone()),
+            vec![first_pepper],
+        ));
+    }
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let mut migrated = false;
+    for _ in 0..50 {
+        let pepper_version = app
+            .user_store
+            .read()
+            .await
+            .password_pepper_version(&email)
+            .await
+            .unwrap();
+        if pepper_version == Some(2) {
+            migrated = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(migrated, "password hash was not migrated to the new pepper");
+
+    // Once migrated, the old pepper is no longer needed
+    {
+        let mut user_store = app.user_store.write().await;
+        *user_store = user_store
+            .clone()
+            .with_peppers(PasswordPeppers::new(Some(second_pepper), vec![]));
+    }
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_set_cookies_with_configured_attributes() {
+    let cookie_policy = CookiePolicy::new(true, None, true, SameSite::Strict).unwrap();
+    let app = TestApp::with_cookie_policy(cookie_policy).await;
+
+    let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "correct-Horse-battery-st4ple",
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let cookies = response.cookies().collect::<Vec<_>>();
+    let auth_cookie = cookies
+        .iter()
+        .find(|cookie| cookie.name() == "__Host-jwt")
+        .expect("No auth cookie found");
+    assert!(auth_cookie.secure());
+    assert!(auth_cookie.http_only());
+    assert!(auth_cookie.same_site_strict());
+    assert_eq!(auth_cookie.path(), Some("/"));
+    assert_eq!(auth_cookie.domain(), None);
+    assert_eq!(
+        auth_cookie.max_age(),
+        Some(Duration::from_secs(TOKEN_TTL_SECONDS))
+    );
+
+    let csrf_cookie = cookies
+        .iter()
+        .find(|cookie| cookie.name() == "__Host-csrf_token")
+        .expect("No CSRF cookie found");
+    assert!(csrf_cookie.secure());
+    assert!(!csrf_cookie.http_only());
+}
+
+#[tokio::test]
+async fn should_return_token_in_body_if_requested() {
+    let app = TestApp::new().await;
+    let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "correct-Horse-battery-st4ple",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "correct-Horse-battery-st4ple",
+            "includeToken": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The cookie is still set for browsers
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    let body = response
+        .json::<TokenResponse>()
+        .await
+        .expect("Failed to parse JSON response");
+    assert_eq!(body.token, auth_cookie);
+    assert_eq!(body.token_type, "Bearer");
+    assert_eq!(body.expires_in, TOKEN_TTL_SECONDS);
+
+    let response = app.post_verify_token_with_bearer(&body.token).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use auth_service::{
+    domain::mock_email_client::MockEmail,
+    routes::{LoginReportResponse, TokenResponse},
+    ErrorResponse,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Url,
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "correct-Horse-battery-st4ple";
+const NEW_PASSWORD: &str = "Tr0ubadour-and-a-h0rse";
+
+async fn signup(app: &TestApp) -> String {
+    let email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    email
+}
+
+// A browser of its own, with its own cookies, in `country` if the proxy reports one
+fn browser(user_agent: &str, country: Option<&'static str>) -> reqwest::Client {
+    let mut headers = HeaderMap::new();
+    if let Some(country) = country {
+        headers.insert("cf-ipcountry", HeaderValue::from_static(country));
+    }
+    reqwest::Client::builder()
+        .cookie_provider(Arc::new(reqwest::cookie::Jar::default()))
+        .user_agent(user_agent)
+        .default_headers(headers)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+// Logs in from `browser` and returns the token of the session
+async fn login_from(app: &TestApp, browser: &reqwest::Client, email: &str) -> String {
+    let response = app
+        .post_login_from(
+            browser,
+            &serde_json::json!({ "email": email, "password": PASSWORD, "includeToken": true }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json::<TokenResponse>().await.unwrap().token
+}
+
+// Reads the "this wasn't me" link out of the alert the way a user would follow it
+fn report_token(alert: &MockEmail) -> String {
+    let link = alert
+        .text
+        .split_whitespace()
+        .find(|word| word.contains("reportToken="))
+        .expect("No report link in alert");
+    let url = Url::parse(link).unwrap();
+    let (_, token) = url
+        .query_pairs()
+        .find(|(key, _)| key == "reportToken")
+        .unwrap();
+    token.into_owned()
+}
+
+#[tokio::test]
+async fn should_only_alert_on_logins_from_new_devices() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    let laptop = browser("Firefox", None);
+
+    // The first login, and any later one from the same browser, send nothing
+    login_from(&app, &laptop, &email).await;
+    login_from(&app, &laptop, &email).await;
+
+    login_from(&app, &browser("Safari", None), &email).await;
+    let alert = app.wait_for_emails_to(&email, 1).await;
+    assert_eq!(alert.subject, "New sign-in to your Auth Service account");
+    assert!(alert.text.contains("Browser: Safari"));
+    assert!(alert.text.contains("IP address: 127.0.0.1"));
+
+    login_from(&app, &laptop, &email).await;
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(app.inbox.messages_to(&email).len(), 1);
+}
+
+#[tokio::test]
+async fn should_alert_on_logins_from_new_countries() {
+    let app = TestApp::with_login_country_header("cf-ipcountry").await;
+    let email = signup(&app).await;
+
+    login_from(&app, &browser("Firefox", Some("DE")), &email).await;
+    login_from(&app, &browser("Firefox", Some("FR")), &email).await;
+
+    let alert = app.wait_for_emails_to(&email, 1).await;
+    assert_eq!(
+        alert.subject,
+        "Sign-in to your Auth Service account from a new country"
+    );
+    assert!(alert.text.contains("Country: FR"));
+}
+
+#[tokio::test]
+async fn should_end_session_and_replace_password_when_login_is_reported() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    login_from(&app, &browser("Firefox", None), &email).await;
+    let intruder = browser("curl", None);
+    let session = login_from(&app, &intruder, &email).await;
+    let token = report_token(&app.wait_for_emails_to(&email, 1).await);
+
+    let response = app
+        .post_login_report(&serde_json::json!({ "token": token, "newPassword": NEW_PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json::<LoginReportResponse>().await.unwrap();
+
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": session }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_login_from(
+            &intruder,
+            &serde_json::json!({ "email": email, "password": PASSWORD }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": NEW_PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The link only works once
+    let response = app
+        .post_login_report(&serde_json::json!({ "token": token, "newPassword": NEW_PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_return_400_for_weak_password_without_using_link() {
+    let app = TestApp::new().await;
+    let email = signup(&app).await;
+    login_from(&app, &browser("Firefox", None), &email).await;
+    login_from(&app, &browser("curl", None), &email).await;
+    let token = report_token(&app.wait_for_emails_to(&email, 1).await);
+
+    let response = app
+        .post_login_report(&serde_json::json!({ "token": token, "newPassword": "password" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_login_report(&serde_json::json!({ "token": token, "newPassword": NEW_PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_401_for_invalid_token() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_login_report(
+            &serde_json::json!({ "token": "not.a.token", "newPassword": NEW_PASSWORD }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.json::<ErrorResponse>().await.unwrap().error,
+        "Invalid or expired link"
+    );
+}
@@ -1,9 +1,16 @@
 use auth_service::domain::models::Email;
 use auth_service::services::BannedTokenStore;
-use auth_service::utils::{auth::generate_auth_cookie, constants::JWT_COOKIE_NAME};
-use reqwest::Url;
+use auth_service::utils::{
+    auth::generate_auth_cookie,
+    constants::{CSRF_COOKIE_NAME, CSRF_HEADER_NAME, JWT_COOKIE_NAME},
+    cookies::CookiePolicy,
+    csrf::generate_csrf_cookie,
+};
+use axum_extra::extract::cookie::SameSite;
+use redis::AsyncCommands;
+use reqwest::{header::COOKIE, Url};
 
-use crate::helpers::TestApp;
+use crate::helpers::{configure_redis, get_random_email, get_token_claims, TestApp};
 
 #[tokio::test]
 async fn should_return_400_if_jwt_cookie_missing() {
@@ -25,6 +32,12 @@ async fn should_return_401_if_invalid_token() {
         ),
         &Url::parse("http://127.0.0.1").expect("Failed to parse URL"),
     );
+    app.cookie_jar.add_cookie_str(
+        &generate_csrf_cookie("invalid", &CookiePolicy::default())
+            .unwrap()
+            .to_string(),
+        &Url::parse("http://127.0.0.1").expect("Failed to parse URL"),
+    );
     let response = app.post_logout().await;
 
     assert_eq!(response.status(), 401);
@@ -37,7 +50,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "correct-Horse-battery-st4ple",
         "requires2FA": false
     });
 
@@ -47,7 +60,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
 
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "correct-Horse-battery-st4ple",
     });
 
     let response = app.post_login(&login_body).await;
@@ -64,11 +77,10 @@ async fn should_return_200_if_valid_jwt_cookie() {
     let response = app.post_logout().await;
     let banned_token_store = app.banned_token_store.read().await;
 
-    assert!(
-        banned_token_store
-            .is_token_banned(auth_cookie.value())
-            .await
-    );
+    assert!(banned_token_store
+        .is_token_banned(&get_token_claims(auth_cookie.value()).jti)
+        .await
+        .unwrap());
 
     assert_eq!(response.status(), 200);
 }
@@ -77,13 +89,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
 async fn should_return_400_if_logout_called_twice_in_a_row() {
     let app = TestApp::new().await;
 
-    // add valid cookie
-    app.cookie_jar.add_cookie_str(
-        &generate_auth_cookie(&Email::new("email@example.com".to_string()).unwrap())
-            .unwrap()
-            .to_string(),
-        &Url::parse("http://127.0.0.1").expect("Failed to parse URL"),
-    );
+    add_valid_session_cookies(&app, true);
     let response = app.post_logout().await;
 
     assert_eq!(response.status(), 200);
@@ -92,3 +98,184 @@ async fn should_return_400_if_logout_called_twice_in_a_row() {
 
     assert_eq!(response.status(), 400);
 }
+
+#[tokio::test]
+async fn should_return_403_if_csrf_token_missing() {
+    let app = TestApp::new().await;
+    add_valid_session_cookies(&app, false);
+
+    let response = app
+        .http_client
+        .post(format!("{}/logout", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), 403);
+}
+
+#[tokio::test]
+async fn should_return_403_if_csrf_token_belongs_to_other_session() {
+    let app = TestApp::new().await;
+    add_valid_session_cookies(&app, false);
+
+    let other_session = generate_auth_cookie(
+        &Email::new("other@example.com".into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap();
+    let other_csrf_cookie =
+        generate_csrf_cookie(other_session.value(), &CookiePolicy::default()).unwrap();
+
+    let response = app
+        .http_client
+        .post(format!("{}/logout", &app.address))
+        .header(CSRF_HEADER_NAME, other_csrf_cookie.value())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), 403);
+}
+
+#[tokio::test]
+async fn should_not_require_csrf_token_from_bearer_clients() {
+    let app = TestApp::new().await;
+    add_valid_session_cookies(&app, false);
+
+    // A cross-site form cannot set this header, and CORS does not let other origins send it
+    let response = app
+        .http_client
+        .post(format!("{}/logout", &app.address))
+        .bearer_auth("api-client-token")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_ne!(response.status(), 403);
+}
+
+#[tokio::test]
+async fn should_clear_cookies_with_matching_attributes() {
+    let cookie_policy = CookiePolicy::new(
+        true,
+        Some("example.com".to_owned()),
+        false,
+        SameSite::Strict,
+    )
+    .unwrap();
+    let app = TestApp::with_cookie_policy(cookie_policy.clone()).await;
+
+    let auth_cookie = generate_auth_cookie(
+        &Email::new("email@example.com".into()).unwrap(),
+        &cookie_policy,
+    )
+    .unwrap();
+    let csrf_cookie = generate_csrf_cookie(auth_cookie.value(), &cookie_policy).unwrap();
+
+    // Secure cookies for another domain are not sent from the jar, so attach them by hand
+    let response = reqwest::Client::new()
+        .post(format!("{}/logout", &app.address))
+        .header(
+            COOKIE,
+            format!(
+                "{}={}; {}={}",
+                JWT_COOKIE_NAME,
+                auth_cookie.value(),
+                CSRF_COOKIE_NAME,
+                csrf_cookie.value()
+            ),
+        )
+        .header(CSRF_HEADER_NAME, csrf_cookie.value())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), 200);
+
+    let cookies = response.cookies().collect::<Vec<_>>();
+    for name in [JWT_COOKIE_NAME, CSRF_COOKIE_NAME] {
+        let cookie = cookies
+            .iter()
+            .find(|cookie| cookie.name() == name)
+            .unwrap_or_else(|| panic!("{} was not cleared", name));
+        assert_eq!(cookie.value(), "");
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.domain(), Some("example.com"));
+        assert!(cookie.secure());
+        assert_eq!(cookie.max_age(), Some(std::time::Duration::ZERO));
+    }
+}
+
+#[tokio::test]
+async fn should_ban_bearer_token_without_csrf_token() {
+    let app = TestApp::new().await;
+    let token = generate_auth_cookie(
+        &Email::new(get_random_email().into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap()
+    .value()
+    .to_owned();
+
+    let response = app.post_logout_with_bearer(&token).await;
+    assert_eq!(response.status(), 200);
+    assert!(app
+        .banned_token_store
+        .read()
+        .await
+        .is_token_banned(&get_token_claims(&token).jti)
+        .await
+        .unwrap());
+
+    let response = app.post_logout_with_bearer(&token).await;
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn should_ban_jti_until_token_expires() {
+    let app = TestApp::new().await;
+    let token = generate_auth_cookie(
+        &Email::new(get_random_email().into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap()
+    .value()
+    .to_owned();
+
+    let response = app.post_logout_with_bearer(&token).await;
+    assert_eq!(response.status(), 200);
+
+    let claims = get_token_claims(&token);
+    let mut redis_connection = configure_redis().await;
+    let ttl: i64 = redis_connection
+        .ttl(format!("banned_token:{}", claims.jti))
+        .await
+        .unwrap();
+    let remaining_lifetime = claims.remaining_lifetime().as_secs() as i64;
+    assert!(ttl > 0 && ttl <= remaining_lifetime + 1);
+    let full_token_banned: bool = redis_connection
+        .exists(format!("banned_token:{}", token))
+        .await
+        .unwrap();
+    assert!(!full_token_banned);
+}
+
+// Adds an auth cookie for a made up user, and optionally its CSRF cookie
+fn add_valid_session_cookies(app: &TestApp, with_csrf_cookie: bool) {
+    let url = Url::parse("http://127.0.0.1").expect("Failed to parse URL");
+    let auth_cookie = generate_auth_cookie(
+        &Email::new("email@example.com".into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap();
+    app.cookie_jar
+        .add_cookie_str(&auth_cookie.to_string(), &url);
+    if with_csrf_cookie {
+        app.cookie_jar.add_cookie_str(
+            &generate_csrf_cookie(auth_cookie.value(), &CookiePolicy::default())
+                .unwrap()
+                .to_string(),
+            &url,
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use auth_service::{
+    domain::models::Email,
+    routes::{MagicLinkResponse, TwoFactorAuthResponse},
+    services::TwoFACodeStore,
+    utils::{
+        constants::{JWT_COOKIE_NAME, MAGIC_LINK_NONCE_COOKIE_NAME},
+        magic_link::MagicLinks,
+    },
+};
+use reqwest::Url;
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn signup(app: &TestApp, requires_2fa: bool) -> String {
+    let email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "correct-Horse-battery-st4ple",
+            "requires2FA": requires_2fa
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    email
+}
+
+// Requests a link for `email` and returns the one that was emailed, pointed at the test app. The
+// mock email client keeps no messages, so the link is signed again for the browser's nonce.
+async fn request_link(app: &TestApp, email: &str) -> String {
+    let response = app
+        .post_magic_link(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<MagicLinkResponse>()
+        .await
+        .expect("Failed to parse JSON response");
+
+    let nonce = app
+        .get_cookie(MAGIC_LINK_NONCE_COOKIE_NAME)
+        .expect("No nonce cookie was set");
+    let link = MagicLinks::default()
+        .issue(&Email::new(email.to_owned().into()).unwrap(), &nonce)
+        .unwrap();
+    let token = Url::parse(&link)
+        .unwrap()
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, token)| token.into_owned())
+        .unwrap();
+    app.url(&format!("/login/magic-link/consume?token={}", token))
+}
+
+#[tokio::test]
+async fn link_sets_auth_cookie_once() {
+    let app = TestApp::new().await;
+    let email = signup(&app, false).await;
+    let link = request_link(&app, &email).await;
+
+    let response = app.get_magic_link(&link).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    assert!(!auth_cookie.value().is_empty());
+
+    let response = app.get_magic_link(&link).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn link_requires_requesting_browser() {
+    let app = TestApp::new().await;
+    let email = signup(&app, false).await;
+    let link = request_link(&app, &email).await;
+
+    // Opened in another browser, without the nonce cookie
+    let response = reqwest::get(&link)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+
+    // That did not use the link up
+    let response = app.get_magic_link(&link).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn link_continues_with_2fa() {
+    let app = TestApp::new().await;
+    let email = signup(&app, true).await;
+    let link = request_link(&app, &email).await;
+
+    let response = app.get_magic_link(&link).await;
+    assert_eq!(response.status().as_u16(), 206);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Failed to parse JSON response")
+        .login_attempt_id;
+    let (stored_attempt, _) = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&Email::new(email.into()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(stored_attempt.as_ref(), login_attempt_id);
+}
+
+#[tokio::test]
+async fn request_does_not_reveal_unknown_accounts() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_magic_link(&serde_json::json!({ "email": get_random_email() }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(app.get_cookie(MAGIC_LINK_NONCE_COOKIE_NAME).is_some());
+
+    let response = app
+        .post_magic_link(&serde_json::json!({ "email": "not-an-email" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn rejects_tampered_link() {
+    let app = TestApp::new().await;
+    let email = signup(&app, false).await;
+    let link = request_link(&app, &email).await;
+
+    let response = app.get_magic_link(&format!("{}x", link)).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
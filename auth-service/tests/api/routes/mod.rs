@@ -1,14 +1,17 @@
-// mod login;
-// mod logout;
+mod csrf_token;
+mod dev_mailbox;
+mod dpop;
+mod login;
+mod login_report;
+mod logout;
+mod magic_link;
+mod oidc;
+mod passkeys;
+mod phone_number;
+mod rate_limit;
 mod root;
+mod saml;
+mod security_headers;
 mod signup;
-// mod verify_2fa;
-// mod verify_token;
-
-// Note: Re-exports are available if needed by specific tests
-// pub use login::*;
-// pub use logout::*;
-// pub use root::*;
-// pub use signup::*;
-// pub use verify_2fa::*;
-// pub use verify_token::*;
+mod verify_2fa;
+mod verify_token;
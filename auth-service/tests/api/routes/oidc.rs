@@ -0,0 +1,215 @@
+use auth_service::utils::constants::{JWT_COOKIE_NAME, OIDC_STATE_COOKIE_NAME};
+use reqwest::Url;
+
+use crate::helpers::{get_random_email, MockIdp, TestApp};
+
+const PROVIDER: &str = "mockidp";
+
+async fn signup(app: &TestApp, email: &str, requires_2fa: bool) {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "correct-Horse-battery-st4ple",
+            "requires2FA": requires_2fa
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+// Starts a login at the provider and returns the state and nonce it was sent off with
+async fn start_login(app: &TestApp) -> (String, String) {
+    let response = app.get_oidc_login(PROVIDER).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("location")
+        .expect("No redirect to the provider")
+        .to_str()
+        .unwrap();
+    let url = Url::parse(location).unwrap();
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_else(|| panic!("No {} in the authorization URL", name))
+    };
+    (param("state"), param("nonce"))
+}
+
+// Logs in at the provider as `subject`, and comes back to the test app with the code
+async fn login_as(
+    app: &TestApp,
+    idp: &MockIdp,
+    subject: &str,
+    email: &str,
+    verified: bool,
+) -> reqwest::Response {
+    let (state, nonce) = start_login(app).await;
+    idp.issue_id_token(idp.id_token(&idp.claims(&nonce, subject, email, verified)))
+        .await;
+    app.get_oidc_callback(PROVIDER, &[("state", &state), ("code", "mock-code")])
+        .await
+}
+
+fn auth_cookie(response: &reqwest::Response) -> Option<String> {
+    response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .map(|cookie| cookie.value().to_owned())
+}
+
+#[tokio::test]
+async fn login_redirects_to_provider() {
+    let idp = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+
+    let response = app.get_oidc_login(PROVIDER).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let url = Url::parse(location).unwrap();
+    assert_eq!(url.path(), "/authorize");
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    assert_eq!(param("client_id").as_deref(), Some(MockIdp::CLIENT_ID));
+    assert_eq!(param("response_type").as_deref(), Some("code"));
+    assert_eq!(param("code_challenge_method").as_deref(), Some("S256"));
+    assert!(param("nonce").is_some());
+    assert!(param("redirect_uri")
+        .unwrap()
+        .ends_with(&format!("/oidc/{}/callback", PROVIDER)));
+    assert_eq!(app.get_cookie(OIDC_STATE_COOKIE_NAME), param("state"));
+}
+
+#[tokio::test]
+async fn callback_links_verified_email_and_matches_subject_after() {
+    let idp = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let response = login_as(&app, &idp, "subject-1", &email, true).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(auth_cookie(&response).is_some_and(|cookie| !cookie.is_empty()));
+    assert!(app.get_cookie(OIDC_STATE_COOKIE_NAME).is_none());
+
+    // Once linked, the account is found by its subject, whatever email the provider now has
+    let response = login_as(&app, &idp, "subject-1", &get_random_email(), false).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(auth_cookie(&response).is_some());
+}
+
+#[tokio::test]
+async fn callback_continues_with_2fa() {
+    let idp = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+    let email = get_random_email();
+    signup(&app, &email, true).await;
+
+    let response = login_as(&app, &idp, "subject-1", &email, true).await;
+    assert_eq!(response.status().as_u16(), 206);
+    assert!(auth_cookie(&response).is_none());
+}
+
+#[tokio::test]
+async fn callback_does_not_link_unverified_email() {
+    let idp = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let response = login_as(&app, &idp, "subject-1", &email, false).await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(auth_cookie(&response).is_none());
+}
+
+#[tokio::test]
+async fn callback_rejects_unknown_account() {
+    let idp = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+
+    let response = login_as(&app, &idp, "subject-1", &get_random_email(), true).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn callback_rejects_nonce_of_another_login() {
+    let idp = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let (state, _) = start_login(&app).await;
+    let claims = idp.claims("some-other-nonce", "subject-1", &email, true);
+    idp.issue_id_token(idp.id_token(&claims)).await;
+    let response = app
+        .get_oidc_callback(PROVIDER, &[("state", &state), ("code", "mock-code")])
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn callback_rejects_token_signed_by_another_key() {
+    let idp = MockIdp::start().await;
+    let impostor = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let (state, nonce) = start_login(&app).await;
+    let mut claims = idp.claims(&nonce, "subject-1", &email, true);
+    claims["iss"] = idp.server.uri().into();
+    idp.issue_id_token(impostor.id_token(&claims)).await;
+    let response = app
+        .get_oidc_callback(PROVIDER, &[("state", &state), ("code", "mock-code")])
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn callback_requires_browser_that_started_login() {
+    let idp = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let (state, nonce) = start_login(&app).await;
+    idp.issue_id_token(idp.id_token(&idp.claims(&nonce, "subject-1", &email, true)))
+        .await;
+
+    // Another browser, without the state cookie
+    let mut url = Url::parse(&app.url(&format!("/oidc/{}/callback", PROVIDER))).unwrap();
+    url.query_pairs_mut()
+        .append_pair("state", &state)
+        .append_pair("code", "mock-code");
+    let response = reqwest::get(url).await.expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(auth_cookie(&response).is_none());
+}
+
+#[tokio::test]
+async fn callback_rejects_provider_error() {
+    let idp = MockIdp::start().await;
+    let app = TestApp::with_oidc_provider(idp.provider(PROVIDER)).await;
+
+    let (state, _) = start_login(&app).await;
+    let response = app
+        .get_oidc_callback(PROVIDER, &[("state", &state), ("error", "access_denied")])
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unknown_provider_is_not_found() {
+    let app = TestApp::new().await;
+
+    let response = app.get_oidc_login("nosuchidp").await;
+    assert_eq!(response.status().as_u16(), 404);
+}
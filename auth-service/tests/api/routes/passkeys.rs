@@ -0,0 +1,226 @@
+use auth_service::{
+    domain::models::Email,
+    routes::{TokenResponse, TwoFactorAuthResponse},
+    services::TwoFACodeStore,
+    utils::constants::JWT_COOKIE_NAME,
+};
+
+use crate::helpers::{get_random_email, get_token_claims, SoftwareAuthenticator, TestApp};
+
+const PASSWORD: &str = "correct-Horse-battery-st4ple";
+
+async fn signup(app: &TestApp, requires_2fa: bool) -> String {
+    let email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "requires2FA": requires_2fa
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    email
+}
+
+async fn token_from(response: reqwest::Response) -> String {
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<TokenResponse>()
+        .await
+        .expect("Failed to parse JSON response")
+        .token
+}
+
+// Returns the login attempt of a user who requires 2FA and got past their password
+async fn start_2fa_login(app: &TestApp, email: &str) -> String {
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Failed to parse JSON response")
+        .login_attempt_id
+}
+
+async fn register_passkey(app: &TestApp, token: &str, authenticator: &SoftwareAuthenticator) {
+    let response = app
+        .post_passkeys_register("start", &serde_json::json!({}), token)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let options = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse JSON response");
+
+    let response = app
+        .post_passkeys_register(
+            "finish",
+            &serde_json::json!({
+                "challengeId": options["challengeId"],
+                "credential": authenticator.create(&options)
+            }),
+            token,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+async fn login_with_passkey(
+    app: &TestApp,
+    start: serde_json::Value,
+    authenticator: &mut SoftwareAuthenticator,
+) -> reqwest::Response {
+    let response = app.post_passkeys_login("start", &start).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let options = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse JSON response");
+
+    app.post_passkeys_login(
+        "finish",
+        &serde_json::json!({
+            "challengeId": options["challengeId"],
+            "credential": authenticator.get(&options),
+            "includeToken": true
+        }),
+    )
+    .await
+}
+
+// Signs up a user without 2FA and registers a passkey for them
+async fn signup_with_passkey(app: &TestApp) -> (String, SoftwareAuthenticator) {
+    let email = signup(app, false).await;
+    let token = token_from(
+        app.post_login(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "includeToken": true
+        }))
+        .await,
+    )
+    .await;
+    let authenticator = SoftwareAuthenticator::generate();
+    register_passkey(app, &token, &authenticator).await;
+    (email, authenticator)
+}
+
+#[tokio::test]
+async fn registration_requires_signed_in_user() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_passkeys_register("start", &serde_json::json!({}), "not.a.token")
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn passwordless_login_sets_auth_cookie() {
+    let app = TestApp::new().await;
+    let (email, mut authenticator) = signup_with_passkey(&app).await;
+
+    let response = login_with_passkey(&app, serde_json::json!({}), &mut authenticator).await;
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME && !cookie.value().is_empty()));
+    let token = token_from(response).await;
+    assert_eq!(get_token_claims(&token).sub, email);
+}
+
+#[tokio::test]
+async fn passkey_replaces_2fa_code() {
+    let app = TestApp::new().await;
+    let email = signup(&app, true).await;
+    let parsed_email = Email::new(email.clone().into()).unwrap();
+
+    // A 2FA user signs in with their code once to register a passkey
+    let login_attempt_id = start_2fa_login(&app, &email).await;
+    let (_, code) = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&parsed_email)
+        .await
+        .unwrap();
+    let token = token_from(
+        app.post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code.as_ref(),
+            "includeToken": true
+        }))
+        .await,
+    )
+    .await;
+    let mut authenticator = SoftwareAuthenticator::generate();
+    register_passkey(&app, &token, &authenticator).await;
+
+    let login_attempt_id = start_2fa_login(&app, &email).await;
+    let response = login_with_passkey(
+        &app,
+        serde_json::json!({ "email": email, "loginAttemptId": login_attempt_id }),
+        &mut authenticator,
+    )
+    .await;
+    assert_eq!(get_token_claims(&token_from(response).await).sub, email);
+
+    // The emailed code of the completed login attempt can no longer be used
+    assert!(app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&parsed_email)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn login_start_rejects_unknown_login_attempt() {
+    let app = TestApp::new().await;
+    let email = signup(&app, true).await;
+    start_2fa_login(&app, &email).await;
+
+    let response = app
+        .post_passkeys_login(
+            "start",
+            &serde_json::json!({
+                "email": email,
+                "loginAttemptId": uuid::Uuid::new_v4().to_string()
+            }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_passkeys_login("start", &serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn login_rejects_cloned_authenticator() {
+    let app = TestApp::new().await;
+    let (_, mut authenticator) = signup_with_passkey(&app).await;
+
+    authenticator.sign_count = 10;
+    let response = login_with_passkey(&app, serde_json::json!({}), &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // A clone whose counter is behind the original's
+    authenticator.sign_count = 3;
+    let response = login_with_passkey(&app, serde_json::json!({}), &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn login_rejects_unregistered_passkey() {
+    let app = TestApp::new().await;
+    signup_with_passkey(&app).await;
+
+    let mut authenticator = SoftwareAuthenticator::generate();
+    let response = login_with_passkey(&app, serde_json::json!({}), &mut authenticator).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
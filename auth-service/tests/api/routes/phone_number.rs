@@ -0,0 +1,228 @@
+use auth_service::{
+    domain::{
+        mock_sms_client::MockSms,
+        models::{Email, Password},
+        PhoneNumber, SmsClientBackend, TwoFAChannel, User,
+    },
+    routes::{PhoneNumberResponse, TwoFactorAuthResponse},
+    services::UserStore,
+    utils::{auth::generate_auth_cookie, cookies::CookiePolicy},
+    ErrorResponse,
+};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "correct-Horse-battery-st4ple";
+
+// Adds a user who requires 2FA and returns their email and a token to act as them
+async fn signed_in_user(app: &TestApp) -> (String, String) {
+    let email = get_random_email();
+    app.user_store
+        .write()
+        .await
+        .insert(User::new(
+            Email::new(email.clone().into()).unwrap(),
+            Password::new(PASSWORD.to_owned().into()).unwrap(),
+            true,
+        ))
+        .await
+        .unwrap();
+    let token = generate_auth_cookie(
+        &Email::new(email.clone().into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap()
+    .value()
+    .to_owned();
+    (email, token)
+}
+
+// Reads the code out of a text message or call the way a user would. Calls spell the code out
+// digit by digit, and no other digits come before it.
+fn code_from(sms: &MockSms) -> String {
+    let code: String = sms
+        .message
+        .chars()
+        .filter(char::is_ascii_digit)
+        .take(6)
+        .collect();
+    assert_eq!(code.len(), 6, "No code in message");
+    code
+}
+
+fn last_sms(app: &TestApp) -> MockSms {
+    app.sms.messages().pop().expect("Nothing was sent")
+}
+
+#[tokio::test]
+async fn should_send_code_and_save_number_once_verified() {
+    let app = TestApp::new().await;
+    let (email, token) = signed_in_user(&app).await;
+
+    let response = app
+        .post_phone_number(
+            "",
+            &serde_json::json!({ "phoneNumber": "+49 (0)30 123-456" }),
+            &token,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body = response.json::<PhoneNumberResponse>().await.unwrap();
+    assert_eq!(body.phone_number, "+4930123456");
+    assert_eq!(body.channel, "sms");
+
+    let sms = last_sms(&app);
+    assert_eq!(sms.recipient.as_str(), "+4930123456");
+    assert_eq!(sms.channel, TwoFAChannel::Sms);
+
+    let response = app
+        .post_phone_number(
+            "/verify",
+            &serde_json::json!({ "code": code_from(&sms) }),
+            &token,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let user = app
+        .user_store
+        .read()
+        .await
+        .get(&Email::new(email.into()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        user.phone_number,
+        Some(PhoneNumber::parse("+4930123456").unwrap())
+    );
+    assert_eq!(user.two_fa_channel, TwoFAChannel::Sms);
+}
+
+#[tokio::test]
+async fn should_send_2fa_code_over_chosen_channel() {
+    let app = TestApp::new().await;
+    let (email, token) = signed_in_user(&app).await;
+    app.post_phone_number(
+        "",
+        &serde_json::json!({ "phoneNumber": "+14155552671", "channel": "voice" }),
+        &token,
+    )
+    .await;
+    let code = code_from(&last_sms(&app));
+    app.post_phone_number("/verify", &serde_json::json!({ "code": code }), &token)
+        .await;
+
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .unwrap()
+        .login_attempt_id;
+
+    let call = last_sms(&app);
+    assert_eq!(call.channel, TwoFAChannel::Voice);
+    assert_eq!(app.sms.messages().len(), 2);
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code_from(&call)
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(app.inbox.messages_to(&email).is_empty());
+}
+
+#[tokio::test]
+async fn should_return_401_if_code_is_wrong() {
+    let app = TestApp::new().await;
+    let (email, token) = signed_in_user(&app).await;
+    app.post_phone_number(
+        "",
+        &serde_json::json!({ "phoneNumber": "+4930123456" }),
+        &token,
+    )
+    .await;
+    let code = code_from(&last_sms(&app));
+    let wrong_code = if code == "000000" { "111111" } else { "000000" };
+
+    let response = app
+        .post_phone_number(
+            "/verify",
+            &serde_json::json!({ "code": wrong_code }),
+            &token,
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // A wrong guess uses the code up
+    let response = app
+        .post_phone_number("/verify", &serde_json::json!({ "code": code }), &token)
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let user = app
+        .user_store
+        .read()
+        .await
+        .get(&Email::new(email.into()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(user.phone_number, None);
+}
+
+#[tokio::test]
+async fn should_return_400_if_number_is_invalid() {
+    let app = TestApp::new().await;
+    let (_, token) = signed_in_user(&app).await;
+
+    for phone_number in ["030 123456", "+49 30 12x456", "+123"] {
+        let response = app
+            .post_phone_number(
+                "",
+                &serde_json::json!({ "phoneNumber": phone_number }),
+                &token,
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 400, "{}", phone_number);
+        assert_eq!(
+            response.json::<ErrorResponse>().await.unwrap().error,
+            "Invalid phone number"
+        );
+    }
+    assert!(app.sms.messages().is_empty());
+}
+
+#[tokio::test]
+async fn should_return_401_if_not_signed_in() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_phone_number(
+            "",
+            &serde_json::json!({ "phoneNumber": "+4930123456" }),
+            "not.a.token",
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn should_return_404_if_sms_is_not_configured() {
+    let app = TestApp::with_sms_client(SmsClientBackend::Disabled).await;
+    let (_, token) = signed_in_user(&app).await;
+
+    let response = app
+        .post_phone_number(
+            "",
+            &serde_json::json!({ "phoneNumber": "+4930123456" }),
+            &token,
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
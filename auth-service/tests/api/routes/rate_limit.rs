@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use auth_service::{
+    services::rate_limiting::{RateLimitPolicy, RateLimitStoreBackend, RedisRateLimitStore},
+    utils::rate_limit::{parse_trusted_proxies, RateLimiter},
+};
+
+use crate::helpers::{configure_redis, get_random_email, TestApp};
+
+fn login_body() -> serde_json::Value {
+    serde_json::json!({
+        "email": get_random_email(),
+        "password": "correct-Horse-battery-st4ple",
+    })
+}
+
+async fn post_login_from(app: &TestApp, client_ip: &str) -> reqwest::Response {
+    app.http_client
+        .post(format!("{}/login", &app.address))
+        .header("X-Forwarded-For", client_ip)
+        .json(&login_body())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn should_return_429_once_route_limit_is_exhausted() {
+    let rate_limiter = RateLimiter::default()
+        .with_policy("/login", RateLimitPolicy::new(2, Duration::from_secs(60)));
+    let app = TestApp::with_rate_limiter(rate_limiter).await;
+
+    for remaining in ["1", "0"] {
+        let response = app.post_login(&login_body()).await;
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(response.headers()["ratelimit-limit"], "2");
+        assert_eq!(response.headers()["ratelimit-remaining"], remaining);
+        assert_eq!(response.headers()["ratelimit-policy"], "2;w=60");
+    }
+
+    let response = app.post_login(&login_body()).await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(response.headers()["ratelimit-remaining"], "0");
+    assert_eq!(response.headers()["ratelimit-reset"], "60");
+    assert_eq!(response.headers()["retry-after"], "30");
+    assert_eq!(
+        response
+            .json::<auth_service::ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Too many requests"
+    );
+
+    // Other routes have their own buckets
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": get_random_email(),
+            "password": "correct-Horse-battery-st4ple",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    assert!(response.headers().contains_key("ratelimit-limit"));
+}
+
+#[tokio::test]
+async fn should_rate_limit_phone_number_routes_by_default() {
+    let app = TestApp::new().await;
+
+    // Each accepted number is texted or called, so even rejected requests use up the limit
+    for route in ["/phone-number", "/phone-number/verify"] {
+        let mut limited = false;
+        for _ in 0..50 {
+            let response = app
+                .http_client
+                .post(app.url(route))
+                .json(&serde_json::json!({}))
+                .send()
+                .await
+                .expect("Failed to execute request.");
+            assert!(response.headers().contains_key("ratelimit-limit"));
+            if response.status().as_u16() == 429 {
+                limited = true;
+                break;
+            }
+        }
+        assert!(limited, "{} was never rate limited", route);
+    }
+}
+
+#[tokio::test]
+async fn should_rate_limit_login_reports_by_default() {
+    let app = TestApp::new().await;
+
+    // Report links set a new password, so guessing them is limited like logging in
+    let mut limited = false;
+    for _ in 0..50 {
+        let response = app.post_login_report(&serde_json::json!({})).await;
+        if response.status().as_u16() == 429 {
+            limited = true;
+            break;
+        }
+    }
+    assert!(limited, "/login/report was never rate limited");
+}
+
+#[tokio::test]
+async fn should_not_rate_limit_routes_without_policy() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": "invalid" }))
+        .await;
+    assert!(!response.headers().contains_key("ratelimit-limit"));
+}
+
+#[tokio::test]
+async fn should_key_by_forwarded_client_ip_behind_trusted_proxy() {
+    let rate_limiter = RateLimiter::default()
+        .with_policy("/login", RateLimitPolicy::new(1, Duration::from_secs(60)))
+        .with_trusted_proxies(parse_trusted_proxies("127.0.0.1").unwrap());
+    let app = TestApp::with_rate_limiter(rate_limiter).await;
+
+    assert_eq!(
+        post_login_from(&app, "198.51.100.1")
+            .await
+            .status()
+            .as_u16(),
+        401
+    );
+    assert_eq!(
+        post_login_from(&app, "198.51.100.1")
+            .await
+            .status()
+            .as_u16(),
+        429
+    );
+    assert_eq!(
+        post_login_from(&app, "198.51.100.2")
+            .await
+            .status()
+            .as_u16(),
+        401
+    );
+}
+
+#[tokio::test]
+async fn should_ignore_forwarded_for_from_untrusted_peer() {
+    let rate_limiter = RateLimiter::default()
+        .with_policy("/login", RateLimitPolicy::new(1, Duration::from_secs(60)))
+        .with_trusted_proxies(vec![]);
+    let app = TestApp::with_rate_limiter(rate_limiter).await;
+
+    assert_eq!(
+        post_login_from(&app, "198.51.100.1")
+            .await
+            .status()
+            .as_u16(),
+        401
+    );
+    // A spoofed header does not buy a fresh bucket
+    assert_eq!(
+        post_login_from(&app, "198.51.100.2")
+            .await
+            .status()
+            .as_u16(),
+        429
+    );
+}
+
+#[tokio::test]
+async fn should_rate_limit_with_redis_store() {
+    let store = RateLimitStoreBackend::Redis(RedisRateLimitStore::new(configure_redis().await));
+    let rate_limiter = RateLimiter::new(store)
+        .with_policy("/login", RateLimitPolicy::new(2, Duration::from_secs(60)))
+        .with_trusted_proxies(parse_trusted_proxies("127.0.0.1").unwrap());
+    let app = TestApp::with_rate_limiter(rate_limiter).await;
+
+    // A random documentation range address keeps the Redis key unique to this test run
+    let client_ip = format!(
+        "2001:db8:{:x}:{:x}::1",
+        rand::random::<u16>(),
+        rand::random::<u16>()
+    );
+    let response = post_login_from(&app, &client_ip).await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["ratelimit-remaining"], "1");
+    assert_eq!(
+        post_login_from(&app, &client_ip).await.status().as_u16(),
+        401
+    );
+
+    let response = post_login_from(&app, &client_ip).await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(response.headers()["retry-after"], "30");
+}
@@ -8,7 +8,10 @@ async fn root_returns_auth_ui() {
     let response = app.get_root().await;
 
     assert_eq!(response.status().as_u16(), 200);
-    assert_eq!(response.headers().get("content-type").unwrap(), "text/html");
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "text/html; charset=utf-8"
+    );
 }
 
 // TODO: Implement tests for all other routes (signup, login, logout, verify-2fa, and verify-token)
@@ -0,0 +1,165 @@
+use auth_service::utils::constants::JWT_COOKIE_NAME;
+
+use crate::helpers::{get_random_email, MockSamlIdp, TestApp};
+
+async fn signup(app: &TestApp, email: &str, requires_2fa: bool) {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "correct-Horse-battery-st4ple",
+            "requires2FA": requires_2fa
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+// Starts a login at the identity provider and returns the ID of the request sent there
+async fn start_login(app: &TestApp) -> String {
+    let response = app.get_saml_login().await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("location")
+        .expect("No redirect to the identity provider")
+        .to_str()
+        .unwrap();
+    assert!(location.starts_with(MockSamlIdp::SSO_URL));
+    MockSamlIdp::request_id(location)
+}
+
+fn auth_cookie(response: &reqwest::Response) -> Option<String> {
+    response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .map(|cookie| cookie.value().to_owned())
+}
+
+#[tokio::test]
+async fn metadata_describes_service_provider() {
+    let idp = MockSamlIdp::new();
+    let app = TestApp::with_saml_idp(idp.config()).await;
+
+    let response = app.get_saml_metadata().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "application/samlmetadata+xml"
+    );
+    let metadata = response.text().await.unwrap();
+    assert!(metadata.contains("AssertionConsumerService"));
+    assert!(metadata.contains("/saml/acs"));
+}
+
+#[tokio::test]
+async fn acs_logs_in_user_of_signed_assertion() {
+    let idp = MockSamlIdp::new();
+    let app = TestApp::with_saml_idp(idp.config()).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let request_id = start_login(&app).await;
+    let response = app.post_saml_acs(&idp.response(&request_id, &email)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(auth_cookie(&response).is_some_and(|cookie| !cookie.is_empty()));
+}
+
+#[tokio::test]
+async fn acs_continues_with_2fa() {
+    let idp = MockSamlIdp::new();
+    let app = TestApp::with_saml_idp(idp.config()).await;
+    let email = get_random_email();
+    signup(&app, &email, true).await;
+
+    let request_id = start_login(&app).await;
+    let response = app.post_saml_acs(&idp.response(&request_id, &email)).await;
+    assert_eq!(response.status().as_u16(), 206);
+    assert!(auth_cookie(&response).is_none());
+}
+
+#[tokio::test]
+async fn acs_rejects_replayed_response() {
+    let idp = MockSamlIdp::new();
+    let app = TestApp::with_saml_idp(idp.config()).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let request_id = start_login(&app).await;
+    let saml_response = idp.response(&request_id, &email);
+    assert_eq!(
+        app.post_saml_acs(&saml_response).await.status().as_u16(),
+        200
+    );
+
+    let response = app.post_saml_acs(&saml_response).await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(auth_cookie(&response).is_none());
+}
+
+#[tokio::test]
+async fn acs_rejects_unsolicited_response() {
+    let idp = MockSamlIdp::new();
+    let app = TestApp::with_saml_idp(idp.config()).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let response = app
+        .post_saml_acs(&idp.response("_unsolicited", &email))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn acs_requires_browser_that_started_login() {
+    let idp = MockSamlIdp::new();
+    let app = TestApp::with_saml_idp(idp.config()).await;
+    let email = get_random_email();
+    signup(&app, &email, false).await;
+
+    let request_id = start_login(&app).await;
+    let saml_response = idp.response(&request_id, &email);
+
+    // Another browser, without the request cookie
+    let response = reqwest::Client::new()
+        .post(app.url("/saml/acs"))
+        .form(&[("SAMLResponse", saml_response.as_str()), ("RelayState", "")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(auth_cookie(&response).is_none());
+
+    // The login is still open in the browser that started it
+    let response = app.post_saml_acs(&saml_response).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn acs_rejects_unknown_user() {
+    let idp = MockSamlIdp::new();
+    let app = TestApp::with_saml_idp(idp.config()).await;
+
+    let request_id = start_login(&app).await;
+    let response = app
+        .post_saml_acs(&idp.response(&request_id, &get_random_email()))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn acs_rejects_malformed_response() {
+    let idp = MockSamlIdp::new();
+    let app = TestApp::with_saml_idp(idp.config()).await;
+    start_login(&app).await;
+
+    let response = app.post_saml_acs("not base64 at all").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn not_found_without_identity_provider() {
+    let app = TestApp::new().await;
+
+    assert_eq!(app.get_saml_metadata().await.status().as_u16(), 404);
+    assert_eq!(app.get_saml_login().await.status().as_u16(), 404);
+    assert_eq!(app.post_saml_acs("").await.status().as_u16(), 404);
+}
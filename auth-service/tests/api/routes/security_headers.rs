@@ -0,0 +1,119 @@
+use auth_service::utils::security_headers::{auth_routes_policy, SecurityHeaders};
+
+use crate::helpers::TestApp;
+
+fn csp_nonce(response: &reqwest::Response) -> String {
+    let policy = response
+        .headers()
+        .get("content-security-policy")
+        .expect("No Content-Security-Policy header")
+        .to_str()
+        .unwrap();
+    policy
+        .split("'nonce-")
+        .nth(1)
+        .and_then(|rest| rest.split('\'').next())
+        .expect("No nonce in Content-Security-Policy")
+        .to_owned()
+}
+
+#[tokio::test]
+async fn login_page_scripts_carry_csp_nonce() {
+    let app = TestApp::new().await;
+
+    let response = app.get_root().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let nonce = csp_nonce(&response);
+    let page = response.text().await.unwrap();
+
+    assert!(!page.contains("{{csp_nonce}}"));
+    assert_eq!(
+        page.matches(&format!("<script nonce=\"{}\"", nonce))
+            .count(),
+        page.matches("<script").count()
+    );
+
+    // Every response gets a fresh nonce
+    let response = app.get_root().await;
+    assert_ne!(csp_nonce(&response), nonce);
+}
+
+#[tokio::test]
+async fn should_set_security_headers_on_all_responses() {
+    let app = TestApp::new().await;
+
+    let asset = app
+        .http_client
+        .get(format!("{}/lgr_logo.png", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let login = app.post_login(&serde_json::json!({})).await;
+
+    for response in [&asset, &login] {
+        let headers = response.headers();
+        assert!(headers.contains_key("content-security-policy"));
+        assert!(headers.contains_key("strict-transport-security"));
+        assert_eq!(headers["x-frame-options"], "DENY");
+        assert_eq!(headers["referrer-policy"], "no-referrer");
+        assert!(headers.contains_key("permissions-policy"));
+    }
+
+    // Only auth responses are kept out of caches
+    assert_eq!(login.headers()["cache-control"], "no-store");
+    assert!(!asset.headers().contains_key("cache-control"));
+}
+
+#[tokio::test]
+async fn should_apply_route_overrides() {
+    let headers = SecurityHeaders {
+        frame_options: Some("DENY".to_owned()),
+        ..Default::default()
+    };
+    let embeddable = SecurityHeaders {
+        frame_options: Some("SAMEORIGIN".to_owned()),
+        ..headers.clone()
+    };
+    let app =
+        TestApp::with_security_headers(auth_routes_policy(headers).with_route("/", embeddable))
+            .await;
+
+    let response = app.get_root().await;
+    assert_eq!(response.headers()["x-frame-options"], "SAMEORIGIN");
+    assert!(!response.headers().contains_key("content-security-policy"));
+
+    let response = app.post_login(&serde_json::json!({})).await;
+    assert_eq!(response.headers()["x-frame-options"], "DENY");
+    assert_eq!(response.headers()["cache-control"], "no-store");
+}
+
+#[tokio::test]
+async fn should_not_cache_phone_number_routes() {
+    let app = TestApp::new().await;
+
+    // Rejected requests still carry the header
+    for path in ["", "/verify"] {
+        let response = app
+            .post_phone_number(path, &serde_json::json!({}), "invalid")
+            .await;
+        assert_eq!(response.headers()["cache-control"], "no-store");
+    }
+}
+
+#[tokio::test]
+async fn should_not_cache_login_reports() {
+    let app = TestApp::new().await;
+
+    let response = app.post_login_report(&serde_json::json!({})).await;
+    assert_eq!(response.headers()["cache-control"], "no-store");
+}
+
+#[tokio::test]
+async fn should_not_cache_dev_mailbox() {
+    let app = TestApp::with_dev_mailbox().await;
+
+    // Holds live 2FA codes and sign-in links
+    let response = app.get_dev_mailbox("").await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["cache-control"], "no-store");
+}
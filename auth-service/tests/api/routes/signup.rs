@@ -1,4 +1,8 @@
-use auth_service::ErrorResponse;
+use auth_service::{
+    domain::{models::Email, Locale},
+    services::UserStore,
+    ErrorResponse, PasswordPolicyErrorResponse,
+};
 
 use crate::helpers::{get_random_email, TestApp};
 
@@ -34,6 +38,37 @@ async fn should_return_201_for_valid_signup() {
     assert_eq!(response.status().as_u16(), 201);
 }
 
+#[tokio::test]
+async fn should_store_supported_locale() {
+    let app = TestApp::new().await;
+
+    for (locale, expected) in [
+        (Some("de-AT"), Some(Locale::De)),
+        (Some("ja"), None),
+        (None, None),
+    ] {
+        let email = get_random_email();
+        let body = serde_json::json!({
+            "email": email,
+            "password": "anotherPassword!",
+            "requires2FA": true,
+            "locale": locale
+        });
+
+        let response = app.post_signup(&body).await;
+        assert_eq!(response.status().as_u16(), 201);
+
+        let user = app
+            .user_store
+            .read()
+            .await
+            .get(&Email::new(email.into()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(user.locale, expected, "Failed for locale: {:?}", locale);
+    }
+}
+
 #[tokio::test]
 async fn should_return_409_for_duplicate_signup() {
     let app = TestApp::new().await;
@@ -84,3 +119,69 @@ async fn should_return_400_if_invalid_input() {
         );
     }
 }
+
+#[tokio::test]
+async fn should_return_400_with_feedback_if_password_violates_policy() {
+    let app = TestApp::new().await;
+
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+
+    let response = app.post_signup(&body).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let body = response
+        .json::<PasswordPolicyErrorResponse>()
+        .await
+        .expect("Could not deserialize response body to PasswordPolicyErrorResponse");
+    assert_eq!(body.error, "Password does not meet requirements");
+
+    let rules: Vec<&str> = body.violations.iter().map(|v| v.rule.as_str()).collect();
+    assert!(rules.contains(&"common_password"), "rules: {:?}", rules);
+    assert!(rules.contains(&"min_strength"), "rules: {:?}", rules);
+}
+
+#[tokio::test]
+async fn should_return_400_if_password_contains_email() {
+    let app = TestApp::new().await;
+
+    let body = serde_json::json!({
+        "email": "marguerite@example.com",
+        "password": "Zq8!marguerite#Vw3",
+        "requires2FA": false
+    });
+
+    let response = app.post_signup(&body).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let body = response
+        .json::<PasswordPolicyErrorResponse>()
+        .await
+        .expect("Could not deserialize response body to PasswordPolicyErrorResponse");
+    assert!(body.violations.iter().any(|v| v.rule == "email_local_part"));
+}
+
+#[tokio::test]
+async fn should_return_400_if_password_was_breached() {
+    let app = TestApp::new().await;
+
+    let body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "Tr0ub4dor&3-Xylophone",
+        "requires2FA": false
+    });
+
+    let response = app.post_signup(&body).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let body = response
+        .json::<PasswordPolicyErrorResponse>()
+        .await
+        .expect("Could not deserialize response body to PasswordPolicyErrorResponse");
+    let rules: Vec<&str> = body.violations.iter().map(|v| v.rule.as_str()).collect();
+    assert_eq!(rules, vec!["breached_password"]);
+}
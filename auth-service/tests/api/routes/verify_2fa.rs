@@ -3,12 +3,12 @@ use auth_service::{
         models::{Email, Password},
         User,
     },
-    routes::{LoginResponse, TwoFactorAuthResponse},
-    services::{TwoFACodeStore, UserStore},
+    routes::{TokenResponse, TwoFactorAuthResponse},
+    services::UserStore,
     utils::constants::JWT_COOKIE_NAME,
 };
 
-use crate::{helpers::TestApp, routes::login};
+use crate::helpers::{two_fa_code_from, TestApp};
 
 #[tokio::test]
 async fn should_return_422_if_malformed_input() {
@@ -41,8 +41,8 @@ async fn should_return_401_if_incorrect_credentials() {
         .write()
         .await
         .insert(User::new(
-            Email::new("user@example.com".to_string()).unwrap(),
-            Password::new("correct_password".to_string()).unwrap(),
+            Email::new("user@example.com".into()).unwrap(),
+            Password::new("correct_password".into()).unwrap(),
             true,
         ))
         .await
@@ -76,8 +76,8 @@ async fn should_return_401_if_old_code() {
         .write()
         .await
         .insert(User::new(
-            Email::new("user@example.com".to_string()).unwrap(),
-            Password::new("correct_password".to_string()).unwrap(),
+            Email::new("user@example.com".into()).unwrap(),
+            Password::new("correct_password".into()).unwrap(),
             true,
         ))
         .await
@@ -90,35 +90,52 @@ async fn should_return_401_if_old_code() {
         }))
         .await;
     assert_eq!(login_response.status().as_u16(), 206);
+    let old_code = two_fa_code_from(&app.wait_for_emails_to("user@example.com", 1).await);
 
-    let login_response = app
-        .post_login(&serde_json::json!({
-            "email": "user@example.com",
-            "password": "correct_password"
-        }))
-        .await;
+    // Logs in again until the new code differs, as the same code would not tell anything
+    let mut emails_sent = 1;
+    let login_response = loop {
+        let login_response = app
+            .post_login(&serde_json::json!({
+                "email": "user@example.com",
+                "password": "correct_password"
+            }))
+            .await;
 
-    assert_eq!(login_response.status().as_u16(), 206);
+        assert_eq!(login_response.status().as_u16(), 206);
+        let login_response = login_response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("Failed to parse JSON response");
+        emails_sent += 1;
+        let new_code = two_fa_code_from(
+            &app.wait_for_emails_to("user@example.com", emails_sent)
+                .await,
+        );
+        if new_code != old_code {
+            break login_response;
+        }
+    };
 
     let response = app
         .post_verify_2fa(&serde_json::json!({
             "email": "user@example.com",
-            "loginAttemptId": uuid::Uuid::new_v4().to_string(),
-            "2FACode": "123456"
+            "loginAttemptId": login_response.login_attempt_id,
+            "2FACode": old_code
         }))
         .await;
     assert_eq!(response.status().as_u16(), 401);
 }
 
 #[tokio::test]
-async fn should_return_200_if_correct_code() {
+async fn should_email_code_to_user() {
     let app = TestApp::new().await;
     app.user_store
         .write()
         .await
         .insert(User::new(
-            Email::new("user@example.com".to_string()).unwrap(),
-            Password::new("correct_password".to_string()).unwrap(),
+            Email::new("user@example.com".into()).unwrap(),
+            Password::new("correct_password".into()).unwrap(),
             true,
         ))
         .await
@@ -132,14 +149,38 @@ async fn should_return_200_if_correct_code() {
         .await;
     assert_eq!(login_response.status().as_u16(), 206);
 
-    let (_login_attempt, code) = app
-        .two_fa_code_store
-        .read()
+    let email = app.wait_for_emails_to("user@example.com", 1).await;
+    assert_eq!(email.recipient, "user@example.com");
+    assert!(email.subject.contains("verification code"));
+
+    let code = two_fa_code_from(&email);
+    assert!(email.html.unwrap().contains(&code));
+}
+
+#[tokio::test]
+async fn should_return_200_if_correct_code() {
+    let app = TestApp::new().await;
+    app.user_store
+        .write()
         .await
-        .get_code(&Email::new("user@example.com".to_string()).unwrap())
+        .insert(User::new(
+            Email::new("user@example.com".into()).unwrap(),
+            Password::new("correct_password".into()).unwrap(),
+            true,
+        ))
         .await
         .unwrap();
 
+    let login_response = app
+        .post_login(&serde_json::json!({
+            "email": "user@example.com",
+            "password": "correct_password"
+        }))
+        .await;
+    assert_eq!(login_response.status().as_u16(), 206);
+
+    let code = two_fa_code_from(&app.wait_for_emails_to("user@example.com", 1).await);
+
     let login_response = login_response
         .json::<TwoFactorAuthResponse>()
         .await
@@ -149,7 +190,7 @@ async fn should_return_200_if_correct_code() {
         .post_verify_2fa(&serde_json::json!({
             "email": "user@example.com",
             "loginAttemptId": login_response.login_attempt_id,
-            "2FACode": code.as_ref(),
+            "2FACode": code,
         }))
         .await;
 
@@ -169,8 +210,8 @@ async fn should_return_401_if_same_code_twice() {
         .write()
         .await
         .insert(User::new(
-            Email::new("user@example.com".to_string()).unwrap(),
-            Password::new("correct_password".to_string()).unwrap(),
+            Email::new("user@example.com".into()).unwrap(),
+            Password::new("correct_password".into()).unwrap(),
             true,
         ))
         .await
@@ -184,13 +225,7 @@ async fn should_return_401_if_same_code_twice() {
         .await;
     assert_eq!(login_response.status().as_u16(), 206);
 
-    let (_login_attempt, code) = app
-        .two_fa_code_store
-        .read()
-        .await
-        .get_code(&Email::new("user@example.com".to_string()).unwrap())
-        .await
-        .unwrap();
+    let code = two_fa_code_from(&app.wait_for_emails_to("user@example.com", 1).await);
 
     let login_response = login_response
         .json::<TwoFactorAuthResponse>()
@@ -201,7 +236,7 @@ async fn should_return_401_if_same_code_twice() {
         .post_verify_2fa(&serde_json::json!({
             "email": "user@example.com",
             "loginAttemptId": login_response.login_attempt_id,
-            "2FACode": code.as_ref(),
+            "2FACode": code,
         }))
         .await;
     assert_eq!(response.status().as_u16(), 200);
@@ -210,8 +245,54 @@ async fn should_return_401_if_same_code_twice() {
         .post_verify_2fa(&serde_json::json!({
             "email": "user@example.com",
             "loginAttemptId": login_response.login_attempt_id,
-            "2FACode": code.as_ref(),
+            "2FACode": code,
         }))
         .await;
     assert_eq!(response.status().as_u16(), 401);
 }
+
+#[tokio::test]
+async fn should_return_token_in_body_if_requested() {
+    let app = TestApp::new().await;
+    app.user_store
+        .write()
+        .await
+        .insert(User::new(
+            Email::new("user@example.com".into()).unwrap(),
+            Password::new("correct_password".into()).unwrap(),
+            true,
+        ))
+        .await
+        .unwrap();
+
+    let login_response = app
+        .post_login(&serde_json::json!({
+            "email": "user@example.com",
+            "password": "correct_password"
+        }))
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Failed to parse JSON response");
+
+    let code = two_fa_code_from(&app.wait_for_emails_to("user@example.com", 1).await);
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": "user@example.com",
+            "loginAttemptId": login_response.login_attempt_id,
+            "2FACode": code,
+            "includeToken": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = response
+        .json::<TokenResponse>()
+        .await
+        .expect("Failed to parse JSON response");
+    assert_eq!(body.token_type, "Bearer");
+
+    let response = app.post_verify_token_with_bearer(&body.token).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -1,9 +1,11 @@
 use auth_service::{
-    domain::models::Email, services::BannedTokenStore, utils::auth::generate_auth_cookie,
+    domain::models::Email,
+    services::BannedTokenStore,
+    utils::{auth::generate_auth_cookie, cookies::CookiePolicy},
 };
 use reqwest::Url;
 
-use crate::helpers::TestApp;
+use crate::helpers::{get_random_email, get_token_claims, TestApp};
 
 #[tokio::test]
 async fn should_return_422_if_malformed_input() {
@@ -20,7 +22,11 @@ async fn should_return_200_valid_token() {
 
     let random_email = "user".to_string() + &uuid::Uuid::new_v4().to_string() + "@example.com";
 
-    let cookie = generate_auth_cookie(&Email::new(random_email).unwrap()).unwrap();
+    let cookie = generate_auth_cookie(
+        &Email::new(random_email.into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap();
 
     // add valid cookie
     app.cookie_jar.add_cookie_str(
@@ -53,15 +59,19 @@ async fn should_return_401_if_invalid_token() {
 #[tokio::test]
 async fn should_return_401_if_banned_token() {
     let app = TestApp::new().await;
-    let cookie =
-        generate_auth_cookie(&Email::new("email@example.com".to_string()).unwrap()).unwrap();
+    let cookie = generate_auth_cookie(
+        &Email::new("email@example.com".into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap();
     let token = cookie.value().to_owned();
 
     {
         let mut token_store = app.banned_token_store.write().await;
+        let claims = get_token_claims(&token);
 
         token_store
-            .ban_token(&token)
+            .ban_token(&claims.jti, claims.remaining_lifetime())
             .await
             .expect("Failed to ban token");
     }
@@ -74,3 +84,90 @@ async fn should_return_401_if_banned_token() {
 
     assert_eq!(response.status(), 401);
 }
+
+#[tokio::test]
+async fn should_verify_bearer_token_without_body() {
+    let app = TestApp::new().await;
+    let token = generate_auth_cookie(
+        &Email::new(get_random_email().into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap()
+    .value()
+    .to_owned();
+
+    let response = app.post_verify_token_with_bearer(&token).await;
+    assert_eq!(response.status(), 200);
+
+    let response = app
+        .post_verify_token_with_bearer("invalid.token.here")
+        .await;
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn should_return_400_if_no_token_given() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .http_client
+        .post(format!("{}/verify-token", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn should_check_expected_audience() {
+    let app = TestApp::new().await;
+    let token = generate_auth_cookie(
+        &Email::new(get_random_email().into()).unwrap(),
+        &CookiePolicy::default(),
+    )
+    .unwrap()
+    .value()
+    .to_owned();
+
+    let response = app
+        .post_verify_token(&serde_json::json!({
+            "token": &token,
+            "audience": "app-service"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_verify_token(&serde_json::json!({
+            "token": &token,
+            "audience": "billing-service"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .http_client
+        .post(format!(
+            "{}/verify-token?audience=billing-service",
+            &app.address
+        ))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+
+    // The query parameter also applies to tokens sent in the body
+    let response = app
+        .http_client
+        .post(format!(
+            "{}/verify-token?audience=billing-service",
+            &app.address
+        ))
+        .json(&serde_json::json!({ "token": &token }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 401);
+}
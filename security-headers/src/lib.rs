@@ -0,0 +1,225 @@
+use std::{collections::HashMap, env, sync::Arc};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{
+        header::{
+            CACHE_CONTROL, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+            X_FRAME_OPTIONS,
+        },
+        HeaderMap, HeaderName, HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+const PERMISSIONS_POLICY: HeaderName = HeaderName::from_static("permissions-policy");
+
+// Replaced with the nonce of the request in the Content-Security-Policy header
+pub const CSP_NONCE_PLACEHOLDER: &str = "{nonce}";
+
+// A fresh random value per request, which lets the scripts of a page through the
+// Content-Security-Policy while injected scripts are blocked
+#[derive(Debug, Clone)]
+pub struct CspNonce(String);
+
+impl CspNonce {
+    fn generate() -> Self {
+        Self(format!("{:032x}", rand::random::<u128>()))
+    }
+}
+
+impl AsRef<str> for CspNonce {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// The security headers of a response. Headers set to `None` are left out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SecurityHeaders {
+    pub content_security_policy: Option<String>,
+    pub strict_transport_security: Option<String>,
+    pub frame_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub permissions_policy: Option<String>,
+    pub cache_control: Option<String>,
+}
+
+impl SecurityHeaders {
+    // Each header of `defaults` can be replaced through its environment variable, or turned off
+    // with `off`
+    pub fn from_env(defaults: SecurityHeaders) -> Self {
+        Self {
+            content_security_policy: header_from_env(
+                "SECURITY_CONTENT_SECURITY_POLICY",
+                defaults.content_security_policy,
+            ),
+            strict_transport_security: header_from_env(
+                "SECURITY_STRICT_TRANSPORT_SECURITY",
+                defaults.strict_transport_security,
+            ),
+            frame_options: header_from_env("SECURITY_FRAME_OPTIONS", defaults.frame_options),
+            referrer_policy: header_from_env("SECURITY_REFERRER_POLICY", defaults.referrer_policy),
+            permissions_policy: header_from_env(
+                "SECURITY_PERMISSIONS_POLICY",
+                defaults.permissions_policy,
+            ),
+            cache_control: defaults.cache_control,
+        }
+    }
+
+    pub fn no_store(self) -> Self {
+        Self {
+            cache_control: Some("no-store".to_owned()),
+            ..self
+        }
+    }
+
+    // Headers already set by the handler are kept
+    fn apply(&self, headers: &mut HeaderMap, nonce: &CspNonce) {
+        let content_security_policy = self
+            .content_security_policy
+            .as_ref()
+            .map(|policy| policy.replace(CSP_NONCE_PLACEHOLDER, nonce.as_ref()));
+
+        let values = [
+            (CONTENT_SECURITY_POLICY, content_security_policy.as_ref()),
+            (
+                STRICT_TRANSPORT_SECURITY,
+                self.strict_transport_security.as_ref(),
+            ),
+            (X_FRAME_OPTIONS, self.frame_options.as_ref()),
+            (REFERRER_POLICY, self.referrer_policy.as_ref()),
+            (PERMISSIONS_POLICY, self.permissions_policy.as_ref()),
+            (CACHE_CONTROL, self.cache_control.as_ref()),
+        ];
+        for (name, value) in values {
+            let Some(value) = value else {
+                continue;
+            };
+            match HeaderValue::from_str(value) {
+                Ok(value) => {
+                    headers.entry(name).or_insert(value);
+                }
+                Err(_) => warn!(header = %name, "Invalid security header value, skipping"),
+            }
+        }
+    }
+}
+
+fn header_from_env(name: &str, default: Option<String>) -> Option<String> {
+    match env::var(name) {
+        Ok(value) if value.eq_ignore_ascii_case("off") => None,
+        Ok(value) if !value.is_empty() => Some(value),
+        _ => default,
+    }
+}
+
+// Holds the security headers sent with every response, and replacements for individual routes,
+// keyed by the route path as registered on the router
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersPolicy {
+    default: SecurityHeaders,
+    routes: HashMap<String, SecurityHeaders>,
+}
+
+impl SecurityHeadersPolicy {
+    pub fn new(headers: SecurityHeaders) -> Self {
+        Self {
+            default: headers,
+            routes: HashMap::new(),
+        }
+    }
+
+    pub fn with_route(mut self, route: &str, headers: SecurityHeaders) -> Self {
+        self.routes.insert(route.to_owned(), headers);
+        self
+    }
+
+    pub fn default_headers(&self) -> &SecurityHeaders {
+        &self.default
+    }
+
+    pub fn headers_for(&self, route: Option<&str>) -> &SecurityHeaders {
+        route
+            .and_then(|route| self.routes.get(route))
+            .unwrap_or(&self.default)
+    }
+}
+
+// Generates the CSP nonce of the request, which handlers read with `Extension<CspNonce>`, and
+// adds the security headers of the matched route to the response
+pub async fn security_headers(
+    State(policy): State<Arc<SecurityHeadersPolicy>>,
+    matched_path: Option<MatchedPath>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let nonce = CspNonce::generate();
+    request.extensions_mut().insert(nonce.clone());
+
+    let mut response = next.run(request).await;
+    policy
+        .headers_for(matched_path.as_ref().map(MatchedPath::as_str))
+        .apply(response.headers_mut(), &nonce);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> SecurityHeaders {
+        SecurityHeaders {
+            content_security_policy: Some("script-src 'nonce-{nonce}'".to_owned()),
+            frame_options: Some("DENY".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_apply_fills_in_nonce() {
+        let nonce = CspNonce::generate();
+        let mut response_headers = HeaderMap::new();
+        headers().apply(&mut response_headers, &nonce);
+
+        assert_eq!(
+            response_headers[CONTENT_SECURITY_POLICY],
+            format!("script-src 'nonce-{}'", nonce.as_ref())
+        );
+        assert_eq!(response_headers[X_FRAME_OPTIONS], "DENY");
+        assert!(!response_headers.contains_key(STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[test]
+    fn test_apply_keeps_headers_set_by_handler() {
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("SAMEORIGIN"));
+        headers().apply(&mut response_headers, &CspNonce::generate());
+
+        assert_eq!(response_headers[X_FRAME_OPTIONS], "SAMEORIGIN");
+    }
+
+    #[test]
+    fn test_nonces_are_unique() {
+        assert_ne!(CspNonce::generate().0, CspNonce::generate().0);
+    }
+
+    #[test]
+    fn test_route_override() {
+        let embeddable = SecurityHeaders {
+            frame_options: None,
+            ..headers()
+        };
+        let policy = SecurityHeadersPolicy::new(headers()).with_route("/widget", embeddable);
+
+        assert_eq!(policy.headers_for(Some("/widget")).frame_options, None);
+        assert_eq!(
+            policy.headers_for(Some("/")).frame_options.as_deref(),
+            Some("DENY")
+        );
+        assert_eq!(policy.headers_for(None), policy.default_headers());
+    }
+}